@@ -1,6 +1,21 @@
 pub mod app;
+pub mod args;
+mod blue_noise;
+pub mod bvh;
 pub mod camera;
+pub mod config;
+pub mod environment;
+pub mod exr;
 pub mod geom;
+pub mod gltf;
 pub mod material;
+mod noise;
+pub mod obj;
+mod sampling;
+pub mod scene_file;
+pub mod screenshot;
+pub mod settings;
 mod shader;
+pub mod shader_watcher;
 mod vertex_input;
+pub mod world;
\ No newline at end of file
@@ -1,60 +1,913 @@
 use std::cell::RefCell;
 use std::cmp::max;
+use std::collections::{BTreeMap, VecDeque};
+use std::path::PathBuf;
 use std::rc::Rc;
 use std::sync::Arc;
-use std::time::Instant;
+use std::time::{Duration, Instant};
 
-use imgui::Context;
+use cgmath::{Matrix4, SquareMatrix};
+use half::f16;
+use imgui::{ConfigFlags, Context, TextureId};
 use imgui::Ui;
-use vulkano::buffer::{Buffer, BufferCreateInfo, BufferUsage, Subbuffer};
-use vulkano::descriptor_set::{PersistentDescriptorSet, WriteDescriptorSet};
-use vulkano::memory::allocator::{AllocationCreateInfo, MemoryUsage};
-use vulkano::pipeline::{GraphicsPipeline, Pipeline, PipelineBindPoint};
+use serde::{Deserialize, Serialize};
+use vulkano::buffer::{BufferContents, BufferUsage, Subbuffer};
+use vulkano::buffer::allocator::SubbufferAllocator;
+use vulkano::descriptor_set::{DescriptorSet, PersistentDescriptorSet, WriteDescriptorSet};
+use vulkano::format::Format;
+use vulkano::image::{ImageAccess, ImageDimensions, ImmutableImage, MipmapsCount};
+use vulkano::image::view::{ImageView, ImageViewAbstract};
+use vulkano::pipeline::{ComputePipeline, GraphicsPipeline, Pipeline, PipelineBindPoint};
 use vulkano::pipeline::graphics::vertex_input::Vertex;
 use vulkano::pipeline::graphics::viewport::Viewport;
-use vulkano::render_pass::Subpass;
 use vulkano::sampler::{Filter, Sampler, SamplerAddressMode, SamplerCreateInfo};
+use vulkano::swapchain::PresentMode;
 use vulkano_win::create_surface_from_winit;
 use winit::dpi::PhysicalSize;
-use winit::event::{ElementState, Event, VirtualKeyCode, WindowEvent};
+use winit::event::{DeviceEvent, ElementState, Event, MouseButton, VirtualKeyCode, WindowEvent};
 use winit::event_loop::EventLoop;
 use winit::platform::run_return::EventLoopExtRunReturn;
-use winit::window::{Window, WindowBuilder};
+use winit::window::{CursorGrabMode, Fullscreen, Window, WindowBuilder};
 
+use crate::app::blue_noise;
+use crate::app::blue_noise::BLUE_NOISE_SIZE;
+use crate::app::bvh::Bvh;
 use crate::app::camera::Camera;
-use crate::app::geom::Circle;
+use crate::app::config::AppConfig;
+use crate::app::environment;
+use crate::app::environment::ImageError;
+use crate::app::exr;
+use crate::app::exr::ExrError;
+use crate::app::geom;
+use crate::app::geom::{Box, Capsule, Circle, Cylinder, DirectionalLight, HomogeneousVolume, Plane, Torus, Triangle};
+use crate::app::gltf;
+use crate::app::gltf::{GltfError, GltfLoadReport};
 use crate::app::material::Material;
+use crate::app::obj;
+use crate::app::obj::ObjError;
+use crate::app::sampling;
+use crate::app::scene_file::SceneError;
+use crate::app::screenshot;
+use crate::app::settings::RenderSettings;
 use crate::app::shader;
+use crate::app::shader_watcher;
+use crate::app::shader_watcher::ShaderWatcher;
 use crate::app::vertex_input::ScreenVertex;
+use crate::error::{RaytracingError, shader_error};
 use crate::imgui_winit_support::{HiDpiMode, WinitPlatform};
+use crate::vk::descriptor_pool::DescriptorPoolAllocator;
 use crate::vk::imgui::ImGuiRenderer;
-use crate::vk::vk::{DrawStatus, Vk};
+use crate::vk::profiler::Timestamp;
+use crate::vk::shader_cache::ShaderKey;
+use crate::vk::vk::{DrawStatus, Readback, Vk, VkBuilder};
+
+// radians per pixel of mouse motion while looking around
+const MOUSE_LOOK_SENSITIVITY: f32 = 0.003;
+
+// written by Ctrl+S and read by Ctrl+O, in the working directory
+const SCENE_FILE: &str = "scene.ron";
+
+// window positions and docking, saved by imgui in the working directory
+const IMGUI_LAYOUT_FILE: &str = "imgui_layout.ini";
+
+// same as MAX_MATERIAL_TEXTURES in the raytracing shader
+const MAX_MATERIAL_TEXTURES: usize = 8;
+
+// samples per pixel after which the accumulated image counts as converged
+const CONVERGED_SAMPLE_COUNT: u32 = 1024;
+// frame rate once converged, new frames barely change the image
+const IDLE_FPS: f32 = 10.0;
+
+// undo steps kept by the scene history
+const HISTORY_LENGTH: usize = 50;
+
+// listed by the Keyboard Shortcuts window: keys, action and description
+const SHORTCUTS: [(&str, &str, &str); 12] = [
+    ("W / S", "Move", "Forward and backward, towards the target in orbit mode"),
+    ("A / D", "Strafe", "Left and right, around the target in orbit mode"),
+    ("Q / E", "Rise", "Vertically along the camera's up axis"),
+    ("Right mouse drag", "Look", "Rotates the camera, or orbits the target in orbit mode"),
+    ("Ctrl+S", "Save scene", "Writes the scene to scene.ron"),
+    ("Ctrl+O", "Load scene", "Reads the scene back from scene.ron"),
+    ("Ctrl+Z", "Undo", "Restores the circles, materials and camera before the last edit"),
+    ("Ctrl+Shift+Z", "Redo", "Reapplies the last undone edit"),
+    ("F1", "Help", "Shows or hides this window"),
+    ("F11", "Fullscreen", "Switches between the window and borderless fullscreen"),
+    ("F12", "Screenshot", "Saves the current image as a PNG"),
+    ("Shift+F12", "Export EXR", "Saves the color, albedo, normal and depth layers as an EXR"),
+];
+
+/// Objects, materials and camera at some point of the edit history, without the dirty flags.
+///
+/// Every list is kept since removing a material renumbers the objects using the ones after it.
+#[derive(Clone, Default, PartialEq)]
+pub struct SceneSnapshot {
+    camera: Camera,
+    materials: Vec<Material>,
+    circles: Vec<Circle>,
+    boxes: Vec<Box>,
+    planes: Vec<Plane>,
+    triangles: Vec<Triangle>,
+    cylinders: Vec<Cylinder>,
+    capsules: Vec<Capsule>,
+    tori: Vec<Torus>,
+    directional_lights: Vec<DirectionalLight>,
+    volumes: Vec<HomogeneousVolume>,
+}
+
+impl SceneSnapshot {
+    /// Whether `scene` holds the same state, compared in place so nothing is cloned.
+    pub fn matches(&self, scene: &Scene) -> bool {
+        self.camera == scene.camera
+            && same_items(&self.materials, &scene.all_materials, |m| m.dirty = false)
+            && same_items(&self.circles, &scene.all_circles, |c| c.dirty = false)
+            && same_items(&self.boxes, &scene.all_boxes, |b| b.dirty = false)
+            && same_items(&self.planes, &scene.all_planes, |p| p.dirty = false)
+            && same_items(&self.triangles, &scene.all_triangles, |t| t.dirty = false)
+            && same_items(&self.cylinders, &scene.all_cylinders, |c| c.dirty = false)
+            && same_items(&self.capsules, &scene.all_capsules, |c| c.dirty = false)
+            && same_items(&self.tori, &scene.all_tori, |t| t.dirty = false)
+            && same_items(&self.directional_lights, &scene.all_directional_lights, |l| l.dirty = false)
+            && same_items(&self.volumes, &scene.all_volumes, |v| v.dirty = false)
+    }
+
+    /// Copies the state of `scene`, only the lists that changed are cloned again.
+    pub fn update(&mut self, scene: &Scene) {
+        if self.camera != scene.camera {
+            self.camera = scene.camera.clone();
+        }
+        update_items(&mut self.materials, &scene.all_materials, |m| m.dirty = false);
+        update_items(&mut self.circles, &scene.all_circles, |c| c.dirty = false);
+        update_items(&mut self.boxes, &scene.all_boxes, |b| b.dirty = false);
+        update_items(&mut self.planes, &scene.all_planes, |p| p.dirty = false);
+        update_items(&mut self.triangles, &scene.all_triangles, |t| t.dirty = false);
+        update_items(&mut self.cylinders, &scene.all_cylinders, |c| c.dirty = false);
+        update_items(&mut self.capsules, &scene.all_capsules, |c| c.dirty = false);
+        update_items(&mut self.tori, &scene.all_tori, |t| t.dirty = false);
+        update_items(&mut self.directional_lights, &scene.all_directional_lights, |l| l.dirty = false);
+        update_items(&mut self.volumes, &scene.all_volumes, |v| v.dirty = false);
+    }
+}
+
+// `saved` has the dirty flags cleared, `clean` clears them on a copy of each current item
+fn same_items<T: Clone + PartialEq>(saved: &[T], current: &[T], clean: fn(&mut T)) -> bool {
+    saved.len() == current.len() && saved.iter().zip(current).all(|(s, c)| {
+        let mut c = c.clone();
+        clean(&mut c);
+        *s == c
+    })
+}
+
+fn update_items<T: Clone + PartialEq>(saved: &mut Vec<T>, current: &[T], clean: fn(&mut T)) {
+    if !same_items(saved, current, clean) {
+        *saved = current.to_vec();
+        saved.iter_mut().for_each(clean);
+    }
+}
+
+/// Buffer slot and dirty flag of the objects kept in the scene lists.
+trait SceneObject {
+    fn index_mut(&mut self) -> &mut usize;
+    fn dirty_mut(&mut self) -> &mut bool;
+}
+
+macro_rules! scene_object {
+    ($($object:ty),*) => {
+        $(impl SceneObject for $object {
+            fn index_mut(&mut self) -> &mut usize {
+                &mut self.index
+            }
+
+            fn dirty_mut(&mut self) -> &mut bool {
+                &mut self.dirty
+            }
+        })*
+    };
+}
+
+scene_object!(Material, Circle, Box, Plane, Triangle, Cylinder, Capsule, Torus, DirectionalLight, HomogeneousVolume);
+
+/// Storage buffer declared in the raytracing shader as a single `list[]` of objects.
+trait GpuList {
+    type Item;
+    fn set(&mut self, index: usize, item: Self::Item);
+}
+
+macro_rules! gpu_list {
+    ($($buffer:ident => $item:ident),*) => {
+        $(impl GpuList for shader::raytrace::fs::$buffer {
+            type Item = shader::raytrace::fs::$item;
+
+            fn set(&mut self, index: usize, item: Self::Item) {
+                self.list[index] = item.into();
+            }
+        })*
+    };
+}
+
+gpu_list!(
+    MaterialBuffer => Material,
+    CircleBuffer => Circle,
+    BoxBuffer => Box,
+    PlaneBuffer => Plane,
+    TriangleBuffer => Triangle,
+    CylinderBuffer => Cylinder,
+    CapsuleBuffer => Capsule,
+    TorusBuffer => Torus,
+    DirectionalLightBuffer => DirectionalLight,
+    VolumeBuffer => Volume
+);
+
+/// Removes the object at `index`, the ones after it move down by one slot.
+fn remove_indexed<T: SceneObject>(objects: &mut Vec<T>, index: usize) {
+    objects.remove(index);
+    for (i, object) in objects.iter_mut().enumerate() {
+        *object.index_mut() = i;
+        *object.dirty_mut() = true;
+    }
+}
+
+/// Writes the dirty `objects` to their slot of `buffer`. The buffer is replaced when the length
+/// changes or the GPU still reads it, then every object is written again. It always has at least
+/// one entry so it can be bound, `empty` fills it for an empty list.
+///
+/// Returns whether the buffer was replaced, the descriptor set has to point at the new one.
+fn upload_list<T, B>(
+    allocator: &SubbufferAllocator,
+    objects: &mut [T],
+    buffer: &mut Option<Rc<RefCell<Subbuffer<B>>>>,
+    buffer_size: &mut usize,
+    empty: Option<T>,
+) -> bool
+where
+    T: SceneObject + Copy + Into<B::Item>,
+    B: GpuList + BufferContents + ?Sized,
+{
+    upload_list_with(allocator, objects, buffer, buffer_size, empty.map(Into::into), |o| (*o).into())
+}
+
+/// `upload_list` for objects that need more than their own fields to be converted.
+fn upload_list_with<T, B>(
+    allocator: &SubbufferAllocator,
+    objects: &mut [T],
+    buffer: &mut Option<Rc<RefCell<Subbuffer<B>>>>,
+    buffer_size: &mut usize,
+    empty: Option<B::Item>,
+    convert: impl Fn(&T) -> B::Item,
+) -> bool
+where
+    T: SceneObject,
+    B: GpuList + BufferContents + ?Sized,
+{
+    let any_dirty = objects.iter_mut().any(|o| *o.dirty_mut());
+    let replace = match buffer {
+        Some(buffer) => objects.len() != *buffer_size || (any_dirty && buffer.borrow().write().is_err()),
+        None => true,
+    };
+    if replace {
+        let new: Subbuffer<B> = allocator.allocate_unsized(max(objects.len(), 1) as u64).unwrap();
+        if let (true, Some(empty)) = (objects.is_empty(), empty) {
+            new.write().unwrap().set(0, empty);
+        }
+        *buffer = Some(Rc::new(RefCell::new(new)));
+        *buffer_size = objects.len();
+        objects.iter_mut().for_each(|o| *o.dirty_mut() = true);
+    }
 
-#[derive(Default)]
+    let buffer = buffer.as_ref().unwrap().borrow();
+    let mut writer = None;
+    for (i, object) in objects.iter_mut().enumerate() {
+        if std::mem::take(object.dirty_mut()) {
+            let writer = writer.get_or_insert_with(|| buffer.write().unwrap());
+            writer.set(i, convert(object));
+        }
+    }
+    replace
+}
+
+#[derive(Serialize, Deserialize)]
+#[serde(default)]
 pub struct Scene {
     pub camera: Camera,
     pub all_materials: Vec<Material>,
     pub all_circles: Vec<Circle>,
+    pub all_boxes: Vec<Box>,
+    pub all_planes: Vec<Plane>,
+    pub all_triangles: Vec<Triangle>,
+    pub all_cylinders: Vec<Cylinder>,
+    pub all_capsules: Vec<Capsule>,
+    pub all_tori: Vec<Torus>,
+    pub all_directional_lights: Vec<DirectionalLight>,
+    pub all_volumes: Vec<HomogeneousVolume>,
+
+    pub sort_by_material: bool,
+    pub anaglyph_mode: bool,
+    pub eye_separation: f32,
+    pub jitter_mode: i32,
+    /// 0 is linear, 1 is Reinhard and 2 is ACES filmic.
+    pub tone_mapping_mode: i32,
+    /// Bounces traced per path, up to 32.
+    pub max_bounce_depth: u32,
+    /// Randomly ends paths with a low throughput after `rr_min_bounces` bounces.
+    pub use_russian_roulette: bool,
+    pub rr_min_bounces: u32,
+    /// Samples the emissive circles directly at every bounce, next event estimation.
+    pub use_nee: bool,
+    /// Standard deviations above its accumulated neighborhood a new sample is clamped to, 0 disables.
+    pub firefly_threshold: f32,
+    /// Share of the previous denoised frame blended into the current one, from 0.0 to 0.99.
+    pub temporal_alpha: f32,
+    /// Averages the frames while nothing changes, off renders every frame on its own.
+    pub accumulate: bool,
+    /// Runs the denoiser as a horizontal and a vertical 1D pass instead of one 2D kernel.
+    pub denoiser_separable: bool,
+    /// Rays per pixel of the ambient occlusion view.
+    pub ao_sample_count: u32,
+    /// Distance within which geometry occludes in the ambient occlusion view.
+    pub ao_radius: f32,
+    /// Resolves the denoised frame with the reprojected history, implies the Halton jitter.
+    pub taa_enabled: bool,
+    /// Weight of the current frame in the TAA resolve, from 0.05 to 0.2.
+    pub taa_blend_factor: f32,
+    /// Blurs the colors brighter than `bloom_threshold` over their surroundings.
+    pub bloom_enabled: bool,
+    /// Luminance a color has to exceed to bloom, before the exposure.
+    pub bloom_threshold: f32,
+    /// Weight of the bloom added to the tone mapped color.
+    pub bloom_strength: f32,
+    /// Offsets the projection every frame by a Halton (2, 3) sub-pixel jitter.
+    pub use_halton_jitter: bool,
+    /// Multiplies the color before tone mapping.
+    pub exposure: f32,
+    /// Scales `exposure` so the average luminance of the image ends up middle gray.
+    pub auto_exposure: bool,
+
+    /// Skip uploading geometry changes, the GPU keeps rendering the last uploaded state.
+    pub freeze_geometry: bool,
+
+    /// Preetham sky instead of the sky gradient, an environment map still takes precedence.
+    pub sky_enabled: bool,
+    /// Points towards the sun, normalized by the shader.
+    pub sun_direction: [f32; 3],
+    /// Haziness of the atmosphere, from 1.0 (clear) to 10.0 (hazy).
+    pub sun_turbidity: f32,
+
+    /// Latitude-longitude image seen by the rays leaving the scene instead of the sky gradient,
+    /// see `App::set_environment_map`. Not saved with the scene, like material textures.
+    #[serde(skip)]
+    pub environment_map: Option<TextureId>,
+
+    /// Frames averaged in the accumulation image, reset to 0 when the view or scene changes.
+    #[serde(skip)]
+    pub frame_index: u32,
+    /// Something visible changed since the last frame, the accumulation starts over.
+    #[serde(skip)]
+    pub scene_dirty: bool,
+
+    /// States before each edit, the last one is restored by `undo`.
+    #[serde(skip)]
+    history: VecDeque<SceneSnapshot>,
+    /// States left by `undo`, emptied by any new edit.
+    #[serde(skip)]
+    redo_history: Vec<SceneSnapshot>,
+}
+
+impl Default for Scene {
+    fn default() -> Self {
+        Self {
+            camera: Camera::default(),
+            all_materials: Vec::new(),
+            all_circles: Vec::new(),
+            all_boxes: Vec::new(),
+            all_planes: Vec::new(),
+            all_triangles: Vec::new(),
+            all_cylinders: Vec::new(),
+            all_capsules: Vec::new(),
+            all_tori: Vec::new(),
+            all_directional_lights: Vec::new(),
+            all_volumes: Vec::new(),
+            sort_by_material: false,
+            anaglyph_mode: false,
+            eye_separation: 0.065,
+            jitter_mode: 0,
+            tone_mapping_mode: 0,
+            max_bounce_depth: 8,
+            use_russian_roulette: false,
+            rr_min_bounces: 3,
+            use_nee: true,
+            firefly_threshold: 10.0,
+            temporal_alpha: 0.9,
+            accumulate: true,
+            denoiser_separable: false,
+            ao_sample_count: 8,
+            ao_radius: 1.0,
+            taa_enabled: false,
+            taa_blend_factor: 0.1,
+            bloom_enabled: false,
+            bloom_threshold: 1.0,
+            bloom_strength: 0.3,
+            use_halton_jitter: false,
+            exposure: 1.0,
+            auto_exposure: false,
+            freeze_geometry: false,
+            sky_enabled: false,
+            sun_direction: [0.3, 0.5, 0.3],
+            sun_turbidity: 2.5,
+            environment_map: None,
+            frame_index: 0,
+            scene_dirty: true,
+            history: VecDeque::new(),
+            redo_history: Vec::new(),
+        }
+    }
+}
+
+impl Scene {
+    /// Copy of the editable state, without the dirty flags.
+    pub fn snapshot(&self) -> SceneSnapshot {
+        let mut snapshot = SceneSnapshot::default();
+        snapshot.update(self);
+        snapshot
+    }
+
+    /// Saves the current state so the next edit can be undone.
+    pub fn push_history(&mut self) {
+        let snapshot = self.snapshot();
+        self.push_snapshot(snapshot);
+    }
+
+    /// Saves a state from before an edit, `snapshot` should come from `Scene::snapshot`.
+    pub fn push_snapshot(&mut self, snapshot: SceneSnapshot) {
+        if self.history.len() == HISTORY_LENGTH {
+            self.history.pop_front();
+        }
+        self.history.push_back(snapshot);
+        self.redo_history.clear();
+    }
+
+    pub fn undo(&mut self) {
+        let Some(snapshot) = self.history.pop_back() else {
+            return;
+        };
+        let current = self.snapshot();
+        self.redo_history.push(current);
+        self.restore(snapshot);
+    }
+
+    pub fn redo(&mut self) {
+        let Some(snapshot) = self.redo_history.pop() else {
+            return;
+        };
+        let current = self.snapshot();
+        self.history.push_back(current);
+        self.restore(snapshot);
+    }
+
+    fn restore(&mut self, snapshot: SceneSnapshot) {
+        // the aspect ratio follows the window, not the history
+        let aspect = self.camera.aspect;
+        self.camera = snapshot.camera;
+        self.camera.aspect = aspect;
+        self.all_materials = snapshot.materials;
+        self.all_circles = snapshot.circles;
+        self.all_boxes = snapshot.boxes;
+        self.all_planes = snapshot.planes;
+        self.all_triangles = snapshot.triangles;
+        self.all_cylinders = snapshot.cylinders;
+        self.all_capsules = snapshot.capsules;
+        self.all_tori = snapshot.tori;
+        self.all_directional_lights = snapshot.directional_lights;
+        self.all_volumes = snapshot.volumes;
+        self.force_dirty_all();
+        self.scene_dirty = true;
+    }
+
+    /// Marks every object and material dirty, so everything is uploaded again.
+    pub fn force_dirty_all(&mut self) {
+        self.all_materials.iter_mut().for_each(|m| m.mark_dirty());
+        self.all_circles.iter_mut().for_each(|c| c.mark_dirty());
+        self.all_boxes.iter_mut().for_each(|b| b.mark_dirty());
+        self.all_planes.iter_mut().for_each(|p| p.mark_dirty());
+        self.all_triangles.iter_mut().for_each(|t| t.mark_dirty());
+        self.all_cylinders.iter_mut().for_each(|c| c.mark_dirty());
+        self.all_capsules.iter_mut().for_each(|c| c.mark_dirty());
+        self.all_tori.iter_mut().for_each(|t| t.mark_dirty());
+        self.all_directional_lights.iter_mut().for_each(|l| l.mark_dirty());
+        self.all_volumes.iter_mut().for_each(|v| v.mark_dirty());
+    }
+
+    /// Removes the circle at `index`, the ones after it move down by one.
+    pub fn remove_circle(&mut self, index: usize) {
+        remove_indexed(&mut self.all_circles, index);
+    }
+
+    pub fn add_cylinder(&mut self) -> &mut Cylinder {
+        let index = self.all_cylinders.len();
+        self.all_cylinders.push(Cylinder::new());
+        let c = self.all_cylinders.get_mut(index).unwrap();
+        c.index = index;
+        c
+    }
+
+    /// Removes the cylinder at `index`, the ones after it move down by one.
+    pub fn remove_cylinder(&mut self, index: usize) {
+        remove_indexed(&mut self.all_cylinders, index);
+    }
+
+    pub fn add_capsule(&mut self) -> &mut Capsule {
+        let index = self.all_capsules.len();
+        self.all_capsules.push(Capsule::new());
+        let c = self.all_capsules.get_mut(index).unwrap();
+        c.index = index;
+        c
+    }
+
+    /// Removes the capsule at `index`, the ones after it move down by one.
+    pub fn remove_capsule(&mut self, index: usize) {
+        remove_indexed(&mut self.all_capsules, index);
+    }
+
+    pub fn add_torus(&mut self) -> &mut Torus {
+        let index = self.all_tori.len();
+        self.all_tori.push(Torus::new());
+        let t = self.all_tori.get_mut(index).unwrap();
+        t.index = index;
+        t
+    }
+
+    /// Removes the torus at `index`, the ones after it move down by one.
+    pub fn remove_torus(&mut self, index: usize) {
+        remove_indexed(&mut self.all_tori, index);
+    }
+
+    pub fn add_directional_light(&mut self) -> &mut DirectionalLight {
+        let index = self.all_directional_lights.len();
+        self.all_directional_lights.push(DirectionalLight::new());
+        let l = self.all_directional_lights.get_mut(index).unwrap();
+        l.index = index;
+        l
+    }
+
+    /// Removes the directional light at `index`, the ones after it move down by one.
+    pub fn remove_directional_light(&mut self, index: usize) {
+        remove_indexed(&mut self.all_directional_lights, index);
+    }
+
+    pub fn add_volume(&mut self) -> &mut HomogeneousVolume {
+        let index = self.all_volumes.len();
+        self.all_volumes.push(HomogeneousVolume::new());
+        let v = self.all_volumes.get_mut(index).unwrap();
+        v.index = index;
+        v
+    }
+
+    /// Removes the volume at `index`, the ones after it move down by one.
+    pub fn remove_volume(&mut self, index: usize) {
+        remove_indexed(&mut self.all_volumes, index);
+    }
+
+    /// Id for the triangles of a new mesh, one past the highest in use.
+    pub fn next_mesh_id(&self) -> u32 {
+        self.all_triangles.iter().map(|t| t.mesh + 1).max().unwrap_or(0)
+    }
+
+    /// Id, triangle count and whether all triangles are smooth shaded, for every mesh.
+    pub fn meshes(&self) -> Vec<(u32, usize, bool)> {
+        let mut meshes: BTreeMap<u32, (usize, bool)> = BTreeMap::new();
+        for t in &self.all_triangles {
+            let (count, smooth) = meshes.entry(t.mesh).or_insert((0, true));
+            *count += 1;
+            *smooth &= t.smooth_normals;
+        }
+        meshes.into_iter().map(|(id, (count, smooth))| (id, count, smooth)).collect()
+    }
+
+    /// Switches the triangles of `mesh` between their vertex normals and flat shading.
+    pub fn set_smooth_normals(&mut self, mesh: u32, smooth_normals: bool) {
+        for t in self.all_triangles.iter_mut().filter(|t| t.mesh == mesh) {
+            t.smooth_normals(smooth_normals).mark_dirty();
+        }
+    }
+
+    /// Replaces the vertex normals of `mesh` by angle weighted averages of the faces around
+    /// each vertex, see `geom::compute_smooth_normals`.
+    pub fn compute_smooth_normals(&mut self, mesh: u32) {
+        geom::compute_smooth_normals(self.all_triangles.iter_mut().filter(|t| t.mesh == mesh));
+    }
+
+    /// Appends a copy of the circle at `index`, returns the index of the copy.
+    pub fn duplicate_circle(&mut self, index: usize) -> usize {
+        let mut circle = self.all_circles[index];
+        circle.index = self.all_circles.len();
+        circle.mark_dirty();
+        self.all_circles.push(circle);
+        circle.index
+    }
+
+    /// Appends a copy of the material at `index`, returns the index of the copy.
+    pub fn duplicate_material(&mut self, index: usize) -> usize {
+        let mut material = self.all_materials[index].clone();
+        material.index = self.all_materials.len();
+        material.name = format!("{} copy", material.name);
+        material.mark_dirty();
+        self.all_materials.push(material);
+        self.all_materials.len() - 1
+    }
+
+    /// Removes the material at `index` unless an object uses it, objects using the materials
+    /// after it are renumbered. Returns whether it was removed.
+    pub fn remove_material(&mut self, index: usize) -> bool {
+        let material = index as i32;
+        let users = self.all_circles.iter().filter(|c| c.material == material).count()
+            + self.all_boxes.iter().filter(|b| b.material == material).count()
+            + self.all_planes.iter().filter(|p| p.material == material).count()
+            + self.all_triangles.iter().filter(|t| t.material == material).count()
+            + self.all_cylinders.iter().filter(|c| c.material == material).count()
+            + self.all_capsules.iter().filter(|c| c.material == material).count()
+            + self.all_tori.iter().filter(|t| t.material == material).count();
+        if users > 0 {
+            println!("Material \"{}\" is used by {} objects, it can't be removed", self.all_materials[index].name, users);
+            return false;
+        }
+
+        remove_indexed(&mut self.all_materials, index);
+        let renumber = |m: &mut i32, dirty: &mut bool| {
+            if *m > material {
+                *m -= 1;
+                *dirty = true;
+            }
+        };
+        self.all_circles.iter_mut().for_each(|c| renumber(&mut c.material, &mut c.dirty));
+        self.all_boxes.iter_mut().for_each(|b| renumber(&mut b.material, &mut b.dirty));
+        self.all_planes.iter_mut().for_each(|p| renumber(&mut p.material, &mut p.dirty));
+        self.all_triangles.iter_mut().for_each(|t| renumber(&mut t.material, &mut t.dirty));
+        self.all_cylinders.iter_mut().for_each(|c| renumber(&mut c.material, &mut c.dirty));
+        self.all_capsules.iter_mut().for_each(|c| renumber(&mut c.material, &mut c.dirty));
+        self.all_tori.iter_mut().for_each(|t| renumber(&mut t.material, &mut t.dirty));
+        true
+    }
+
+    /// Whether any object or material has changes that weren't uploaded yet.
+    pub fn any_dirty(&self) -> bool {
+        self.all_materials.iter().any(|m| m.dirty)
+            || self.all_circles.iter().any(|c| c.dirty)
+            || self.all_boxes.iter().any(|b| b.dirty)
+            || self.all_planes.iter().any(|p| p.dirty)
+            || self.all_triangles.iter().any(|t| t.dirty)
+            || self.all_cylinders.iter().any(|c| c.dirty)
+            || self.all_capsules.iter().any(|c| c.dirty)
+            || self.all_tori.iter().any(|t| t.dirty)
+            || self.all_directional_lights.iter().any(|l| l.dirty)
+            || self.all_volumes.iter().any(|v| v.dirty)
+    }
+
+    /// Indices of the circles overlapping the sphere at `center` with `radius`, none for a
+    /// negative or NaN radius.
+    pub fn spheres_in_radius(&self, center: [f32; 3], radius: f32) -> Vec<usize> {
+        if radius.is_nan() || radius < 0.0 {
+            return Vec::new();
+        }
+        let query = *Circle::new().position(center).radius(radius);
+        let (query_min, query_max) = query.aabb();
+        self.all_circles.iter()
+            .enumerate()
+            .filter(|(_, circle)| circle.intersect_aabb(query_min, query_max))
+            .filter(|(_, circle)| {
+                let distance_squared: f32 = (0..3).map(|i| (circle.position[i] - center[i]).powi(2)).sum();
+                let max_distance = circle.radius + radius;
+                distance_squared < max_distance * max_distance
+            })
+            .map(|(i, _)| i)
+            .collect()
+    }
 
-    pub sample_count: u32,
+    /// Volume weighted centroid of the objects.
+    pub fn center_of_mass(&self) -> [f32; 3] {
+        let mut center = [0.0; 3];
+        let mut total_volume = 0.0;
+        for circle in &self.all_circles {
+            let volume = circle.radius.powi(3);
+            for (c, p) in center.iter_mut().zip(circle.position) {
+                *c += p * volume;
+            }
+            total_volume += volume;
+        }
+        for b in &self.all_boxes {
+            let volume: f32 = (0..3).map(|i| b.max[i] - b.min[i]).product();
+            for (i, c) in center.iter_mut().enumerate() {
+                *c += (b.min[i] + b.max[i]) * 0.5 * volume;
+            }
+            total_volume += volume;
+        }
+        for cylinder in &self.all_cylinders {
+            let volume = std::f32::consts::PI * cylinder.radius * cylinder.radius * cylinder.height;
+            let mid = [0.0, cylinder.height * 0.5, 0.0];
+            for (i, c) in center.iter_mut().enumerate() {
+                *c += (cylinder.position[i] + mid[i]) * volume;
+            }
+            total_volume += volume;
+        }
+        for capsule in &self.all_capsules {
+            let length: f32 = (0..3).map(|i| (capsule.end[i] - capsule.start[i]).powi(2)).sum::<f32>().sqrt();
+            let volume = std::f32::consts::PI * capsule.radius.powi(2) * (length + capsule.radius * 4.0 / 3.0);
+            for (i, c) in center.iter_mut().enumerate() {
+                *c += (capsule.start[i] + capsule.end[i]) * 0.5 * volume;
+            }
+            total_volume += volume;
+        }
+        for torus in &self.all_tori {
+            let volume = 2.0 * std::f32::consts::PI.powi(2) * torus.major_radius * torus.minor_radius.powi(2);
+            for (c, p) in center.iter_mut().zip(torus.center) {
+                *c += p * volume;
+            }
+            total_volume += volume;
+        }
+        if total_volume == 0.0 {
+            return [0.0; 3];
+        }
+        center.map(|c| c / total_volume)
+    }
+
+    pub fn translate(&mut self, offset: [f32; 3]) {
+        for circle in self.all_circles.iter_mut() {
+            for (p, o) in circle.position.iter_mut().zip(offset) {
+                *p += o;
+            }
+            circle.mark_dirty();
+        }
+        for b in self.all_boxes.iter_mut() {
+            for (i, o) in offset.iter().enumerate() {
+                b.min[i] += o;
+                b.max[i] += o;
+            }
+            b.mark_dirty();
+        }
+        for plane in self.all_planes.iter_mut() {
+            plane.distance += (0..3).map(|i| plane.normal[i] * offset[i]).sum::<f32>();
+            plane.mark_dirty();
+        }
+        for triangle in self.all_triangles.iter_mut() {
+            for v in [&mut triangle.v0, &mut triangle.v1, &mut triangle.v2] {
+                for (p, o) in v.iter_mut().zip(offset) {
+                    *p += o;
+                }
+            }
+            triangle.mark_dirty();
+        }
+        for cylinder in self.all_cylinders.iter_mut() {
+            for (p, o) in cylinder.position.iter_mut().zip(offset) {
+                *p += o;
+            }
+            cylinder.mark_dirty();
+        }
+        for capsule in self.all_capsules.iter_mut() {
+            for (i, o) in offset.iter().enumerate() {
+                capsule.start[i] += o;
+                capsule.end[i] += o;
+            }
+            capsule.mark_dirty();
+        }
+        for torus in self.all_tori.iter_mut() {
+            for (p, o) in torus.center.iter_mut().zip(offset) {
+                *p += o;
+            }
+            torus.mark_dirty();
+        }
+    }
 
-    pub current_view: i32,
-    pub kernel_size: i32,
-    pub kernel_offset: i32,
-    pub denoiser_albedo_weight: f32,
-    pub denoiser_normal_weight: f32,
-    pub denoiser_depth_weight: f32,
+    /// Scales positions and sizes around the origin so every object fits in a sphere of `target_radius`.
+    pub fn normalize_scale(&mut self, target_radius: f32) {
+        let length = |v: [f32; 3]| v.iter().map(|p| p * p).sum::<f32>().sqrt();
+        let circle_extent = self.all_circles.iter()
+            .map(|c| length(c.position) + c.radius)
+            .fold(0.0, f32::max);
+        // the farthest corner of a box is the one with the largest coordinate on each axis
+        let box_extent = self.all_boxes.iter()
+            .map(|b| length([0, 1, 2].map(|i| b.min[i].abs().max(b.max[i].abs()))))
+            .fold(0.0, f32::max);
+        let triangle_extent = self.all_triangles.iter()
+            .flat_map(|t| [t.v0, t.v1, t.v2])
+            .map(length)
+            .fold(0.0, f32::max);
+        // bounded by the sphere of the radius around the farther cap center
+        let cylinder_extent = self.all_cylinders.iter()
+            .map(|c| {
+                let top = [c.position[0], c.position[1] + c.height, c.position[2]];
+                length(c.position).max(length(top)) + c.radius
+            })
+            .fold(0.0, f32::max);
+        let capsule_extent = self.all_capsules.iter()
+            .map(|c| length(c.start).max(length(c.end)) + c.radius)
+            .fold(0.0, f32::max);
+        let torus_extent = self.all_tori.iter()
+            .map(|t| length(t.center) + t.major_radius + t.minor_radius)
+            .fold(0.0, f32::max);
+        let extent = circle_extent.max(box_extent).max(triangle_extent).max(cylinder_extent).max(capsule_extent).max(torus_extent);
+        if extent == 0.0 {
+            return;
+        }
+        let scale = target_radius / extent;
+        for circle in self.all_circles.iter_mut() {
+            circle.position = circle.position.map(|p| p * scale);
+            circle.radius *= scale;
+            circle.mark_dirty();
+        }
+        for b in self.all_boxes.iter_mut() {
+            b.min = b.min.map(|p| p * scale);
+            b.max = b.max.map(|p| p * scale);
+            b.mark_dirty();
+        }
+        for plane in self.all_planes.iter_mut() {
+            plane.distance *= scale;
+            plane.mark_dirty();
+        }
+        for triangle in self.all_triangles.iter_mut() {
+            triangle.v0 = triangle.v0.map(|p| p * scale);
+            triangle.v1 = triangle.v1.map(|p| p * scale);
+            triangle.v2 = triangle.v2.map(|p| p * scale);
+            triangle.mark_dirty();
+        }
+        for cylinder in self.all_cylinders.iter_mut() {
+            cylinder.position = cylinder.position.map(|p| p * scale);
+            cylinder.radius *= scale;
+            cylinder.height *= scale;
+            cylinder.mark_dirty();
+        }
+        for capsule in self.all_capsules.iter_mut() {
+            capsule.start = capsule.start.map(|p| p * scale);
+            capsule.end = capsule.end.map(|p| p * scale);
+            capsule.radius *= scale;
+            capsule.mark_dirty();
+        }
+        for torus in self.all_tori.iter_mut() {
+            torus.center = torus.center.map(|p| p * scale);
+            torus.major_radius *= scale;
+            torus.minor_radius *= scale;
+            torus.mark_dirty();
+        }
+    }
 }
 
-#[derive(Debug, Clone, Copy, Default)]
+/// Frames kept in the FPS and frame time histories.
+pub const FRAME_HISTORY_LENGTH: usize = 128;
+
+#[derive(Debug, Clone, Copy)]
 pub struct Info {
     pub time: f32,
     pub fps: f32,
+    pub frame_count: u32,
+    /// Last `history_len` frames, oldest first.
+    pub fps_history: [f32; FRAME_HISTORY_LENGTH],
+    pub frame_time_history: [f32; FRAME_HISTORY_LENGTH],
+    pub history_len: usize,
+    /// GPU time of the raytrace pass, from its end to the start of the denoiser submit, of the
+    /// denoiser and imgui passes and of the whole frame.
+    pub pass_times_ms: [f32; 5],
+    /// Fragment shader invocations of the raytrace pass, 0 when pipeline statistics aren't supported.
+    pub raytrace_invocations: u64,
+    /// `time` of the last shader hot reload.
+    pub shader_reload_time: Option<f32>,
+    /// Whether the surface has a present mode without vsync, so it can be turned off.
+    pub vsync_optional: bool,
+    /// Whether the device has timeline semaphores, so `RenderSettings::timeline_semaphore` matters.
+    pub timeline_semaphore: bool,
+    /// Device local memory used by the app, only known when `memory_budget` is set.
+    pub gpu_memory_used_mb: f32,
+    pub gpu_memory_total_mb: f32,
+    pub memory_budget: bool,
+    /// Scene storage buffers currently allocated.
+    pub scene_buffers: u32,
+    /// Descriptor sets allocated for the last frame.
+    pub frame_descriptor_sets: u32,
+}
+
+impl Default for Info {
+    fn default() -> Self {
+        Self {
+            time: 0.0,
+            fps: 0.0,
+            frame_count: 0,
+            fps_history: [0.0; FRAME_HISTORY_LENGTH],
+            frame_time_history: [0.0; FRAME_HISTORY_LENGTH],
+            history_len: 0,
+            pass_times_ms: [0.0; 5],
+            raytrace_invocations: 0,
+            shader_reload_time: None,
+            vsync_optional: false,
+            timeline_semaphore: false,
+            gpu_memory_used_mb: 0.0,
+            gpu_memory_total_mb: 0.0,
+            memory_budget: false,
+            scene_buffers: 0,
+            frame_descriptor_sets: 0,
+        }
+    }
 }
 
 pub struct App<F>
 {
-    window: Arc<Window>,
+    /// `None` with `AppConfig::headless`, like the event loop.
+    window: Option<Arc<Window>>,
     event_loop: Option<EventLoop<()>>,
 
     size: [u32; 2],
@@ -62,18 +915,59 @@ pub struct App<F>
 
     run_ui: F,
     scene: Scene,
+    settings: RenderSettings,
+    /// Settings the last frame was rendered with, a change resets the accumulation.
+    applied_settings: RenderSettings,
+    /// Settings last written to disk.
+    saved_settings: RenderSettings,
 
     pub vulkan: Vk,
     viewport: Viewport,
     raytracing_pipeline: Arc<GraphicsPipeline>,
     denoiser_pipeline: Arc<GraphicsPipeline>,
+    exposure_pipeline: Arc<ComputePipeline>,
+    variance_pipeline: Arc<ComputePipeline>,
+    denoiser_horizontal_pipeline: Arc<ComputePipeline>,
+    taa_pipeline: Arc<GraphicsPipeline>,
+    bloom_pipeline: Arc<ComputePipeline>,
+    frame_descriptor_allocator: DescriptorPoolAllocator,
     sampler: Arc<Sampler>,
+    /// Linear sampler clamped to the edges, for the bloom blur.
+    clamp_sampler: Arc<Sampler>,
+    blue_noise_texture: Arc<ImageView<ImmutableImage>>,
     vertex_buffer: Subbuffer<[ScreenVertex]>,
+    halton_sequence: Vec<[f32; 2]>,
+    /// Adapted average luminance, written by the exposure compute shader.
+    auto_exposure_buffer: Subbuffer<[f32]>,
 
     material_buffer: Option<Rc<RefCell<Subbuffer<shader::raytrace::fs::MaterialBuffer>>>>,
     material_buffer_size: usize,
     circle_buffer: Option<Rc<RefCell<Subbuffer<shader::raytrace::fs::CircleBuffer>>>>,
     circle_buffer_size: usize,
+    box_buffer: Option<Rc<RefCell<Subbuffer<shader::raytrace::fs::BoxBuffer>>>>,
+    box_buffer_size: usize,
+    plane_buffer: Option<Rc<RefCell<Subbuffer<shader::raytrace::fs::PlaneBuffer>>>>,
+    plane_buffer_size: usize,
+    directional_light_buffer: Option<Rc<RefCell<Subbuffer<shader::raytrace::fs::DirectionalLightBuffer>>>>,
+    directional_light_buffer_size: usize,
+    volume_buffer: Option<Rc<RefCell<Subbuffer<shader::raytrace::fs::VolumeBuffer>>>>,
+    volume_buffer_size: usize,
+    /// Indices of the emissive circles, sampled by next event estimation.
+    light_buffer: Option<Rc<RefCell<Subbuffer<shader::raytrace::fs::LightBuffer>>>>,
+    light_list: Vec<u32>,
+    triangle_buffer: Option<Rc<RefCell<Subbuffer<shader::raytrace::fs::TriangleBuffer>>>>,
+    triangle_buffer_size: usize,
+    cylinder_buffer: Option<Rc<RefCell<Subbuffer<shader::raytrace::fs::CylinderBuffer>>>>,
+    cylinder_buffer_size: usize,
+    capsule_buffer: Option<Rc<RefCell<Subbuffer<shader::raytrace::fs::CapsuleBuffer>>>>,
+    capsule_buffer_size: usize,
+    torus_buffer: Option<Rc<RefCell<Subbuffer<shader::raytrace::fs::TorusBuffer>>>>,
+    torus_buffer_size: usize,
+    bvh_buffer: Option<Rc<RefCell<Subbuffer<shader::raytrace::fs::BvhBuffer>>>>,
+    bvh_index_buffer: Option<Rc<RefCell<Subbuffer<shader::raytrace::fs::BvhIndexBuffer>>>>,
+    material_textures: Vec<TextureId>,
+    /// Environment map bound in `geom_set`.
+    environment_texture: Option<TextureId>,
 
     geom_set: Option<Arc<PersistentDescriptorSet>>,
 
@@ -84,55 +978,137 @@ pub struct App<F>
     start_time: Instant,
     info: Info,
     pressed_keys: [bool; 165],
+    mouse_buttons: [bool; 8],
+    /// F12 was pressed, the accumulated color is read back after the current frame.
+    screenshot_requested: bool,
+    pending_screenshot: Option<Readback>,
+    /// Exit once this many frames were rendered, see `set_frame_limit`.
+    frame_limit: Option<u32>,
+    /// Position, rotation, and blur with the clip distances, a change restarts the accumulation.
+    last_camera_pose: Option<([f32; 3], [f32; 3], [f32; 3])>,
+    /// The camera was moving with the keyboard last update, the history has its position from before.
+    camera_moving: bool,
+    /// The Keyboard Shortcuts window is shown, toggled with F1 or the Help menu.
+    help_open: bool,
+    /// State the next UI edit starts from, pushed to the history once the edit ends.
+    ui_edit_base: SceneSnapshot,
+    /// A UI item was active last frame, so an edit may be in progress.
+    ui_editing: bool,
+    last_projection: Option<Matrix4<f32>>,
+    /// World to view transform and projection of the last frame, for the velocity buffer.
+    previous_view: Option<(Matrix4<f32>, Matrix4<f32>)>,
+    /// The last frame was resolved with TAA, so its history image holds something.
+    taa_history_valid: bool,
+    shader_watcher: Option<ShaderWatcher>,
+    fullscreen: bool,
+    present_modes: Vec<PresentMode>,
+    present_mode: PresentMode,
+    target_fps: Option<f32>,
+    fps_history: VecDeque<f32>,
+    /// Milliseconds.
+    frame_time_history: VecDeque<f32>,
 }
 
 impl<F> App<F>
-    where F: FnMut(&mut bool, &mut Ui, &mut Scene, Info) + 'static
+    where F: FnMut(&mut bool, &mut Ui, &mut Scene, &mut RenderSettings, Info) + 'static
 {
-    pub fn create(run_ui: F) -> Self
+    pub fn create(config: AppConfig, run_ui: F) -> Result<Self, RaytracingError>
     {
-        let default_window_size = [1200, 900];
-
-        let vk_instance = Vk::create_instance();
+        let mut settings = RenderSettings::load();
+        settings.vsync = config.vsync;
+        let default_window_size = [config.width, config.height];
 
-        let event_loop = EventLoop::new();
-        let window = WindowBuilder::new()
-            .with_title("Raytracing Demo")
-            .with_inner_size(PhysicalSize::new(
-                default_window_size[0] as f32,
-                default_window_size[1] as f32,
-            ))
-            .build(&event_loop)
-            .expect("Failed to create window");
-        let window = Arc::new(window);
+        let vk_instance = Vk::create_instance(config.enable_validation)?;
 
-        let surface = create_surface_from_winit(window.clone(), vk_instance.clone())
-            .expect("Failed to create surface");
+        let mut builder = VkBuilder::default();
+        if let Some(name) = &config.device {
+            match Vk::select_device_by_name(&vk_instance, name) {
+                Some(device) => {
+                    builder.use_device(device);
+                }
+                None => {
+                    println!("No device matches \"{}\", available devices:", name);
+                    for (device_name, device_type) in Vk::list_devices(&vk_instance) {
+                        println!("\t{} ({:?})", device_name, device_type);
+                    }
+                    return Err(RaytracingError::NoSuitableDevice);
+                }
+            }
+        }
+        let (event_loop, window, mut vulkan) = if config.headless {
+            let vulkan = builder.build_headless(vk_instance, default_window_size)?;
+            (None, None, vulkan)
+        } else {
+            let event_loop = EventLoop::new();
+            let window = WindowBuilder::new()
+                .with_title(config.title)
+                .with_inner_size(PhysicalSize::new(
+                    default_window_size[0] as f32,
+                    default_window_size[1] as f32,
+                ))
+                .build(&event_loop)?;
+            let window = Arc::new(window);
+
+            let surface = create_surface_from_winit(window.clone(), vk_instance.clone())?;
+            let vulkan = builder.build(vk_instance, surface)?;
+            (Some(event_loop), Some(window), vulkan)
+        };
 
-        let mut vulkan = Vk::create_device(vk_instance, surface);
+        let raytrace_vs = shader::raytrace::vs::load(vulkan.device.clone()).map_err(shader_error("raytrace vertex"))?;
+        let raytrace_fs = shader::raytrace::fs::load(vulkan.device.clone()).map_err(shader_error("raytrace fragment"))?;
+        let denoiser_vs = shader::denoiser::vs::load(vulkan.device.clone()).map_err(shader_error("denoiser vertex"))?;
+        let denoiser_fs = shader::denoiser::fs::load(vulkan.device.clone()).map_err(shader_error("denoiser fragment"))?;
+        let taa_fs = shader::taa::fs::load(vulkan.device.clone()).map_err(shader_error("taa fragment"))?;
+        let exposure_cs = shader::exposure::cs::load(vulkan.device.clone()).map_err(shader_error("exposure compute"))?;
+        let variance_cs = shader::variance::cs::load(vulkan.device.clone()).map_err(shader_error("variance compute"))?;
+        let bloom_cs = shader::bloom::cs::load(vulkan.device.clone()).map_err(shader_error("bloom compute"))?;
+        let denoiser_horizontal_cs = shader::denoiser_horizontal::cs::load(vulkan.device.clone())
+            .map_err(shader_error("denoiser horizontal compute"))?;
 
-        let raytracing_subpass = Subpass::from(vulkan.raytrace_render_pass.clone(), 0).unwrap();
         let raytracing_pipeline = vulkan.create_pipeline(
-            raytracing_subpass.clone(),
+            ShaderKey::new("raytrace"),
+            vulkan.raytrace_pipeline_target(),
             ScreenVertex::per_vertex(),
-            shader::raytrace::vs::load(vulkan.device.clone()).unwrap()
-                .entry_point("main").unwrap(),
-            shader::raytrace::fs::load(vulkan.device.clone()).unwrap()
-                .entry_point("main").unwrap(),
-            raytracing_subpass.num_color_attachments(),
+            |_| (raytrace_vs, raytrace_fs),
         );
 
-        let denoiser_subpass = Subpass::from(vulkan.screen_render_pass.clone(), 0).unwrap();
         let denoiser_pipeline = vulkan.create_pipeline(
-            denoiser_subpass.clone(),
+            ShaderKey::new("denoiser"),
+            vulkan.screen_pipeline_target(),
+            ScreenVertex::per_vertex(),
+            |_| (denoiser_vs.clone(), denoiser_fs),
+        );
+
+        let taa_pipeline = vulkan.create_pipeline(
+            ShaderKey::new("taa"),
+            vulkan.screen_pipeline_target(),
             ScreenVertex::per_vertex(),
-            shader::denoiser::vs::load(vulkan.device.clone()).unwrap()
-                .entry_point("main").unwrap(),
-            shader::denoiser::fs::load(vulkan.device.clone()).unwrap()
-                .entry_point("main").unwrap(),
-            denoiser_subpass.num_color_attachments(),
+            |_| (denoiser_vs, taa_fs),
         );
 
+        let exposure_pipeline = vulkan.create_compute_pipeline(exposure_cs, "exposure_pipeline");
+        let variance_pipeline = vulkan.create_compute_pipeline(variance_cs, "variance_pipeline");
+        let bloom_pipeline = vulkan.create_compute_pipeline(bloom_cs, "bloom_pipeline");
+        let denoiser_horizontal_pipeline = vulkan.create_compute_pipeline(denoiser_horizontal_cs, "denoiser_horizontal_pipeline");
+
+        let present_modes = vulkan.supported_present_modes();
+        println!("Present modes: {:?}", present_modes);
+
+        let frame_descriptor_allocator = DescriptorPoolAllocator::new(
+            vulkan.device.clone(),
+            &[
+                &raytracing_pipeline.layout().set_layouts()[0],
+                &denoiser_pipeline.layout().set_layouts()[0],
+                &exposure_pipeline.layout().set_layouts()[0],
+                &variance_pipeline.layout().set_layouts()[0],
+                &denoiser_horizontal_pipeline.layout().set_layouts()[0],
+                &taa_pipeline.layout().set_layouts()[0],
+                &bloom_pipeline.layout().set_layouts()[0],
+            ],
+            config.descriptor_pool_count,
+            256,
+        )?;
+
         let mut viewport = Viewport {
             origin: [0.0, 0.0],
             dimensions: [0.0, 0.0],
@@ -140,27 +1116,18 @@ impl<F> App<F>
         };
         vulkan.setup_framebuffer(&mut viewport);
 
-        // quad filling the screen
+        // one triangle covering the screen, the part outside is clipped and the uv is 0 to 1 inside.
+        // Unlike a quad, no pixels along a diagonal seam are shaded twice
         let vertices = [
-            ScreenVertex { position: [-1.0, -1.0] },
-            ScreenVertex { position: [-1.0, 1.0] },
-            ScreenVertex { position: [1.0, -1.0] },
-            ScreenVertex { position: [1.0, 1.0] },
-            ScreenVertex { position: [1.0, -1.0] },
-            ScreenVertex { position: [-1.0, 1.0] },
+            ScreenVertex { position: [-1.0, -1.0], uv: [0.0, 0.0] },
+            ScreenVertex { position: [-1.0, 3.0], uv: [0.0, 2.0] },
+            ScreenVertex { position: [3.0, -1.0], uv: [2.0, 0.0] },
         ];
-        let vertex_buffer = Buffer::from_iter(
-            &vulkan.memory_allocator,
-            BufferCreateInfo {
-                usage: BufferUsage::VERTEX_BUFFER,
-                ..Default::default()
-            },
-            AllocationCreateInfo {
-                usage: MemoryUsage::Upload,
-                ..Default::default()
-            },
-            vertices,
-        ).unwrap();
+        let vertex_buffer = vulkan.upload_buffer_data(&vertices, BufferUsage::VERTEX_BUFFER);
+        // 0 until the first measurement
+        let auto_exposure_buffer = vulkan.upload_buffer_data(&[0.0_f32], BufferUsage::STORAGE_BUFFER);
+        vulkan.set_debug_name(&**vertex_buffer.buffer(), "screen_vertex_buffer");
+        vulkan.set_debug_name(&**auto_exposure_buffer.buffer(), "auto_exposure_buffer");
 
         let sampler = Sampler::new(
             vulkan.device.clone(),
@@ -170,59 +1137,127 @@ impl<F> App<F>
                 address_mode: [SamplerAddressMode::Repeat; 3],
                 ..Default::default()
             },
-        ).unwrap();
+        )?;
+
+        let clamp_sampler = Sampler::new(
+            vulkan.device.clone(),
+            SamplerCreateInfo {
+                mag_filter: Filter::Linear,
+                min_filter: Filter::Linear,
+                address_mode: [SamplerAddressMode::ClampToEdge; 3],
+                ..Default::default()
+            },
+        )?;
 
         let mut imgui = Context::create();
-        imgui.set_ini_filename(None);
+        if config.reset_layout {
+            std::fs::remove_file(IMGUI_LAYOUT_FILE).ok();
+        }
+        imgui.io_mut().config_flags |= ConfigFlags::DOCKING_ENABLE;
 
         let mut imgui_platform = WinitPlatform::init(&mut imgui);
-        imgui_platform.attach_window(imgui.io_mut(), &window, HiDpiMode::Rounded);
+        match &window {
+            Some(window) => {
+                imgui.set_ini_filename(Some(PathBuf::from(IMGUI_LAYOUT_FILE)));
+                imgui_platform.attach_window(imgui.io_mut(), window, HiDpiMode::Rounded);
+            }
+            None => {
+                // the layout of the window isn't overwritten by a run without it
+                imgui.set_ini_filename(None);
+                imgui.io_mut().display_size = default_window_size.map(|d| d as f32);
+            }
+        }
 
         let imgui_renderer = ImGuiRenderer::init(
             &mut imgui,
             &vulkan,
             // vulkan.swapchain.image_format(),
-            denoiser_subpass.clone(),
-        ).unwrap();
+            vulkan.screen_pipeline_target(),
+        ).map_err(|e| RaytracingError::ResourceCreation(e.to_string()))?;
+
+        let blue_noise_texture = {
+            let mut uploads = vulkan.uploads.as_ref().unwrap().borrow_mut();
+            let image = ImmutableImage::from_iter(
+                &vulkan.memory_allocator,
+                blue_noise::generate(),
+                ImageDimensions::Dim2d {
+                    width: BLUE_NOISE_SIZE,
+                    height: BLUE_NOISE_SIZE,
+                    array_layers: 1,
+                },
+                MipmapsCount::One,
+                Format::R8_UNORM,
+                &mut uploads,
+            )?;
+            ImageView::new_default(image)?
+        };
 
         vulkan.do_upload();
+        let timeline_semaphore = vulkan.timeline_semaphore.is_some();
 
-        let mut camera = Camera::new();
-        camera.position[2] = -3.0;
+        let camera = Camera::from_look_at([0.0, 0.0, -3.0], [0.0, 0.0, 0.0], [0.0, 1.0, 0.0]);
 
         let scene = Scene {
             camera,
-            sample_count: 8,
-            current_view: 0,
-            kernel_size: 5,
-            kernel_offset: 2,
-            denoiser_albedo_weight: 0.01,
-            denoiser_normal_weight: 0.01,
-            denoiser_depth_weight: 0.3,
             ..Default::default()
         };
 
-        Self {
+        Ok(Self {
             window,
-            event_loop: Some(event_loop),
+            event_loop,
 
             size: default_window_size,
             recreate_swapchain: true,
 
             run_ui,
             scene,
+            settings,
+            applied_settings: settings,
+            saved_settings: settings,
 
             vulkan,
             viewport,
             raytracing_pipeline,
             denoiser_pipeline,
+            exposure_pipeline,
+            variance_pipeline,
+            denoiser_horizontal_pipeline,
+            taa_pipeline,
+            bloom_pipeline,
+            frame_descriptor_allocator,
             sampler,
+            clamp_sampler,
+            blue_noise_texture,
             vertex_buffer,
+            halton_sequence: sampling::halton_sequence(sampling::HALTON_SEQUENCE_LENGTH),
+            auto_exposure_buffer,
 
             material_buffer: Default::default(),
             material_buffer_size: 0,
             circle_buffer: Default::default(),
             circle_buffer_size: 0,
+            box_buffer: Default::default(),
+            box_buffer_size: 0,
+            plane_buffer: Default::default(),
+            plane_buffer_size: 0,
+            directional_light_buffer: Default::default(),
+            directional_light_buffer_size: 0,
+            volume_buffer: Default::default(),
+            volume_buffer_size: 0,
+            light_buffer: Default::default(),
+            light_list: Vec::new(),
+            triangle_buffer: Default::default(),
+            triangle_buffer_size: 0,
+            cylinder_buffer: Default::default(),
+            cylinder_buffer_size: 0,
+            capsule_buffer: Default::default(),
+            capsule_buffer_size: 0,
+            torus_buffer: Default::default(),
+            torus_buffer_size: 0,
+            bvh_buffer: Default::default(),
+            bvh_index_buffer: Default::default(),
+            material_textures: Vec::new(),
+            environment_texture: None,
 
             geom_set: None,
 
@@ -231,82 +1266,156 @@ impl<F> App<F>
             imgui_renderer,
 
             start_time: Instant::now(),
-            info: Default::default(),
+            info: Info {
+                vsync_optional: select_present_mode(&present_modes, false) != PresentMode::Fifo,
+                timeline_semaphore,
+                ..Default::default()
+            },
             pressed_keys: [false; 165],
+            mouse_buttons: [false; 8],
+            screenshot_requested: false,
+            pending_screenshot: None,
+            frame_limit: None,
+            last_camera_pose: None,
+            camera_moving: false,
+            help_open: false,
+            ui_edit_base: SceneSnapshot::default(),
+            ui_editing: false,
+            last_projection: None,
+            previous_view: None,
+            taa_history_valid: false,
+            shader_watcher: config.watch_shaders.then(|| ShaderWatcher::new(shader_watcher::SHADER_DIR)),
+            fullscreen: false,
+            present_mode: select_present_mode(&present_modes, config.vsync),
+            present_modes,
+            target_fps: config.target_fps.filter(|fps| *fps > 0.0),
+            fps_history: VecDeque::with_capacity(FRAME_HISTORY_LENGTH),
+            frame_time_history: VecDeque::with_capacity(FRAME_HISTORY_LENGTH),
+        })
+    }
+
+    /// Warns about changed objects whose material index doesn't exist, the shader would read past
+    /// the material buffer.
+    fn check_material_references(&self) {
+        let count = self.scene.all_materials.len() as i32;
+        let scene = &self.scene;
+        let dangling = scene.all_circles.iter().filter(|o| o.dirty).map(|o| ("Circle", o.material))
+            .chain(scene.all_boxes.iter().filter(|o| o.dirty).map(|o| ("Box", o.material)))
+            .chain(scene.all_planes.iter().filter(|o| o.dirty).map(|o| ("Plane", o.material)))
+            .chain(scene.all_triangles.iter().filter(|o| o.dirty).map(|o| ("Triangle", o.material)))
+            .chain(scene.all_cylinders.iter().filter(|o| o.dirty).map(|o| ("Cylinder", o.material)))
+            .chain(scene.all_capsules.iter().filter(|o| o.dirty).map(|o| ("Capsule", o.material)))
+            .chain(scene.all_tori.iter().filter(|o| o.dirty).map(|o| ("Torus", o.material)))
+            .filter(|(_, material)| !(0..count).contains(material));
+        for (kind, material) in dangling {
+            println!("Warning: {} uses material {} but there are only {} materials", kind, material, count);
         }
     }
 
     fn check_buffers(&mut self) {
+        if self.scene.freeze_geometry && self.geom_set.is_some() {
+            return;
+        }
+
+        if self.scene.any_dirty() {
+            self.scene.scene_dirty = true;
+            self.check_material_references();
+        }
         let mut update_descriptors = false;
 
-        let mut recreate_buffer = |s: &mut Self| {
-            s.material_buffer = Some(Rc::new(RefCell::new(
-                s.vulkan.storage_buffer.allocate_unsized(s.material_buffer_size.clone() as u64).unwrap()
-            )));
-            s.scene.all_materials.iter_mut().for_each(|m| m.dirty = true);
-            update_descriptors = true;
-        };
-        if self.scene.all_materials.len() != self.material_buffer_size {
-            self.material_buffer_size = self.scene.all_materials.len();
-            recreate_buffer(self);
-        }
-
-        let material_length = self.scene.all_materials.len();
-        for i in 0..material_length {
-            let m = self.scene.all_materials[i];
-            if m.dirty {
-                self.scene.all_materials[i].dirty = false;
-                let writer = self.material_buffer.as_ref().cloned().unwrap().clone();
-                let writer = writer.borrow_mut();
-                let writer = writer.write();
-                if writer.is_ok() {
-                    let mut w = writer.unwrap();
-                    w.list[i] = m.into();
-                } else {
-                    recreate_buffer(self);
-                    let writer = self.material_buffer.as_ref().cloned().unwrap().clone();
-                    let writer = writer.borrow_mut();
-                    let mut w = writer.write().unwrap();
-                    w.list[i] = m.into();
-                }
-            }
-        }
-
-        let mut recreate_buffer = |s: &mut Self| {
-            s.circle_buffer = Some(Rc::new(RefCell::new(
-                s.vulkan.storage_buffer.allocate_unsized(s.circle_buffer_size.clone() as u64).unwrap()
-            )));
-            s.scene.all_circles.iter_mut().for_each(|c| c.dirty = true);
+        // textures used by materials get a slot in the shader's texture array, in order of use
+        let mut material_textures = Vec::new();
+        let used = self.scene.all_materials.iter().flat_map(|m| [m.albedo_texture, m.normal_texture]).flatten();
+        for id in used {
+            if !material_textures.contains(&id) && self.imgui_renderer.texture(id).is_some() {
+                if material_textures.len() == MAX_MATERIAL_TEXTURES {
+                    println!("More than {} material textures in use, the rest are ignored", MAX_MATERIAL_TEXTURES);
+                    break;
+                }
+                material_textures.push(id);
+            }
+        }
+        if material_textures != self.material_textures {
+            self.material_textures = material_textures;
+            self.scene.all_materials.iter_mut().for_each(|m| m.dirty = true);
             update_descriptors = true;
-        };
+        }
 
-        let circle_length = self.scene.all_circles.len();
-        if circle_length != self.circle_buffer_size {
-            self.circle_buffer_size = circle_length;
-            recreate_buffer(self);
+        if self.scene.environment_map != self.environment_texture {
+            self.environment_texture = self.scene.environment_map;
+            update_descriptors = true;
         }
 
-        for i in 0..circle_length {
-            let c = self.scene.all_circles[i];
-            if c.dirty {
-                self.scene.all_circles[i].dirty = false;
-                let writer = self.circle_buffer.as_ref().cloned().unwrap().clone();
-                let writer = writer.borrow_mut();
-                let writer = writer.write();
-                if writer.is_ok() {
-                    let mut w = writer.unwrap();
-                    w.list[i] = c.into();
-                } else {
-                    recreate_buffer(self);
-                    let writer = self.circle_buffer.as_ref().cloned().unwrap().clone();
-                    let writer = writer.borrow_mut();
-                    let mut w = writer.write().unwrap();
-                    w.list[i] = c.into();
-                }
+        // the texture slots are only known here, the material itself has the texture ids
+        let textures = &self.material_textures;
+        let slot = |id: Option<TextureId>| id.and_then(|id| textures.iter().position(|&t| t == id)).map_or(-1, |s| s as i32);
+        update_descriptors |= upload_list_with(
+            &self.vulkan.storage_buffer,
+            &mut self.scene.all_materials,
+            &mut self.material_buffer,
+            &mut self.material_buffer_size,
+            None,
+            |m| shader::raytrace::fs::Material {
+                albedo_texture: slot(m.albedo_texture),
+                normal_texture: slot(m.normal_texture),
+                ..m.clone().into()
+            },
+        );
+
+        // any moved circle can change the bounds, so the tree is rebuilt instead of refitted
+        let circles_changed = self.scene.all_circles.len() != self.circle_buffer_size
+            || self.scene.all_circles.iter().any(|c| c.dirty);
+        if circles_changed || self.bvh_buffer.is_none() {
+            let bvh = Bvh::build(&self.scene.all_circles);
+            let nodes: Subbuffer<shader::raytrace::fs::BvhBuffer> = self.vulkan.storage_buffer.allocate_unsized(bvh.nodes.len() as u64).unwrap();
+            for (dst, node) in nodes.write().unwrap().list.iter_mut().zip(bvh.nodes) {
+                *dst = node.into();
             }
+            let indices: Subbuffer<shader::raytrace::fs::BvhIndexBuffer> = self.vulkan.storage_buffer.allocate_unsized(max(bvh.indices.len(), 1) as u64).unwrap();
+            indices.write().unwrap().list[..bvh.indices.len()].copy_from_slice(&bvh.indices);
+            self.bvh_buffer = Some(Rc::new(RefCell::new(nodes)));
+            self.bvh_index_buffer = Some(Rc::new(RefCell::new(indices)));
+            update_descriptors = true;
+        }
+
+        // the BVH of no circles references none, so an empty buffer needs no placeholder
+        let storage = &self.vulkan.storage_buffer;
+        update_descriptors |= upload_list(storage, &mut self.scene.all_circles, &mut self.circle_buffer, &mut self.circle_buffer_size, None);
+        // the other buffers get an object that can't be hit, or adds nothing, when the list is empty
+        update_descriptors |= upload_list(storage, &mut self.scene.all_boxes, &mut self.box_buffer, &mut self.box_buffer_size, Some(Box::empty()));
+        update_descriptors |= upload_list(storage, &mut self.scene.all_planes, &mut self.plane_buffer, &mut self.plane_buffer_size, Some(Plane::empty()));
+        // a zeroed triangle is degenerate
+        let empty_triangle = Triangle::new([0.0; 3], [0.0; 3], [0.0; 3]);
+        update_descriptors |= upload_list(storage, &mut self.scene.all_triangles, &mut self.triangle_buffer, &mut self.triangle_buffer_size, Some(empty_triangle));
+        update_descriptors |= upload_list(storage, &mut self.scene.all_cylinders, &mut self.cylinder_buffer, &mut self.cylinder_buffer_size, Some(Cylinder::empty()));
+        update_descriptors |= upload_list(storage, &mut self.scene.all_capsules, &mut self.capsule_buffer, &mut self.capsule_buffer_size, Some(Capsule::empty()));
+        update_descriptors |= upload_list(storage, &mut self.scene.all_tori, &mut self.torus_buffer, &mut self.torus_buffer_size, Some(Torus::empty()));
+        let (lights, light_buffer) = (&mut self.scene.all_directional_lights, &mut self.directional_light_buffer);
+        update_descriptors |= upload_list(storage, lights, light_buffer, &mut self.directional_light_buffer_size, Some(DirectionalLight::empty()));
+        update_descriptors |= upload_list(storage, &mut self.scene.all_volumes, &mut self.volume_buffer, &mut self.volume_buffer_size, Some(HomogeneousVolume::empty()));
+
+        // same test as the shader, a material is emissive when any channel of its emission is
+        let light_list: Vec<u32> = self.scene.all_circles.iter()
+            .enumerate()
+            .filter(|(_, c)| {
+                let material = usize::try_from(c.material).ok().and_then(|m| self.scene.all_materials.get(m));
+                material.is_some_and(|m| m.emission.iter().any(|e| e * m.emission_strength > 0.0))
+            })
+            .map(|(i, _)| i as u32)
+            .collect();
+        if light_list != self.light_list || self.light_buffer.is_none() {
+            // the shader only reads the first light_count entries
+            let buffer: Subbuffer<shader::raytrace::fs::LightBuffer> = self.vulkan.storage_buffer.allocate_unsized(max(light_list.len(), 1) as u64).unwrap();
+            buffer.write().unwrap().list[..light_list.len()].copy_from_slice(&light_list);
+            self.light_buffer = Some(Rc::new(RefCell::new(buffer)));
+            self.light_list = light_list;
+            update_descriptors = true;
         }
 
         if update_descriptors {
+            // a buffer was replaced, e.g. after the last object of a kind was removed, which no
+            // dirty flag reports
+            self.scene.scene_dirty = true;
             if let Some(layout) = self.raytracing_pipeline.layout().set_layouts().get(1) {
                 let mut descriptor_set = Vec::new();
                 if let Some(m) = self.material_buffer.clone() {
@@ -317,11 +1426,70 @@ impl<F> App<F>
                     let buf = c.borrow().clone();
                     descriptor_set.push(WriteDescriptorSet::buffer(1, buf));
                 }
+                if let Some(b) = self.box_buffer.clone() {
+                    let buf = b.borrow().clone();
+                    descriptor_set.push(WriteDescriptorSet::buffer(2, buf));
+                }
+                if let Some(p) = self.plane_buffer.clone() {
+                    let buf = p.borrow().clone();
+                    descriptor_set.push(WriteDescriptorSet::buffer(3, buf));
+                }
+                if let Some(t) = self.triangle_buffer.clone() {
+                    let buf = t.borrow().clone();
+                    descriptor_set.push(WriteDescriptorSet::buffer(4, buf));
+                }
+                if let Some(b) = self.bvh_buffer.clone() {
+                    let buf = b.borrow().clone();
+                    descriptor_set.push(WriteDescriptorSet::buffer(5, buf));
+                }
+                if let Some(b) = self.bvh_index_buffer.clone() {
+                    let buf = b.borrow().clone();
+                    descriptor_set.push(WriteDescriptorSet::buffer(6, buf));
+                }
+                // unused slots are never sampled, they only need something valid bound
+                let textures = (0..MAX_MATERIAL_TEXTURES).map(|i| {
+                    match self.material_textures.get(i).and_then(|&id| self.imgui_renderer.texture(id)) {
+                        Some((view, sampler)) => (view.clone() as Arc<dyn ImageViewAbstract>, sampler.clone()),
+                        None => (self.blue_noise_texture.clone() as Arc<dyn ImageViewAbstract>, self.sampler.clone()),
+                    }
+                });
+                descriptor_set.push(WriteDescriptorSet::image_view_sampler_array(7, 0, textures));
+                if let Some(l) = self.directional_light_buffer.clone() {
+                    let buf = l.borrow().clone();
+                    descriptor_set.push(WriteDescriptorSet::buffer(8, buf));
+                }
+                // like the texture slots, the blue noise stands in when there is no environment map
+                let (view, sampler) = match self.environment_texture.and_then(|id| self.imgui_renderer.texture(id)) {
+                    Some((view, sampler)) => (view.clone() as Arc<dyn ImageViewAbstract>, sampler.clone()),
+                    None => (self.blue_noise_texture.clone() as Arc<dyn ImageViewAbstract>, self.sampler.clone()),
+                };
+                descriptor_set.push(WriteDescriptorSet::image_view_sampler(9, view, sampler));
+                if let Some(l) = self.light_buffer.clone() {
+                    let buf = l.borrow().clone();
+                    descriptor_set.push(WriteDescriptorSet::buffer(10, buf));
+                }
+                if let Some(v) = self.volume_buffer.clone() {
+                    let buf = v.borrow().clone();
+                    descriptor_set.push(WriteDescriptorSet::buffer(11, buf));
+                }
+                if let Some(c) = self.cylinder_buffer.clone() {
+                    let buf = c.borrow().clone();
+                    descriptor_set.push(WriteDescriptorSet::buffer(12, buf));
+                }
+                if let Some(c) = self.capsule_buffer.clone() {
+                    let buf = c.borrow().clone();
+                    descriptor_set.push(WriteDescriptorSet::buffer(13, buf));
+                }
+                if let Some(t) = self.torus_buffer.clone() {
+                    let buf = t.borrow().clone();
+                    descriptor_set.push(WriteDescriptorSet::buffer(14, buf));
+                }
                 let geom_set = PersistentDescriptorSet::new(
                     &self.vulkan.descriptor_set_allocator,
                     layout.clone(),
                     descriptor_set,
                 ).unwrap();
+                self.vulkan.set_debug_name(geom_set.inner(), "scene_descriptor_set");
 
                 self.geom_set = Some(geom_set);
             }
@@ -331,45 +1499,323 @@ impl<F> App<F>
     pub fn resize(&mut self, width: u32, height: u32) {
         self.size = [width, height];
         self.recreate_swapchain = true;
-        self.scene.camera.set_perspective(75.0, height as f32 / width as f32, 0.1, 100.0);
+        // kept as the windowed size to go back to when leaving fullscreen
+        if width != 0 && height != 0 && !self.fullscreen {
+            self.settings.window_size = [width, height];
+        }
+        // the accumulation image is created again
+        self.scene.scene_dirty = true;
+        let camera = &mut self.scene.camera;
+        camera.set_perspective(camera.fov, height as f32 / width as f32, camera.near_clip_distance, camera.far_clip_distance);
+    }
+
+    /// Hides and grabs the cursor while the right button rotates the camera.
+    fn set_mouse_look(&self, enabled: bool) {
+        let Some(window) = &self.window else { return };
+        if enabled {
+            let grabbed = window.set_cursor_grab(CursorGrabMode::Locked)
+                .or_else(|_| window.set_cursor_grab(CursorGrabMode::Confined));
+            if let Err(e) = grabbed {
+                println!("Could not grab the cursor: {}", e);
+            }
+        } else {
+            window.set_cursor_grab(CursorGrabMode::None).ok();
+        }
+        window.set_cursor_visible(!enabled);
+    }
+
+    /// Switches between the window and borderless fullscreen on the current monitor.
+    pub fn set_fullscreen(&mut self, fullscreen: bool) {
+        let Some(window) = self.window.clone() else { return };
+        if self.fullscreen == fullscreen {
+            return;
+        }
+        self.fullscreen = fullscreen;
+        self.settings.fullscreen = fullscreen;
+        if fullscreen {
+            window.set_fullscreen(Some(Fullscreen::Borderless(None)));
+        } else {
+            window.set_fullscreen(None);
+            let [width, height] = self.settings.window_size;
+            window.set_inner_size(PhysicalSize::new(width, height));
+        }
+        self.recreate_swapchain = true;
+    }
+
+    fn update_memory_info(&mut self) {
+        const MB: f32 = 1024.0 * 1024.0;
+        let (used, total) = self.vulkan.memory_usage();
+        self.info.memory_budget = used.is_some();
+        self.info.gpu_memory_used_mb = used.unwrap_or(0) as f32 / MB;
+        self.info.gpu_memory_total_mb = total as f32 / MB;
+        self.info.scene_buffers = [
+            self.material_buffer.is_some(),
+            self.circle_buffer.is_some(),
+            self.box_buffer.is_some(),
+            self.plane_buffer.is_some(),
+            self.triangle_buffer.is_some(),
+            self.cylinder_buffer.is_some(),
+            self.capsule_buffer.is_some(),
+            self.torus_buffer.is_some(),
+            self.directional_light_buffer.is_some(),
+            self.volume_buffer.is_some(),
+            self.light_buffer.is_some(),
+            self.bvh_buffer.is_some(),
+            self.bvh_index_buffer.is_some(),
+        ].into_iter().filter(|&b| b).count() as u32;
+        self.info.frame_descriptor_sets = self.frame_descriptor_allocator.allocated_sets();
+    }
+
+    fn record_frame_time(&mut self, delta: f32) {
+        if self.fps_history.len() == FRAME_HISTORY_LENGTH {
+            self.fps_history.pop_front();
+            self.frame_time_history.pop_front();
+        }
+        self.fps_history.push_back(self.info.fps);
+        self.frame_time_history.push_back(delta * 1000.0);
+
+        self.info.history_len = self.fps_history.len();
+        for (i, (fps, frame_time)) in self.fps_history.iter().zip(&self.frame_time_history).enumerate() {
+            self.info.fps_history[i] = *fps;
+            self.info.frame_time_history[i] = *frame_time;
+        }
+    }
+
+    /// Shortest time between frames in seconds, from `target_fps`, or `IDLE_FPS` once the
+    /// accumulation converged. Runs with a frame limit are never throttled when idle.
+    fn min_frame_time(&self) -> Option<f32> {
+        let samples = self.scene.frame_index.saturating_mul(max(1, self.settings.sample_count));
+        let converged = self.frame_limit.is_none() && samples >= CONVERGED_SAMPLE_COUNT;
+        let fps = match self.target_fps {
+            Some(fps) if converged => fps.min(IDLE_FPS),
+            Some(fps) => fps,
+            None if converged => IDLE_FPS,
+            None => return None,
+        };
+        Some(1.0 / fps)
+    }
+
+    pub fn window_size(&self) -> [u32; 2] {
+        self.size
+    }
+
+    /// Resizes the window, remembered in the settings like a resize by the user.
+    pub fn set_window_size(&mut self, width: u32, height: u32) {
+        if let Some(window) = &self.window {
+            window.set_inner_size(PhysicalSize::new(width, height));
+        }
+        self.resize(width, height);
+    }
+
+    /// Makes `main_loop` return after rendering `frames` frames, printing how long they took.
+    pub fn set_frame_limit(&mut self, frames: u32) {
+        self.frame_limit = Some(frames);
+    }
+
+    /// Replaces the scene with the one saved in `path`, keeping the camera aspect of the window.
+    pub fn load_scene(&mut self, path: &str) -> Result<(), SceneError> {
+        let mut scene = Scene::load_ron(path)?;
+        let camera = &mut scene.camera;
+        camera.set_perspective(camera.fov, self.size[1] as f32 / self.size[0] as f32, camera.near_clip_distance, camera.far_clip_distance);
+        self.scene = scene;
+        Ok(())
+    }
+
+    /// Reads back the last frame and writes it to `path` as a half float EXR, blocking until the
+    /// copies are done. Besides the RGBA color it has `albedo`, `normal` and `depth` layers, the
+    /// depth being the distance along the camera ray.
+    pub fn save_exr(&mut self, path: &str) -> Result<(), ExrError> {
+        let buffers = self.vulkan.current_buffers();
+        let images = [
+            buffers.ray_color_image.image().clone(),
+            buffers.ray_albedo_image.image().clone(),
+            buffers.ray_normal_image.image().clone(),
+            buffers.ray_depth_image.image().clone(),
+        ];
+        let readbacks: Vec<Readback> = images.into_iter()
+            .map(|image| self.vulkan.read_image(image))
+            .collect();
+
+        let mut texels = Vec::new();
+        for readback in &readbacks {
+            readback.wait();
+            texels.push(readback.texels().ok_or_else(|| ExrError::UnsupportedFormat(format!("{:?}", readback.format)))?);
+        }
+        let [width, height] = readbacks[0].dimensions;
+
+        let channel = |name: &str, texels: &[[f32; 4]], component: usize| {
+            (name.to_string(), texels.iter().map(|t| t[component]).collect::<Vec<_>>())
+        };
+        let (color, albedo, normal, depth) = (&texels[0], &texels[1], &texels[2], &texels[3]);
+        let channels = vec![
+            channel("R", color, 0),
+            channel("G", color, 1),
+            channel("B", color, 2),
+            channel("A", color, 3),
+            channel("albedo.R", albedo, 0),
+            channel("albedo.G", albedo, 1),
+            channel("albedo.B", albedo, 2),
+            channel("normal.X", normal, 0),
+            channel("normal.Y", normal, 1),
+            channel("normal.Z", normal, 2),
+            // the shader stores the inverse of the distance
+            ("depth.Z".to_string(), depth.iter().map(|t| 1.0 / t[0]).collect()),
+        ];
+        exr::write(path, width, height, channels)
+    }
+
+    /// Writes the pending screenshot to a timestamped PNG once its readback finished.
+    fn flush_screenshot(&mut self) {
+        if !self.pending_screenshot.as_ref().is_some_and(Readback::is_ready) {
+            return;
+        }
+        let readback = self.pending_screenshot.take().unwrap();
+        let rgba: Vec<f32> = readback.texels().unwrap().concat();
+
+        let path = screenshot::timestamped_name("screenshot", "png");
+        match screenshot::save_png(&path, readback.dimensions, &rgba, self.scene.tone_mapping_mode, self.scene.exposure) {
+            Ok(()) => println!("Saved screenshot to {path}"),
+            Err(e) => println!("Failed to save screenshot {path}: {e}"),
+        }
     }
 
     pub fn update(&mut self, delta: f32) {
         let mut mov_x = 0_f32;
         let mut mov_y = 0_f32;
         let mut mov_z = 0_f32;
-        if self.pressed_keys[VirtualKeyCode::W as usize] {
+        // Ctrl+S and the other shortcuts share keys with the movement
+        let ctrl = self.pressed_keys[VirtualKeyCode::LControl as usize]
+            || self.pressed_keys[VirtualKeyCode::RControl as usize];
+        let held = |key: VirtualKeyCode| !ctrl && self.pressed_keys[key as usize];
+        if held(VirtualKeyCode::W) {
             mov_z -= 1_f32;
         }
-        if self.pressed_keys[VirtualKeyCode::S as usize] {
+        if held(VirtualKeyCode::S) {
             mov_z += 1_f32;
         }
-        if self.pressed_keys[VirtualKeyCode::A as usize] {
+        if held(VirtualKeyCode::A) {
             mov_x -= 1_f32;
         }
-        if self.pressed_keys[VirtualKeyCode::D as usize] {
+        if held(VirtualKeyCode::D) {
             mov_x += 1_f32;
         }
-        if self.pressed_keys[VirtualKeyCode::E as usize] {
+        if held(VirtualKeyCode::E) {
             mov_y -= 1_f32;
         }
-        if self.pressed_keys[VirtualKeyCode::Q as usize] {
+        if held(VirtualKeyCode::Q) {
             mov_y += 1_f32;
         }
-        if mov_x != 0_f32 || mov_y != 0_f32 || mov_z != 0_f32 {
+        let moving = mov_x != 0_f32 || mov_y != 0_f32 || mov_z != 0_f32;
+        if moving && !self.camera_moving {
+            self.scene.push_history();
+        }
+        self.camera_moving = moving;
+        if moving {
             self.scene.camera.move_by(mov_x, mov_y, mov_z, delta);
         }
+
+        // everything about the camera the rays depend on, the fov and clip planes also change
+        // the projection, which is checked again when the frame starts
+        let camera = &self.scene.camera;
+        let pose = (camera.position, camera.rotation, [camera.blur, camera.near_clip_distance, camera.far_clip_distance]);
+        if self.last_camera_pose != Some(pose) {
+            self.last_camera_pose = Some(pose);
+            self.scene.scene_dirty = true;
+        }
+
+        let time = self.info.time + delta;
+        for circle in self.scene.all_circles.iter_mut() {
+            if let Some(animation) = circle.animation {
+                circle.position = animation.position_at(time);
+                circle.mark_dirty();
+            }
+        }
+    }
+
+    /// Rebuilds the raytracing or denoiser pipeline when its sources changed, keeping the
+    /// current one if they don't compile.
+    fn reload_shaders(&mut self) {
+        let Some(watcher) = self.shader_watcher.as_mut() else {
+            return;
+        };
+        let changed = watcher.changed_files();
+        let directory = watcher.directory().to_path_buf();
+        let changed_pipeline = |name: &str| changed.iter()
+            .any(|path| path.file_name().is_some_and(|f| f.to_string_lossy().ends_with(&format!("_{}.glsl", name))));
+        let reload_raytrace = changed_pipeline("raytracing");
+        let reload_denoiser = changed_pipeline("denoiser");
+        if !reload_raytrace && !reload_denoiser {
+            return;
+        }
+
+        let compile = |name: &str| {
+            let vs = shader_watcher::compile(&self.vulkan.device, &directory.join(format!("vert_{}.glsl", name)));
+            let fs = shader_watcher::compile(&self.vulkan.device, &directory.join(format!("frag_{}.glsl", name)));
+            match (vs, fs) {
+                (Ok(vs), Ok(fs)) => Some((vs, fs)),
+                (Err(e), _) | (_, Err(e)) => {
+                    eprintln!("Shader reload failed: {}", e);
+                    None
+                }
+            }
+        };
+        let raytrace_shaders = reload_raytrace.then(|| compile("raytracing")).flatten();
+        let denoiser_shaders = reload_denoiser.then(|| compile("denoiser")).flatten();
+        if raytrace_shaders.is_none() && denoiser_shaders.is_none() {
+            return;
+        }
+
+        // the old pipelines may still be used by the frame in flight
+        self.vulkan.wait_idle();
+        if let Some((vs, fs)) = raytrace_shaders {
+            let key = ShaderKey::new("raytrace");
+            self.vulkan.shader_cache.invalidate(&key);
+            self.raytracing_pipeline = self.vulkan.create_pipeline(
+                key,
+                self.vulkan.raytrace_pipeline_target(),
+                ScreenVertex::per_vertex(),
+                |_| (vs, fs),
+            );
+            // the geometry set is rebuilt with the layout of the new pipeline
+            self.scene.force_dirty_all();
+        }
+        if let Some((vs, fs)) = denoiser_shaders {
+            let key = ShaderKey::new("denoiser");
+            self.vulkan.shader_cache.invalidate(&key);
+            self.denoiser_pipeline = self.vulkan.create_pipeline(
+                key,
+                self.vulkan.screen_pipeline_target(),
+                ScreenVertex::per_vertex(),
+                |_| (vs, fs),
+            );
+        }
+        println!("Shader reloaded");
+        self.scene.scene_dirty = true;
+        self.info.shader_reload_time = Some(self.info.time);
     }
 
+    /// Runs until the window is closed or the frame limit is reached. Without a window the
+    /// frames are rendered back to back, a frame limit has to be set then.
     pub fn main_loop(&mut self) {
         let mut last_frame = Instant::now();
 
+        if self.window.is_none() {
+            assert!(self.frame_limit.is_some(), "a headless app needs a frame limit");
+            loop {
+                self.advance_frame(&mut last_frame);
+                if !self.draw_frame() {
+                    return;
+                }
+            }
+        }
+
+        let window = self.window.clone().unwrap();
         self.event_loop.take().unwrap().run_return(|event, _, control_flow| {
             match event {
                 Event::WindowEvent {
                     event: WindowEvent::CloseRequested,
                     window_id,
-                } if window_id == self.window.id() => {
+                } if window_id == window.id() => {
                     control_flow.set_exit();
                 }
                 Event::WindowEvent {
@@ -377,188 +1823,613 @@ impl<F> App<F>
                     ..
                 } => {
                     self.resize(new_size.width, new_size.height);
-                    self.imgui_platform.handle_event(self.imgui.io_mut(), &self.window, &event);
+                    self.imgui_platform.handle_event(self.imgui.io_mut(), &window, &event);
                 }
                 Event::WindowEvent {
                     event: WindowEvent::KeyboardInput { input, .. },
                     ..
                 } => {
                     if let Some(keycode) = input.virtual_keycode {
-                        self.pressed_keys[keycode as usize] = input.state == ElementState::Pressed;
+                        let pressed = input.state == ElementState::Pressed;
+                        let ctrl = self.pressed_keys[VirtualKeyCode::LControl as usize]
+                            || self.pressed_keys[VirtualKeyCode::RControl as usize];
+                        let shift = self.pressed_keys[VirtualKeyCode::LShift as usize]
+                            || self.pressed_keys[VirtualKeyCode::RShift as usize];
+                        if ctrl && pressed && !self.pressed_keys[keycode as usize] {
+                            match keycode {
+                                VirtualKeyCode::S => match self.scene.save_ron(SCENE_FILE) {
+                                    Ok(()) => println!("Saved scene to {SCENE_FILE}"),
+                                    Err(e) => println!("Failed to save scene: {e}"),
+                                },
+                                VirtualKeyCode::O => match self.load_scene(SCENE_FILE) {
+                                    Ok(()) => println!("Loaded scene from {SCENE_FILE}"),
+                                    Err(e) => println!("Failed to load scene: {e}"),
+                                },
+                                VirtualKeyCode::Z if shift => self.scene.redo(),
+                                VirtualKeyCode::Z => self.scene.undo(),
+                                _ => (),
+                            }
+                        }
+                        if keycode == VirtualKeyCode::F1 && pressed && !self.pressed_keys[keycode as usize] {
+                            self.help_open = !self.help_open;
+                        }
+                        if keycode == VirtualKeyCode::F11 && pressed && !self.pressed_keys[keycode as usize] {
+                            self.set_fullscreen(!self.fullscreen);
+                        }
+                        if keycode == VirtualKeyCode::F12 && pressed && !self.pressed_keys[keycode as usize] {
+                            if shift {
+                                let path = screenshot::timestamped_name("render", "exr");
+                                match self.save_exr(&path) {
+                                    Ok(()) => println!("Saved EXR to {path}"),
+                                    Err(e) => println!("Failed to save EXR {path}: {e}"),
+                                }
+                            } else {
+                                self.screenshot_requested = true;
+                            }
+                        }
+                        self.pressed_keys[keycode as usize] = pressed;
                     }
-                    self.imgui_platform.handle_event(self.imgui.io_mut(), &self.window, &event);
+                    self.imgui_platform.handle_event(self.imgui.io_mut(), &window, &event);
                 }
-                Event::MainEventsCleared => {
-                    if self.recreate_swapchain {
-                        self.recreate_swapchain = false;
-                        self.vulkan.recreate_swapchain(self.size, &mut self.viewport);
+                Event::WindowEvent {
+                    event: WindowEvent::MouseInput { state, button, .. },
+                    ..
+                } => {
+                    let index = match button {
+                        MouseButton::Left => 0,
+                        MouseButton::Right => 1,
+                        MouseButton::Middle => 2,
+                        MouseButton::Other(n) => (3 + n as usize).min(self.mouse_buttons.len() - 1),
+                    };
+                    let pressed = state == ElementState::Pressed;
+                    if button == MouseButton::Right {
+                        // look mode, only started when the click isn't meant for the UI
+                        let look = pressed && (self.mouse_buttons[index] || !self.imgui.io().want_capture_mouse);
+                        if look && !self.mouse_buttons[index] {
+                            self.scene.push_history();
+                        }
+                        self.mouse_buttons[index] = look;
+                        self.set_mouse_look(look);
+                    } else {
+                        self.mouse_buttons[index] = pressed;
                     }
-
-                    let now = Instant::now();
-                    let delta = now.duration_since(last_frame).as_secs_f32();
-                    last_frame = now;
-
-                    self.update(delta);
-                    self.info.time = now.duration_since(self.start_time).as_secs_f32();
-                    self.info.fps = 1.0 / delta;
-
+                    self.imgui_platform.handle_event(self.imgui.io_mut(), &window, &event);
+                }
+                Event::DeviceEvent {
+                    event: DeviceEvent::MouseMotion { delta },
+                    ..
+                } => {
+                    if self.mouse_buttons[1] && self.scene.camera.orbit_mode && self.scene.camera.target.is_some() {
+                        self.scene.camera.orbit(-delta.0 as f32 * MOUSE_LOOK_SENSITIVITY, delta.1 as f32 * MOUSE_LOOK_SENSITIVITY);
+                    } else if self.mouse_buttons[1] {
+                        let rotation = &mut self.scene.camera.rotation;
+                        let max_pitch = 89.0_f32.to_radians();
+                        rotation[1] += delta.0 as f32 * MOUSE_LOOK_SENSITIVITY;
+                        rotation[0] = (rotation[0] + delta.1 as f32 * MOUSE_LOOK_SENSITIVITY).clamp(-max_pitch, max_pitch);
+                    }
+                }
+                Event::MainEventsCleared => {
+                    self.advance_frame(&mut last_frame);
                     self.imgui_platform
-                        .prepare_frame(self.imgui.io_mut(), &self.window)
+                        .prepare_frame(self.imgui.io_mut(), &window)
                         .expect("Failed to start frame");
-
-                    self.window.request_redraw();
+                    window.request_redraw();
                 }
                 Event::RedrawRequested(_) => {
-                    if self.size[0] == 0 || self.size[1] == 0 {
-                        return;
+                    if !self.draw_frame() {
+                        control_flow.set_exit();
                     }
+                }
+                event => {
+                    self.imgui_platform.handle_event(self.imgui.io_mut(), &window, &event);
+                }
+            }
+        });
+    }
 
-                    self.check_buffers();
+    /// Waits for the frame rate cap, then moves the camera and updates the frame timings.
+    fn advance_frame(&mut self, last_frame: &mut Instant) {
+        if self.recreate_swapchain {
+            self.recreate_swapchain = false;
+            self.vulkan.recreate_swapchain(self.size, self.present_mode, &mut self.viewport);
+        }
 
-                    self.vulkan.wait_frame();
+        if let Some(min_frame_time) = self.min_frame_time() {
+            let elapsed = last_frame.elapsed().as_secs_f32();
+            if elapsed < min_frame_time {
+                std::thread::sleep(Duration::from_secs_f32(min_frame_time - elapsed));
+            }
+        }
 
-                    let mut imgui_ui = self.imgui.frame();
-                    {
-                        let mut run = true;
-                        (self.run_ui)(&mut run, &mut imgui_ui, &mut self.scene, self.info);
-                        if !run {
-                            control_flow.set_exit();
-                        }
-                    }
+        let now = Instant::now();
+        let delta = now.duration_since(*last_frame).as_secs_f32();
+        *last_frame = now;
 
-                    self.imgui_platform.prepare_render(&imgui_ui, &self.window);
-                    let imgui_draw_data = self.imgui.render();
-
-                    let mut render_pass = self.vulkan.begin_frame();
-                    if render_pass.is_some() {
-                        let render_pass = render_pass.as_mut().unwrap();
-
-                        let view_set = {
-                            let view_buffer = {
-                                self.scene.camera.update_view();
-                                let view_data: shader::raytrace::fs::ViewData = (&self.scene.camera).into();
-                                let subbuffer = self.vulkan.uniform_buffer.allocate_sized().unwrap();
-                                *subbuffer.write().unwrap() = view_data;
-                                subbuffer
-                            };
-
-                            let render_info_buffer = {
-                                let render_data = shader::raytrace::fs::RenderInfo {
-                                    time: self.info.time,
-                                    sample_count: self.scene.sample_count as i32,
-                                };
-                                let subbuffer = self.vulkan.uniform_buffer.allocate_sized().unwrap();
-                                *subbuffer.write().unwrap() = render_data;
-                                subbuffer
-                            };
-
-                            let layout = self.raytracing_pipeline.layout().set_layouts().get(0).unwrap();
-                            PersistentDescriptorSet::new(
-                                &self.vulkan.descriptor_set_allocator,
-                                layout.clone(),
-                                [
-                                    WriteDescriptorSet::buffer(0, view_buffer),
-                                    WriteDescriptorSet::buffer(1, render_info_buffer),
-                                ],
-                            ).unwrap()
-                        };
-
-                        render_pass
-                            .set_viewport(0, [self.viewport.clone()])
-                            .bind_pipeline_graphics(self.raytracing_pipeline.clone())
-                            .bind_vertex_buffers(0, self.vertex_buffer.clone())
-                            .bind_descriptor_sets(
-                                PipelineBindPoint::Graphics,
-                                self.raytracing_pipeline.layout().clone(),
-                                0,
-                                view_set,
-                            );
-                        if let Some(geom_set) = self.geom_set.as_ref() {
-                            render_pass
-                                .bind_descriptor_sets(
-                                    PipelineBindPoint::Graphics,
-                                    self.raytracing_pipeline.layout().clone(),
-                                    1,
-                                    geom_set.clone(),
-                                );
-                        }
-                        render_pass
-                            .draw(self.vertex_buffer.len() as u32, 1, 0, 0)
-                            .unwrap();
-
-
-                        // END RAYTRACING RENDER_PASS
-                        // START DENOISER RENDER_PASS
-
-                        let buffers = self.vulkan.next_render_pass(render_pass);
-                        let ray_color = buffers.ray_color_image.clone();
-                        let ray_albedo = buffers.ray_albedo_image.clone();
-                        let ray_normal = buffers.ray_normal_image.clone();
-                        let ray_depth = buffers.ray_depth_image.clone();
-
-                        let render_info = {
-                            let render_data = shader::denoiser::fs::RenderInfo {
-                                selected_view: self.scene.current_view,
-                                kernel_size: self.scene.kernel_size,
-                                kernel_offset: max(1, self.scene.kernel_offset),
-                                albedo_weight: self.scene.denoiser_albedo_weight,
-                                normal_weight: self.scene.denoiser_normal_weight,
-                                depth_weight: self.scene.denoiser_depth_weight,
-                            };
-                            let subbuffer = self.vulkan.uniform_buffer.allocate_sized().unwrap();
-                            *subbuffer.write().unwrap() = render_data;
-                            subbuffer
-                        };
-
-                        let denoiser_descriptor_set = {
-                            let layout = self.denoiser_pipeline.layout().set_layouts().get(0).unwrap();
-                            PersistentDescriptorSet::new(
-                                &self.vulkan.descriptor_set_allocator,
-                                layout.clone(),
-                                [
-                                    WriteDescriptorSet::image_view_sampler(0, ray_color, self.sampler.clone()),
-                                    WriteDescriptorSet::image_view_sampler(1, ray_albedo, self.sampler.clone()),
-                                    WriteDescriptorSet::image_view_sampler(2, ray_normal, self.sampler.clone()),
-                                    WriteDescriptorSet::image_view_sampler(3, ray_depth, self.sampler.clone()),
-                                    WriteDescriptorSet::buffer(4, render_info),
-                                ],
-                            ).unwrap()
-                        };
-
-                        render_pass
-                            .set_viewport(0, [self.viewport.clone()])
-                            .bind_vertex_buffers(0, self.vertex_buffer.clone())
-                            .bind_pipeline_graphics(self.denoiser_pipeline.clone())
-                            .bind_descriptor_sets(
-                                PipelineBindPoint::Graphics,
-                                self.denoiser_pipeline.layout().clone(),
-                                0,
-                                denoiser_descriptor_set,
-                            );
-
-                        render_pass
-                            .draw(self.vertex_buffer.len() as u32, 1, 0, 0)
-                            .unwrap();
-
-                        self.imgui_renderer.draw_commands(
-                            render_pass,
-                            &self.vulkan,
-                            imgui_draw_data,
-                        );
-                    }
-                    let status = self.vulkan.end_frame(render_pass);
-                    match status {
-                        DrawStatus::Ok => (),
-                        DrawStatus::ShouldRecreateSwapchain => {
-                            self.recreate_swapchain = true;
-                        }
+        self.update(delta);
+        self.info.time = now.duration_since(self.start_time).as_secs_f32();
+        self.info.fps = 1.0 / delta;
+        self.record_frame_time(delta);
+        self.info.frame_count = self.info.frame_count.wrapping_add(1);
+    }
+
+    /// Records and submits a frame, returns `false` once the app should exit.
+    fn draw_frame(&mut self) -> bool {
+        let mut keep_running = true;
+        if self.size[0] == 0 || self.size[1] == 0 {
+            return true;
+        }
+
+        self.check_buffers();
+
+        // e.g. the View menu, or the fullscreen setting saved by the last run
+        if self.settings.fullscreen != self.fullscreen {
+            self.set_fullscreen(self.settings.fullscreen);
+        }
+
+        self.vulkan.use_timeline_semaphore = self.settings.timeline_semaphore;
+
+        let present_mode = select_present_mode(&self.present_modes, self.settings.vsync);
+        if present_mode != self.present_mode {
+            self.present_mode = present_mode;
+            self.recreate_swapchain = true;
+        }
+
+        self.reload_shaders();
+        self.vulkan.wait_frame();
+        if let Some(pass_times) = self.vulkan.profiler.pass_times_ms() {
+            self.info.pass_times_ms = pass_times;
+        }
+        if let Some(invocations) = self.vulkan.profiler.raytrace_invocations() {
+            self.info.raytrace_invocations = invocations;
+        }
+        self.flush_screenshot();
+        self.frame_descriptor_allocator.next_frame().unwrap();
+
+        // a drag or a click spans several frames, it becomes one history entry when released.
+        // changes made outside the UI, like moving the camera or undoing, aren't UI edits
+        if !self.ui_editing {
+            self.ui_edit_base.update(&self.scene);
+        }
+        let mut imgui_ui = self.imgui.frame();
+        {
+            imgui_ui.dockspace_over_main_viewport();
+            let mut run = true;
+            (self.run_ui)(&mut run, &mut imgui_ui, &mut self.scene, &mut self.settings, self.info);
+            if !run {
+                keep_running = false;
+            }
+            help_ui(imgui_ui, &mut self.help_open);
+        }
+        // a slider or drag that was active last frame was just released
+        let ui_released = self.ui_editing && !imgui_ui.is_any_item_active();
+        self.ui_editing = imgui_ui.is_any_item_active();
+        if !self.ui_editing && !self.ui_edit_base.matches(&self.scene) {
+            let start = std::mem::replace(&mut self.ui_edit_base, self.scene.snapshot());
+            self.scene.push_snapshot(start);
+        }
+
+        if self.settings != self.applied_settings {
+            self.scene.scene_dirty = true;
+            self.applied_settings = self.settings;
+        }
+        // not while dragging, changes from resizes or shortcuts are written at exit
+        if ui_released {
+            self.settings.save_if_changed(&mut self.saved_settings);
+        }
+
+        if let Some(window) = &self.window {
+            self.imgui_platform.prepare_render(&imgui_ui, window);
+        }
+        let imgui_draw_data = self.imgui.render();
+
+        let mut render_pass = self.vulkan.begin_frame();
+        if render_pass.is_some() {
+            let render_pass = render_pass.as_mut().unwrap();
+            self.vulkan.profiler.write_timestamp(render_pass, Timestamp::FrameStart);
+
+            let view_data = {
+                self.scene.camera.update_view();
+                // e.g. the field of view slider, the pose is checked in `update`
+                if self.last_projection != Some(self.scene.camera.projection) {
+                    self.last_projection = Some(self.scene.camera.projection);
+                    self.scene.scene_dirty = true;
+                }
+                let mut view_data: shader::raytrace::fs::ViewData = (&self.scene.camera).into();
+                if self.scene.use_halton_jitter || self.scene.taa_enabled {
+                    // by frame count, the jitter keeps moving while the accumulation restarts
+                    let jitter = self.halton_sequence[self.info.frame_count as usize % self.halton_sequence.len()];
+                    // in clip space, one pixel is 2 / size
+                    view_data.proj[2][0] -= jitter[0] * 2.0 / self.size[0] as f32;
+                    view_data.proj[2][1] -= jitter[1] * 2.0 / self.size[1] as f32;
+                }
+                view_data
+            };
+
+            let current_view = (self.scene.camera.view.invert().unwrap(), self.scene.camera.projection);
+            let (prev_view, prev_proj) = self.previous_view.replace(current_view).unwrap_or(current_view);
+            // the accumulation restarts while the camera moves, but the reprojected
+            // history stays valid
+            let camera_moved = (prev_view, prev_proj) != current_view;
+
+            let view_set = {
+                if self.scene.scene_dirty {
+                    self.scene.scene_dirty = false;
+                    self.scene.frame_index = 0;
+                }
+
+                let render_info_buffer = {
+                    // smallest grid with about square cells that fits every sample
+                    let sample_count = max(1, self.settings.sample_count);
+                    let stratum_x = (sample_count as f32).sqrt() as u32;
+                    let stratum_y = sample_count.div_ceil(stratum_x);
+                    let render_data = shader::raytrace::fs::RenderInfo {
+                        time: self.info.time,
+                        sample_count: self.settings.sample_count as i32,
+                        sort_by_material: self.scene.sort_by_material as i32,
+                        anaglyph: self.scene.anaglyph_mode as i32,
+                        eye_separation: self.scene.eye_separation,
+                        jitter_mode: self.scene.jitter_mode,
+                        frame: self.info.frame_count as i32,
+                        frame_index: self.scene.frame_index as i32,
+                        max_bounce_depth: self.scene.max_bounce_depth as i32,
+                        use_russian_roulette: self.scene.use_russian_roulette as i32,
+                        rr_min_bounces: self.scene.rr_min_bounces as i32,
+                        stratum_x: stratum_x as i32,
+                        stratum_y: stratum_y as i32,
+                        blur: self.scene.camera.blur,
+                        near: self.scene.camera.near_clip_distance,
+                        far: self.scene.camera.far_clip_distance,
+                        use_environment_map: self.environment_texture.is_some() as i32,
+                        sky_enabled: (self.scene.sky_enabled as i32).into(),
+                        sun_direction: self.scene.sun_direction,
+                        sun_turbidity: self.scene.sun_turbidity.clamp(1.0, 10.0),
+                        use_nee: self.scene.use_nee as i32,
+                        light_count: self.light_list.len() as i32,
+                        firefly_threshold: self.scene.firefly_threshold.max(0.0),
+                        // the rays are only traced while the AO view shows them
+                        ao_sample_count: if self.settings.current_view == 5 {
+                            self.scene.ao_sample_count as i32
+                        } else {
+                            0
+                        },
+                        ao_radius: self.scene.ao_radius.into(),
+                        prev_view: prev_view.into(),
+                        prev_proj: prev_proj.into(),
+                    };
+                    let subbuffer = self.vulkan.uniform_buffer.allocate_sized().unwrap();
+                    *subbuffer.write().unwrap() = render_data;
+                    subbuffer
+                };
+
+                let layout = self.raytracing_pipeline.layout().set_layouts().get(0).unwrap();
+                PersistentDescriptorSet::new(
+                    &self.frame_descriptor_allocator,
+                    layout.clone(),
+                    [
+                        WriteDescriptorSet::buffer(1, render_info_buffer),
+                        WriteDescriptorSet::image_view_sampler(2, self.blue_noise_texture.clone(), self.sampler.clone()),
+                        // shared by all swapchain images, so any of them has it
+                        WriteDescriptorSet::image_view(3, self.vulkan.buffers.as_ref().unwrap()[0].accumulation_image.clone()),
+                    ],
+                ).unwrap()
+            };
+
+            Vk::begin_debug_label(render_pass, "Raytracing Pass", [1.0, 0.5, 0.0, 1.0]);
+            render_pass
+                .set_viewport(0, [self.viewport.clone()])
+                .bind_pipeline_graphics(self.raytracing_pipeline.clone())
+                .bind_vertex_buffers(0, self.vertex_buffer.clone())
+                .bind_descriptor_sets(
+                    PipelineBindPoint::Graphics,
+                    self.raytracing_pipeline.layout().clone(),
+                    0,
+                    view_set,
+                )
+                .push_constants(self.raytracing_pipeline.layout().clone(), 0, view_data);
+            if let Some(geom_set) = self.geom_set.as_ref() {
+                render_pass
+                    .bind_descriptor_sets(
+                        PipelineBindPoint::Graphics,
+                        self.raytracing_pipeline.layout().clone(),
+                        1,
+                        geom_set.clone(),
+                    );
+            }
+            self.vulkan.profiler.begin_raytrace_stats(render_pass);
+            render_pass
+                .draw(self.vertex_buffer.len() as u32, 1, 0, 0)
+                .unwrap();
+            self.vulkan.profiler.end_raytrace_stats(render_pass);
+            Vk::end_debug_label(render_pass);
+            self.vulkan.profiler.write_timestamp(render_pass, Timestamp::RaytraceEnd);
+
+
+            // END RAYTRACING RENDER_PASS
+            // START DENOISER RENDER_PASS
+
+            let exposure_set = self.scene.auto_exposure.then(|| {
+                let layout = &self.exposure_pipeline.layout().set_layouts()[0];
+                PersistentDescriptorSet::new(
+                    &self.frame_descriptor_allocator,
+                    layout.clone(),
+                    [
+                        WriteDescriptorSet::image_view(0, self.vulkan.buffers.as_ref().unwrap()[0].accumulation_image.clone()),
+                        WriteDescriptorSet::buffer(1, self.auto_exposure_buffer.clone()),
+                    ],
+                ).unwrap()
+            });
+            let exposure_pipeline = self.exposure_pipeline.clone();
+
+            let variance_set = {
+                let layout = &self.variance_pipeline.layout().set_layouts()[0];
+                let buffers = &self.vulkan.buffers.as_ref().unwrap()[0];
+                PersistentDescriptorSet::new(
+                    &self.frame_descriptor_allocator,
+                    layout.clone(),
+                    [
+                        WriteDescriptorSet::image_view(0, buffers.accumulation_image.clone()),
+                        WriteDescriptorSet::image_view(1, buffers.history_color_image.clone()),
+                        WriteDescriptorSet::image_view(2, buffers.variance_image.clone()),
+                    ],
+                ).unwrap()
+            };
+            let variance_pipeline = self.variance_pipeline.clone();
+            let variance_info = shader::variance::cs::VarianceInfo {
+                frame_index: self.scene.frame_index as i32,
+            };
+            let variance_groups = [self.size[0].div_ceil(16), self.size[1].div_ceil(16), 1];
+
+            let separable = self.scene.denoiser_separable && self.settings.current_view == 0;
+            let horizontal_set = separable.then(|| {
+                let layout = &self.denoiser_horizontal_pipeline.layout().set_layouts()[0];
+                let buffers = self.vulkan.current_buffers();
+                PersistentDescriptorSet::new(
+                    &self.frame_descriptor_allocator,
+                    layout.clone(),
+                    [
+                        WriteDescriptorSet::image_view_sampler(0, buffers.ray_color_image.clone(), self.sampler.clone()),
+                        WriteDescriptorSet::image_view_sampler(1, buffers.ray_albedo_image.clone(), self.sampler.clone()),
+                        WriteDescriptorSet::image_view_sampler(2, buffers.ray_normal_image.clone(), self.sampler.clone()),
+                        WriteDescriptorSet::image_view_sampler(3, buffers.ray_depth_image.clone(), self.sampler.clone()),
+                        WriteDescriptorSet::image_view_sampler(4, buffers.ray_color_right_image.clone(), self.sampler.clone()),
+                        WriteDescriptorSet::image_view_sampler(5, buffers.variance_image.clone(), self.sampler.clone()),
+                        WriteDescriptorSet::image_view(6, buffers.denoiser_ping_image.clone()),
+                    ],
+                ).unwrap()
+            });
+            let denoiser_horizontal_pipeline = self.denoiser_horizontal_pipeline.clone();
+            let denoise_info = shader::denoiser_horizontal::cs::DenoiseInfo {
+                kernel_size: self.settings.kernel_size,
+                kernel_offset: max(1, self.settings.kernel_offset),
+                albedo_weight: self.settings.denoiser_albedo_weight,
+                normal_weight: self.settings.denoiser_normal_weight,
+                depth_weight: self.settings.denoiser_depth_weight,
+                variance_weight: self.settings.denoiser_variance_weight,
+                anaglyph: self.scene.anaglyph_mode as i32,
+            };
+
+            // (source, target, mode) of each step of the chain, see comp_bloom.glsl
+            let bloom_steps = (self.scene.bloom_enabled && self.settings.current_view == 0).then(|| {
+                let buffers = self.vulkan.current_buffers();
+                let levels = &buffers.bloom_images;
+                let mut steps: Vec<(Arc<dyn ImageViewAbstract>, _, _)> = vec![(buffers.ray_color_image.clone(), levels[0].clone(), 0)];
+                steps.extend(levels.windows(2).map(|pair| (pair[0].clone() as Arc<dyn ImageViewAbstract>, pair[1].clone(), 1)));
+                steps.extend(levels.windows(2).rev().map(|pair| (pair[1].clone() as Arc<dyn ImageViewAbstract>, pair[0].clone(), 2)));
+                steps.into_iter().map(|(source, target, mode)| {
+                    let [width, height] = target.image().dimensions().width_height();
+                    let set = PersistentDescriptorSet::new(
+                        &self.frame_descriptor_allocator,
+                        self.bloom_pipeline.layout().set_layouts()[0].clone(),
+                        [
+                            WriteDescriptorSet::image_view_sampler(0, source, self.clamp_sampler.clone()),
+                            WriteDescriptorSet::image_view(1, target),
+                        ],
+                    ).unwrap();
+                    let info = shader::bloom::cs::BloomInfo {
+                        mode,
+                        threshold: self.scene.bloom_threshold,
+                    };
+                    (set, info, [width.div_ceil(16), height.div_ceil(16), 1])
+                }).collect::<Vec<_>>()
+            });
+            let bloom_pipeline = self.bloom_pipeline.clone();
+
+            let buffers = self.vulkan.next_render_pass(render_pass, |command_builder| {
+                if let Some(exposure_set) = exposure_set {
+                    Vk::begin_debug_label(command_builder, "Auto Exposure", [1.0, 1.0, 0.0, 1.0]);
+                    Vk::dispatch_compute(command_builder, exposure_pipeline, exposure_set, [1, 1, 1]);
+                    Vk::end_debug_label(command_builder);
+                }
+
+                Vk::begin_debug_label(command_builder, "Variance", [1.0, 0.5, 0.0, 1.0]);
+                command_builder.push_constants(variance_pipeline.layout().clone(), 0, variance_info);
+                Vk::dispatch_compute(command_builder, variance_pipeline, variance_set, variance_groups);
+                Vk::end_debug_label(command_builder);
+
+                if let Some(bloom_steps) = bloom_steps {
+                    Vk::begin_debug_label(command_builder, "Bloom", [1.0, 1.0, 0.5, 1.0]);
+                    for (set, info, groups) in bloom_steps {
+                        command_builder.push_constants(bloom_pipeline.layout().clone(), 0, info);
+                        Vk::dispatch_compute(command_builder, bloom_pipeline.clone(), set, groups);
                     }
+                    Vk::end_debug_label(command_builder);
                 }
-                event => {
-                    self.imgui_platform.handle_event(self.imgui.io_mut(), &self.window, &event);
+
+                // the vertical half runs in the denoiser pass
+                if let Some(horizontal_set) = horizontal_set {
+                    Vk::begin_debug_label(command_builder, "Denoiser Horizontal", [0.0, 0.5, 1.0, 1.0]);
+                    command_builder.push_constants(denoiser_horizontal_pipeline.layout().clone(), 0, denoise_info);
+                    Vk::dispatch_compute(command_builder, denoiser_horizontal_pipeline, horizontal_set, variance_groups);
+                    Vk::end_debug_label(command_builder);
                 }
+            });
+            let ray_color = buffers.ray_color_image.clone();
+            let ray_albedo = buffers.ray_albedo_image.clone();
+            let ray_normal = buffers.ray_normal_image.clone();
+            let ray_depth = buffers.ray_depth_image.clone();
+            let ray_color_right = buffers.ray_color_right_image.clone();
+            let ray_shading_normal = buffers.ray_shading_normal_image.clone();
+            let variance = buffers.variance_image.clone();
+            let history_index = self.info.frame_count as usize % 2;
+            let temporal_history = buffers.temporal_history_images[history_index].clone();
+            let previous_history = buffers.temporal_history_images[1 - history_index].clone();
+            let ray_velocity = buffers.ray_velocity_image.clone();
+            let taa = self.scene.taa_enabled && self.settings.current_view == 0;
+            let taa_input = buffers.taa_input_image.clone();
+            let taa_history = buffers.taa_history_images[history_index].clone();
+            let previous_taa_history = buffers.taa_history_images[1 - history_index].clone();
+            let bloom = buffers.bloom_images[0].clone();
+            let denoiser_ping = buffers.denoiser_ping_image.clone();
+            let ray_ao = buffers.ray_ao_image.clone();
+
+            let render_info = {
+                let render_data = shader::denoiser::fs::RenderInfo {
+                    selected_view: self.settings.current_view,
+                    kernel_size: self.settings.kernel_size,
+                    kernel_offset: max(1, self.settings.kernel_offset),
+                    albedo_weight: self.settings.denoiser_albedo_weight,
+                    normal_weight: self.settings.denoiser_normal_weight,
+                    depth_weight: self.settings.denoiser_depth_weight,
+                    anaglyph: self.scene.anaglyph_mode as i32,
+                    tone_mapping_mode: self.scene.tone_mapping_mode,
+                    exposure: self.scene.exposure,
+                    auto_exposure: self.scene.auto_exposure as i32,
+                    variance_weight: self.settings.denoiser_variance_weight,
+                    // the history is from before the change, drop it with the accumulation.
+                    // The TAA resolve has its own history.
+                    temporal_alpha: if taa || (self.scene.frame_index == 0 && !camera_moved) {
+                        0.0
+                    } else {
+                        self.scene.temporal_alpha.clamp(0.0, 0.99)
+                    },
+                    separable: separable as i32,
+                    taa_enabled: taa as i32,
+                    bloom_strength: if self.scene.bloom_enabled { self.scene.bloom_strength } else { 0.0 },
+                };
+                let subbuffer = self.vulkan.uniform_buffer.allocate_sized().unwrap();
+                *subbuffer.write().unwrap() = render_data;
+                subbuffer
+            };
+
+            let denoiser_descriptor_set = {
+                let layout = self.denoiser_pipeline.layout().set_layouts().get(0).unwrap();
+                PersistentDescriptorSet::new(
+                    &self.frame_descriptor_allocator,
+                    layout.clone(),
+                    [
+                        WriteDescriptorSet::image_view_sampler(0, ray_color, self.sampler.clone()),
+                        WriteDescriptorSet::image_view_sampler(1, ray_albedo, self.sampler.clone()),
+                        WriteDescriptorSet::image_view_sampler(2, ray_normal, self.sampler.clone()),
+                        WriteDescriptorSet::image_view_sampler(3, ray_depth, self.sampler.clone()),
+                        WriteDescriptorSet::buffer(4, render_info),
+                        WriteDescriptorSet::image_view_sampler(5, ray_color_right, self.sampler.clone()),
+                        WriteDescriptorSet::image_view_sampler(6, ray_shading_normal, self.sampler.clone()),
+                        WriteDescriptorSet::buffer(7, self.auto_exposure_buffer.clone()),
+                        WriteDescriptorSet::image_view_sampler(8, variance, self.sampler.clone()),
+                        WriteDescriptorSet::image_view(9, temporal_history),
+                        WriteDescriptorSet::image_view_sampler(10, denoiser_ping, self.sampler.clone()),
+                        WriteDescriptorSet::image_view_sampler(11, ray_ao, self.sampler.clone()),
+                        WriteDescriptorSet::image_view_sampler(12, previous_history, self.sampler.clone()),
+                        WriteDescriptorSet::image_view_sampler(13, ray_velocity.clone(), self.sampler.clone()),
+                        WriteDescriptorSet::image_view(14, taa_input.clone()),
+                        WriteDescriptorSet::image_view_sampler(15, bloom, self.clamp_sampler.clone()),
+                    ],
+                ).unwrap()
+            };
+
+            Vk::begin_debug_label(render_pass, "Denoiser Pass", [0.0, 0.5, 1.0, 1.0]);
+            render_pass
+                .set_viewport(0, [self.viewport.clone()])
+                .bind_vertex_buffers(0, self.vertex_buffer.clone())
+                .bind_pipeline_graphics(self.denoiser_pipeline.clone())
+                .bind_descriptor_sets(
+                    PipelineBindPoint::Graphics,
+                    self.denoiser_pipeline.layout().clone(),
+                    0,
+                    denoiser_descriptor_set,
+                );
+
+            render_pass
+                .draw(self.vertex_buffer.len() as u32, 1, 0, 0)
+                .unwrap();
+            Vk::end_debug_label(render_pass);
+            self.vulkan.profiler.write_timestamp(render_pass, Timestamp::DenoiserEnd);
+
+            if taa {
+                let taa_set = PersistentDescriptorSet::new(
+                    &self.frame_descriptor_allocator,
+                    self.taa_pipeline.layout().set_layouts()[0].clone(),
+                    [
+                        WriteDescriptorSet::image_view_sampler(0, taa_input, self.sampler.clone()),
+                        WriteDescriptorSet::image_view_sampler(1, previous_taa_history, self.sampler.clone()),
+                        WriteDescriptorSet::image_view_sampler(2, ray_velocity, self.sampler.clone()),
+                        WriteDescriptorSet::image_view(3, taa_history),
+                    ],
+                ).unwrap();
+                let taa_info = shader::taa::fs::TaaInfo {
+                    blend_factor: self.scene.taa_blend_factor.clamp(0.05, 0.2),
+                    reset: (!self.taa_history_valid || (self.scene.frame_index == 0 && !camera_moved)) as i32,
+                };
+
+                // the resolve samples the neighbors of the denoiser output
+                self.vulkan.next_screen_pass(render_pass);
+                Vk::begin_debug_label(render_pass, "TAA Pass", [0.0, 1.0, 1.0, 1.0]);
+                render_pass
+                    .set_viewport(0, [self.viewport.clone()])
+                    .bind_vertex_buffers(0, self.vertex_buffer.clone())
+                    .bind_pipeline_graphics(self.taa_pipeline.clone())
+                    .bind_descriptor_sets(
+                        PipelineBindPoint::Graphics,
+                        self.taa_pipeline.layout().clone(),
+                        0,
+                        taa_set,
+                    )
+                    .push_constants(self.taa_pipeline.layout().clone(), 0, taa_info)
+                    .draw(self.vertex_buffer.len() as u32, 1, 0, 0)
+                    .unwrap();
+                Vk::end_debug_label(render_pass);
             }
-        });
+            self.taa_history_valid = taa;
+
+            Vk::begin_debug_label(render_pass, "ImGui Pass", [0.5, 1.0, 0.0, 1.0]);
+            self.imgui_renderer.draw_commands(
+                render_pass,
+                &self.vulkan,
+                imgui_draw_data,
+            );
+            Vk::end_debug_label(render_pass);
+            self.vulkan.profiler.write_timestamp(render_pass, Timestamp::ImguiEnd);
+        }
+        if self.scene.accumulate {
+            self.scene.frame_index = self.scene.frame_index.saturating_add(1);
+        }
+        self.update_memory_info();
+        let status = self.vulkan.end_frame(render_pass);
+        match status {
+            DrawStatus::Ok => (),
+            DrawStatus::ShouldRecreateSwapchain => {
+                self.recreate_swapchain = true;
+            }
+        }
+
+        if self.frame_limit.is_some_and(|limit| self.info.frame_count >= limit) {
+            let elapsed = self.start_time.elapsed().as_secs_f32();
+            println!(
+                "Rendered {} frames in {:.2}s ({:.2}ms per frame)",
+                self.info.frame_count,
+                elapsed,
+                elapsed * 1000.0 / self.info.frame_count as f32,
+            );
+            keep_running = false;
+        }
+
+        // a single readback at a time, the request waits for the previous one
+        if self.screenshot_requested && self.pending_screenshot.is_none() {
+            self.screenshot_requested = false;
+            let image = self.vulkan.buffers.as_ref().unwrap()[0].accumulation_image.image().clone();
+            self.pending_screenshot = Some(self.vulkan.read_image(image));
+        }
+        keep_running
     }
 
     pub fn add_circle(&mut self) -> &mut Circle {
@@ -570,12 +2441,290 @@ impl<F> App<F>
         return c;
     }
 
+    pub fn add_box(&mut self) -> &mut Box {
+        let index = self.scene.all_boxes.len();
+        self.scene.all_boxes.push(Box::new());
+        let b = self.scene.all_boxes.get_mut(index).unwrap();
+        b.index = index;
+        b
+    }
+
+    pub fn add_plane(&mut self) -> &mut Plane {
+        let index = self.scene.all_planes.len();
+        self.scene.all_planes.push(Plane::new());
+        let p = self.scene.all_planes.get_mut(index).unwrap();
+        p.index = index;
+        p
+    }
+
+    pub fn add_cylinder(&mut self) -> &mut Cylinder {
+        self.scene.add_cylinder()
+    }
+
+    pub fn add_capsule(&mut self) -> &mut Capsule {
+        self.scene.add_capsule()
+    }
+
+    pub fn add_torus(&mut self) -> &mut Torus {
+        self.scene.add_torus()
+    }
+
+    pub fn add_directional_light(&mut self) -> &mut DirectionalLight {
+        self.scene.add_directional_light()
+    }
+
+    pub fn add_volume(&mut self) -> &mut HomogeneousVolume {
+        self.scene.add_volume()
+    }
+
+    /// Appends one triangle per entry of `triangles` as a new mesh, with normals from the winding order.
+    pub fn add_mesh(&mut self, triangles: &[[[f32; 3]; 3]]) -> &mut [Triangle] {
+        let first = self.scene.all_triangles.len();
+        let mesh = self.scene.next_mesh_id();
+        for (i, [v0, v1, v2]) in triangles.iter().enumerate() {
+            let mut t = Triangle::new(*v0, *v1, *v2);
+            t.index = first + i;
+            t.mesh = mesh;
+            self.scene.all_triangles.push(t);
+        }
+        &mut self.scene.all_triangles[first..]
+    }
+
+    /// Gives every vertex of the mesh the average normal of the faces around it and shades the
+    /// mesh smooth, `mesh_index` is the `Triangle::mesh` id.
+    pub fn compute_smooth_normals(&mut self, mesh_index: u32) {
+        self.scene.compute_smooth_normals(mesh_index);
+    }
+
+    /// Appends the faces of an OBJ file as triangles, returns how many were added.
+    pub fn load_obj(&mut self, path: &str, material_index: i32) -> Result<usize, ObjError> {
+        let triangles = obj::load(path, material_index)?;
+        let first = self.scene.all_triangles.len();
+        let mesh = self.scene.next_mesh_id();
+        for (i, mut t) in triangles.into_iter().enumerate() {
+            t.index = first + i;
+            t.mesh = mesh;
+            self.scene.all_triangles.push(t);
+        }
+        Ok(self.scene.all_triangles.len() - first)
+    }
+
+    /// Appends the materials and the triangles of all meshes of a GLTF file.
+    pub fn load_gltf(&mut self, path: &str) -> Result<GltfLoadReport, GltfError> {
+        let loaded = gltf::load(path, self.scene.all_materials.len())?;
+        for m in loaded.materials {
+            *self.add_material() = Material { index: self.scene.all_materials.len() - 1, ..m };
+        }
+        let first = self.scene.all_triangles.len();
+        let mesh = self.scene.next_mesh_id();
+        for (i, mut t) in loaded.triangles.into_iter().enumerate() {
+            t.index = first + i;
+            t.mesh = mesh;
+            self.scene.all_triangles.push(t);
+        }
+        for warning in &loaded.report.warnings {
+            println!("{}: {}", path, warning);
+        }
+        Ok(loaded.report)
+    }
+
+    /// Uploads an RGBA8 sRGB image and registers it, so materials can use it as albedo.
+    pub fn add_texture(&mut self, rgba: &[u8], width: u32, height: u32) -> TextureId {
+        self.register_texture(rgba, width, height, Format::R8G8B8A8_SRGB)
+    }
+
+    /// Same as `add_texture` for data that isn't a color, like normal maps.
+    pub fn add_linear_texture(&mut self, rgba: &[u8], width: u32, height: u32) -> TextureId {
+        self.register_texture(rgba, width, height, Format::R8G8B8A8_UNORM)
+    }
+
+    fn register_texture(&mut self, rgba: &[u8], width: u32, height: u32, format: Format) -> TextureId {
+        let image = self.vulkan.upload_image_data(
+            rgba,
+            ImageDimensions::Dim2d {
+                width,
+                height,
+                array_layers: 1,
+            },
+            format,
+        );
+
+        let sampler = Sampler::new(
+            self.vulkan.device.clone(),
+            SamplerCreateInfo {
+                mag_filter: Filter::Linear,
+                min_filter: Filter::Linear,
+                address_mode: [SamplerAddressMode::Repeat; 3],
+                ..Default::default()
+            },
+        ).unwrap();
+        self.imgui_renderer.add_texture((ImageView::new_default(image).unwrap(), sampler))
+    }
+
+    /// Loads a `.hdr` or `.exr` latitude-longitude image and lights the scene with it, replacing
+    /// the sky gradient.
+    pub fn set_environment_map(&mut self, path: &str) -> Result<TextureId, ImageError> {
+        let image = environment::load(path)?;
+        let data: Vec<u8> = image.rgba.iter()
+            .flatten()
+            .flat_map(|&c| f16::from_f32(c).to_le_bytes())
+            .collect();
+        // half floats can be filtered linearly on every device, 32 bit floats can't
+        let id = self.register_texture(&data, image.width, image.height, Format::R16G16B16A16_SFLOAT);
+        self.scene.environment_map = Some(id);
+        self.scene.scene_dirty = true;
+        Ok(id)
+    }
+
+    /// See `Scene::remove_material`, a material still used by an object is kept.
+    pub fn remove_material(&mut self, index: usize) -> bool {
+        self.scene.remove_material(index)
+    }
+
     pub fn add_material(&mut self) -> &mut Material {
         let index = self.scene.all_materials.len();
         self.scene.all_materials.push(Material::new());
         let mut m = self.scene.all_materials.get_mut(index).unwrap();
         m.index = index;
+        m.name = format!("Material {}", index);
         m.dirty;
         return m;
     }
 }
+
+impl<F> Drop for App<F> {
+    fn drop(&mut self) {
+        // Wait for in-flight frames before the pipelines, descriptor sets and buffers
+        // owned by the app are released. `Vk` is dropped afterwards and waits again.
+        self.vulkan.wait_idle();
+        self.settings.save_if_changed(&mut self.saved_settings);
+        self.geom_set = None;
+        self.material_buffer = None;
+        self.circle_buffer = None;
+    }
+}
+
+/// FIFO with vsync, otherwise mailbox or immediate, whichever the surface has.
+fn select_present_mode(supported: &[PresentMode], vsync: bool) -> PresentMode {
+    if vsync {
+        return PresentMode::Fifo;
+    }
+    [PresentMode::Mailbox, PresentMode::Immediate]
+        .into_iter()
+        .find(|mode| supported.contains(mode))
+        .unwrap_or(PresentMode::Fifo)
+}
+
+/// Adds the Help menu to the main menu bar and draws the Keyboard Shortcuts window while `open`.
+fn help_ui(ui: &Ui, open: &mut bool) {
+    // appends to the menu bar started by the app's UI, if any
+    if let Some(_menu_bar) = ui.begin_main_menu_bar() {
+        if let Some(_menu) = ui.begin_menu("Help") {
+            ui.menu_item_config("Keyboard Shortcuts")
+                .shortcut("F1")
+                .build_with_ref(open);
+        }
+    }
+    if !*open {
+        return;
+    }
+
+    ui.window("Keyboard Shortcuts##shortcuts")
+        .size([480.0, 340.0], imgui::Condition::FirstUseEver)
+        .opened(open)
+        .build(|| {
+            if let Some(_table) = ui.begin_table("##shortcuts_table", 2) {
+                for (keys, action, description) in SHORTCUTS {
+                    ui.table_next_row();
+                    ui.table_next_column();
+                    ui.text(keys);
+                    ui.table_next_column();
+                    ui.text(action);
+                    ui.text_disabled(description);
+                }
+            }
+        });
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn scene_with_circles(circles: &[([f32; 3], f32)]) -> Scene {
+        let mut scene = Scene::default();
+        scene.all_circles = circles.iter()
+            .map(|&(position, radius)| *Circle::new().position(position).radius(radius))
+            .collect();
+        scene
+    }
+
+    #[test]
+    fn spheres_in_radius_finds_overlapping_circles() {
+        let scene = scene_with_circles(&[
+            ([0.0, 0.0, 0.0], 1.0),
+            // reaches into the query from outside of it
+            ([2.5, 0.0, 0.0], 1.0),
+            // inside the query's bounding box, but not the sphere
+            ([1.4, 1.4, 1.4], 0.1),
+            ([10.0, 0.0, 0.0], 1.0),
+        ]);
+        assert_eq!(scene.spheres_in_radius([0.0, 0.0, 0.0], 2.0), vec![0, 1]);
+        assert_eq!(scene.spheres_in_radius([10.0, 0.5, 0.0], 0.1), vec![3]);
+        assert!(scene.spheres_in_radius([5.0, 5.0, 5.0], 1.0).is_empty());
+    }
+
+    #[test]
+    fn spheres_in_radius_ignores_invalid_radius() {
+        let scene = scene_with_circles(&[([0.0, 0.0, 0.0], 1.0)]);
+        assert!(scene.spheres_in_radius([0.0, 0.0, 0.0], -1.0).is_empty());
+        assert!(scene.spheres_in_radius([0.0, 0.0, 0.0], f32::NAN).is_empty());
+    }
+
+    #[test]
+    fn undo_restores_objects_renumbered_by_remove_material() {
+        let mut scene = Scene::default();
+        scene.all_materials = vec![Material::new(), Material::new()];
+        scene.all_boxes.push(*Box::new().material(1));
+        scene.all_triangles.push(*Triangle::new([0.0; 3], [1.0, 0.0, 0.0], [0.0, 1.0, 0.0]).material(1));
+
+        scene.push_history();
+        assert!(scene.remove_material(0));
+        assert_eq!(scene.all_boxes[0].material, 0);
+        assert_eq!(scene.all_triangles[0].material, 0);
+
+        scene.undo();
+        assert_eq!(scene.all_materials.len(), 2);
+        assert_eq!(scene.all_boxes[0].material, 1);
+        assert_eq!(scene.all_triangles[0].material, 1);
+        scene.redo();
+        assert_eq!(scene.all_triangles[0].material, 0);
+    }
+
+    #[test]
+    fn snapshot_ignores_dirty_flags() {
+        let mut scene = scene_with_circles(&[([0.0, 0.0, 0.0], 1.0)]);
+        let mut snapshot = scene.snapshot();
+        scene.force_dirty_all();
+        assert!(snapshot.matches(&scene));
+
+        scene.all_circles[0].radius(2.0);
+        assert!(!snapshot.matches(&scene));
+        snapshot.update(&scene);
+        assert!(snapshot.matches(&scene));
+        assert!(snapshot == scene.snapshot());
+    }
+
+    #[test]
+    fn remove_moves_later_objects_down() {
+        let mut scene = Scene::default();
+        for i in 0..4 {
+            scene.add_cylinder().radius(i as f32);
+        }
+        scene.all_cylinders.iter_mut().for_each(|c| c.dirty = false);
+
+        scene.remove_cylinder(1);
+        let radii: Vec<f32> = scene.all_cylinders.iter().map(|c| c.radius).collect();
+        assert_eq!(radii, vec![0.0, 2.0, 3.0]);
+        assert!(scene.all_cylinders.iter().enumerate().all(|(i, c)| c.index == i && c.dirty));
+    }
+}
@@ -0,0 +1,89 @@
+/// Command line options of the demo.
+#[derive(Debug, Clone, Default)]
+pub struct Args {
+    /// Scene file to load instead of the built-in scene.
+    pub scene: Option<String>,
+    /// `.hdr` or `.exr` latitude-longitude image lighting the scene instead of the sky gradient.
+    pub environment: Option<String>,
+    pub width: Option<u32>,
+    pub height: Option<u32>,
+    /// Renders this many frames and exits, for benchmarks.
+    pub frames: Option<u32>,
+    /// Renders offscreen without opening a window, for CI. Needs `frames`.
+    pub headless: bool,
+    /// Part of the name of the GPU to render with.
+    pub device: Option<String>,
+    pub watch_shaders: bool,
+    pub reset_layout: bool,
+}
+
+pub const USAGE: &str = "usage: raytracing-demo [--scene <path>] [--environment <.hdr or .exr>] [--width <pixels>] [--height <pixels>] [--frames <count>] [--headless] [--device <name>] [--watch-shaders] [--reset-layout]";
+
+impl Args {
+    pub fn parse() -> Result<Self, String> {
+        Self::parse_from(std::env::args().skip(1))
+    }
+
+    pub fn parse_from(args: impl IntoIterator<Item = String>) -> Result<Self, String> {
+        let mut result = Self::default();
+        let mut args = args.into_iter();
+        while let Some(arg) = args.next() {
+            let mut value = || args.next().ok_or_else(|| format!("missing value for {arg}"));
+            match arg.as_str() {
+                "--scene" => result.scene = Some(value()?),
+                "--environment" => result.environment = Some(value()?),
+                "--width" => result.width = Some(parse_number(&arg, &value()?)?),
+                "--height" => result.height = Some(parse_number(&arg, &value()?)?),
+                "--frames" => result.frames = Some(parse_number(&arg, &value()?)?),
+                "--device" => result.device = Some(value()?),
+                "--watch-shaders" => result.watch_shaders = true,
+                "--reset-layout" => result.reset_layout = true,
+                "--headless" => result.headless = true,
+                _ => return Err(format!("unknown argument {arg}")),
+            }
+        }
+        if result.headless && result.frames.is_none() {
+            return Err("--headless needs --frames, nothing would stop it otherwise".to_string());
+        }
+        Ok(result)
+    }
+}
+
+fn parse_number(arg: &str, value: &str) -> Result<u32, String> {
+    value.parse().map_err(|_| format!("invalid value for {arg}: {value}"))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn parse(args: &[&str]) -> Result<Args, String> {
+        Args::parse_from(args.iter().map(|a| a.to_string()))
+    }
+
+    #[test]
+    fn parses_values_and_flags() {
+        let args = parse(&["--scene", "a.ron", "--width", "640", "--frames", "10", "--watch-shaders"]).unwrap();
+        assert_eq!(args.scene.as_deref(), Some("a.ron"));
+        assert_eq!(args.width, Some(640));
+        assert_eq!(args.height, None);
+        assert_eq!(args.frames, Some(10));
+        assert!(args.watch_shaders);
+    }
+
+    #[test]
+    fn rejects_bad_arguments() {
+        assert!(parse(&["--width"]).is_err());
+        assert!(parse(&["--width", "wide"]).is_err());
+        assert!(parse(&["--windowless"]).is_err());
+    }
+
+    #[test]
+    fn headless_needs_a_frame_count() {
+        assert!(parse(&["--headless"]).is_err());
+        let args = parse(&["--headless", "--frames", "100"]).unwrap();
+        assert!(args.headless);
+        assert_eq!(args.frames, Some(100));
+        assert!(!parse(&["--frames", "100"]).unwrap().headless);
+    }
+}
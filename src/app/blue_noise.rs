@@ -0,0 +1,88 @@
+pub const BLUE_NOISE_SIZE: u32 = 64;
+
+/// Generates a tileable `BLUE_NOISE_SIZE` x `BLUE_NOISE_SIZE` blue noise texture using the
+/// void-and-cluster method. Each texel is its rank in the dither order, mapped to `0..=255`.
+pub fn generate() -> Vec<u8> {
+    const SIGMA: f32 = 1.5;
+    let n = BLUE_NOISE_SIZE as usize;
+    let len = n * n;
+
+    // energy contribution of a point at the origin, wrapping around the edges
+    let mut kernel = vec![0.0_f32; len];
+    for y in 0..n {
+        for x in 0..n {
+            let dx = x.min(n - x) as f32;
+            let dy = y.min(n - y) as f32;
+            kernel[y * n + x] = (-(dx * dx + dy * dy) / (2.0 * SIGMA * SIGMA)).exp();
+        }
+    }
+
+    let update = |energy: &mut [f32], index: usize, sign: f32| {
+        let (px, py) = (index % n, index / n);
+        for y in 0..n {
+            for x in 0..n {
+                energy[y * n + x] += sign * kernel[((y + n - py) % n) * n + (x + n - px) % n];
+            }
+        }
+    };
+    let tightest_cluster = |points: &[bool], energy: &[f32]| {
+        (0..len).filter(|&i| points[i]).max_by(|&a, &b| energy[a].total_cmp(&energy[b])).unwrap()
+    };
+    let largest_void = |points: &[bool], energy: &[f32]| {
+        (0..len).filter(|&i| !points[i]).min_by(|&a, &b| energy[a].total_cmp(&energy[b])).unwrap()
+    };
+
+    let mut points = vec![false; len];
+    let mut energy = vec![0.0_f32; len];
+
+    // random initial pattern covering 10% of the texels
+    let initial = len / 10;
+    let mut rng: u32 = 0x9E37_79B9;
+    let mut count = 0;
+    while count < initial {
+        rng ^= rng << 13;
+        rng ^= rng >> 17;
+        rng ^= rng << 5;
+        let i = rng as usize % len;
+        if !points[i] {
+            points[i] = true;
+            update(&mut energy, i, 1.0);
+            count += 1;
+        }
+    }
+
+    // spread it out by moving the tightest cluster into the largest void until stable
+    for _ in 0..len {
+        let cluster = tightest_cluster(&points, &energy);
+        points[cluster] = false;
+        update(&mut energy, cluster, -1.0);
+        let void = largest_void(&points, &energy);
+        points[void] = true;
+        update(&mut energy, void, 1.0);
+        if void == cluster {
+            break;
+        }
+    }
+
+    let mut rank = vec![0_usize; len];
+
+    // rank the initial points by removing clusters
+    let mut cluster_points = points.clone();
+    let mut cluster_energy = energy.clone();
+    for r in (0..initial).rev() {
+        let cluster = tightest_cluster(&cluster_points, &cluster_energy);
+        cluster_points[cluster] = false;
+        update(&mut cluster_energy, cluster, -1.0);
+        rank[cluster] = r;
+    }
+
+    // rank the remaining texels by filling voids
+    for r in initial..len {
+        let void = largest_void(&points, &energy);
+        points[void] = true;
+        update(&mut energy, void, 1.0);
+        rank[void] = r;
+    }
+
+    rank.iter().map(|&r| (r * 256 / len) as u8).collect()
+}
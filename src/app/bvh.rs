@@ -0,0 +1,215 @@
+use vulkano::padded::Padded;
+
+use crate::app::geom::Circle;
+use crate::app::shader;
+
+// relative cost of visiting a node against testing one sphere
+const TRAVERSAL_COST: f32 = 1.0;
+// BVH_STACK_SIZE in the raytracing shader, the traversal holds at most one node per level plus
+// the sibling of each, so deeper nodes are kept as leaves
+const STACK_SIZE: usize = 64;
+const MAX_DEPTH: usize = STACK_SIZE - 1;
+
+#[derive(Debug, Clone, Copy)]
+pub struct BvhNode {
+    pub min: [f32; 3],
+    pub max: [f32; 3],
+    /// Index of the left child, the right one follows it. For leaves, the first entry in `Bvh::indices`.
+    pub left_or_first: u32,
+    /// Number of circles in a leaf, 0 for inner nodes.
+    pub count: u32,
+}
+
+/// Flat bounding volume hierarchy over the scene circles, the root is the first node.
+///
+/// Split with the surface area heuristic, so leaves are only created when splitting costs more
+/// than testing every circle in them.
+#[derive(Debug, Clone, Default)]
+pub struct Bvh {
+    pub nodes: Vec<BvhNode>,
+    /// Circle indices, leaves reference contiguous ranges of it.
+    pub indices: Vec<u32>,
+}
+
+impl Bvh {
+    pub fn build(circles: &[Circle]) -> Self {
+        Self::build_with_max_depth(circles, MAX_DEPTH)
+    }
+
+    fn build_with_max_depth(circles: &[Circle], max_depth: usize) -> Self {
+        let mut bvh = Self {
+            nodes: Vec::with_capacity(circles.len() * 2),
+            indices: (0..circles.len() as u32).collect(),
+        };
+        let aabbs: Vec<_> = circles.iter().map(|c| c.aabb()).collect();
+        bvh.nodes.push(BvhNode {
+            min: [0.0; 3],
+            max: [0.0; 3],
+            left_or_first: 0,
+            count: circles.len() as u32,
+        });
+        bvh.subdivide(0, max_depth, &aabbs);
+        assert!(bvh.depth() <= max_depth);
+        bvh
+    }
+
+    /// Levels below the root, 0 for a tree that is a single leaf.
+    pub fn depth(&self) -> usize {
+        let mut depth = 0;
+        let mut stack = vec![(0, 0)];
+        while let Some((node, level)) = stack.pop() {
+            depth = depth.max(level);
+            let node = &self.nodes[node];
+            if node.count == 0 && node.left_or_first != 0 {
+                stack.push((node.left_or_first as usize, level + 1));
+                stack.push((node.left_or_first as usize + 1, level + 1));
+            }
+        }
+        depth
+    }
+
+    /// Splits the node while that is cheaper, at most `levels` levels further down.
+    fn subdivide(&mut self, node_index: usize, levels: usize, aabbs: &[([f32; 3], [f32; 3])]) {
+        let first = self.nodes[node_index].left_or_first as usize;
+        let count = self.nodes[node_index].count as usize;
+        let bounds = self.indices[first..first + count].iter()
+            .map(|&i| aabbs[i as usize])
+            .fold(empty_bounds(), union);
+        self.nodes[node_index].min = bounds.0;
+        self.nodes[node_index].max = bounds.1;
+        if count <= 1 || levels == 0 {
+            return;
+        }
+
+        let centroid = |i: u32, axis: usize| (aabbs[i as usize].0[axis] + aabbs[i as usize].1[axis]) * 0.5;
+
+        // sweep every axis with the circles sorted by centroid, `split` circles go to the left
+        let mut best: Option<(usize, usize, f32)> = None;
+        let mut right_areas = vec![0.0; count];
+        for axis in 0..3 {
+            let range = &mut self.indices[first..first + count];
+            range.sort_by(|&a, &b| centroid(a, axis).total_cmp(&centroid(b, axis)));
+
+            let mut right = empty_bounds();
+            for k in (1..count).rev() {
+                right = union(right, aabbs[range[k] as usize]);
+                right_areas[k] = surface_area(right);
+            }
+            let mut left = empty_bounds();
+            for split in 1..count {
+                left = union(left, aabbs[range[split - 1] as usize]);
+                let cost = surface_area(left) * split as f32 + right_areas[split] * (count - split) as f32;
+                if !matches!(best, Some((_, _, c)) if c <= cost) {
+                    best = Some((axis, split, cost));
+                }
+            }
+        }
+
+        let (axis, split, cost) = best.unwrap();
+        let leaf_cost = surface_area(bounds) * count as f32;
+        if TRAVERSAL_COST * surface_area(bounds) + cost >= leaf_cost {
+            return;
+        }
+
+        let range = &mut self.indices[first..first + count];
+        range.sort_by(|&a, &b| centroid(a, axis).total_cmp(&centroid(b, axis)));
+
+        let left = self.nodes.len();
+        self.nodes.push(BvhNode { min: [0.0; 3], max: [0.0; 3], left_or_first: first as u32, count: split as u32 });
+        self.nodes.push(BvhNode { min: [0.0; 3], max: [0.0; 3], left_or_first: (first + split) as u32, count: (count - split) as u32 });
+        self.nodes[node_index].left_or_first = left as u32;
+        self.nodes[node_index].count = 0;
+
+        self.subdivide(left, levels - 1, aabbs);
+        self.subdivide(left + 1, levels - 1, aabbs);
+    }
+}
+
+fn empty_bounds() -> ([f32; 3], [f32; 3]) {
+    ([f32::INFINITY; 3], [f32::NEG_INFINITY; 3])
+}
+
+fn union(a: ([f32; 3], [f32; 3]), b: ([f32; 3], [f32; 3])) -> ([f32; 3], [f32; 3]) {
+    (
+        [a.0[0].min(b.0[0]), a.0[1].min(b.0[1]), a.0[2].min(b.0[2])],
+        [a.1[0].max(b.1[0]), a.1[1].max(b.1[1]), a.1[2].max(b.1[2])],
+    )
+}
+
+fn surface_area((min, max): ([f32; 3], [f32; 3])) -> f32 {
+    let d = [max[0] - min[0], max[1] - min[1], max[2] - min[2]];
+    2.0 * (d[0] * d[1] + d[1] * d[2] + d[2] * d[0])
+}
+
+impl From<BvhNode> for shader::raytrace::fs::BvhNode {
+    fn from(value: BvhNode) -> Self {
+        shader::raytrace::fs::BvhNode {
+            min: value.min,
+            left_or_first: value.left_or_first,
+            max: value.max,
+            count: value.count,
+        }
+    }
+}
+
+impl<const N: usize> From<BvhNode> for ::vulkano::padded::Padded<shader::raytrace::fs::BvhNode, N> {
+    fn from(value: BvhNode) -> Self {
+        Padded(value.into())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn circles(positions: impl Iterator<Item = [f32; 3]>, radius: f32) -> Vec<Circle> {
+        positions.map(|p| *Circle::new().position(p).radius(radius)).collect()
+    }
+
+    // every circle is in exactly one leaf, and each leaf's bounds contain its circles
+    fn check_leaves(bvh: &Bvh, circles: &[Circle]) {
+        let mut seen = vec![0; circles.len()];
+        for node in bvh.nodes.iter().filter(|n| n.count > 0) {
+            for &i in &bvh.indices[node.left_or_first as usize..(node.left_or_first + node.count) as usize] {
+                seen[i as usize] += 1;
+                let (min, max) = circles[i as usize].aabb();
+                for axis in 0..3 {
+                    assert!(node.min[axis] <= min[axis] && max[axis] <= node.max[axis]);
+                }
+            }
+        }
+        assert!(seen.iter().all(|&n| n == 1), "{:?}", seen);
+    }
+
+    #[test]
+    fn build_covers_every_circle() {
+        let circles = circles((0..200).map(|i| [(i % 10) as f32 * 3.0, (i / 10 % 5) as f32 * 3.0, (i / 50) as f32 * 3.0]), 1.0);
+        let bvh = Bvh::build(&circles);
+        check_leaves(&bvh, &circles);
+        assert!(bvh.nodes.len() > 1);
+    }
+
+    #[test]
+    fn build_empty_scene() {
+        let bvh = Bvh::build(&[]);
+        assert_eq!(bvh.nodes.len(), 1);
+        assert_eq!(bvh.nodes[0].count, 0);
+    }
+
+    #[test]
+    fn depth_is_limited() {
+        // circles growing along a diagonal, the surface area heuristic peels the largest ones off
+        // one level at a time, so the tree gets much deeper than a balanced one
+        let circles: Vec<Circle> = (-20..20)
+            .map(|i| {
+                let size = 3.0_f32.powi(i);
+                *Circle::new().position([size; 3]).radius(size * 0.1)
+            })
+            .collect();
+        assert!(Bvh::build(&circles).depth() > 8);
+
+        let bvh = Bvh::build_with_max_depth(&circles, 8);
+        assert_eq!(bvh.depth(), 8);
+        check_leaves(&bvh, &circles);
+    }
+}
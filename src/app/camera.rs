@@ -1,14 +1,29 @@
-use cgmath::{Matrix4, SquareMatrix};
+use cgmath::{InnerSpace, Matrix4, SquareMatrix, Vector3};
+use serde::{Deserialize, Serialize};
 use crate::app::shader;
 
+#[derive(Clone, PartialEq, Serialize, Deserialize)]
+#[serde(default)]
 pub struct Camera {
     pub position: [f32; 3],
     pub rotation: [f32; 3],
     pub blur: f32,
+    pub near_clip_distance: f32,
+    pub far_clip_distance: f32,
+    /// Vertical field of view in degrees, applied by `update_view`.
+    pub fov: f32,
+    pub aspect: f32,
 
     pub speed: f32,
+    orthographic: bool,
 
+    /// Point looked at and orbited around while `orbit_mode` is on.
+    pub target: Option<[f32; 3]>,
+    pub orbit_mode: bool,
+
+    #[serde(skip)]
     pub view: Matrix4<f32>,
+    #[serde(skip)]
     pub projection: Matrix4<f32>,
 }
 
@@ -18,35 +33,122 @@ impl Camera {
             position: [0.0, 0.0, 0.0],
             rotation: [0.0, 0.0, 0.0],
             blur: 0.0,
+            near_clip_distance: 0.1,
+            far_clip_distance: 100.0,
+            fov: 75.0,
+            aspect: 1.0,
             speed: 5.0,
+            orthographic: false,
+            target: None,
+            orbit_mode: false,
             view: Matrix4::identity(),
             projection: Matrix4::identity(),
         }
     }
 
+    /// Camera at `eye` looking at `target`, rolled so that `up` points up on screen.
+    pub fn from_look_at(eye: [f32; 3], target: [f32; 3], up: [f32; 3]) -> Self {
+        let forward = (Vector3::from(target) - Vector3::from(eye)).normalize();
+        // update_view looks down +z, rotated by yaw * pitch * roll
+        let pitch = (-forward.y).asin();
+        let yaw = forward.x.atan2(forward.z);
+
+        let rotation = Matrix4::from_angle_y(cgmath::Rad(yaw)) * Matrix4::from_angle_x(cgmath::Rad(pitch));
+        let right = (rotation * Vector3::unit_x().extend(0.0)).truncate();
+        let camera_up = (rotation * Vector3::unit_y().extend(0.0)).truncate();
+        let up = Vector3::from(up);
+        let up = up - forward * up.dot(forward);
+        let roll = (-up.dot(right)).atan2(up.dot(camera_up));
+
+        let mut camera = Self::new();
+        camera.position = eye;
+        camera.rotation = [pitch, yaw, roll];
+        camera.update_view();
+        camera
+    }
+
     pub fn set_perspective(&mut self, fov: f32, aspect: f32, near: f32, far: f32) {
+        self.fov = fov;
+        self.aspect = aspect;
+        self.orthographic = false;
         self.projection = cgmath::perspective(cgmath::Deg(fov), aspect, near, far);
     }
 
+    /// Replaces the perspective projection until `set_perspective` is called again.
     pub fn set_orthographic(&mut self, left: f32, right: f32, bottom: f32, top: f32, near: f32, far: f32) {
+        self.orthographic = true;
         self.projection = cgmath::ortho(left, right, bottom, top, near, far);
     }
 
     pub fn update_view(&mut self) {
-        let rotation = Matrix4::from_angle_y(cgmath::Rad(self.rotation[1]))
-            * Matrix4::from_angle_x(cgmath::Rad(self.rotation[0]))
-            * Matrix4::from_angle_z(cgmath::Rad(self.rotation[2]));
+        if !self.orthographic {
+            self.set_perspective(self.fov, self.aspect, self.near_clip_distance, self.far_clip_distance);
+        }
+
+        if let Some(target) = self.orbit_target() {
+            // look at the target, the roll is kept
+            let forward = (Vector3::from(target) - Vector3::from(self.position)).normalize();
+            if forward.x.is_finite() {
+                self.rotation[0] = (-forward.y).asin();
+                self.rotation[1] = forward.x.atan2(forward.z);
+            }
+        }
+
         let translation = Matrix4::from_translation(self.position.into());
-        self.view = translation * rotation;
+        self.view = translation * self.rotation_matrix();
+    }
+
+    fn orbit_target(&self) -> Option<[f32; 3]> {
+        self.target.filter(|_| self.orbit_mode)
     }
 
+    /// Rotates the position around the target by azimuth and elevation angles in radians,
+    /// keeping the distance. Does nothing outside of orbit mode.
+    pub fn orbit(&mut self, azimuth: f32, elevation: f32) {
+        let Some(target) = self.orbit_target() else { return };
+        let offset = Vector3::from(self.position) - Vector3::from(target);
+        let radius = offset.magnitude();
+        if radius == 0.0 {
+            return;
+        }
+        let max_elevation = 89.0_f32.to_radians();
+        let current_azimuth = offset.x.atan2(offset.z) + azimuth;
+        let current_elevation = ((offset.y / radius).asin() + elevation).clamp(-max_elevation, max_elevation);
+        let offset = Vector3::new(
+            current_elevation.cos() * current_azimuth.sin(),
+            current_elevation.sin(),
+            current_elevation.cos() * current_azimuth.cos(),
+        ) * radius;
+        self.position = (Vector3::from(target) + offset).into();
+    }
+
+    fn rotation_matrix(&self) -> Matrix4<f32> {
+        Matrix4::from_angle_y(cgmath::Rad(self.rotation[1]))
+            * Matrix4::from_angle_x(cgmath::Rad(self.rotation[0]))
+            * Matrix4::from_angle_z(cgmath::Rad(self.rotation[2]))
+    }
+
+    /// Moves along the camera's own axes, so forward is wherever it is looking.
+    ///
+    /// In orbit mode, x and y turn around the target instead and z moves towards it.
     pub(crate) fn move_by(&mut self, mov_x: f32, mov_y: f32, mov_z: f32, delta: f32) {
         let speed = self.speed * delta;
-        let mut new_pos = self.position;
-        new_pos[0] -= mov_x * speed;
-        new_pos[1] -= mov_y * speed;
-        new_pos[2] -= mov_z * speed;
-        self.position = new_pos;
+        if let Some(target) = self.orbit_target() {
+            let offset = Vector3::from(self.position) - Vector3::from(target);
+            let radius = offset.magnitude();
+            let new_radius = (radius + mov_z * speed).max(0.01);
+            if radius > 0.0 {
+                self.position = (Vector3::from(target) + offset * (new_radius / radius)).into();
+            }
+            // same speed along the orbit as when flying
+            let angle = speed / new_radius;
+            self.orbit(mov_x * angle, -mov_y * angle);
+            return;
+        }
+
+        let local = Vector3::new(mov_x, mov_y, mov_z) * speed;
+        let world = (self.rotation_matrix() * local.extend(0.0)).truncate();
+        self.position = (Vector3::from(self.position) - world).into();
     }
 }
 
@@ -56,12 +158,47 @@ impl Default for Camera {
     }
 }
 
-impl Into<shader::raytrace::fs::ViewData> for &Camera {
-    fn into(self) -> shader::raytrace::fs::ViewData {
+impl From<&Camera> for shader::raytrace::fs::ViewData {
+    fn from(value: &Camera) -> Self {
         shader::raytrace::fs::ViewData {
-            proj: self.projection.into(),
-            worldview: self.view.into(),
-            blur: self.blur.into(),
+            proj: value.projection.into(),
+            worldview: value.view.into(),
         }
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use std::f32::consts::FRAC_PI_2;
+
+    use super::*;
+
+    fn assert_near(actual: [f32; 3], expected: [f32; 3]) {
+        let distance = (Vector3::from(actual) - Vector3::from(expected)).magnitude();
+        assert!(distance < 1e-5, "{:?} != {:?}", actual, expected);
+    }
+
+    fn moved(rotation: [f32; 3], mov: [f32; 3]) -> [f32; 3] {
+        let mut camera = Camera::new();
+        camera.speed = 1.0;
+        camera.rotation = rotation;
+        camera.move_by(mov[0], mov[1], mov[2], 1.0);
+        camera.position
+    }
+
+    #[test]
+    fn move_by_without_rotation_follows_world_axes() {
+        assert_near(moved([0.0; 3], [0.0, 0.0, 1.0]), [0.0, 0.0, -1.0]);
+        assert_near(moved([0.0; 3], [1.0, 0.0, 0.0]), [-1.0, 0.0, 0.0]);
+    }
+
+    #[test]
+    fn move_by_after_90_degree_yaw() {
+        let yawed = [0.0, FRAC_PI_2, 0.0];
+        // the local z axis turned onto world x and the local x axis onto world -z
+        assert_near(moved(yawed, [0.0, 0.0, 1.0]), [-1.0, 0.0, 0.0]);
+        assert_near(moved(yawed, [1.0, 0.0, 0.0]), [0.0, 0.0, 1.0]);
+        // yaw leaves up alone
+        assert_near(moved(yawed, [0.0, 1.0, 0.0]), [0.0, -1.0, 0.0]);
+    }
+}
@@ -0,0 +1,44 @@
+/// Options fixed when the app is created.
+#[derive(Debug, Clone)]
+pub struct AppConfig {
+    /// Initial window size, in physical pixels.
+    pub width: u32,
+    pub height: u32,
+    pub title: String,
+    /// Enables the Khronos validation layer and the debug labels of the render passes.
+    pub enable_validation: bool,
+    /// Per-frame descriptor pools cycled through, a pool is reset when its turn comes again. The
+    /// frames themselves are not throttled by it, only the previous frame is waited on.
+    pub descriptor_pool_count: usize,
+    /// Part of the name of the device to use, the best ranked device when `None`.
+    pub device: Option<String>,
+    /// Rebuilds the pipelines when the GLSL sources in `src/shaders` change.
+    pub watch_shaders: bool,
+    /// Starts with FIFO presentation, it can be switched from the Info window.
+    pub vsync: bool,
+    /// Frame rate cap, unlimited when `None`.
+    pub target_fps: Option<f32>,
+    /// Deletes the saved imgui window layout before it is loaded.
+    pub reset_layout: bool,
+    /// Renders to an offscreen image without a window or surface, `width` and `height` give its
+    /// size. `main_loop` needs a frame limit then.
+    pub headless: bool,
+}
+
+impl Default for AppConfig {
+    fn default() -> Self {
+        Self {
+            width: 1200,
+            height: 900,
+            title: "Raytracing Demo".to_string(),
+            enable_validation: cfg!(debug_assertions),
+            descriptor_pool_count: 3,
+            device: None,
+            watch_shaders: false,
+            vsync: true,
+            target_fps: None,
+            reset_layout: false,
+            headless: false,
+        }
+    }
+}
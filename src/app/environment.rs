@@ -0,0 +1,161 @@
+use std::fmt;
+
+use crate::app::exr;
+use crate::app::exr::ExrError;
+
+#[derive(Debug)]
+pub enum ImageError {
+    Io(std::io::Error),
+    Exr(ExrError),
+    /// The file isn't a Radiance HDR image this reader understands.
+    InvalidHdr(String),
+    UnsupportedExtension(String),
+}
+
+impl fmt::Display for ImageError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Self::Io(e) => write!(f, "Could not read image: {}", e),
+            Self::Exr(e) => write!(f, "{}", e),
+            Self::InvalidHdr(reason) => write!(f, "Invalid HDR file: {}", reason),
+            Self::UnsupportedExtension(path) => write!(f, "{} is not an .hdr or .exr file", path),
+        }
+    }
+}
+
+impl std::error::Error for ImageError {}
+
+impl From<std::io::Error> for ImageError {
+    fn from(e: std::io::Error) -> Self {
+        Self::Io(e)
+    }
+}
+
+impl From<ExrError> for ImageError {
+    fn from(e: ExrError) -> Self {
+        Self::Exr(e)
+    }
+}
+
+/// Linear RGBA pixels in row order, top row first.
+pub struct HdrImage {
+    pub width: u32,
+    pub height: u32,
+    pub rgba: Vec<[f32; 4]>,
+}
+
+/// Reads a Radiance `.hdr` or an uncompressed OpenEXR file, picked by the extension.
+pub fn load(path: &str) -> Result<HdrImage, ImageError> {
+    let extension = path.rsplit_once('.').map(|(_, e)| e.to_ascii_lowercase());
+    match extension.as_deref() {
+        Some("hdr") => read_hdr(&std::fs::read(path)?),
+        Some("exr") => {
+            let (width, height, rgba) = exr::read(path)?;
+            Ok(HdrImage { width, height, rgba })
+        }
+        _ => Err(ImageError::UnsupportedExtension(path.to_string())),
+    }
+}
+
+/// Parses a Radiance RGBE image with a `-Y <height> +X <width>` resolution line, flat or with
+/// the run length encoded scanlines most writers produce.
+fn read_hdr(data: &[u8]) -> Result<HdrImage, ImageError> {
+    let invalid = |reason: &str| ImageError::InvalidHdr(reason.to_string());
+
+    let mut position = 0;
+    let mut next_line = || {
+        let start = position;
+        let end = data[start..].iter().position(|&b| b == b'\n').map(|i| start + i)?;
+        position = end + 1;
+        Some(String::from_utf8_lossy(&data[start..end]).trim().to_string())
+    };
+
+    let magic = next_line().ok_or_else(|| invalid("empty file"))?;
+    if !magic.starts_with("#?") {
+        return Err(invalid("missing #? signature"));
+    }
+    loop {
+        let line = next_line().ok_or_else(|| invalid("unterminated header"))?;
+        if line.is_empty() {
+            break;
+        }
+        if let Some(format) = line.strip_prefix("FORMAT=") {
+            if format != "32-bit_rle_rgbe" {
+                return Err(ImageError::InvalidHdr(format!("unsupported format {}", format)));
+            }
+        }
+    }
+    let resolution = next_line().ok_or_else(|| invalid("missing resolution"))?;
+    let parts: Vec<&str> = resolution.split_whitespace().collect();
+    let (height, width) = match parts[..] {
+        ["-Y", height, "+X", width] => (height.parse::<u32>().ok(), width.parse::<u32>().ok()),
+        _ => return Err(ImageError::InvalidHdr(format!("unsupported orientation {}", resolution))),
+    };
+    let (Some(height), Some(width)) = (height, width) else {
+        return Err(invalid("invalid resolution"));
+    };
+
+    let mut rgba = Vec::with_capacity(width as usize * height as usize);
+    let mut scanline = vec![[0u8; 4]; width as usize];
+    for _ in 0..height {
+        position = read_scanline(data, position, &mut scanline).ok_or_else(|| invalid("truncated pixel data"))?;
+        rgba.extend(scanline.iter().map(|&rgbe| rgbe_to_float(rgbe)));
+    }
+    Ok(HdrImage { width, height, rgba })
+}
+
+/// Decodes one scanline starting at `position`, returns the position after it.
+fn read_scanline(data: &[u8], mut position: usize, scanline: &mut [[u8; 4]]) -> Option<usize> {
+    let width = scanline.len();
+    let header = data.get(position..position + 4)?;
+    let run_length = (8..0x8000).contains(&width)
+        && header[0] == 2
+        && header[1] == 2
+        && ((header[2] as usize) << 8 | header[3] as usize) == width;
+    if !run_length {
+        for pixel in scanline.iter_mut() {
+            pixel.copy_from_slice(data.get(position..position + 4)?);
+            position += 4;
+        }
+        return Some(position);
+    }
+
+    // each component is stored apart, as runs of one repeated byte or of literal bytes
+    position += 4;
+    for component in 0..4 {
+        let mut x = 0;
+        while x < width {
+            let count = *data.get(position)? as usize;
+            position += 1;
+            if count > 128 {
+                let count = count - 128;
+                let value = *data.get(position)?;
+                position += 1;
+                for pixel in scanline.get_mut(x..x + count)? {
+                    pixel[component] = value;
+                }
+                x += count;
+            } else {
+                if count == 0 {
+                    return None;
+                }
+                let values = data.get(position..position + count)?;
+                position += count;
+                for (pixel, &value) in scanline.get_mut(x..x + count)?.iter_mut().zip(values) {
+                    pixel[component] = value;
+                }
+                x += count;
+            }
+        }
+    }
+    Some(position)
+}
+
+fn rgbe_to_float([r, g, b, e]: [u8; 4]) -> [f32; 4] {
+    if e == 0 {
+        return [0.0, 0.0, 0.0, 1.0];
+    }
+    // the mantissas are 8 bit fractions of 2^(e - 128)
+    let scale = 2.0_f32.powi(e as i32 - 136);
+    [r as f32 * scale, g as f32 * scale, b as f32 * scale, 1.0]
+}
@@ -0,0 +1,331 @@
+use std::fmt;
+use std::fs::File;
+use std::io::{BufWriter, Write};
+
+use half::f16;
+
+#[derive(Debug)]
+pub enum ExrError {
+    Io(std::io::Error),
+    /// A channel doesn't have one value per pixel.
+    ChannelSize { name: String, expected: usize, actual: usize },
+    /// The render target format can't be converted to float channels.
+    UnsupportedFormat(String),
+    /// The file is damaged or uses a feature `read` doesn't support.
+    Invalid(String),
+}
+
+impl fmt::Display for ExrError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Self::Io(e) => write!(f, "Could not access EXR file: {}", e),
+            Self::ChannelSize { name, expected, actual } => write!(f, "Channel {} has {} values, expected {}", name, actual, expected),
+            Self::UnsupportedFormat(format) => write!(f, "Unsupported image format {}", format),
+            Self::Invalid(reason) => write!(f, "Invalid EXR file: {}", reason),
+        }
+    }
+}
+
+impl std::error::Error for ExrError {}
+
+impl From<std::io::Error> for ExrError {
+    fn from(e: std::io::Error) -> Self {
+        Self::Io(e)
+    }
+}
+
+/// Writes an uncompressed scanline OpenEXR file with one half float channel per entry of
+/// `channels`, each holding `width * height` values in row order.
+///
+/// Layers are expressed with the usual dotted channel names, like `albedo.R`.
+pub fn write(path: &str, width: u32, height: u32, mut channels: Vec<(String, Vec<f32>)>) -> Result<(), ExrError> {
+    let pixels = width as usize * height as usize;
+    for (name, values) in &channels {
+        if values.len() != pixels {
+            return Err(ExrError::ChannelSize { name: name.clone(), expected: pixels, actual: values.len() });
+        }
+    }
+    // readers expect the channel list sorted by name, scanlines store channels in that order
+    channels.sort_by(|a, b| a.0.cmp(&b.0));
+
+    let mut header = Vec::new();
+    let mut channel_list = Vec::new();
+    for (name, _) in &channels {
+        channel_list.extend_from_slice(name.as_bytes());
+        channel_list.push(0);
+        channel_list.extend_from_slice(&1_i32.to_le_bytes()); // HALF
+        channel_list.extend_from_slice(&[0, 0, 0, 0]); // pLinear and reserved
+        channel_list.extend_from_slice(&1_i32.to_le_bytes()); // x sampling
+        channel_list.extend_from_slice(&1_i32.to_le_bytes()); // y sampling
+    }
+    channel_list.push(0);
+    write_attribute(&mut header, "channels", "chlist", &channel_list);
+    write_attribute(&mut header, "compression", "compression", &[0]); // NO_COMPRESSION
+    let window: Vec<u8> = [0, 0, width as i32 - 1, height as i32 - 1].iter()
+        .flat_map(|v| v.to_le_bytes())
+        .collect();
+    write_attribute(&mut header, "dataWindow", "box2i", &window);
+    write_attribute(&mut header, "displayWindow", "box2i", &window);
+    write_attribute(&mut header, "lineOrder", "lineOrder", &[0]); // INCREASING_Y
+    write_attribute(&mut header, "pixelAspectRatio", "float", &1.0_f32.to_le_bytes());
+    write_attribute(&mut header, "screenWindowCenter", "v2f", &[0; 8]);
+    write_attribute(&mut header, "screenWindowWidth", "float", &1.0_f32.to_le_bytes());
+    header.push(0);
+
+    let mut file = BufWriter::new(File::create(path)?);
+    file.write_all(&20000630_u32.to_le_bytes())?;
+    file.write_all(&2_u32.to_le_bytes())?; // version 2, single part scanline
+    file.write_all(&header)?;
+
+    // one scanline per chunk, the offset table points at each of them
+    let line_size = width as usize * channels.len() * 2;
+    let first_chunk = 8 + header.len() + height as usize * 8;
+    for y in 0..height as usize {
+        let offset = (first_chunk + y * (8 + line_size)) as u64;
+        file.write_all(&offset.to_le_bytes())?;
+    }
+
+    for y in 0..height as usize {
+        file.write_all(&(y as i32).to_le_bytes())?;
+        file.write_all(&(line_size as i32).to_le_bytes())?;
+        for (_, values) in &channels {
+            for value in &values[y * width as usize..(y + 1) * width as usize] {
+                file.write_all(&f16::from_f32(*value).to_le_bytes())?;
+            }
+        }
+    }
+    file.flush()?;
+    Ok(())
+}
+
+/// Reads the `R`, `G`, `B` and `A` channels of an uncompressed scanline OpenEXR file, like the
+/// ones `write` produces. Returns the size and the pixels in row order, missing channels are 0
+/// and a missing alpha is 1.
+pub fn read(path: &str) -> Result<(u32, u32, Vec<[f32; 4]>), ExrError> {
+    let data = std::fs::read(path)?;
+    let invalid = |reason: &str| ExrError::Invalid(reason.to_string());
+    let u32_at = |i: usize| data.get(i..i.checked_add(4)?).map(|b| u32::from_le_bytes(b.try_into().unwrap()));
+    let i32_at = |i: usize| u32_at(i).map(|v| v as i32);
+
+    if u32_at(0) != Some(20000630) {
+        return Err(invalid("not an OpenEXR file"));
+    }
+    // tiled, deep and multi part files set flags above the version number
+    if u32_at(4).ok_or_else(|| invalid("truncated header"))? & !0xff != 0 {
+        return Err(invalid("only single part scanline files are supported"));
+    }
+
+    // (name, pixel type) in the stored order
+    let mut channels = Vec::new();
+    let mut compression = None;
+    let mut window = None;
+    let mut position = 8;
+    let read_string = |position: &mut usize| {
+        let start = *position;
+        let end = start + data.get(start..)?.iter().position(|&b| b == 0)?;
+        *position = end + 1;
+        Some(String::from_utf8_lossy(&data[start..end]).to_string())
+    };
+    loop {
+        let name = read_string(&mut position).ok_or_else(|| invalid("truncated header"))?;
+        if name.is_empty() {
+            break;
+        }
+        let _ty = read_string(&mut position).ok_or_else(|| invalid("truncated header"))?;
+        let size = i32_at(position).ok_or_else(|| invalid("truncated header"))?;
+        position += 4;
+        let size = usize::try_from(size).map_err(|_| invalid("negative attribute size"))?;
+        if size > data.len() - position {
+            return Err(invalid("truncated header"));
+        }
+        let value = position..position + size;
+        position += size;
+        match name.as_str() {
+            "channels" => {
+                let mut p = value.start;
+                while p < value.end && data[p] != 0 {
+                    let channel = read_string(&mut p).ok_or_else(|| invalid("truncated channel list"))?;
+                    let pixel_type = i32_at(p).ok_or_else(|| invalid("truncated channel list"))?;
+                    if i32_at(p + 8) != Some(1) || i32_at(p + 12) != Some(1) {
+                        return Err(invalid("subsampled channels are not supported"));
+                    }
+                    channels.push((channel, pixel_type));
+                    p += 16;
+                }
+            }
+            "compression" => compression = data.get(value.start).copied(),
+            "dataWindow" => {
+                let v: Option<Vec<i32>> = (0..4).map(|i| i32_at(value.start + i * 4)).collect();
+                window = v;
+            }
+            _ => (),
+        }
+    }
+    if compression != Some(0) {
+        return Err(invalid("only uncompressed files are supported"));
+    }
+    let Some([x_min, y_min, x_max, y_max]) = window.as_deref().map(|w| [w[0], w[1], w[2], w[3]]) else {
+        return Err(invalid("missing data window"));
+    };
+    if x_max < x_min || y_max < y_min {
+        return Err(invalid("empty data window"));
+    }
+    let width = (x_max as i64 - x_min as i64 + 1) as usize;
+    let height = (y_max as i64 - y_min as i64 + 1) as usize;
+
+    // UINT is 0, HALF 1 and FLOAT 2
+    let sizes: Vec<usize> = channels.iter().map(|(_, ty)| if *ty == 1 { 2 } else { 4 }).collect();
+    // the pixels have to be in the file, so a damaged window can't make the allocation below huge
+    let line_size = width.checked_mul(sizes.iter().sum()).filter(|&size| size > 0)
+        .ok_or_else(|| invalid("no pixel data"))?;
+    if height.checked_mul(line_size + 16).is_none_or(|size| size > data.len()) {
+        return Err(invalid("data window larger than the file"));
+    }
+    let slots: Vec<Option<usize>> = channels.iter()
+        .map(|(name, _)| ["R", "G", "B", "A"].iter().position(|c| c == name))
+        .collect();
+
+    let mut pixels = vec![[0.0, 0.0, 0.0, 1.0]; width * height];
+    for line in 0..height {
+        let offset = data.get(position + line * 8..position + line * 8 + 8)
+            .map(|b| u64::from_le_bytes(b.try_into().unwrap()) as usize)
+            .ok_or_else(|| invalid("truncated offset table"))?;
+        let y = i32_at(offset).ok_or_else(|| invalid("truncated scanline"))? - y_min;
+        if y < 0 || y as usize >= height {
+            return Err(invalid("scanline outside of the data window"));
+        }
+        let mut p = offset.checked_add(8).ok_or_else(|| invalid("truncated scanline"))?;
+        for ((_, ty), (&size, slot)) in channels.iter().zip(sizes.iter().zip(&slots)) {
+            let values = data.get(p..p.saturating_add(width * size)).ok_or_else(|| invalid("truncated scanline"))?;
+            p += width * size;
+            let Some(slot) = *slot else {
+                continue;
+            };
+            for (x, value) in values.chunks_exact(size).enumerate() {
+                pixels[y as usize * width + x][slot] = match ty {
+                    1 => f16::from_le_bytes([value[0], value[1]]).to_f32(),
+                    2 => f32::from_le_bytes(value.try_into().unwrap()),
+                    _ => u32::from_le_bytes(value.try_into().unwrap()) as f32,
+                };
+            }
+        }
+    }
+    Ok((width as u32, height as u32, pixels))
+}
+
+fn write_attribute(header: &mut Vec<u8>, name: &str, ty: &str, value: &[u8]) {
+    header.extend_from_slice(name.as_bytes());
+    header.push(0);
+    header.extend_from_slice(ty.as_bytes());
+    header.push(0);
+    header.extend_from_slice(&(value.len() as i32).to_le_bytes());
+    header.extend_from_slice(value);
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn temp_path(name: &str) -> String {
+        let path = std::env::temp_dir().join(format!("raytracing_demo_{}_{}.exr", name, std::process::id()));
+        path.to_str().unwrap().to_string()
+    }
+
+    #[test]
+    fn write_read_round_trip() {
+        let path = temp_path("round_trip");
+        // values that are exact in half floats, channels given out of order
+        let channels = vec![
+            ("G".to_string(), vec![0.0, 0.5, 1.0, 2.0, 4.0, 8.0]),
+            ("R".to_string(), vec![1.0, 2.0, 3.0, 4.0, 5.0, 6.0]),
+            ("B".to_string(), vec![-1.0, 0.25, 0.125, 100.0, 0.0, 1024.0]),
+            ("albedo.R".to_string(), vec![0.0; 6]),
+        ];
+        write(&path, 3, 2, channels).unwrap();
+        let result = read(&path);
+        std::fs::remove_file(&path).ok();
+
+        let (width, height, pixels) = result.unwrap();
+        assert_eq!((width, height), (3, 2));
+        assert_eq!(pixels, vec![
+            [1.0, 0.0, -1.0, 1.0],
+            [2.0, 0.5, 0.25, 1.0],
+            [3.0, 1.0, 0.125, 1.0],
+            [4.0, 2.0, 100.0, 1.0],
+            [5.0, 4.0, 0.0, 1.0],
+            [6.0, 8.0, 1024.0, 1.0],
+        ]);
+    }
+
+    #[test]
+    fn write_rejects_wrong_channel_size() {
+        let path = temp_path("channel_size");
+        let result = write(&path, 2, 2, vec![("R".to_string(), vec![0.0; 3])]);
+        assert!(matches!(result, Err(ExrError::ChannelSize { expected: 4, actual: 3, .. })));
+        assert!(!std::path::Path::new(&path).exists());
+    }
+
+    #[test]
+    fn read_rejects_other_files() {
+        let path = temp_path("not_exr");
+        std::fs::write(&path, b"P6 1 1 255 abc").unwrap();
+        let result = read(&path);
+        std::fs::remove_file(&path).ok();
+        assert!(matches!(result, Err(ExrError::Invalid(_))));
+    }
+
+    // writes a small valid file, lets `damage` change its bytes and reads it back
+    fn read_damaged(name: &str, damage: impl FnOnce(&mut Vec<u8>)) -> Result<(u32, u32, Vec<[f32; 4]>), ExrError> {
+        let path = temp_path(name);
+        write(&path, 2, 2, vec![("R".to_string(), vec![1.0; 4])]).unwrap();
+        let mut data = std::fs::read(&path).unwrap();
+        damage(&mut data);
+        std::fs::write(&path, data).unwrap();
+        let result = read(&path);
+        std::fs::remove_file(&path).ok();
+        result
+    }
+
+    fn data_window(data: &[u8]) -> usize {
+        let name = b"dataWindow\0box2i\0";
+        data.windows(name.len()).position(|w| w == name).unwrap() + name.len() + 4
+    }
+
+    #[test]
+    fn read_rejects_negative_attribute_size() {
+        // the size of the first attribute, after the magic number, version, "channels" and "chlist"
+        let result = read_damaged("negative_size", |data| data[24..28].copy_from_slice(&(-8_i32).to_le_bytes()));
+        assert!(matches!(result, Err(ExrError::Invalid(_))));
+        let result = read_damaged("huge_size", |data| data[24..28].copy_from_slice(&i32::MAX.to_le_bytes()));
+        assert!(matches!(result, Err(ExrError::Invalid(_))));
+    }
+
+    #[test]
+    fn read_rejects_truncated_files() {
+        for length in [6, 20, 40, 120] {
+            let result = read_damaged("truncated", |data| data.truncate(length));
+            assert!(matches!(result, Err(ExrError::Invalid(_))), "length {}", length);
+        }
+        let result = read_damaged("truncated_pixels", |data| {
+            data.pop();
+        });
+        assert!(matches!(result, Err(ExrError::Invalid(_))));
+    }
+
+    #[test]
+    fn read_rejects_bad_data_window() {
+        // x_max before x_min
+        let result = read_damaged("inverted_window", |data| {
+            let window = data_window(data);
+            data[window + 8..window + 12].copy_from_slice(&(-2_i32).to_le_bytes());
+        });
+        assert!(matches!(result, Err(ExrError::Invalid(_))));
+        // far more pixels than the file holds
+        let result = read_damaged("huge_window", |data| {
+            let window = data_window(data);
+            data[window..window + 4].copy_from_slice(&i32::MIN.to_le_bytes());
+            data[window + 8..window + 12].copy_from_slice(&i32::MAX.to_le_bytes());
+        });
+        assert!(matches!(result, Err(ExrError::Invalid(_))));
+    }
+}
@@ -1,14 +1,58 @@
+use std::collections::HashMap;
+
+use cgmath::{Euler, InnerSpace, Quaternion, Rad, Vector3, Zero};
+use serde::{Deserialize, Serialize};
 use vulkano::padded::Padded;
+use crate::app::noise::perlin3d;
 use crate::app::shader;
 
-#[derive(Debug, Clone, Copy)]
+/// Moves a circle around its base position following 3D Perlin noise over time.
+#[derive(Debug, Clone, Copy, PartialEq, Serialize, Deserialize)]
+pub struct NoiseAnimation {
+    pub frequency: f32,
+    pub amplitude: [f32; 3],
+    pub phase_offset: f32,
+    pub base_position: [f32; 3],
+}
+
+impl NoiseAnimation {
+    pub fn new(frequency: f32, amplitude: [f32; 3], phase_offset: f32) -> Self {
+        Self {
+            frequency,
+            amplitude,
+            phase_offset,
+            base_position: [0.0, 0.0, 0.0],
+        }
+    }
+
+    pub fn position_at(&self, time: f32) -> [f32; 3] {
+        let p = self.base_position.map(|c| c * self.frequency + time * self.phase_offset);
+        // decorrelate the axes by sampling the noise at different offsets
+        let mut position = self.base_position;
+        for (i, offset) in [0.0, 31.7, 73.3].iter().enumerate() {
+            position[i] += self.amplitude[i] * perlin3d(p[0] + offset, p[1] + offset, p[2] + offset);
+        }
+        position
+    }
+}
+
+impl Default for NoiseAnimation {
+    fn default() -> Self {
+        Self::new(0.5, [0.2, 0.2, 0.2], 0.5)
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Serialize, Deserialize)]
 pub struct Circle {
+    #[serde(skip)]
     pub(in super) index: usize,
+    #[serde(skip)]
     pub(in super) dirty: bool,
 
     pub position: [f32; 3],
     pub radius: f32,
     pub material: i32,
+    pub animation: Option<NoiseAnimation>,
 }
 
 impl Circle {
@@ -19,6 +63,7 @@ impl Circle {
             position: [0.0, 0.0, 0.0],
             radius: 1.0,
             material: 0,
+            animation: None,
         }
     }
 
@@ -40,6 +85,42 @@ impl Circle {
         self.material = material;
         self
     }
+
+    /// Animates the circle around its current position.
+    pub fn animation(&mut self, mut animation: NoiseAnimation) -> &mut Self {
+        animation.base_position = self.position;
+        self.animation = Some(animation);
+        self
+    }
+
+    /// Stops the animation, moving the circle back to its base position.
+    pub fn remove_animation(&mut self) -> &mut Self {
+        if let Some(animation) = self.animation.take() {
+            self.position = animation.base_position;
+            self.dirty = true;
+        }
+        self
+    }
+
+    /// Axis-aligned bounding box, as `(min, max)`.
+    pub fn aabb(&self) -> ([f32; 3], [f32; 3]) {
+        (
+            self.position.map(|c| c - self.radius),
+            self.position.map(|c| c + self.radius),
+        )
+    }
+
+    /// Sphere vs axis-aligned box overlap test, for broad-phase culling.
+    pub fn intersect_aabb(&self, aabb_min: [f32; 3], aabb_max: [f32; 3]) -> bool {
+        let mut distance_squared = 0.0;
+        for i in 0..3 {
+            // closest point of the box to the center
+            let closest = self.position[i].clamp(aabb_min[i], aabb_max[i]);
+            let d = self.position[i] - closest;
+            distance_squared += d * d;
+        }
+        distance_squared < self.radius * self.radius
+    }
 }
 
 impl Default for Circle {
@@ -48,18 +129,789 @@ impl Default for Circle {
     }
 }
 
-impl Into<shader::raytrace::fs::Circle> for Circle {
-    fn into(self) -> shader::raytrace::fs::Circle {
+impl From<Circle> for shader::raytrace::fs::Circle {
+    fn from(value: Circle) -> Self {
         shader::raytrace::fs::Circle {
-            position: self.position,
-            radius: self.radius,
-            material: self.material,
+            position: value.position,
+            radius: value.radius,
+            material: value.material,
         }
     }
 }
 
-impl<const N: usize> Into<::vulkano::padded::Padded<shader::raytrace::fs::Circle, N>> for Circle {
-    fn into(self) -> Padded<shader::raytrace::fs::Circle, N> {
-        Padded(self.into())
+impl<const N: usize> From<Circle> for ::vulkano::padded::Padded<shader::raytrace::fs::Circle, N> {
+    fn from(value: Circle) -> Self {
+        Padded(value.into())
+    }
+}
+
+
+#[derive(Debug, Clone, Copy, PartialEq, Serialize, Deserialize)]
+pub struct Box {
+    #[serde(skip)]
+    pub(in super) index: usize,
+    #[serde(skip)]
+    pub(in super) dirty: bool,
+
+    pub min: [f32; 3],
+    pub max: [f32; 3],
+    pub material: i32,
+}
+
+impl Box {
+    pub fn new() -> Self {
+        Self {
+            index: usize::MAX,
+            dirty: true,
+            min: [-0.5, -0.5, -0.5],
+            max: [0.5, 0.5, 0.5],
+            material: 0,
+        }
+    }
+
+    /// A box no ray can hit, used to fill the buffer when the scene has no boxes.
+    pub(in super) fn empty() -> Self {
+        let mut b = Self::new();
+        b.min = [1.0, 1.0, 1.0];
+        b.max = [-1.0, -1.0, -1.0];
+        b
+    }
+
+    pub fn mark_dirty(&mut self) {
+        self.dirty = true;
+    }
+
+    pub fn min(&mut self, min: [f32; 3]) -> &mut Self {
+        self.min = min;
+        self
+    }
+
+    pub fn max(&mut self, max: [f32; 3]) -> &mut Self {
+        self.max = max;
+        self
+    }
+
+    pub fn material(&mut self, material: i32) -> &mut Self {
+        self.material = material;
+        self
+    }
+}
+
+impl Default for Box {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl From<Box> for shader::raytrace::fs::Box {
+    fn from(value: Box) -> Self {
+        shader::raytrace::fs::Box {
+            min: value.min,
+            max: value.max,
+            material: value.material,
+        }
+    }
+}
+
+impl<const N: usize> From<Box> for ::vulkano::padded::Padded<shader::raytrace::fs::Box, N> {
+    fn from(value: Box) -> Self {
+        Padded(value.into())
+    }
+}
+
+/// Infinite plane of the points `p` with `dot(p, normal) == distance`.
+#[derive(Debug, Clone, Copy, PartialEq, Serialize, Deserialize)]
+pub struct Plane {
+    #[serde(skip)]
+    pub(in super) index: usize,
+    #[serde(skip)]
+    pub(in super) dirty: bool,
+
+    pub normal: [f32; 3],
+    pub distance: f32,
+    pub material: i32,
+}
+
+impl Plane {
+    pub fn new() -> Self {
+        Self {
+            index: usize::MAX,
+            dirty: true,
+            normal: [0.0, 1.0, 0.0],
+            distance: 0.0,
+            material: 0,
+        }
+    }
+
+    /// A plane no ray can hit, used to fill the buffer when the scene has no planes.
+    pub(in super) fn empty() -> Self {
+        let mut p = Self::new();
+        p.normal = [0.0, 0.0, 0.0];
+        p
+    }
+
+    pub fn mark_dirty(&mut self) {
+        self.dirty = true;
+    }
+
+    pub fn normal(&mut self, normal: [f32; 3]) -> &mut Self {
+        self.normal = normal;
+        self
+    }
+
+    pub fn distance(&mut self, distance: f32) -> &mut Self {
+        self.distance = distance;
+        self
+    }
+
+    pub fn material(&mut self, material: i32) -> &mut Self {
+        self.material = material;
+        self
+    }
+}
+
+impl Default for Plane {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl From<Plane> for shader::raytrace::fs::Plane {
+    fn from(value: Plane) -> Self {
+        shader::raytrace::fs::Plane {
+            normal: value.normal,
+            distance: value.distance,
+            material: value.material,
+        }
+    }
+}
+
+impl<const N: usize> From<Plane> for ::vulkano::padded::Padded<shader::raytrace::fs::Plane, N> {
+    fn from(value: Plane) -> Self {
+        Padded(value.into())
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Serialize, Deserialize)]
+pub struct Triangle {
+    #[serde(skip)]
+    pub(in super) index: usize,
+    #[serde(skip)]
+    pub(in super) dirty: bool,
+
+    pub v0: [f32; 3],
+    pub v1: [f32; 3],
+    pub v2: [f32; 3],
+    pub normal: [f32; 3],
+    /// Normals at v0, v1 and v2, interpolated over the face when `smooth_normals` is set.
+    #[serde(default)]
+    pub normals: [[f32; 3]; 3],
+    #[serde(default)]
+    pub smooth_normals: bool,
+    /// Triangles added by the same `add_mesh` or file load share this id.
+    #[serde(default)]
+    pub mesh: u32,
+    pub material: i32,
+}
+
+impl Triangle {
+    /// Triangle with the normal given by the winding order, counter-clockwise facing front.
+    pub fn new(v0: [f32; 3], v1: [f32; 3], v2: [f32; 3]) -> Self {
+        let normal = face_normal(v0, v1, v2);
+        Self {
+            index: usize::MAX,
+            dirty: true,
+            v0,
+            v1,
+            v2,
+            normal,
+            normals: [normal; 3],
+            smooth_normals: false,
+            mesh: 0,
+            material: 0,
+        }
+    }
+
+    pub fn mark_dirty(&mut self) {
+        self.dirty = true;
+    }
+
+    pub fn vertices(&mut self, v0: [f32; 3], v1: [f32; 3], v2: [f32; 3]) -> &mut Self {
+        self.v0 = v0;
+        self.v1 = v1;
+        self.v2 = v2;
+        self.normal = face_normal(v0, v1, v2);
+        self.normals = [self.normal; 3];
+        self
+    }
+
+    pub fn normal(&mut self, normal: [f32; 3]) -> &mut Self {
+        self.normal = normal;
+        self
+    }
+
+    pub fn vertex_normals(&mut self, n0: [f32; 3], n1: [f32; 3], n2: [f32; 3]) -> &mut Self {
+        self.normals = [n0, n1, n2];
+        self
+    }
+
+    /// Interpolates the vertex normals over the face instead of using the flat `normal`.
+    pub fn smooth_normals(&mut self, smooth_normals: bool) -> &mut Self {
+        self.smooth_normals = smooth_normals;
+        self
+    }
+
+    pub fn mesh(&mut self, mesh: u32) -> &mut Self {
+        self.mesh = mesh;
+        self
+    }
+
+    pub fn material(&mut self, material: i32) -> &mut Self {
+        self.material = material;
+        self
+    }
+}
+
+impl Default for Triangle {
+    fn default() -> Self {
+        Self::new([0.0, 0.0, 0.0], [1.0, 0.0, 0.0], [0.0, 1.0, 0.0])
+    }
+}
+
+/// Sets angle weighted vertex normals on `triangles` and turns `smooth_normals` on. Vertices at
+/// the same position are treated as shared, so the faces around them are blended.
+pub fn compute_smooth_normals<'a>(triangles: impl Iterator<Item = &'a mut Triangle>) {
+    let triangles: Vec<&mut Triangle> = triangles.collect();
+    let key = |v: [f32; 3]| v.map(f32::to_bits);
+
+    let mut sums: HashMap<[u32; 3], Vector3<f32>> = HashMap::new();
+    for t in &triangles {
+        let corners = [Vector3::from(t.v0), Vector3::from(t.v1), Vector3::from(t.v2)];
+        let normal = Vector3::from(face_normal(t.v0, t.v1, t.v2));
+        for i in 0..3 {
+            let a = corners[(i + 1) % 3] - corners[i];
+            let b = corners[(i + 2) % 3] - corners[i];
+            if a.magnitude2() == 0.0 || b.magnitude2() == 0.0 {
+                continue;
+            }
+            let angle = a.angle(b).0;
+            *sums.entry(key(corners[i].into())).or_insert(Vector3::zero()) += normal * angle;
+        }
+    }
+
+    for t in triangles {
+        let flat = t.normal;
+        let normal = |v: [f32; 3]| match sums.get(&key(v)) {
+            Some(sum) if sum.magnitude2() > 0.0 => sum.normalize().into(),
+            _ => flat,
+        };
+        t.normals = [normal(t.v0), normal(t.v1), normal(t.v2)];
+        t.smooth_normals = true;
+        t.mark_dirty();
+    }
+}
+
+fn face_normal(v0: [f32; 3], v1: [f32; 3], v2: [f32; 3]) -> [f32; 3] {
+    let (v0, v1, v2) = (Vector3::from(v0), Vector3::from(v1), Vector3::from(v2));
+    let normal = (v1 - v0).cross(v2 - v0);
+    if normal.magnitude2() == 0.0 {
+        return [0.0, 0.0, 0.0];
+    }
+    normal.normalize().into()
+}
+
+impl From<Triangle> for shader::raytrace::fs::Triangle {
+    fn from(value: Triangle) -> Self {
+        shader::raytrace::fs::Triangle {
+            v0: value.v0,
+            material: value.material,
+            v1: value.v1,
+            v2: value.v2.into(),
+            normal: value.normal.into(),
+            n0: value.normals[0].into(),
+            n1: value.normals[1].into(),
+            n2: value.normals[2],
+            smooth_normals: value.smooth_normals as i32,
+        }
+    }
+}
+
+impl<const N: usize> From<Triangle> for ::vulkano::padded::Padded<shader::raytrace::fs::Triangle, N> {
+    fn from(value: Triangle) -> Self {
+        Padded(value.into())
+    }
+}
+
+/// Capped cylinder standing on the y axis, `position` is the center of its bottom cap.
+#[derive(Debug, Clone, Copy, PartialEq, Serialize, Deserialize)]
+pub struct Cylinder {
+    #[serde(skip)]
+    pub(in super) index: usize,
+    #[serde(skip)]
+    pub(in super) dirty: bool,
+
+    pub position: [f32; 3],
+    pub radius: f32,
+    pub height: f32,
+    pub material: i32,
+}
+
+impl Cylinder {
+    pub fn new() -> Self {
+        Self {
+            index: usize::MAX,
+            dirty: true,
+            position: [0.0, 0.0, 0.0],
+            radius: 0.25,
+            height: 1.0,
+            material: 0,
+        }
+    }
+
+    /// A cylinder no ray can hit, used to fill the buffer when the scene has no cylinders.
+    pub(in super) fn empty() -> Self {
+        let mut c = Self::new();
+        c.radius = 0.0;
+        c.height = 0.0;
+        c
+    }
+
+    pub fn mark_dirty(&mut self) {
+        self.dirty = true;
+    }
+
+    pub fn position(&mut self, position: [f32; 3]) -> &mut Self {
+        self.position = position;
+        self
+    }
+
+    pub fn radius(&mut self, radius: f32) -> &mut Self {
+        self.radius = radius;
+        self
+    }
+
+    pub fn height(&mut self, height: f32) -> &mut Self {
+        self.height = height;
+        self
+    }
+
+    pub fn material(&mut self, material: i32) -> &mut Self {
+        self.material = material;
+        self
+    }
+}
+
+impl Default for Cylinder {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl From<Cylinder> for shader::raytrace::fs::Cylinder {
+    fn from(value: Cylinder) -> Self {
+        shader::raytrace::fs::Cylinder {
+            position: value.position,
+            radius: value.radius,
+            height: value.height,
+            material: value.material,
+        }
+    }
+}
+
+impl<const N: usize> From<Cylinder> for ::vulkano::padded::Padded<shader::raytrace::fs::Cylinder, N> {
+    fn from(value: Cylinder) -> Self {
+        Padded(value.into())
+    }
+}
+
+/// Points within `radius` of the segment from `start` to `end`, a cylinder with hemispheres at
+/// both ends.
+#[derive(Debug, Clone, Copy, PartialEq, Serialize, Deserialize)]
+pub struct Capsule {
+    #[serde(skip)]
+    pub(in super) index: usize,
+    #[serde(skip)]
+    pub(in super) dirty: bool,
+
+    pub start: [f32; 3],
+    pub end: [f32; 3],
+    pub radius: f32,
+    pub material: i32,
+}
+
+impl Capsule {
+    pub fn new() -> Self {
+        Self {
+            index: usize::MAX,
+            dirty: true,
+            start: [0.0, 0.0, 0.0],
+            end: [0.0, 1.0, 0.0],
+            radius: 0.25,
+            material: 0,
+        }
+    }
+
+    /// A capsule no ray can hit, used to fill the buffer when the scene has no capsules.
+    pub(in super) fn empty() -> Self {
+        let mut c = Self::new();
+        c.end = c.start;
+        c.radius = 0.0;
+        c
+    }
+
+    pub fn mark_dirty(&mut self) {
+        self.dirty = true;
+    }
+
+    pub fn start(&mut self, start: [f32; 3]) -> &mut Self {
+        self.start = start;
+        self
+    }
+
+    pub fn end(&mut self, end: [f32; 3]) -> &mut Self {
+        self.end = end;
+        self
+    }
+
+    pub fn radius(&mut self, radius: f32) -> &mut Self {
+        self.radius = radius;
+        self
+    }
+
+    pub fn material(&mut self, material: i32) -> &mut Self {
+        self.material = material;
+        self
+    }
+}
+
+impl Default for Capsule {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl From<Capsule> for shader::raytrace::fs::Capsule {
+    fn from(value: Capsule) -> Self {
+        shader::raytrace::fs::Capsule {
+            start: value.start,
+            radius: value.radius,
+            end: value.end,
+            material: value.material,
+        }
+    }
+}
+
+impl<const N: usize> From<Capsule> for ::vulkano::padded::Padded<shader::raytrace::fs::Capsule, N> {
+    fn from(value: Capsule) -> Self {
+        Padded(value.into())
+    }
+}
+
+/// Ring of the points `minor_radius` away from the circle of `major_radius` around `center`. The
+/// circle lies in the xz plane before `orientation` rotates it.
+#[derive(Debug, Clone, Copy, PartialEq, Serialize, Deserialize)]
+pub struct Torus {
+    #[serde(skip)]
+    pub(in super) index: usize,
+    #[serde(skip)]
+    pub(in super) dirty: bool,
+
+    pub center: [f32; 3],
+    pub major_radius: f32,
+    pub minor_radius: f32,
+    /// Unit quaternion as `[x, y, z, w]`.
+    pub orientation: [f32; 4],
+    pub material: i32,
+}
+
+impl Torus {
+    pub fn new() -> Self {
+        Self {
+            index: usize::MAX,
+            dirty: true,
+            center: [0.0, 0.0, 0.0],
+            major_radius: 0.5,
+            minor_radius: 0.15,
+            orientation: [0.0, 0.0, 0.0, 1.0],
+            material: 0,
+        }
+    }
+
+    /// A torus no ray can hit, used to fill the buffer when the scene has no tori.
+    pub(in super) fn empty() -> Self {
+        let mut t = Self::new();
+        t.major_radius = 0.0;
+        t.minor_radius = 0.0;
+        t
+    }
+
+    pub fn mark_dirty(&mut self) {
+        self.dirty = true;
+    }
+
+    pub fn center(&mut self, center: [f32; 3]) -> &mut Self {
+        self.center = center;
+        self
+    }
+
+    pub fn major_radius(&mut self, major_radius: f32) -> &mut Self {
+        self.major_radius = major_radius;
+        self
+    }
+
+    pub fn minor_radius(&mut self, minor_radius: f32) -> &mut Self {
+        self.minor_radius = minor_radius;
+        self
+    }
+
+    /// Normalized, so any non zero quaternion can be passed.
+    pub fn orientation(&mut self, orientation: [f32; 4]) -> &mut Self {
+        let [x, y, z, w] = orientation;
+        let q = Quaternion::new(w, x, y, z).normalize();
+        self.orientation = [q.v.x, q.v.y, q.v.z, q.s];
+        self
+    }
+
+    /// Sets the orientation from angles in radians around x, y and z, as a cgmath `Euler`.
+    pub fn rotation(&mut self, angles: [f32; 3]) -> &mut Self {
+        let q = Quaternion::from(Euler::new(Rad(angles[0]), Rad(angles[1]), Rad(angles[2])));
+        self.orientation([q.v.x, q.v.y, q.v.z, q.s])
+    }
+
+    /// The angles `rotation` takes for the current orientation.
+    pub fn rotation_angles(&self) -> [f32; 3] {
+        let [x, y, z, w] = self.orientation;
+        let euler = Euler::from(Quaternion::new(w, x, y, z));
+        [euler.x.0, euler.y.0, euler.z.0]
+    }
+
+    pub fn material(&mut self, material: i32) -> &mut Self {
+        self.material = material;
+        self
+    }
+}
+
+impl Default for Torus {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl From<Torus> for shader::raytrace::fs::Torus {
+    fn from(value: Torus) -> Self {
+        shader::raytrace::fs::Torus {
+            orientation: value.orientation,
+            center: value.center,
+            major_radius: value.major_radius,
+            minor_radius: value.minor_radius,
+            material: value.material,
+        }
+    }
+}
+
+impl<const N: usize> From<Torus> for ::vulkano::padded::Padded<shader::raytrace::fs::Torus, N> {
+    fn from(value: Torus) -> Self {
+        Padded(value.into())
+    }
+}
+/// Infinitely distant light like the sun, seen by the paths that leave the scene within a small
+/// cone around it.
+#[derive(Debug, Clone, Copy, PartialEq, Serialize, Deserialize)]
+pub struct DirectionalLight {
+    #[serde(skip)]
+    pub(in super) index: usize,
+    #[serde(skip)]
+    pub(in super) dirty: bool,
+
+    /// Direction the light travels in, from the light towards the scene.
+    pub direction: [f32; 3],
+    pub color: [f32; 3],
+    pub intensity: f32,
+}
+
+impl DirectionalLight {
+    pub fn new() -> Self {
+        Self {
+            index: usize::MAX,
+            dirty: true,
+            direction: [0.0, -1.0, 0.0],
+            color: [1.0, 1.0, 1.0],
+            intensity: 1.0,
+        }
+    }
+
+    /// A light that adds nothing, used to fill the buffer when the scene has no lights.
+    pub(in super) fn empty() -> Self {
+        let mut l = Self::new();
+        l.intensity = 0.0;
+        l
+    }
+
+    pub fn mark_dirty(&mut self) {
+        self.dirty = true;
+    }
+
+    /// Normalized, a zero vector is ignored.
+    pub fn direction(&mut self, direction: [f32; 3]) -> &mut Self {
+        let direction = Vector3::from(direction);
+        if direction.magnitude2() > 0.0 {
+            self.direction = direction.normalize().into();
+        }
+        self
+    }
+
+    pub fn color(&mut self, color: [f32; 3]) -> &mut Self {
+        self.color = color;
+        self
+    }
+
+    pub fn intensity(&mut self, intensity: f32) -> &mut Self {
+        self.intensity = intensity;
+        self
+    }
+}
+
+impl Default for DirectionalLight {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl From<DirectionalLight> for shader::raytrace::fs::DirectionalLight {
+    fn from(value: DirectionalLight) -> Self {
+        shader::raytrace::fs::DirectionalLight {
+            direction: value.direction,
+            intensity: value.intensity,
+            color: value.color,
+        }
+    }
+}
+
+impl<const N: usize> From<DirectionalLight> for ::vulkano::padded::Padded<shader::raytrace::fs::DirectionalLight, N> {
+    fn from(value: DirectionalLight) -> Self {
+        Padded(value.into())
+    }
+}
+
+/// Axis aligned box of fog, smoke or cloud with the same density everywhere inside.
+#[derive(Debug, Clone, Copy, PartialEq, Serialize, Deserialize)]
+pub struct HomogeneousVolume {
+    #[serde(skip)]
+    pub(in super) index: usize,
+    #[serde(skip)]
+    pub(in super) dirty: bool,
+
+    pub bounds_min: [f32; 3],
+    pub bounds_max: [f32; 3],
+    /// Scales `absorption` and `scattering`.
+    pub density: f32,
+    /// Share of each channel absorbed per unit of distance at density 1.
+    pub absorption: [f32; 3],
+    /// Share of each channel scattered per unit of distance at density 1.
+    pub scattering: [f32; 3],
+    /// Henyey-Greenstein g, from -1 (back scattering) to 1 (forward scattering), 0 is isotropic.
+    pub anisotropy: f32,
+}
+
+impl HomogeneousVolume {
+    pub fn new() -> Self {
+        Self {
+            index: usize::MAX,
+            dirty: true,
+            bounds_min: [-1.0, -1.0, -1.0],
+            bounds_max: [1.0, 1.0, 1.0],
+            density: 0.5,
+            absorption: [0.1, 0.1, 0.1],
+            scattering: [0.9, 0.9, 0.9],
+            anisotropy: 0.0,
+        }
+    }
+
+    /// A volume that scatters nothing, used to fill the buffer when the scene has no volumes.
+    pub(in super) fn empty() -> Self {
+        let mut v = Self::new();
+        v.density = 0.0;
+        v
+    }
+
+    pub fn mark_dirty(&mut self) {
+        self.dirty = true;
+    }
+
+    /// Any two opposite corners.
+    pub fn bounds(&mut self, a: [f32; 3], b: [f32; 3]) -> &mut Self {
+        self.bounds_min = [a[0].min(b[0]), a[1].min(b[1]), a[2].min(b[2])];
+        self.bounds_max = [a[0].max(b[0]), a[1].max(b[1]), a[2].max(b[2])];
+        self
+    }
+
+    pub fn density(&mut self, density: f32) -> &mut Self {
+        self.density = density;
+        self
+    }
+
+    pub fn absorption(&mut self, absorption: [f32; 3]) -> &mut Self {
+        self.absorption = absorption;
+        self
+    }
+
+    pub fn scattering(&mut self, scattering: [f32; 3]) -> &mut Self {
+        self.scattering = scattering;
+        self
+    }
+
+    /// Clamped to the open range (-1, 1).
+    pub fn anisotropy(&mut self, anisotropy: f32) -> &mut Self {
+        self.anisotropy = anisotropy.clamp(-0.99, 0.99);
+        self
+    }
+}
+
+impl Default for HomogeneousVolume {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl From<HomogeneousVolume> for shader::raytrace::fs::Volume {
+    fn from(value: HomogeneousVolume) -> Self {
+        shader::raytrace::fs::Volume {
+            bounds_min: value.bounds_min,
+            density: value.density,
+            bounds_max: value.bounds_max,
+            anisotropy: value.anisotropy,
+            absorption: value.absorption.into(),
+            scattering: value.scattering,
+        }
+    }
+}
+
+impl<const N: usize> From<HomogeneousVolume> for ::vulkano::padded::Padded<shader::raytrace::fs::Volume, N> {
+    fn from(value: HomogeneousVolume) -> Self {
+        Padded(value.into())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn smooth_normals_blend_faces_sharing_a_vertex() {
+        // two faces of a roof meeting at the ridge from x = 0 to x = 1
+        let mut triangles = [
+            Triangle::new([0.0, 1.0, 0.0], [0.0, 0.0, 1.0], [1.0, 1.0, 0.0]),
+            Triangle::new([0.0, 1.0, 0.0], [1.0, 1.0, 0.0], [0.0, 0.0, -1.0]),
+        ];
+        compute_smooth_normals(triangles.iter_mut());
+
+        for t in &triangles {
+            assert!(t.smooth_normals);
+        }
+        // on the ridge the two slopes average to straight up
+        let ridge = Vector3::from(triangles[0].normals[0]);
+        assert!((ridge - Vector3::new(0.0, 1.0, 0.0)).magnitude() < 1e-5, "{:?}", ridge);
+        // the eaves belong to a single face and keep its normal
+        assert_eq!(triangles[0].normals[1], triangles[0].normal);
     }
 }
@@ -0,0 +1,213 @@
+use std::fmt;
+use std::path::Path;
+
+use cgmath::{Matrix4, Vector4};
+
+use crate::app::geom::Triangle;
+use crate::app::material::Material;
+
+#[derive(Debug)]
+pub enum GltfError {
+    Gltf(::gltf::Error),
+    Invalid(String),
+}
+
+impl fmt::Display for GltfError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Self::Gltf(e) => write!(f, "Could not read GLTF file: {}", e),
+            Self::Invalid(message) => write!(f, "Invalid GLTF: {}", message),
+        }
+    }
+}
+
+impl std::error::Error for GltfError {}
+
+impl From<::gltf::Error> for GltfError {
+    fn from(e: ::gltf::Error) -> Self {
+        Self::Gltf(e)
+    }
+}
+
+#[derive(Debug, Clone, Default)]
+pub struct GltfLoadReport {
+    pub meshes: usize,
+    pub materials: usize,
+    pub triangles: usize,
+    pub warnings: Vec<String>,
+}
+
+/// Geometry and materials read from a GLTF file, material indices start at `first_material`.
+pub struct GltfScene {
+    pub triangles: Vec<Triangle>,
+    pub materials: Vec<Material>,
+    pub report: GltfLoadReport,
+}
+
+/// Loads a `.gltf` or `.glb` file, with node transforms baked into the triangles.
+///
+/// Only triangle primitives and the material factors are used, textures are skipped.
+pub fn load(path: &str, first_material: usize) -> Result<GltfScene, GltfError> {
+    let bytes = std::fs::read(path).map_err(::gltf::Error::Io)?;
+    let ::gltf::Gltf { document, blob } = ::gltf::Gltf::from_slice_without_validation(&bytes)?;
+    // the validation of the POSITION bounds indexes the accessors without checking them first
+    let json = document.into_json();
+    let accessors = json.accessors.len();
+    for (i, mesh) in json.meshes.iter().enumerate() {
+        if mesh.primitives.iter().flat_map(|p| p.attributes.values()).any(|a| a.value() >= accessors) {
+            return Err(GltfError::Invalid(format!("mesh {} uses an accessor that does not exist", i)));
+        }
+    }
+    let document = ::gltf::Document::from_json(json)?;
+    let base = Path::new(path).parent().unwrap_or(Path::new(""));
+    // the images are not used, so only the buffers are read
+    let buffers = ::gltf::import_buffers(&document, Some(base), blob)?;
+
+    let mut report = GltfLoadReport::default();
+
+    let materials: Vec<Material> = document.materials().map(|def| {
+        let pbr = def.pbr_metallic_roughness();
+        let base_color = pbr.base_color_factor();
+
+        let has_texture = pbr.base_color_texture().is_some()
+            || pbr.metallic_roughness_texture().is_some()
+            || def.normal_texture().is_some()
+            || def.emissive_texture().is_some()
+            || def.occlusion_texture().is_some();
+        let i = def.index().unwrap_or_default();
+        if has_texture {
+            report.warnings.push(format!("material {} uses textures, only its factors were loaded", i));
+        }
+
+        let mut m = Material::metalness_workflow([base_color[0], base_color[1], base_color[2]], pbr.metallic_factor(), pbr.roughness_factor());
+        m.emission(def.emissive_factor());
+        match def.name() {
+            Some(name) => m.name(name),
+            None => m.name(&format!("Material {}", first_material + i)),
+        };
+        m
+    }).collect();
+    report.materials = materials.len();
+
+    // nodes that are nobody's child are the roots when the file has no scene
+    let roots: Vec<_> = match document.default_scene().or_else(|| document.scenes().next()) {
+        Some(scene) => scene.nodes().collect(),
+        None => document.nodes()
+            .filter(|node| !document.nodes().any(|n| n.children().any(|c| c.index() == node.index())))
+            .collect(),
+    };
+
+    let mut loaded_meshes = vec![false; document.meshes().len()];
+    // nodes have a single parent, one reached twice is in a cycle or shared
+    let mut visited = vec![false; document.nodes().len()];
+    let mut triangles = Vec::new();
+    let mut degenerate = 0;
+    let mut stack: Vec<_> = roots.into_iter().map(|n| (n, Matrix4::from_scale(1.0))).collect();
+    while let Some((node, parent)) = stack.pop() {
+        if std::mem::replace(&mut visited[node.index()], true) {
+            return Err(GltfError::Invalid(format!("node {} is reached more than once, the node graph is not a tree", node.index())));
+        }
+        let transform = parent * Matrix4::from(node.transform().matrix());
+        stack.extend(node.children().map(|c| (c, transform)));
+
+        let Some(mesh) = node.mesh() else { continue };
+        loaded_meshes[mesh.index()] = true;
+
+        for primitive in mesh.primitives() {
+            if primitive.mode() != ::gltf::mesh::Mode::Triangles {
+                report.warnings.push(format!("mesh {} has a non-triangle primitive, skipped", mesh.index()));
+                continue;
+            }
+            let reader = primitive.reader(|buffer| buffers.get(buffer.index()).map(|data| &data[..]));
+            let positions: Vec<[f32; 3]> = reader.read_positions()
+                .ok_or_else(|| GltfError::Invalid(format!("mesh {} has a primitive without readable positions", mesh.index())))?
+                .collect();
+            let indices: Vec<u32> = match reader.read_indices() {
+                Some(indices) => indices.into_u32().collect(),
+                None => (0..positions.len() as u32).collect(),
+            };
+            let material = primitive.material().index().map_or(0, |m| first_material + m) as i32;
+
+            let vertex = |i: u32| -> Result<[f32; 3], GltfError> {
+                let p = positions.get(i as usize)
+                    .ok_or_else(|| GltfError::Invalid(format!("mesh {} has an index out of range", mesh.index())))?;
+                let p = transform * Vector4::new(p[0], p[1], p[2], 1.0);
+                Ok([p.x, p.y, p.z])
+            };
+            for face in indices.chunks_exact(3) {
+                let mut t = Triangle::new(vertex(face[0])?, vertex(face[1])?, vertex(face[2])?);
+                if t.normal == [0.0; 3] {
+                    degenerate += 1;
+                    continue;
+                }
+                t.material(material);
+                triangles.push(t);
+            }
+        }
+    }
+
+    if degenerate > 0 {
+        report.warnings.push(format!("skipped {} degenerate triangles", degenerate));
+    }
+    report.meshes = loaded_meshes.iter().filter(|&&l| l).count();
+    report.triangles = triangles.len();
+
+    Ok(GltfScene { triangles, materials, report })
+}
+
+#[cfg(test)]
+mod tests {
+    use std::fs;
+
+    use super::*;
+
+    // a triangle in the z = 0 plane, three float VEC3 positions as base64
+    const TRIANGLE_BUFFER: &str = "data:application/octet-stream;base64,AAAAAAAAAAAAAAAAAACAPwAAAAAAAAAAAAAAAAAAgD8AAAAA";
+
+    fn load_json(name: &str, json: &str) -> Result<GltfScene, GltfError> {
+        let path = std::env::temp_dir().join(format!("raytracing_demo_{}_{}.gltf", name, std::process::id()));
+        fs::write(&path, json).unwrap();
+        let result = load(path.to_str().unwrap(), 0);
+        fs::remove_file(&path).ok();
+        result
+    }
+
+    fn triangle_document(nodes: &str, position_accessor: usize) -> String {
+        format!(
+            r#"{{
+                "asset": {{ "version": "2.0" }},
+                "scenes": [{{ "nodes": [0] }}],
+                "nodes": {},
+                "meshes": [{{ "primitives": [{{ "attributes": {{ "POSITION": {} }} }}] }}],
+                "accessors": [{{
+                    "bufferView": 0, "componentType": 5126, "count": 3, "type": "VEC3",
+                    "min": [0.0, 0.0, 0.0], "max": [1.0, 1.0, 0.0]
+                }}],
+                "bufferViews": [{{ "buffer": 0, "byteLength": 36 }}],
+                "buffers": [{{ "uri": "{}", "byteLength": 36 }}]
+            }}"#,
+            nodes, position_accessor, TRIANGLE_BUFFER,
+        )
+    }
+
+    #[test]
+    fn loads_a_triangle() {
+        let scene = load_json("triangle", &triangle_document(r#"[{ "mesh": 0 }]"#, 0)).unwrap();
+        assert_eq!(scene.triangles.len(), 1);
+        let t = scene.triangles[0];
+        assert_eq!([t.v0, t.v1, t.v2], [[0.0, 0.0, 0.0], [1.0, 0.0, 0.0], [0.0, 1.0, 0.0]]);
+    }
+
+    #[test]
+    fn missing_accessor_is_an_error() {
+        let result = load_json("missing_accessor", &triangle_document(r#"[{ "mesh": 0 }]"#, 7));
+        assert!(matches!(result, Err(GltfError::Invalid(_))));
+    }
+
+    #[test]
+    fn node_cycle_is_an_error() {
+        let nodes = r#"[{ "mesh": 0, "children": [1] }, { "children": [0] }]"#;
+        let result = load_json("node_cycle", &triangle_document(nodes, 0));
+        assert!(matches!(result, Err(GltfError::Invalid(_))));
+    }
+}
@@ -1,14 +1,48 @@
+use imgui::TextureId;
+use serde::{Deserialize, Serialize};
 use vulkano::padded::Padded;
 use crate::app::shader;
 
-#[derive(Debug, Clone, Copy)]
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+#[serde(default)]
 pub struct Material {
+    #[serde(skip)]
     pub(in super) index: usize,
+    #[serde(skip)]
     pub(in super) dirty: bool,
 
+    /// Only shown in the UI, not uploaded.
+    pub name: String,
+
     pub color: [f32; 3],
+    /// Alternates with `color` in the checker pattern.
+    pub color2: [f32; 3],
+    /// Checker squares per unit of the surface uv, 0.0 is a flat `color`.
+    pub checker_scale: f32,
+    /// Tiling of the textures and the checker pattern, the surface uv is scaled before offset.
+    pub uv_scale: [f32; 2],
+    pub uv_offset: [f32; 2],
+    /// Frequency of the Perlin noise over world space positions.
+    pub noise_scale: f32,
+    /// How far the noise blends towards `color2`, 0.0 disables it.
+    pub noise_strength: f32,
     pub emission: [f32; 3],
+    /// Multiplies `emission`, so the color and the power of a light can be set apart.
+    pub emission_strength: f32,
     pub smoothness: f32,
+    pub metallic: f32,
+    pub f0: [f32; 3],
+    /// Index of refraction of transmitted rays, 1.0 doesn't bend them.
+    pub ior: f32,
+    /// Share of the light that enters the material, 0.0 is opaque. Inside, `color` sets how
+    /// much of each channel survives per unit of distance.
+    pub transmission: f32,
+    /// Replaces `color` where set, see `App::add_texture`. Textures are not saved with the scene.
+    #[serde(skip)]
+    pub albedo_texture: Option<TextureId>,
+    /// Tangent space normal map, see `App::add_linear_texture`.
+    #[serde(skip)]
+    pub normal_texture: Option<TextureId>,
 }
 
 impl Material {
@@ -16,30 +50,181 @@ impl Material {
         Self {
             index: usize::MAX,
             dirty: true,
+            name: "Material".to_string(),
             color: [1.0, 1.0, 1.0],
+            color2: [0.0, 0.0, 0.0],
+            checker_scale: 0.0,
+            uv_scale: [1.0, 1.0],
+            uv_offset: [0.0, 0.0],
+            noise_scale: 1.0,
+            noise_strength: 0.0,
             emission: [0.0, 0.0, 0.0],
+            emission_strength: 1.0,
             smoothness: 0.5,
+            metallic: 0.0,
+            f0: [0.04, 0.04, 0.04],
+            ior: 1.0,
+            transmission: 0.0,
+            albedo_texture: None,
+            normal_texture: None,
         }
     }
 
+    /// Creates a material from the PBR metalness workflow parameters.
+    pub fn metalness_workflow(base_color: [f32; 3], metalness: f32, roughness: f32) -> Self {
+        let mut m = Self::new();
+        m.metalness(base_color, metalness, roughness);
+        m
+    }
+
     pub fn mark_dirty(&mut self) {
         self.dirty = true;
     }
 
+    pub fn name(&mut self, name: &str) -> &mut Self {
+        self.name = name.to_string();
+        self
+    }
+
     pub fn color(&mut self, color: [f32; 3]) -> &mut Self {
         self.color = color;
         self
     }
 
+    pub fn color2(&mut self, color2: [f32; 3]) -> &mut Self {
+        self.color2 = color2;
+        self
+    }
+
+    pub fn checker_scale(&mut self, checker_scale: f32) -> &mut Self {
+        self.checker_scale = checker_scale;
+        self
+    }
+
+    pub fn uv_scale(&mut self, uv_scale: [f32; 2]) -> &mut Self {
+        self.uv_scale = uv_scale;
+        self
+    }
+
+    pub fn uv_offset(&mut self, uv_offset: [f32; 2]) -> &mut Self {
+        self.uv_offset = uv_offset;
+        self
+    }
+
+    pub fn noise_scale(&mut self, noise_scale: f32) -> &mut Self {
+        self.noise_scale = noise_scale;
+        self
+    }
+
+    pub fn noise_strength(&mut self, noise_strength: f32) -> &mut Self {
+        self.noise_strength = noise_strength;
+        self
+    }
+
     pub fn emission(&mut self, emission: [f32; 3]) -> &mut Self {
         self.emission = emission;
         self
     }
 
+    pub fn emission_strength(&mut self, strength: f32) -> &mut Self {
+        self.emission_strength = strength;
+        self
+    }
+
     pub fn smoothness(&mut self, smoothness: f32) -> &mut Self {
         self.smoothness = smoothness;
         self
     }
+
+    /// 0 is a dielectric (diffuse plus a specular with `f0` reflectance), 1 a metal whose
+    /// specular is tinted by `color`. Values in between blend the two, as in glTF 2.0.
+    pub fn metallic(&mut self, metallic: f32) -> &mut Self {
+        self.metallic = metallic;
+        self
+    }
+
+    pub fn ior(&mut self, ior: f32) -> &mut Self {
+        self.ior = ior;
+        self
+    }
+
+    pub fn transmission(&mut self, transmission: f32) -> &mut Self {
+        self.transmission = transmission;
+        self
+    }
+
+    pub fn set_albedo_texture(&mut self, id: TextureId) -> &mut Self {
+        self.albedo_texture = Some(id);
+        self
+    }
+
+    pub fn normal_texture(&mut self, id: TextureId) -> &mut Self {
+        self.normal_texture = Some(id);
+        self
+    }
+
+    /// Sets color, emission, smoothness, metallic and F0 from a base color, metalness and
+    /// roughness, as in the usual PBR metalness workflow.
+    pub fn metalness(&mut self, base_color: [f32; 3], metalness: f32, roughness: f32) -> &mut Self {
+        self.color = base_color;
+        self.emission = [0.0, 0.0, 0.0];
+        self.smoothness = 1.0 - roughness;
+        self.metallic = metalness;
+        self.f0 = [0.04, 0.04, 0.04];
+        self
+    }
+
+    /// Pseudo-GLSL showing how the raytracing shader evaluates this material.
+    pub fn debug_glsl(&self) -> String {
+        // same threshold as getMaterialClass in the raytracing shader
+        let class = if self.transmission > 0.0 {
+            "Dielectric"
+        } else if self.metallic >= 0.5 {
+            "Metallic"
+        } else {
+            "Diffuse"
+        };
+        let vec3 = |v: [f32; 3]| format!("vec3({:.3}, {:.3}, {:.3})", v[0], v[1], v[2]);
+
+        let mut glsl = String::new();
+        if self.index == usize::MAX {
+            glsl += &format!("// Material (not uploaded yet): {}\n", class);
+        } else {
+            glsl += &format!("// Material {}: {}\n", self.index, class);
+        }
+        if self.uv_scale != [1.0, 1.0] || self.uv_offset != [0.0, 0.0] {
+            glsl += &format!(
+                "uv = uv * vec2({:.3}, {:.3}) + vec2({:.3}, {:.3});\n",
+                self.uv_scale[0], self.uv_scale[1], self.uv_offset[0], self.uv_offset[1],
+            );
+        }
+        match self.albedo_texture {
+            Some(id) => glsl += &format!("albedo = texture(materialTextures[/* texture {} */], uv).rgb;\n", id.id()),
+            None if self.checker_scale > 0.0 => {
+                glsl += &format!("checker = mod(floor(uv.x * {0:.3}) + floor(uv.y * {0:.3}), 2.0);\n", self.checker_scale);
+                glsl += &format!("albedo = checker < 1.0 ? {} : {};\n", vec3(self.color), vec3(self.color2));
+            }
+            None => glsl += &format!("albedo = {};\n", vec3(self.color)),
+        }
+        if self.noise_strength > 0.0 {
+            glsl += &format!("albedo = mix(albedo, {}, {:.3} * (perlin(position * {:.3}) * 0.5 + 0.5));\n", vec3(self.color2), self.noise_strength, self.noise_scale);
+        }
+        if let Some(id) = self.normal_texture {
+            glsl += &format!("shading_normal = TBN * (texture(materialTextures[/* texture {} */], uv).xyz * 2.0 - 1.0);\n", id.id());
+        }
+        glsl += &format!("emission = {} * {:.3};\n", vec3(self.emission), self.emission_strength);
+        glsl += &format!("smoothness = {:.3};\n", self.smoothness);
+        glsl += &format!("f0 = {};\n", vec3(self.f0));
+        glsl += &format!("metallic = {:.3};\n", self.metallic);
+        glsl += "specularF0 = mix(f0, albedo, metallic);\n";
+        glsl += "diffuse = albedo * (1.0 - metallic);\n";
+        if self.transmission > 0.0 {
+            glsl += &format!("transmission = {:.3}; // chance to refract instead of the lobes above\n", self.transmission);
+            glsl += &format!("ior = {:.3};\n", self.ior);
+            glsl += "absorption = 1.0 - albedo; // Beer's law, exp(-absorption * distance)\n";
+        }
+        glsl
+    }
 }
 
 impl Default for Material {
@@ -48,18 +233,31 @@ impl Default for Material {
     }
 }
 
-impl Into<shader::raytrace::fs::Material> for Material {
-    fn into(self) -> shader::raytrace::fs::Material {
+impl From<Material> for shader::raytrace::fs::Material {
+    fn from(value: Material) -> Self {
         shader::raytrace::fs::Material {
-            color: self.color.into(),
-            emission: self.emission.into(),
-            smoothness: self.smoothness.into(),
+            color: value.color.into(),
+            emission: value.emission.map(|e| e * value.emission_strength),
+            smoothness: value.smoothness,
+            f0: value.f0,
+            metallic: value.metallic,
+            ior: value.ior,
+            transmission: value.transmission,
+            color2: value.color2,
+            checker_scale: value.checker_scale,
+            uv_scale: value.uv_scale,
+            uv_offset: value.uv_offset,
+            noise_scale: value.noise_scale,
+            noise_strength: value.noise_strength,
+            // the texture slot is only known when the descriptor set is built
+            albedo_texture: -1,
+            normal_texture: -1,
         }
     }
 }
 
-impl<const N: usize> Into<::vulkano::padded::Padded<shader::raytrace::fs::Material, N>> for Material {
-    fn into(self) -> Padded<shader::raytrace::fs::Material, N> {
-        Padded(self.into())
+impl<const N: usize> From<Material> for ::vulkano::padded::Padded<shader::raytrace::fs::Material, N> {
+    fn from(value: Material) -> Self {
+        Padded(value.into())
     }
 }
\ No newline at end of file
@@ -0,0 +1,107 @@
+use std::fmt;
+use std::fs;
+
+use crate::app::geom::Triangle;
+
+#[derive(Debug)]
+pub enum ObjError {
+    Io(std::io::Error),
+    Malformed { line: usize, message: String },
+}
+
+impl fmt::Display for ObjError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Self::Io(e) => write!(f, "Could not read OBJ file: {}", e),
+            Self::Malformed { line, message } => write!(f, "Malformed OBJ at line {}: {}", line, message),
+        }
+    }
+}
+
+impl std::error::Error for ObjError {}
+
+impl From<std::io::Error> for ObjError {
+    fn from(e: std::io::Error) -> Self {
+        Self::Io(e)
+    }
+}
+
+/// Reads the faces of a Wavefront OBJ file as triangles, polygons are split as a fan.
+///
+/// Only `v`, `vn` and `f` are used, everything else (including `mtllib`) is ignored.
+/// Faces with vertex normals are shaded smooth with them, the others are flat.
+/// Zero-area triangles are skipped.
+pub fn load(path: &str, material: i32) -> Result<Vec<Triangle>, ObjError> {
+    let source = fs::read_to_string(path)?;
+
+    let mut positions: Vec<[f32; 3]> = Vec::new();
+    let mut normals: Vec<[f32; 3]> = Vec::new();
+    let mut triangles = Vec::new();
+    let mut degenerate = 0;
+
+    for (i, line) in source.lines().enumerate() {
+        let line_number = i + 1;
+        let malformed = |message: &str| ObjError::Malformed { line: line_number, message: message.to_string() };
+
+        let mut tokens = line.split_whitespace();
+        match tokens.next() {
+            Some("v") => positions.push(parse_vec3(&mut tokens).ok_or_else(|| malformed("expected 3 coordinates"))?),
+            Some("vn") => normals.push(parse_vec3(&mut tokens).ok_or_else(|| malformed("expected 3 coordinates"))?),
+            Some("f") => {
+                let mut face = Vec::new();
+                for token in tokens {
+                    // v, v/vt, v//vn or v/vt/vn
+                    let mut parts = token.split('/');
+                    let v = parts.next()
+                        .and_then(|p| resolve_index(p, positions.len()))
+                        .ok_or_else(|| malformed("invalid vertex index"))?;
+                    let n = match parts.nth(1) {
+                        Some(p) if !p.is_empty() => Some(resolve_index(p, normals.len()).ok_or_else(|| malformed("invalid normal index"))?),
+                        _ => None,
+                    };
+                    face.push((positions[v], n.map(|n| normals[n])));
+                }
+                if face.len() < 3 {
+                    return Err(malformed("face with less than 3 vertices"));
+                }
+
+                for k in 1..face.len() - 1 {
+                    let (a, b, c) = (face[0], face[k], face[k + 1]);
+                    let mut triangle = Triangle::new(a.0, b.0, c.0);
+                    if triangle.normal == [0.0; 3] {
+                        degenerate += 1;
+                        continue;
+                    }
+                    triangle.material(material);
+                    if let (Some(na), Some(nb), Some(nc)) = (a.1, b.1, c.1) {
+                        triangle.vertex_normals(na, nb, nc).smooth_normals(true);
+                    }
+                    triangles.push(triangle);
+                }
+            }
+            _ => {}
+        }
+    }
+
+    if degenerate > 0 {
+        println!("Skipped {} degenerate triangles in {}", degenerate, path);
+    }
+    Ok(triangles)
+}
+
+fn parse_vec3<'a>(tokens: &mut impl Iterator<Item = &'a str>) -> Option<[f32; 3]> {
+    let x = tokens.next()?.parse().ok()?;
+    let y = tokens.next()?.parse().ok()?;
+    let z = tokens.next()?.parse().ok()?;
+    Some([x, y, z])
+}
+
+// OBJ indices start at 1, negative ones count back from the last element
+fn resolve_index(token: &str, len: usize) -> Option<usize> {
+    let index: i64 = token.parse().ok()?;
+    let index = if index < 0 { len as i64 + index } else { index - 1 };
+    if index < 0 || index >= len as i64 {
+        return None;
+    }
+    Some(index as usize)
+}
@@ -0,0 +1,54 @@
+/// Terms of the camera jitter sequence before it repeats.
+pub const HALTON_SEQUENCE_LENGTH: usize = 128;
+
+/// Radical inverse of `index` in `base`, in `[0, 1)`.
+pub fn halton(mut index: u32, base: u32) -> f32 {
+    let mut f = 1.0;
+    let mut r = 0.0;
+    while index > 0 {
+        f /= base as f32;
+        r += f * (index % base) as f32;
+        index /= base;
+    }
+    r
+}
+
+/// Halton (2, 3) pairs starting at index 1, centered on 0 so they are sub-pixel offsets in the
+/// `[-0.5, 0.5]` range.
+pub fn halton_sequence(count: usize) -> Vec<[f32; 2]> {
+    (1..=count as u32)
+        .map(|i| [halton(i, 2) - 0.5, halton(i, 3) - 0.5])
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn first_halton_2_3_pairs() {
+        let expected = [
+            [1.0 / 2.0, 1.0 / 3.0],
+            [1.0 / 4.0, 2.0 / 3.0],
+            [3.0 / 4.0, 1.0 / 9.0],
+            [1.0 / 8.0, 4.0 / 9.0],
+            [5.0 / 8.0, 7.0 / 9.0],
+            [3.0 / 8.0, 2.0 / 9.0],
+            [7.0 / 8.0, 5.0 / 9.0],
+            [1.0 / 16.0, 8.0 / 9.0],
+        ];
+        for (i, [x, y]) in expected.into_iter().enumerate() {
+            let index = i as u32 + 1;
+            assert!((halton(index, 2) - x).abs() < 1e-6, "halton({}, 2)", index);
+            assert!((halton(index, 3) - y).abs() < 1e-6, "halton({}, 3)", index);
+        }
+    }
+
+    #[test]
+    fn sequence_is_centered_on_the_pixel() {
+        let sequence = halton_sequence(HALTON_SEQUENCE_LENGTH);
+        assert_eq!(sequence.len(), HALTON_SEQUENCE_LENGTH);
+        assert_eq!(sequence[0], [0.0, 1.0 / 3.0 - 0.5]);
+        assert!(sequence.iter().flatten().all(|c| (-0.5..0.5).contains(c)));
+    }
+}
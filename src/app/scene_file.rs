@@ -0,0 +1,114 @@
+use std::fmt;
+use std::fs;
+
+use crate::app::app::Scene;
+
+#[derive(Debug)]
+pub enum SceneError {
+    Io(std::io::Error),
+    Parse(ron::error::SpannedError),
+    Serialize(ron::Error),
+}
+
+impl fmt::Display for SceneError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Self::Io(e) => write!(f, "Could not access scene file: {}", e),
+            Self::Parse(e) => write!(f, "Invalid scene file: {}", e),
+            Self::Serialize(e) => write!(f, "Could not serialize scene: {}", e),
+        }
+    }
+}
+
+impl std::error::Error for SceneError {}
+
+impl From<std::io::Error> for SceneError {
+    fn from(e: std::io::Error) -> Self {
+        Self::Io(e)
+    }
+}
+
+impl From<ron::error::SpannedError> for SceneError {
+    fn from(e: ron::error::SpannedError) -> Self {
+        Self::Parse(e)
+    }
+}
+
+impl From<ron::Error> for SceneError {
+    fn from(e: ron::Error) -> Self {
+        Self::Serialize(e)
+    }
+}
+
+impl Scene {
+    /// Writes the camera, materials, objects and render options to a RON file. Textures and
+    /// runtime state like the accumulated frames are left out.
+    pub fn save_ron(&self, path: &str) -> Result<(), SceneError> {
+        fs::write(path, ron::ser::to_string_pretty(self, ron::ser::PrettyConfig::default())?)?;
+        Ok(())
+    }
+
+    /// Reads a scene written by `save_ron`, with every object and material marked dirty so it is
+    /// uploaded. Missing fields keep their default.
+    pub fn load_ron(path: &str) -> Result<Scene, SceneError> {
+        let mut scene: Scene = ron::from_str(&fs::read_to_string(path)?)?;
+        scene.all_materials.iter_mut().enumerate().for_each(|(i, m)| m.index = i);
+        scene.all_circles.iter_mut().enumerate().for_each(|(i, c)| c.index = i);
+        scene.all_boxes.iter_mut().enumerate().for_each(|(i, b)| b.index = i);
+        scene.all_planes.iter_mut().enumerate().for_each(|(i, p)| p.index = i);
+        scene.all_triangles.iter_mut().enumerate().for_each(|(i, t)| t.index = i);
+        scene.all_cylinders.iter_mut().enumerate().for_each(|(i, c)| c.index = i);
+        scene.all_capsules.iter_mut().enumerate().for_each(|(i, c)| c.index = i);
+        scene.all_tori.iter_mut().enumerate().for_each(|(i, t)| t.index = i);
+        scene.all_directional_lights.iter_mut().enumerate().for_each(|(i, l)| l.index = i);
+        scene.all_volumes.iter_mut().enumerate().for_each(|(i, v)| v.index = i);
+        scene.force_dirty_all();
+        Ok(scene)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::app::geom::Circle;
+    use crate::app::material::Material;
+
+    use super::*;
+
+    fn round_trip(name: &str, scene: &Scene) -> Scene {
+        let path = std::env::temp_dir().join(format!("raytracing_demo_{}_{}.ron", name, std::process::id()));
+        let path = path.to_str().unwrap();
+        scene.save_ron(path).unwrap();
+        let loaded = Scene::load_ron(path);
+        fs::remove_file(path).ok();
+        loaded.unwrap()
+    }
+
+    #[test]
+    fn default_scene_round_trip() {
+        let scene = Scene::default();
+        let loaded = round_trip("default_scene", &scene);
+        // every saved field, compared through the file format itself
+        assert_eq!(ron::to_string(&loaded).unwrap(), ron::to_string(&scene).unwrap());
+        assert_eq!(loaded.eye_separation, scene.eye_separation);
+        assert_eq!(loaded.max_bounce_depth, scene.max_bounce_depth);
+        assert_eq!(loaded.sun_direction, scene.sun_direction);
+        assert!(loaded.camera == scene.camera);
+    }
+
+    #[test]
+    fn loaded_objects_are_indexed_and_dirty() {
+        let mut scene = Scene::default();
+        scene.all_materials.push(Material::new());
+        scene.all_materials.push(Material::new());
+        scene.all_materials[1].name("Glass").transmission(1.0).ior(1.5);
+        scene.all_circles.push(*Circle::new().position([1.0, 2.0, 3.0]).radius(0.5));
+
+        let loaded = round_trip("objects", &scene);
+        assert_eq!(loaded.all_materials.len(), 2);
+        assert_eq!(loaded.all_materials[1].name, "Glass");
+        assert_eq!(loaded.all_materials[1].transmission, 1.0);
+        assert!(loaded.all_materials.iter().enumerate().all(|(i, m)| m.index == i && m.dirty));
+        assert_eq!(loaded.all_circles[0].position, [1.0, 2.0, 3.0]);
+        assert!(loaded.all_circles[0].index == 0 && loaded.all_circles[0].dirty);
+    }
+}
@@ -0,0 +1,60 @@
+use std::fs::File;
+use std::io::BufWriter;
+use std::time::{SystemTime, UNIX_EPOCH};
+
+/// `{prefix}_YYYYMMDD_HHMMSS.{extension}` with the current UTC time.
+pub fn timestamped_name(prefix: &str, extension: &str) -> String {
+    let seconds = SystemTime::now().duration_since(UNIX_EPOCH).unwrap().as_secs() as i64;
+    let (days, time) = (seconds.div_euclid(86400), seconds.rem_euclid(86400));
+
+    // days since the epoch to a civil date, Howard Hinnant's algorithm
+    let z = days + 719468;
+    let era = z.div_euclid(146097);
+    let doe = z.rem_euclid(146097);
+    let yoe = (doe - doe / 1460 + doe / 36524 - doe / 146096) / 365;
+    let doy = doe - (365 * yoe + yoe / 4 - yoe / 100);
+    let mp = (5 * doy + 2) / 153;
+    let day = doy - (153 * mp + 2) / 5 + 1;
+    let month = if mp < 10 { mp + 3 } else { mp - 9 };
+    let year = yoe + era * 400 + (month <= 2) as i64;
+
+    format!(
+        "{prefix}_{year:04}{month:02}{day:02}_{:02}{:02}{:02}.{extension}",
+        time / 3600,
+        time / 60 % 60,
+        time % 60,
+    )
+}
+
+/// Same curves as `toneMap` in the denoiser shader, `mode` 0 is linear, 1 Reinhard and 2 ACES.
+pub fn tone_map(color: [f32; 3], mode: i32, exposure: f32) -> [f32; 3] {
+    color.map(|c| {
+        let c = c * exposure;
+        match mode {
+            1 => c / (1.0 + c),
+            2 => ((c * (2.51 * c + 0.03)) / (c * (2.43 * c + 0.59) + 0.14)).clamp(0.0, 1.0),
+            _ => c,
+        }
+    })
+}
+
+fn linear_to_srgb(c: f32) -> u8 {
+    let c = c.clamp(0.0, 1.0);
+    let c = if c <= 0.0031308 { c * 12.92 } else { 1.055 * c.powf(1.0 / 2.4) - 0.055 };
+    (c * 255.0).round() as u8
+}
+
+/// Writes RGBA float texels as an 8 bit sRGB PNG, tone mapped like the screen output.
+pub fn save_png(path: &str, size: [u32; 2], rgba: &[f32], tone_mapping_mode: i32, exposure: f32) -> Result<(), png::EncodingError> {
+    let data: Vec<u8> = rgba.chunks_exact(4)
+        .flat_map(|texel| tone_map([texel[0], texel[1], texel[2]], tone_mapping_mode, exposure).map(linear_to_srgb))
+        .collect();
+
+    let mut encoder = png::Encoder::new(BufWriter::new(File::create(path)?), size[0], size[1]);
+    encoder.set_color(png::ColorType::Rgb);
+    encoder.set_depth(png::BitDepth::Eight);
+    encoder.set_srgb(png::SrgbRenderingIntent::Perceptual);
+    let mut writer = encoder.write_header()?;
+    writer.write_image_data(&data)?;
+    writer.finish()
+}
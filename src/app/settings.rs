@@ -0,0 +1,96 @@
+use std::fs;
+use std::path::PathBuf;
+
+use serde::{Deserialize, Serialize};
+
+/// User preferences, kept apart from the scene and persisted between runs.
+#[derive(Debug, Clone, Copy, PartialEq, Serialize, Deserialize)]
+#[serde(default)]
+pub struct RenderSettings {
+    /// Size of the window when it is not fullscreen.
+    pub window_size: [u32; 2],
+    /// Borderless fullscreen, toggled with F11.
+    pub fullscreen: bool,
+    /// FIFO presentation, set from `AppConfig::vsync` at startup instead of being saved.
+    #[serde(skip)]
+    pub vsync: bool,
+    pub sample_count: u32,
+    /// Chains the raytrace and denoiser submits with a timeline semaphore when the device has them.
+    pub timeline_semaphore: bool,
+
+    pub current_view: i32,
+    pub kernel_size: i32,
+    pub kernel_offset: i32,
+    pub denoiser_albedo_weight: f32,
+    pub denoiser_normal_weight: f32,
+    pub denoiser_depth_weight: f32,
+    /// Scales the estimated variance in the luminance edge stopping term, 0 disables it.
+    pub denoiser_variance_weight: f32,
+}
+
+impl RenderSettings {
+    /// `~/.config/raytracing_demo/settings.toml`, or `None` if the home directory is unknown.
+    pub fn path() -> Option<PathBuf> {
+        let config_dir = match std::env::var_os("XDG_CONFIG_HOME") {
+            Some(dir) => PathBuf::from(dir),
+            None => PathBuf::from(std::env::var_os("HOME")?).join(".config"),
+        };
+        Some(config_dir.join("raytracing_demo").join("settings.toml"))
+    }
+
+    /// Loads the saved settings, falling back to the defaults if there are none.
+    pub fn load() -> Self {
+        let Some(path) = Self::path() else {
+            return Self::default();
+        };
+        let Ok(content) = fs::read_to_string(&path) else {
+            return Self::default();
+        };
+        match toml::from_str(&content) {
+            Ok(settings) => settings,
+            Err(e) => {
+                println!("failed to parse {}: {e}", path.display());
+                Self::default()
+            }
+        }
+    }
+
+    pub fn save(&self) {
+        let Some(path) = Self::path() else {
+            return;
+        };
+        let content = toml::to_string_pretty(self).unwrap();
+        let result = fs::create_dir_all(path.parent().unwrap())
+            .and_then(|_| fs::write(&path, content));
+        if let Err(e) = result {
+            println!("failed to save {}: {e}", path.display());
+        }
+    }
+
+    /// Saves the settings if they differ from `saved`, the last saved ones, and updates it.
+    pub fn save_if_changed(&self, saved: &mut Self) {
+        if self != saved {
+            self.save();
+            *saved = *self;
+        }
+    }
+}
+
+impl Default for RenderSettings {
+    fn default() -> Self {
+        Self {
+            window_size: [1200, 900],
+            fullscreen: false,
+            vsync: true,
+            sample_count: 8,
+            timeline_semaphore: true,
+            current_view: 0,
+            kernel_size: 5,
+            kernel_offset: 2,
+            denoiser_albedo_weight: 0.01,
+            denoiser_normal_weight: 0.01,
+            denoiser_depth_weight: 0.3,
+            denoiser_variance_weight: 4.0,
+        }
+    }
+}
@@ -25,4 +25,44 @@ pub mod denoiser {
             path: "src/shaders/frag_denoiser.glsl",
         }
     }
+}
+pub mod taa {
+    pub mod fs {
+        vulkano_shaders::shader! {
+            ty: "fragment",
+            path: "src/shaders/frag_taa.glsl",
+        }
+    }
+}
+pub mod denoiser_horizontal {
+    pub mod cs {
+        vulkano_shaders::shader! {
+            ty: "compute",
+            path: "src/shaders/comp_denoiser_horizontal.glsl",
+        }
+    }
+}
+pub mod bloom {
+    pub mod cs {
+        vulkano_shaders::shader! {
+            ty: "compute",
+            path: "src/shaders/comp_bloom.glsl",
+        }
+    }
+}
+pub mod variance {
+    pub mod cs {
+        vulkano_shaders::shader! {
+            ty: "compute",
+            path: "src/shaders/comp_variance.glsl",
+        }
+    }
+}
+pub mod exposure {
+    pub mod cs {
+        vulkano_shaders::shader! {
+            ty: "compute",
+            path: "src/shaders/comp_exposure.glsl",
+        }
+    }
 }
\ No newline at end of file
@@ -0,0 +1,97 @@
+use std::collections::HashMap;
+use std::path::{Path, PathBuf};
+use std::sync::Arc;
+use std::time::{Duration, Instant, SystemTime};
+
+use shaderc::{CompileOptions, Compiler, EnvVersion, ShaderKind, TargetEnv};
+use vulkano::device::Device;
+use vulkano::shader::ShaderModule;
+
+/// Directory the shaders are embedded from at build time.
+pub const SHADER_DIR: &str = concat!(env!("CARGO_MANIFEST_DIR"), "/src/shaders");
+
+const POLL_INTERVAL: Duration = Duration::from_millis(500);
+
+/// Watches the GLSL sources so the pipelines can be rebuilt without restarting.
+///
+/// Compares modification times on each poll rather than subscribing to file system events.
+pub struct ShaderWatcher {
+    directory: PathBuf,
+    modified: HashMap<PathBuf, SystemTime>,
+    last_poll: Instant,
+}
+
+impl ShaderWatcher {
+    pub fn new(directory: impl Into<PathBuf>) -> Self {
+        let directory = directory.into();
+        let modified = scan(&directory);
+        println!("Watching {} shaders in {}", modified.len(), directory.display());
+        Self {
+            directory,
+            modified,
+            last_poll: Instant::now(),
+        }
+    }
+
+    pub fn directory(&self) -> &Path {
+        &self.directory
+    }
+
+    /// Sources written since the last call, checked at most every `POLL_INTERVAL`.
+    pub fn changed_files(&mut self) -> Vec<PathBuf> {
+        if self.last_poll.elapsed() < POLL_INTERVAL {
+            return Vec::new();
+        }
+        self.last_poll = Instant::now();
+
+        let modified = scan(&self.directory);
+        let changed = modified.iter()
+            .filter(|(path, time)| self.modified.get(*path) != Some(time))
+            .map(|(path, _)| path.clone())
+            .collect();
+        self.modified = modified;
+        changed
+    }
+}
+
+fn scan(directory: &Path) -> HashMap<PathBuf, SystemTime> {
+    let Ok(entries) = std::fs::read_dir(directory) else {
+        return HashMap::new();
+    };
+    entries
+        .filter_map(|entry| entry.ok())
+        .map(|entry| entry.path())
+        .filter(|path| path.extension().is_some_and(|ext| ext == "glsl"))
+        .filter_map(|path| {
+            let time = std::fs::metadata(&path).and_then(|m| m.modified()).ok()?;
+            Some((path, time))
+        })
+        .collect()
+}
+
+/// Compiles a GLSL source at runtime, the stage comes from the `vert_`, `frag_` or `comp_` prefix
+/// of the file name like the sources embedded by `vulkano_shaders`.
+pub fn compile(device: &Arc<Device>, path: &Path) -> Result<Arc<ShaderModule>, String> {
+    let file_name = path.file_name().unwrap().to_string_lossy();
+    let kind = if file_name.starts_with("vert_") {
+        ShaderKind::Vertex
+    } else if file_name.starts_with("frag_") {
+        ShaderKind::Fragment
+    } else if file_name.starts_with("comp_") {
+        ShaderKind::Compute
+    } else {
+        return Err(format!("{}: unknown shader stage", file_name));
+    };
+
+    let source = std::fs::read_to_string(path).map_err(|e| format!("{}: {}", file_name, e))?;
+    let compiler = Compiler::new().ok_or("failed to create the shader compiler")?;
+    let mut options = CompileOptions::new().ok_or("failed to create the shader compiler options")?;
+    options.set_target_env(TargetEnv::Vulkan, EnvVersion::Vulkan1_0 as u32);
+    let artifact = compiler
+        .compile_into_spirv(&source, kind, &file_name, "main", Some(&options))
+        .map_err(|e| e.to_string())?;
+
+    // the SPIR-V was just produced by shaderc for this target
+    unsafe { ShaderModule::from_words(device.clone(), artifact.as_binary()) }
+        .map_err(|e| format!("{}: {}", file_name, e))
+}
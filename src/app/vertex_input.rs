@@ -1,8 +1,11 @@
 use vulkano::buffer::BufferContents;
 
-#[derive(BufferContents, vulkano::pipeline::graphics::vertex_input::Vertex)]
+#[derive(BufferContents, vulkano::pipeline::graphics::vertex_input::Vertex, Clone, Copy)]
 #[repr(C)]
 pub struct ScreenVertex {
     #[format(R32G32_SFLOAT)]
     pub position: [f32; 2],
+    /// 0 at the top left corner of the screen, 1 at the bottom right.
+    #[format(R32G32_SFLOAT)]
+    pub uv: [f32; 2],
 }
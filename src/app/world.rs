@@ -0,0 +1,499 @@
+use cgmath::{InnerSpace, Quaternion, Rotation, Vector2, Vector3};
+
+use crate::app::app::Scene;
+use crate::app::geom::{Box, Capsule, Circle, Cylinder, Plane, Torus, Triangle};
+
+#[derive(Debug, Clone, Copy)]
+pub struct HitRecord {
+    pub t: f32,
+    pub point: [f32; 3],
+    pub normal: [f32; 3],
+    pub material: i32,
+}
+
+/// CPU-side copy of the scene geometry, used to cast rays without going through the GPU.
+///
+/// The intersection tests mirror the ones in `frag_raytracing.glsl`, keep them in sync.
+#[derive(Debug, Clone, Default)]
+pub struct World {
+    pub circles: Vec<Circle>,
+    pub boxes: Vec<Box>,
+    pub planes: Vec<Plane>,
+    pub triangles: Vec<Triangle>,
+    pub cylinders: Vec<Cylinder>,
+    pub capsules: Vec<Capsule>,
+    pub tori: Vec<Torus>,
+}
+
+impl World {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    pub fn from_scene(scene: &Scene) -> Self {
+        Self {
+            circles: scene.all_circles.clone(),
+            boxes: scene.all_boxes.clone(),
+            planes: scene.all_planes.clone(),
+            triangles: scene.all_triangles.clone(),
+            cylinders: scene.all_cylinders.clone(),
+            capsules: scene.all_capsules.clone(),
+            tori: scene.all_tori.clone(),
+        }
+    }
+
+    /// Returns the closest hit along `dir` with `t` in `[t_min, t_max]`.
+    pub fn cast_ray(&self, origin: [f32; 3], dir: [f32; 3], t_min: f32, t_max: f32) -> Option<HitRecord> {
+        let origin = Vector3::from(origin);
+        let dir = Vector3::from(dir);
+
+        let mut closest: Option<HitRecord> = None;
+        let mut closest_t = t_max;
+        for circle in &self.circles {
+            if let Some(t) = ray_sphere_intersect(origin, dir, circle.position.into(), circle.radius) {
+                if t >= t_min && t <= closest_t {
+                    closest_t = t;
+                    let point = origin + dir * t;
+                    let normal = (point - Vector3::from(circle.position)).normalize();
+                    closest = Some(HitRecord {
+                        t,
+                        point: point.into(),
+                        normal: normal.into(),
+                        material: circle.material,
+                    });
+                }
+            }
+        }
+        for b in &self.boxes {
+            if let Some((t, normal)) = ray_box_intersect(origin, dir, b.min.into(), b.max.into()) {
+                if t >= t_min && t <= closest_t {
+                    closest_t = t;
+                    closest = Some(HitRecord {
+                        t,
+                        point: (origin + dir * t).into(),
+                        normal: normal.into(),
+                        material: b.material,
+                    });
+                }
+            }
+        }
+        for plane in &self.planes {
+            let normal = Vector3::from(plane.normal);
+            if let Some(t) = ray_plane_intersect(origin, dir, normal * plane.distance, normal) {
+                if t >= t_min && t <= closest_t {
+                    closest_t = t;
+                    closest = Some(HitRecord {
+                        t,
+                        point: (origin + dir * t).into(),
+                        normal: plane.normal,
+                        material: plane.material,
+                    });
+                }
+            }
+        }
+        for triangle in &self.triangles {
+            if let Some(t) = ray_triangle_intersect(origin, dir, triangle) {
+                if t >= t_min && t <= closest_t {
+                    closest_t = t;
+                    let normal = Vector3::from(triangle.normal);
+                    let normal = if normal.dot(dir) > 0.0 { -normal } else { normal };
+                    closest = Some(HitRecord {
+                        t,
+                        point: (origin + dir * t).into(),
+                        normal: normal.into(),
+                        material: triangle.material,
+                    });
+                }
+            }
+        }
+        for cylinder in &self.cylinders {
+            if let Some((t, normal)) = ray_cylinder_intersect(origin, dir, cylinder) {
+                if t >= t_min && t <= closest_t {
+                    closest_t = t;
+                    closest = Some(HitRecord {
+                        t,
+                        point: (origin + dir * t).into(),
+                        normal: normal.into(),
+                        material: cylinder.material,
+                    });
+                }
+            }
+        }
+        for capsule in &self.capsules {
+            if let Some((t, normal)) = ray_capsule_intersect(origin, dir, capsule) {
+                if t >= t_min && t <= closest_t {
+                    closest_t = t;
+                    closest = Some(HitRecord {
+                        t,
+                        point: (origin + dir * t).into(),
+                        normal: normal.into(),
+                        material: capsule.material,
+                    });
+                }
+            }
+        }
+        for torus in &self.tori {
+            if let Some((t, normal)) = ray_torus_intersect(origin, dir, torus) {
+                if t >= t_min && t <= closest_t {
+                    closest_t = t;
+                    closest = Some(HitRecord {
+                        t,
+                        point: (origin + dir * t).into(),
+                        normal: normal.into(),
+                        material: torus.material,
+                    });
+                }
+            }
+        }
+        closest
+    }
+}
+
+// same as raySphereIntersect in the raytracing shader: the far root is only used from inside
+fn ray_sphere_intersect(origin: Vector3<f32>, dir: Vector3<f32>, position: Vector3<f32>, radius: f32) -> Option<f32> {
+    let oc = origin - position;
+    let a = dir.dot(dir);
+    let b = 2.0 * oc.dot(dir);
+    let c = oc.dot(oc) - radius * radius;
+    let discriminant = b * b - 4.0 * a * c;
+    if discriminant > 0.0 {
+        let mut t = (-b - discriminant.sqrt()) / (2.0 * a);
+        if t <= 0.0 {
+            t = (-b + discriminant.sqrt()) / (2.0 * a);
+        }
+        if t > 0.0 {
+            return Some(t);
+        }
+    }
+    None
+}
+
+// same as rayBoxIntersect in the raytracing shader, returns the distance and the face normal
+fn ray_box_intersect(origin: Vector3<f32>, dir: Vector3<f32>, min: Vector3<f32>, max: Vector3<f32>) -> Option<(f32, Vector3<f32>)> {
+    let mut t_near = f32::NEG_INFINITY;
+    let mut t_far = f32::INFINITY;
+    for i in 0..3 {
+        let t0 = (min[i] - origin[i]) / dir[i];
+        let t1 = (max[i] - origin[i]) / dir[i];
+        t_near = t_near.max(t0.min(t1));
+        t_far = t_far.min(t0.max(t1));
+    }
+    if t_near > t_far || t_near <= 0.0 {
+        return None;
+    }
+
+    let point = origin + dir * t_near;
+    let center = (min + max) * 0.5;
+    let half_size = (max - min) * 0.5;
+    let local = Vector3::new(
+        (point.x - center.x) / half_size.x,
+        (point.y - center.y) / half_size.y,
+        (point.z - center.z) / half_size.z,
+    );
+    let d = local.map(f32::abs);
+    let normal = if d.x > d.y && d.x > d.z {
+        Vector3::new(local.x.signum(), 0.0, 0.0)
+    } else if d.y > d.z {
+        Vector3::new(0.0, local.y.signum(), 0.0)
+    } else {
+        Vector3::new(0.0, 0.0, local.z.signum())
+    };
+    Some((t_near, normal))
+}
+
+// same as rayCylinderIntersect in the raytracing shader, returns the distance and the normal
+fn ray_cylinder_intersect(origin: Vector3<f32>, dir: Vector3<f32>, cylinder: &Cylinder) -> Option<(f32, Vector3<f32>)> {
+    let oc = origin - Vector3::from(cylinder.position);
+    let mut closest: Option<(f32, Vector3<f32>)> = None;
+    let mut keep = |t: f32, normal: Vector3<f32>| {
+        if t > 0.0 && closest.is_none_or(|(c, _)| t < c) {
+            closest = Some((t, normal));
+        }
+    };
+
+    let a = dir.x * dir.x + dir.z * dir.z;
+    let b = oc.x * dir.x + oc.z * dir.z;
+    let c = oc.x * oc.x + oc.z * oc.z - cylinder.radius * cylinder.radius;
+    let discriminant = b * b - a * c;
+    if a > 1e-8 && discriminant > 0.0 {
+        for root in [-discriminant.sqrt(), discriminant.sqrt()] {
+            let t = (-b + root) / a;
+            let p = oc + dir * t;
+            if (0.0..=cylinder.height).contains(&p.y) {
+                keep(t, Vector3::new(p.x, 0.0, p.z) / cylinder.radius);
+            }
+        }
+    }
+
+    if dir.y.abs() > 0.0 {
+        for (cap_y, normal_y) in [(0.0, -1.0), (cylinder.height, 1.0)] {
+            let t = (cap_y - oc.y) / dir.y;
+            let p = oc + dir * t;
+            if p.x * p.x + p.z * p.z <= cylinder.radius * cylinder.radius {
+                keep(t, Vector3::new(0.0, normal_y, 0.0));
+            }
+        }
+    }
+    closest
+}
+
+// same as rayCapsuleIntersect in the raytracing shader, returns the distance and the normal
+fn ray_capsule_intersect(origin: Vector3<f32>, dir: Vector3<f32>, capsule: &Capsule) -> Option<(f32, Vector3<f32>)> {
+    let start = Vector3::from(capsule.start);
+    let ba = Vector3::from(capsule.end) - start;
+    let oa = origin - start;
+    let baba = ba.dot(ba);
+    let bard = ba.dot(dir);
+    let baoa = ba.dot(oa);
+    let radius2 = capsule.radius * capsule.radius;
+    let mut closest: Option<(f32, Vector3<f32>)> = None;
+    let mut keep = |t: f32, normal: Vector3<f32>| {
+        if t > 0.0 && closest.is_none_or(|(c, _)| t < c) {
+            closest = Some((t, normal));
+        }
+    };
+
+    let a = baba * dir.dot(dir) - bard * bard;
+    let b = baba * oa.dot(dir) - baoa * bard;
+    let c = baba * oa.dot(oa) - baoa * baoa - radius2 * baba;
+    let discriminant = b * b - a * c;
+    if a > 1e-8 && discriminant > 0.0 {
+        for root in [-discriminant.sqrt(), discriminant.sqrt()] {
+            let t = (-b + root) / a;
+            let y = baoa + t * bard;
+            if (0.0..=baba).contains(&y) {
+                keep(t, (oa + dir * t - ba * (y / baba)) / capsule.radius);
+            }
+        }
+    }
+
+    for (center, side) in [(start, -1.0), (Vector3::from(capsule.end), 1.0)] {
+        let oc = origin - center;
+        let a = dir.dot(dir);
+        let half_b = oc.dot(dir);
+        let discriminant = half_b * half_b - a * (oc.dot(oc) - radius2);
+        if discriminant <= 0.0 {
+            continue;
+        }
+        for root in [-discriminant.sqrt(), discriminant.sqrt()] {
+            let t = (-half_b + root) / a;
+            let p = oc + dir * t;
+            if p.dot(ba) * side >= 0.0 {
+                keep(t, p / capsule.radius);
+            }
+        }
+    }
+    closest
+}
+
+// same as rayTorusIntersect in the raytracing shader, returns the distance and the normal
+fn ray_torus_intersect(origin: Vector3<f32>, dir: Vector3<f32>, torus: &Torus) -> Option<(f32, Vector3<f32>)> {
+    const MAX_STEPS: usize = 128;
+    let [x, y, z, w] = torus.orientation;
+    let orientation = Quaternion::new(w, x, y, z);
+    let inverse = orientation.conjugate();
+    let o = inverse.rotate_vector(origin - Vector3::from(torus.center));
+    let d = inverse.rotate_vector(dir);
+    let scale = d.magnitude();
+    let d = d / scale;
+    let major = torus.major_radius;
+    let minor = torus.minor_radius;
+    let distance = |p: Vector3<f32>| Vector2::new(Vector2::new(p.x, p.z).magnitude() - major, p.y).magnitude() - minor;
+
+    let bound = major + minor;
+    let b = o.dot(d);
+    let h = b * b - (o.dot(o) - bound * bound);
+    if h <= 0.0 {
+        return None;
+    }
+    let t_far = -b + h.sqrt();
+    let mut t = (-b - h.sqrt()).max(0.0);
+    if t_far <= 0.0 {
+        return None;
+    }
+
+    let epsilon = (1e-4 * minor).max(1e-6);
+    let mut found = false;
+    for _ in 0..MAX_STEPS {
+        if t > t_far {
+            break;
+        }
+        let step = distance(o + d * t).abs();
+        if step < epsilon {
+            found = true;
+            break;
+        }
+        t += step;
+    }
+    if !found {
+        return None;
+    }
+
+    for _ in 0..3 {
+        let p = o + d * t;
+        let k = p.dot(p) + major * major - minor * minor;
+        let f = k * k - 4.0 * major * major * (p.x * p.x + p.z * p.z);
+        let df = (p * 4.0 * k - Vector3::new(p.x, 0.0, p.z) * 8.0 * major * major).dot(d);
+        if df.abs() > 1e-8 {
+            t -= f / df;
+        }
+    }
+    if t <= 0.0 {
+        return None;
+    }
+
+    let p = o + d * t;
+    let ring = (Vector3::new(p.x, 0.0, p.z) + Vector3::new(1e-8, 0.0, 0.0)).normalize() * major;
+    let normal = orientation.rotate_vector((p - ring).normalize());
+    Some((t / scale, normal))
+}
+
+// same as rayPlaneIntersect in the raytracing shader
+fn ray_plane_intersect(origin: Vector3<f32>, dir: Vector3<f32>, position: Vector3<f32>, normal: Vector3<f32>) -> Option<f32> {
+    let v = dir.dot(normal);
+    if v.abs() <= 0.0 {
+        return None;
+    }
+    let t = -(origin.dot(normal) - position.dot(normal)) / v;
+    if t > 0.0 {
+        return Some(t);
+    }
+    None
+}
+
+// same as rayTriangleIntersect in the raytracing shader (Möller-Trumbore)
+fn ray_triangle_intersect(origin: Vector3<f32>, dir: Vector3<f32>, triangle: &Triangle) -> Option<f32> {
+    let v0 = Vector3::from(triangle.v0);
+    let edge1 = Vector3::from(triangle.v1) - v0;
+    let edge2 = Vector3::from(triangle.v2) - v0;
+    let p = dir.cross(edge2);
+    let det = edge1.dot(p);
+    if det.abs() < 1e-8 {
+        return None;
+    }
+    let inv_det = 1.0 / det;
+    let s = origin - v0;
+    let u = s.dot(p) * inv_det;
+    if !(0.0..=1.0).contains(&u) {
+        return None;
+    }
+    let q = s.cross(edge1);
+    let v = dir.dot(q) * inv_det;
+    if v < 0.0 || u + v > 1.0 {
+        return None;
+    }
+    let t = edge2.dot(q) * inv_det;
+    if t > 0.0 {
+        return Some(t);
+    }
+    None
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn assert_close(a: [f32; 3], b: [f32; 3]) {
+        for i in 0..3 {
+            assert!((a[i] - b[i]).abs() < 1e-3, "{:?} != {:?}", a, b);
+        }
+    }
+
+    // every test shoots down -z from z = 5
+    fn hit(world: &World) -> Option<HitRecord> {
+        world.cast_ray([0.0, 0.0, 5.0], [0.0, 0.0, -1.0], 0.001, f32::INFINITY)
+    }
+
+    #[test]
+    fn empty_world_misses() {
+        assert!(hit(&World::new()).is_none());
+    }
+
+    #[test]
+    fn sphere_hit_and_inside() {
+        let mut world = World::new();
+        world.circles.push(*Circle::new().radius(1.0).material(3));
+        let h = hit(&world).unwrap();
+        assert!((h.t - 4.0).abs() < 1e-4);
+        assert_close(h.normal, [0.0, 0.0, 1.0]);
+        assert_eq!(h.material, 3);
+
+        // from the center the far root is used
+        let h = world.cast_ray([0.0; 3], [0.0, 0.0, -1.0], 0.001, f32::INFINITY).unwrap();
+        assert!((h.t - 1.0).abs() < 1e-4);
+    }
+
+    #[test]
+    fn closest_hit_wins() {
+        let mut world = World::new();
+        world.circles.push(*Circle::new().position([0.0, 0.0, -2.0]).radius(1.0).material(1));
+        world.boxes.push(*Box::new().min([-1.0, -1.0, 0.0]).max([1.0, 1.0, 1.0]).material(2));
+        world.planes.push(*Plane::new().normal([0.0, 0.0, 1.0]).distance(-5.0).material(3));
+        let h = hit(&world).unwrap();
+        assert_eq!(h.material, 2);
+        assert!((h.t - 4.0).abs() < 1e-4);
+        assert_close(h.normal, [0.0, 0.0, 1.0]);
+
+        // t_max cuts off everything past it
+        assert!(world.cast_ray([0.0, 0.0, 5.0], [0.0, 0.0, -1.0], 0.001, 3.0).is_none());
+    }
+
+    #[test]
+    fn plane_hit() {
+        let mut world = World::new();
+        world.planes.push(*Plane::new().normal([0.0, 0.0, 1.0]).distance(-1.0));
+        let h = hit(&world).unwrap();
+        assert!((h.t - 6.0).abs() < 1e-4);
+        assert_close(h.point, [0.0, 0.0, -1.0]);
+    }
+
+    #[test]
+    fn triangle_normal_faces_the_ray() {
+        let mut world = World::new();
+        world.triangles.push(*Triangle::new([-1.0, -1.0, 0.0], [1.0, -1.0, 0.0], [0.0, 1.0, 0.0]).normal([0.0, 0.0, -1.0]));
+        let h = hit(&world).unwrap();
+        assert!((h.t - 5.0).abs() < 1e-4);
+        assert_close(h.normal, [0.0, 0.0, 1.0]);
+
+        let miss = world.cast_ray([2.0, 0.0, 5.0], [0.0, 0.0, -1.0], 0.001, f32::INFINITY);
+        assert!(miss.is_none());
+    }
+
+    #[test]
+    fn cylinder_side_and_cap() {
+        let mut world = World::new();
+        world.cylinders.push(*Cylinder::new().position([0.0, -0.5, 0.0]).radius(1.0).height(1.0));
+        let h = hit(&world).unwrap();
+        assert!((h.t - 4.0).abs() < 1e-4);
+        assert_close(h.normal, [0.0, 0.0, 1.0]);
+
+        let h = world.cast_ray([0.0, 5.0, 0.0], [0.0, -1.0, 0.0], 0.001, f32::INFINITY).unwrap();
+        assert!((h.t - 4.5).abs() < 1e-4);
+        assert_close(h.normal, [0.0, 1.0, 0.0]);
+    }
+
+    #[test]
+    fn capsule_body_and_end() {
+        let mut world = World::new();
+        world.capsules.push(*Capsule::new().start([0.0, -1.0, 0.0]).end([0.0, 1.0, 0.0]).radius(0.5));
+        let h = hit(&world).unwrap();
+        assert!((h.t - 4.5).abs() < 1e-4);
+        assert_close(h.normal, [0.0, 0.0, 1.0]);
+
+        let h = world.cast_ray([0.0, 5.0, 0.0], [0.0, -1.0, 0.0], 0.001, f32::INFINITY).unwrap();
+        assert!((h.t - 3.5).abs() < 1e-4);
+        assert_close(h.normal, [0.0, 1.0, 0.0]);
+    }
+
+    #[test]
+    fn torus_ring_and_hole() {
+        let mut world = World::new();
+        world.tori.push(*Torus::new().major_radius(1.0).minor_radius(0.25).rotation([std::f32::consts::FRAC_PI_2, 0.0, 0.0]));
+        // the ring lies in the xy plane after the rotation, so the center is the hole
+        assert!(hit(&world).is_none());
+
+        let h = world.cast_ray([1.0, 0.0, 5.0], [0.0, 0.0, -1.0], 0.001, f32::INFINITY).unwrap();
+        assert!((h.t - 4.75).abs() < 1e-3);
+        assert_close(h.normal, [0.0, 0.0, 1.0]);
+    }
+}
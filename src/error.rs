@@ -0,0 +1,93 @@
+use std::fmt;
+
+use vulkano::{LoadingError, OomError, VulkanError};
+use vulkano::command_buffer::CommandBufferBeginError;
+use vulkano::device::DeviceCreationError;
+use vulkano::device::physical::PhysicalDeviceError;
+use vulkano::image::immutable::ImmutableImageCreationError;
+use vulkano::image::view::ImageViewCreationError;
+use vulkano::instance::InstanceCreationError;
+use vulkano::render_pass::RenderPassCreationError;
+use vulkano::sampler::SamplerCreationError;
+use vulkano::shader::ShaderCreationError;
+use vulkano::swapchain::{SurfaceCreationError, SwapchainCreationError};
+use winit::error::OsError;
+
+/// Failures while setting up the window and the Vulkan objects of the renderer.
+#[derive(Debug)]
+pub enum RaytracingError {
+    LibraryLoad(LoadingError),
+    InstanceCreation(InstanceCreationError),
+    VulkanInit(VulkanError),
+    WindowCreation(OsError),
+    SurfaceCreation(SurfaceCreationError),
+    /// No physical device supports the required extensions and a graphics queue.
+    NoSuitableDevice,
+    DeviceCreation(DeviceCreationError),
+    SurfaceQuery(PhysicalDeviceError),
+    SwapchainCreation(SwapchainCreationError),
+    RenderPassCreation(RenderPassCreationError),
+    ShaderLoad(String),
+    /// Creating a sampler, image or command buffer failed.
+    ResourceCreation(String),
+    OutOfMemory(OomError),
+}
+
+impl fmt::Display for RaytracingError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Self::LibraryLoad(e) => write!(f, "Failed to load the Vulkan library: {}", e),
+            Self::InstanceCreation(e) => write!(f, "Failed to create the Vulkan instance: {}", e),
+            Self::VulkanInit(e) => write!(f, "Vulkan initialization failed: {}", e),
+            Self::WindowCreation(e) => write!(f, "Failed to create the window: {}", e),
+            Self::SurfaceCreation(e) => write!(f, "Failed to create the window surface: {}", e),
+            Self::NoSuitableDevice => write!(f, "No Vulkan device supports the required features"),
+            Self::DeviceCreation(e) => write!(f, "Failed to create the Vulkan device: {}", e),
+            Self::SurfaceQuery(e) => write!(f, "Failed to query the surface support: {}", e),
+            Self::SwapchainCreation(e) => write!(f, "Failed to create the swapchain: {}", e),
+            Self::RenderPassCreation(e) => write!(f, "Failed to create a render pass: {}", e),
+            Self::ShaderLoad(e) => write!(f, "Failed to load shader {}", e),
+            Self::ResourceCreation(e) => write!(f, "Failed to create a GPU resource: {}", e),
+            Self::OutOfMemory(e) => write!(f, "Out of memory: {}", e),
+        }
+    }
+}
+
+impl std::error::Error for RaytracingError {}
+
+macro_rules! from_error {
+    ($error:ty, $variant:ident) => {
+        impl From<$error> for RaytracingError {
+            fn from(e: $error) -> Self {
+                Self::$variant(e)
+            }
+        }
+    };
+    ($error:ty) => {
+        impl From<$error> for RaytracingError {
+            fn from(e: $error) -> Self {
+                Self::ResourceCreation(e.to_string())
+            }
+        }
+    };
+}
+
+from_error!(LoadingError, LibraryLoad);
+from_error!(InstanceCreationError, InstanceCreation);
+from_error!(VulkanError, VulkanInit);
+from_error!(OsError, WindowCreation);
+from_error!(SurfaceCreationError, SurfaceCreation);
+from_error!(DeviceCreationError, DeviceCreation);
+from_error!(PhysicalDeviceError, SurfaceQuery);
+from_error!(SwapchainCreationError, SwapchainCreation);
+from_error!(RenderPassCreationError, RenderPassCreation);
+from_error!(OomError, OutOfMemory);
+from_error!(CommandBufferBeginError);
+from_error!(SamplerCreationError);
+from_error!(ImmutableImageCreationError);
+from_error!(ImageViewCreationError);
+
+/// Maps a shader module error to `RaytracingError::ShaderLoad`, naming the shader.
+pub fn shader_error(name: &str) -> impl FnOnce(ShaderCreationError) -> RaytracingError + '_ {
+    move |e| RaytracingError::ShaderLoad(format!("{}: {}", name, e))
+}
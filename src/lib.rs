@@ -1,3 +1,4 @@
 pub mod app;
+pub mod error;
 pub mod vk;
 pub mod imgui_winit_support;
\ No newline at end of file
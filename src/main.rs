@@ -1,15 +1,59 @@
 use std::cell::RefCell;
 use std::rc::Rc;
 
-use imgui::AngleSlider;
+use imgui::{AngleSlider, Drag, SliderFlags, Ui};
 
 use raytracing_demo::app::app::App;
+use raytracing_demo::app::args;
+use raytracing_demo::app::args::Args;
+use raytracing_demo::app::config::AppConfig;
+use raytracing_demo::app::geom::NoiseAnimation;
+use raytracing_demo::app::material::Material;
+use raytracing_demo::app::settings::RenderSettings;
+
+// samples per pixel the accumulation time estimate counts down to
+const TARGET_SAMPLES: u64 = 10_000;
 
 fn main() {
+    let args = match Args::parse() {
+        Ok(args) => args,
+        Err(e) => {
+            println!("{e}");
+            println!("{}", args::USAGE);
+            std::process::exit(2);
+        }
+    };
+
     let device_name: Rc<RefCell<String>> = Rc::new(RefCell::new("Unknown".to_string()));
 
     let device_name_inner = device_name.clone();
-    let mut app = App::create(move |_run, ui, scene, info| {
+    let capabilities: Rc<RefCell<Vec<(&str, bool)>>> = Rc::new(RefCell::new(Vec::new()));
+    let capabilities_inner = capabilities.clone();
+    let mut debug_glsl: Option<String> = None;
+    // duplicates are opened in the next frame
+    let mut expand_material: Option<usize> = None;
+    let mut expand_circle: Option<usize> = None;
+    // the last window size is restored unless it is given on the command line
+    let window_size = RenderSettings::load().window_size;
+    let config = AppConfig {
+        width: args.width.unwrap_or(window_size[0]),
+        height: args.height.unwrap_or(window_size[1]),
+        device: args.device.clone(),
+        watch_shaders: args.watch_shaders,
+        reset_layout: args.reset_layout,
+        headless: args.headless,
+        ..Default::default()
+    };
+
+    let mut app = App::create(config, move |_run, ui, scene, settings, info| {
+        if let Some(_menu_bar) = ui.begin_main_menu_bar() {
+            if let Some(_menu) = ui.begin_menu("View") {
+                ui.menu_item_config("Fullscreen")
+                    .shortcut("F11")
+                    .build_with_ref(&mut settings.fullscreen);
+            }
+        }
+
         ui.window("Camera##camera")
             // .opened()
             .position([0.0, 0.0], imgui::Condition::FirstUseEver)
@@ -17,66 +61,287 @@ fn main() {
             .build(|| {
                 ui.text("Position");
                 let cam_pos = &mut scene.camera.position;
-                ui.slider("X##x", -10.0, 10.0, &mut cam_pos[0]);
-                ui.slider("Y##y", -10.0, 10.0, &mut cam_pos[1]);
-                ui.slider("Z##z", -10.0, 10.0, &mut cam_pos[2]);
+                // drags have no range, double click or ctrl click to type an exact value
+                Drag::new("X##x").speed(0.05).build(ui, &mut cam_pos[0]);
+                Drag::new("Y##y").speed(0.05).build(ui, &mut cam_pos[1]);
+                Drag::new("Z##z").speed(0.05).build(ui, &mut cam_pos[2]);
 
                 ui.text("Rotation");
                 let cam_rot = &mut scene.camera.rotation;
-                AngleSlider::new("X##rx").range_degrees(-90.0, 90.0).build(&ui, &mut cam_rot[0]);
-                AngleSlider::new("Y##ry").range_degrees(-360.0, 360.0).build(&ui, &mut cam_rot[1]);
-                AngleSlider::new("Z##rz").range_degrees(-180.0, 180.0).build(&ui, &mut cam_rot[2]);
+                drag_degrees(ui, "X##rx", &mut cam_rot[0], Some((-90.0, 90.0)));
+                drag_degrees(ui, "Y##ry", &mut cam_rot[1], None);
+                drag_degrees(ui, "Z##rz", &mut cam_rot[2], None);
+
+                ui.text("Orbit");
+                if ui.checkbox("Orbit Mode##orbit_mode", &mut scene.camera.orbit_mode) && scene.camera.target.is_none() {
+                    scene.camera.target = Some([0.0, 0.0, 0.0]);
+                }
+                if scene.camera.orbit_mode {
+                    if let Some(target) = scene.camera.target.as_mut() {
+                        Drag::new("Target X##target_x").speed(0.05).build(ui, &mut target[0]);
+                        Drag::new("Target Y##target_y").speed(0.05).build(ui, &mut target[1]);
+                        Drag::new("Target Z##target_z").speed(0.05).build(ui, &mut target[2]);
+                    }
+                }
+
+                ui.text("Field of view");
+                ui.slider("FOV##fov", 10.0, 170.0, &mut scene.camera.fov);
 
                 ui.text("Blur");
                 ui.slider("Blur##blur", 0.0, 1.0, &mut scene.camera.blur);
 
-                ui.text("Sample count");
-                ui.slider("Sample count##sample_count", 1, 512, &mut scene.sample_count);
+                ui.text("Clipping");
+                ui.slider("Near##near", 0.001, 1.0, &mut scene.camera.near_clip_distance);
+                ui.slider("Far##far", 10.0, 10000.0, &mut scene.camera.far_clip_distance);
+                let cam_pos = scene.camera.position;
+                let clipped = scene.all_circles.iter().any(|circle| {
+                    let distance = (0..3)
+                        .map(|i| (circle.position[i] - cam_pos[i]).powi(2))
+                        .sum::<f32>()
+                        .sqrt();
+                    distance + circle.radius > scene.camera.far_clip_distance
+                        || distance - circle.radius < scene.camera.near_clip_distance
+                });
+                if clipped {
+                    ui.text_colored([1.0, 0.0, 0.0, 1.0], "Some objects are clipped");
+                }
+
+                ui.text("Stereo");
+                // the left eye replaces the center view in the accumulation
+                if ui.checkbox("Anaglyph 3D##anaglyph", &mut scene.anaglyph_mode) {
+                    scene.scene_dirty = true;
+                }
+                if ui.slider("Eye separation##eye_separation", 0.0, 0.5, &mut scene.eye_separation) {
+                    scene.scene_dirty = true;
+                }
+
+                ui.text("Jitter");
+                ui.checkbox("Halton projection jitter##halton_jitter", &mut scene.use_halton_jitter);
+                ui.checkbox("Temporal anti-aliasing##taa", &mut scene.taa_enabled);
+                if scene.taa_enabled {
+                    ui.slider("Blend factor##taa_blend_factor", 0.05, 0.2, &mut scene.taa_blend_factor);
+                }
+                let mut jitter_mode = scene.jitter_mode as usize;
+                if ui.combo_simple_string(
+                    "##jitter_mode",
+                    &mut jitter_mode,
+                    &["None", "White noise", "Halton", "R2", "Blue noise"],
+                ) {
+                    scene.jitter_mode = jitter_mode as i32;
+                    scene.scene_dirty = true;
+                }
+
+                ui.text("Tone mapping");
+                if ui.radio_button_bool("Linear##tone_linear", scene.tone_mapping_mode == 0) {
+                    scene.tone_mapping_mode = 0;
+                }
+                if ui.radio_button_bool("Reinhard##tone_reinhard", scene.tone_mapping_mode == 1) {
+                    scene.tone_mapping_mode = 1;
+                }
+                if ui.radio_button_bool("ACES Filmic##tone_aces", scene.tone_mapping_mode == 2) {
+                    scene.tone_mapping_mode = 2;
+                }
+
+                ui.text("Exposure");
+                ui.slider_config("##exposure", 0.0, 10.0)
+                    .flags(imgui::SliderFlags::LOGARITHMIC)
+                    .build(&mut scene.exposure);
+                ui.checkbox("Auto-expose##auto_exposure", &mut scene.auto_exposure);
+
+                ui.checkbox("Bloom##bloom", &mut scene.bloom_enabled);
+                if scene.bloom_enabled {
+                    ui.slider("Threshold##bloom_threshold", 0.0, 10.0, &mut scene.bloom_threshold);
+                    ui.slider("Strength##bloom_strength", 0.0, 2.0, &mut scene.bloom_strength);
+                }
+
+                ui.text("Max bounce depth");
+                if ui.slider("##max_bounce_depth", 1, 32, &mut scene.max_bounce_depth) {
+                    scene.scene_dirty = true;
+                }
+
+                if ui.checkbox("Next event estimation##nee", &mut scene.use_nee) {
+                    scene.scene_dirty = true;
+                }
+
+                ui.text("Firefly clamping (0 disables)");
+                if ui.slider("##firefly_threshold", 0.0, 50.0, &mut scene.firefly_threshold) {
+                    scene.scene_dirty = true;
+                }
+
+                ui.text("Temporal blending");
+                ui.slider("##temporal_alpha", 0.0, 0.99, &mut scene.temporal_alpha);
+
+                if ui.checkbox("Russian roulette##russian_roulette", &mut scene.use_russian_roulette) {
+                    scene.scene_dirty = true;
+                }
+                if scene.use_russian_roulette && ui.slider("Min bounces##rr_min_bounces", 0, 16, &mut scene.rr_min_bounces) {
+                    scene.scene_dirty = true;
+                }
+
+                if ui.checkbox("Accumulate frames##accumulate", &mut scene.accumulate) {
+                    scene.scene_dirty = true;
+                }
+                if scene.accumulate {
+                    let total = scene.frame_index as u64 * settings.sample_count as u64;
+                    ui.text(format!("Total samples: {}", total));
+                    let samples_per_second = info.fps * settings.sample_count as f32;
+                    if total >= TARGET_SAMPLES {
+                        ui.text(format!("{} samples reached", TARGET_SAMPLES));
+                    } else if samples_per_second > 0.0 {
+                        let seconds = (TARGET_SAMPLES - total) as f32 / samples_per_second;
+                        ui.text(format!("{} samples in {:.0}s", TARGET_SAMPLES, seconds));
+                    }
+                    if ui.button("Reset Accumulation##reset_accumulation") {
+                        scene.scene_dirty = true;
+                    }
+                }
+                ui.text("Per-frame samples");
+                ui.slider("##sample_count", 1, 512, &mut settings.sample_count);
+                ui.checkbox("Sort samples by material##sort_by_material", &mut scene.sort_by_material);
 
                 ui.text("View");
-                if ui.radio_button_bool("Color##color", scene.current_view == 0) {
-                    scene.current_view = 0;
+                if ui.radio_button_bool("Color##color", settings.current_view == 0) {
+                    settings.current_view = 0;
+                }
+                if ui.radio_button_bool("Color - no denoiser##color-nd", settings.current_view == 1) {
+                    settings.current_view = 1;
+                }
+                if ui.radio_button_bool("Albedo##color", settings.current_view == 2) {
+                    settings.current_view = 2;
+                }
+                if ui.radio_button_bool("Geometric normal##color", settings.current_view == 3) {
+                    settings.current_view = 3;
                 }
-                if ui.radio_button_bool("Color - no denoiser##color-nd", scene.current_view == 1) {
-                    scene.current_view = 1;
+                if ui.radio_button_bool("Shading normal##shading_normal", settings.current_view == 12) {
+                    settings.current_view = 12;
                 }
-                if ui.radio_button_bool("Albedo##color", scene.current_view == 2) {
-                    scene.current_view = 2;
+                if ui.radio_button_bool("Normal split##normal_split", settings.current_view == 13) {
+                    settings.current_view = 13;
                 }
-                if ui.radio_button_bool("Normal##color", scene.current_view == 3) {
-                    scene.current_view = 3;
+                if ui.radio_button_bool("Depth##color", settings.current_view == 4) {
+                    settings.current_view = 4;
                 }
-                if ui.radio_button_bool("Depth##color", scene.current_view == 4) {
-                    scene.current_view = 4;
+                if ui.radio_button_bool("Variance##variance", settings.current_view == 14) {
+                    settings.current_view = 14;
+                }
+                if ui.radio_button_bool("Ambient occlusion##ao", settings.current_view == 5) {
+                    settings.current_view = 5;
+                }
+                if settings.current_view == 5 {
+                    ui.slider("AO samples##ao_sample_count", 1, 64, &mut scene.ao_sample_count);
+                    ui.slider("AO radius##ao_radius", 0.01, 10.0, &mut scene.ao_radius);
                 }
 
                 ui.text("Denoiser");
-                ui.slider("Kernel size", 0, 10, &mut scene.kernel_size);
-                ui.slider("Kernel offset", 1, 4, &mut scene.kernel_offset);
-                ui.slider("Albedo weight", 0.001, 4.0, &mut scene.denoiser_albedo_weight);
-                ui.slider("Normal weight", 0.001, 4.0, &mut scene.denoiser_normal_weight);
-                ui.slider("Depth weight", 0.001, 4.0, &mut scene.denoiser_depth_weight);
+                ui.slider("Kernel size", 0, 10, &mut settings.kernel_size);
+                ui.slider("Kernel offset", 1, 4, &mut settings.kernel_offset);
+                ui.checkbox("Separable##denoiser_separable", &mut scene.denoiser_separable);
+                ui.slider("Albedo weight", 0.001, 4.0, &mut settings.denoiser_albedo_weight);
+                ui.slider("Normal weight", 0.001, 4.0, &mut settings.denoiser_normal_weight);
+                ui.slider("Depth weight", 0.001, 4.0, &mut settings.denoiser_depth_weight);
+                ui.slider("Variance weight", 0.0, 16.0, &mut settings.denoiser_variance_weight);
+
+                if ui.button("Reset to Defaults##reset_settings") {
+                    *settings = RenderSettings {
+                        window_size: settings.window_size,
+                        ..Default::default()
+                    };
+                }
             });
         ui.window("Materials##materials")
             .position([0.0, 550.0], imgui::Condition::FirstUseEver)
             .size([300.0, 350.0], imgui::Condition::FirstUseEver)
             .build(|| {
+                let mut removed_material = None;
+                let mut duplicated_material = None;
                 scene.all_materials.iter_mut().enumerate().for_each(|(i, mat)| {
                     let _material_id = ui.push_id(i.to_string());
-                    if ui.collapsing_header(format!("Material {}", i), imgui::TreeNodeFlags::BULLET) {
+                    if expand_material == Some(i) {
+                        // not wrapped by imgui-rs
+                        unsafe { imgui::sys::igSetNextItemOpen(true, imgui::Condition::Always as i32) };
+                        expand_material = None;
+                    }
+                    let opened = ui.collapsing_header(mat.name.as_str(), imgui::TreeNodeFlags::BULLET | imgui::TreeNodeFlags::ALLOW_ITEM_OVERLAP);
+                    ui.same_line_with_pos(ui.window_content_region_max()[0] - 55.0);
+                    if ui.small_button("Dup##dup") {
+                        duplicated_material = Some(i);
+                    }
+                    ui.same_line();
+                    if ui.small_button("X##del") {
+                        removed_material = Some(i);
+                    }
+                    if opened {
+                        ui.input_text("Name##name", &mut mat.name).build();
                         if ui.color_edit3("Color##color", &mut mat.color) {
                             mat.mark_dirty();
                         }
+                        if ui.color_edit3("Checker color##color2", &mut mat.color2) {
+                            mat.mark_dirty();
+                        }
+                        if ui.slider("Checker scale##checker_scale", 0.0, 64.0, &mut mat.checker_scale) {
+                            mat.mark_dirty();
+                        }
+                        if Drag::new("UV scale##uv_scale").speed(0.05).build_array(ui, &mut mat.uv_scale) {
+                            mat.mark_dirty();
+                        }
+                        if Drag::new("UV offset##uv_offset").speed(0.01).build_array(ui, &mut mat.uv_offset) {
+                            mat.mark_dirty();
+                        }
+                        if ui.slider_config("Noise scale##noise_scale", 0.01, 10.0)
+                            .flags(imgui::SliderFlags::LOGARITHMIC)
+                            .build(&mut mat.noise_scale) {
+                            mat.mark_dirty();
+                        }
+                        if ui.slider("Noise strength##noise_strength", 0.0, 1.0, &mut mat.noise_strength) {
+                            mat.mark_dirty();
+                        }
                         if ui.color_edit3("Emission##emission", &mut mat.emission) {
                             mat.mark_dirty();
                         }
+                        if ui.slider_config("Emission Strength##emission_strength", 0.0, 100.0)
+                            .flags(imgui::SliderFlags::LOGARITHMIC)
+                            .build(&mut mat.emission_strength) {
+                            mat.mark_dirty();
+                        }
                         if ui.slider("Smoothness##smoothness", 0.0, 1.0, &mut mat.smoothness) {
                             mat.mark_dirty();
                         }
+                        if ui.slider("Metallic##metallic", 0.0, 1.0, &mut mat.metallic) {
+                            mat.mark_dirty();
+                        }
+                        if ui.slider("IOR##ior", 1.0, 3.0, &mut mat.ior) {
+                            mat.mark_dirty();
+                        }
+                        if ui.slider("Transmission##transmission", 0.0, 1.0, &mut mat.transmission) {
+                            mat.mark_dirty();
+                        }
+                        if ui.button("Debug GLSL##debug_glsl") {
+                            debug_glsl = Some(mat.debug_glsl());
+                        }
                     }
                 });
+                if let Some(i) = duplicated_material {
+                    expand_material = Some(scene.duplicate_material(i));
+                }
+                if let Some(i) = removed_material {
+                    scene.remove_material(i);
+                }
             });
 
+        if let Some(glsl) = debug_glsl.as_ref() {
+            let mut opened = true;
+            ui.window("Material GLSL##material_glsl")
+                .opened(&mut opened)
+                .position([300.0, 550.0], imgui::Condition::FirstUseEver)
+                .size([350.0, 150.0], imgui::Condition::FirstUseEver)
+                .build(|| {
+                    ui.text(glsl);
+                });
+            if !opened {
+                debug_glsl = None;
+            }
+        }
+
         ui.window("Info##info")
             .position([900.0, 0.0], imgui::Condition::FirstUseEver)
             .size([300.0, 65.0], imgui::Condition::FirstUseEver)
@@ -85,97 +350,525 @@ fn main() {
                 ui.same_line();
                 ui.text(format!("{}", info.fps));
 
+                let fps_history = &info.fps_history[..info.history_len];
+                let frame_time_history = &info.frame_time_history[..info.history_len];
+                if !fps_history.is_empty() {
+                    let (min, max, average) = history_stats(fps_history);
+                    ui.plot_lines("##fps_graph", fps_history)
+                        .graph_size([280.0, 50.0])
+                        .scale_min(0.0)
+                        .build();
+                    ui.text(format!("FPS min {:.0} max {:.0} avg {:.0}", min, max, average));
+
+                    let (min, max, average) = history_stats(frame_time_history);
+                    ui.plot_lines("##frame_time_graph", frame_time_history)
+                        .graph_size([280.0, 50.0])
+                        .scale_min(0.0)
+                        .build();
+                    ui.text(format!("Frame time min {:.2} max {:.2} avg {:.2} ms", min, max, average));
+                }
+
                 ui.text("Device: ");
                 ui.same_line();
                 ui.text(format!("{}", device_name_inner.borrow()));
+
+                if info.memory_budget {
+                    ui.text(format!("VRAM: {:.0} / {:.0} MB", info.gpu_memory_used_mb, info.gpu_memory_total_mb));
+                } else {
+                    ui.text(format!("VRAM: {:.0} MB", info.gpu_memory_total_mb));
+                }
+                ui.text(format!("Scene buffers: {}", info.scene_buffers));
+                ui.text(format!("Descriptor sets per frame: {}", info.frame_descriptor_sets));
+
+                ui.text("Accumulated frames: ");
+                ui.same_line();
+                ui.text(format!("{}", scene.frame_index));
+
+                if info.shader_reload_time.is_some_and(|t| info.time - t < 3.0) {
+                    ui.text_colored([0.5, 1.0, 0.5, 1.0], "Shader reloaded");
+                }
+
+                if info.vsync_optional {
+                    ui.checkbox("VSync##vsync", &mut settings.vsync);
+                } else {
+                    ui.text_disabled("VSync: always on");
+                }
+                ui.checkbox("Freeze geometry##freeze_geometry", &mut scene.freeze_geometry);
+                if ui.button("Force upload all##force_dirty_all") {
+                    scene.force_dirty_all();
+                }
+
+                if ui.collapsing_header("GPU Capabilities##capabilities", imgui::TreeNodeFlags::empty()) {
+                    if let Some(_table) = ui.begin_table("##capabilities_table", 2) {
+                        for (name, supported) in capabilities_inner.borrow().iter() {
+                            ui.table_next_row();
+                            ui.table_next_column();
+                            ui.text(name);
+                            ui.table_next_column();
+                            ui.text(if *supported { "Yes" } else { "No" });
+                        }
+                    }
+                }
+            });
+        ui.window("Profiler##profiler")
+            .position([600.0, 0.0], imgui::Condition::FirstUseEver)
+            .size([300.0, 100.0], imgui::Condition::FirstUseEver)
+            .build(|| {
+                let passes = ["Raytrace", "Raytrace to denoiser", "Denoiser", "ImGui", "Frame"];
+                for (name, time) in passes.iter().zip(info.pass_times_ms) {
+                    ui.text(format!("{}: {:.3} ms", name, time));
+                }
+                // toggle to compare the latency of the two ways to chain the submits
+                if info.timeline_semaphore {
+                    ui.checkbox("Timeline semaphore##timeline_semaphore", &mut settings.timeline_semaphore);
+                } else {
+                    ui.text_disabled("Timeline semaphore: not supported");
+                }
+                // compare with and without material sorting to see how much coherence it buys
+                if info.raytrace_invocations > 0 {
+                    let ns = info.pass_times_ms[0] * 1_000_000.0 / info.raytrace_invocations as f32;
+                    ui.text(format!("Raytrace invocations: {} ({:.2} ns each)", info.raytrace_invocations, ns));
+                }
+            });
+        ui.window("Lights##lights")
+            .position([900.0, 565.0], imgui::Condition::FirstUseEver)
+            .size([300.0, 250.0], imgui::Condition::FirstUseEver)
+            .build(|| {
+                if ui.checkbox("Procedural sky##sky_enabled", &mut scene.sky_enabled) {
+                    scene.scene_dirty = true;
+                }
+                if scene.sky_enabled {
+                    // the sun position as seen from the scene
+                    let [x, y, z] = scene.sun_direction;
+                    let length = (x * x + y * y + z * z).sqrt().max(f32::EPSILON);
+                    let mut azimuth = x.atan2(z);
+                    let mut elevation = (y / length).clamp(-1.0, 1.0).asin();
+                    let azimuth_changed = AngleSlider::new("Sun azimuth##sun_azimuth").range_degrees(-180.0, 180.0).build(ui, &mut azimuth);
+                    let elevation_changed = AngleSlider::new("Sun elevation##sun_elevation").range_degrees(0.0, 90.0).build(ui, &mut elevation);
+                    if azimuth_changed || elevation_changed {
+                        scene.sun_direction = [
+                            elevation.cos() * azimuth.sin(),
+                            elevation.sin(),
+                            elevation.cos() * azimuth.cos(),
+                        ];
+                        scene.scene_dirty = true;
+                    }
+                    if ui.slider("Turbidity##sun_turbidity", 1.0, 10.0, &mut scene.sun_turbidity) {
+                        scene.scene_dirty = true;
+                    }
+                }
+                ui.separator();
+
+                if ui.button("Add Directional Light##add_light") {
+                    scene.add_directional_light().direction([-0.3, -1.0, -0.3]);
+                }
+
+                let mut removed_light = None;
+                scene.all_directional_lights.iter_mut().enumerate().for_each(|(i, light)| {
+                    let _light_id = ui.push_id(i.to_string());
+                    let opened = ui.collapsing_header(format!("Directional Light {}", i), imgui::TreeNodeFlags::BULLET | imgui::TreeNodeFlags::ALLOW_ITEM_OVERLAP);
+                    ui.same_line_with_pos(ui.window_content_region_max()[0] - 20.0);
+                    if ui.small_button("X##del") {
+                        removed_light = Some(i);
+                    }
+                    if opened {
+                        // edited as the angles of the light seen from the scene, the opposite of its direction
+                        let [x, y, z] = light.direction.map(|c| -c);
+                        let mut azimuth = x.atan2(z);
+                        let mut elevation = y.clamp(-1.0, 1.0).asin();
+                        let azimuth_changed = AngleSlider::new("Azimuth##azimuth").range_degrees(-180.0, 180.0).build(ui, &mut azimuth);
+                        let elevation_changed = AngleSlider::new("Elevation##elevation").range_degrees(-90.0, 90.0).build(ui, &mut elevation);
+                        if azimuth_changed || elevation_changed {
+                            light.direction = [
+                                -elevation.cos() * azimuth.sin(),
+                                -elevation.sin(),
+                                -elevation.cos() * azimuth.cos(),
+                            ];
+                            light.mark_dirty();
+                        }
+                        if ui.color_edit3("Color##light_color", &mut light.color) {
+                            light.mark_dirty();
+                        }
+                        if ui.slider("Intensity##light_intensity", 0.0, 100.0, &mut light.intensity) {
+                            light.mark_dirty();
+                        }
+                    }
+                });
+                if let Some(i) = removed_light {
+                    scene.remove_directional_light(i);
+                }
+            });
+        ui.window("Volumes##volumes")
+            .position([600.0, 100.0], imgui::Condition::FirstUseEver)
+            .size([300.0, 250.0], imgui::Condition::FirstUseEver)
+            .build(|| {
+                if ui.button("Add Volume##add_volume") {
+                    scene.add_volume();
+                }
+
+                let mut removed_volume = None;
+                scene.all_volumes.iter_mut().enumerate().for_each(|(i, volume)| {
+                    let _volume_id = ui.push_id(i.to_string());
+                    let opened = ui.collapsing_header(format!("Volume {}", i), imgui::TreeNodeFlags::BULLET | imgui::TreeNodeFlags::ALLOW_ITEM_OVERLAP);
+                    ui.same_line_with_pos(ui.window_content_region_max()[0] - 20.0);
+                    if ui.small_button("X##del") {
+                        removed_volume = Some(i);
+                    }
+                    if opened {
+                        let mut min = volume.bounds_min;
+                        let mut max = volume.bounds_max;
+                        let min_changed = Drag::new("Min##volume_min").speed(0.05).build_array(ui, &mut min);
+                        let max_changed = Drag::new("Max##volume_max").speed(0.05).build_array(ui, &mut max);
+                        if min_changed || max_changed {
+                            volume.bounds(min, max).mark_dirty();
+                        }
+                        if ui.slider("Density##volume_density", 0.0, 5.0, &mut volume.density) {
+                            volume.mark_dirty();
+                        }
+                        if ui.color_edit3("Absorption##volume_absorption", &mut volume.absorption) {
+                            volume.mark_dirty();
+                        }
+                        if ui.color_edit3("Scattering##volume_scattering", &mut volume.scattering) {
+                            volume.mark_dirty();
+                        }
+                        let mut anisotropy = volume.anisotropy;
+                        if ui.slider("Anisotropy##volume_anisotropy", -0.99, 0.99, &mut anisotropy) {
+                            volume.anisotropy(anisotropy).mark_dirty();
+                        }
+                    }
+                });
+                if let Some(i) = removed_volume {
+                    scene.remove_volume(i);
+                }
             });
         ui.window("Objects##objects")
             .position([900.0, 65.0], imgui::Condition::FirstUseEver)
             .size([300.0, 500.0], imgui::Condition::FirstUseEver)
             .build(|| {
+                if ui.button("Center Scene at Origin##center_scene") {
+                    let center = scene.center_of_mass();
+                    scene.translate(center.map(|c| -c));
+                }
+
                 ui.text("Circles");
+                let mut removed_circle = None;
+                let mut duplicated_circle = None;
+                let materials = &scene.all_materials;
                 scene.all_circles.iter_mut().enumerate().for_each(|(i, circle)| {
                     let _circle_id = ui.push_id(i.to_string());
-                    if ui.collapsing_header(format!("Circle {}", i), imgui::TreeNodeFlags::BULLET) {
+                    if expand_circle == Some(i) {
+                        // not wrapped by imgui-rs
+                        unsafe { imgui::sys::igSetNextItemOpen(true, imgui::Condition::Always as i32) };
+                        expand_circle = None;
+                    }
+                    let opened = ui.collapsing_header(format!("Circle {}", i), imgui::TreeNodeFlags::BULLET | imgui::TreeNodeFlags::ALLOW_ITEM_OVERLAP);
+                    ui.same_line_with_pos(ui.window_content_region_max()[0] - 55.0);
+                    if ui.small_button("Dup##dup") {
+                        duplicated_circle = Some(i);
+                    }
+                    ui.same_line();
+                    if ui.small_button("X##del") {
+                        removed_circle = Some(i);
+                    }
+                    if opened {
                         ui.text("Position");
-                        if ui.slider("X##cx", -20.0, 20.0, &mut circle.position[0]) {
+                        if Drag::new("X##cx").speed(0.05).build(ui, &mut circle.position[0]) {
                             circle.mark_dirty();
                         }
-                        if ui.slider("Y##cy", -20.0, 20.0, &mut circle.position[1]) {
+                        if Drag::new("Y##cy").speed(0.05).build(ui, &mut circle.position[1]) {
                             circle.mark_dirty();
                         }
-                        if ui.slider("Z##cz", -20.0, 20.0, &mut circle.position[2]) {
+                        if Drag::new("Z##cz").speed(0.05).build(ui, &mut circle.position[2]) {
                             circle.mark_dirty();
                         }
                         ui.text("Radius");
                         if ui.slider("##cr", 0.0, 10.0, &mut circle.radius) {
                             circle.mark_dirty();
                         }
+
+                        ui.text("Material");
+                        if material_combo(ui, "##cmat", materials, &mut circle.material) {
+                            circle.mark_dirty();
+                        }
+
+                        if let Some(animation) = circle.animation.as_mut() {
+                            ui.text("Noise animation");
+                            ui.slider("Frequency##anim_frequency", 0.0, 5.0, &mut animation.frequency);
+                            ui.slider("Amplitude X##anim_ax", 0.0, 2.0, &mut animation.amplitude[0]);
+                            ui.slider("Amplitude Y##anim_ay", 0.0, 2.0, &mut animation.amplitude[1]);
+                            ui.slider("Amplitude Z##anim_az", 0.0, 2.0, &mut animation.amplitude[2]);
+                            ui.slider("Phase##anim_phase", 0.0, 5.0, &mut animation.phase_offset);
+                            if ui.button("Remove Noise Animation##remove_anim") {
+                                circle.remove_animation();
+                            }
+                        } else if ui.button("Add Noise Animation##add_anim") {
+                            circle.animation(NoiseAnimation::default());
+                        }
+                    }
+                });
+                if let Some(i) = duplicated_circle {
+                    expand_circle = Some(scene.duplicate_circle(i));
+                }
+                if let Some(i) = removed_circle {
+                    scene.remove_circle(i);
+                }
+
+                ui.separator();
+                ui.text("Meshes");
+                for (mesh, count, smooth) in scene.meshes() {
+                    let _mesh_id = ui.push_id(format!("mesh{}", mesh));
+                    if ui.collapsing_header(format!("Mesh {} ({} triangles)", mesh, count), imgui::TreeNodeFlags::BULLET) {
+                        let mut smooth_normals = smooth;
+                        if ui.checkbox("Smooth normals##smooth_normals", &mut smooth_normals) {
+                            scene.set_smooth_normals(mesh, smooth_normals);
+                        }
+                        if ui.button("Compute smooth normals##compute_normals") {
+                            scene.compute_smooth_normals(mesh);
+                        }
+                    }
+                }
+
+                ui.separator();
+                ui.text("Cylinders");
+                if ui.button("Add Cylinder##add_cylinder") {
+                    scene.add_cylinder();
+                }
+                let mut removed_cylinder = None;
+                let materials = &scene.all_materials;
+                scene.all_cylinders.iter_mut().enumerate().for_each(|(i, cylinder)| {
+                    let _cylinder_id = ui.push_id(format!("cylinder{}", i));
+                    let opened = ui.collapsing_header(format!("Cylinder {}", i), imgui::TreeNodeFlags::BULLET | imgui::TreeNodeFlags::ALLOW_ITEM_OVERLAP);
+                    ui.same_line_with_pos(ui.window_content_region_max()[0] - 20.0);
+                    if ui.small_button("X##del") {
+                        removed_cylinder = Some(i);
+                    }
+                    if opened {
+                        ui.text("Position");
+                        if Drag::new("##cyl_position").speed(0.05).build_array(ui, &mut cylinder.position) {
+                            cylinder.mark_dirty();
+                        }
+                        if ui.slider("Radius##cyl_radius", 0.0, 10.0, &mut cylinder.radius) {
+                            cylinder.mark_dirty();
+                        }
+                        if ui.slider("Height##cyl_height", 0.0, 20.0, &mut cylinder.height) {
+                            cylinder.mark_dirty();
+                        }
+                        if material_combo(ui, "Material##cyl_mat", materials, &mut cylinder.material) {
+                            cylinder.mark_dirty();
+                        }
+                    }
+                });
+                if let Some(i) = removed_cylinder {
+                    scene.remove_cylinder(i);
+                }
+
+                ui.separator();
+                ui.text("Capsules");
+                if ui.button("Add Capsule##add_capsule") {
+                    scene.add_capsule();
+                }
+                let mut removed_capsule = None;
+                let materials = &scene.all_materials;
+                scene.all_capsules.iter_mut().enumerate().for_each(|(i, capsule)| {
+                    let _capsule_id = ui.push_id(format!("capsule{}", i));
+                    let opened = ui.collapsing_header(format!("Capsule {}", i), imgui::TreeNodeFlags::BULLET | imgui::TreeNodeFlags::ALLOW_ITEM_OVERLAP);
+                    ui.same_line_with_pos(ui.window_content_region_max()[0] - 20.0);
+                    if ui.small_button("X##del") {
+                        removed_capsule = Some(i);
+                    }
+                    if opened {
+                        if Drag::new("Start##cap_start").speed(0.05).build_array(ui, &mut capsule.start) {
+                            capsule.mark_dirty();
+                        }
+                        if Drag::new("End##cap_end").speed(0.05).build_array(ui, &mut capsule.end) {
+                            capsule.mark_dirty();
+                        }
+                        if ui.slider("Radius##cap_radius", 0.0, 10.0, &mut capsule.radius) {
+                            capsule.mark_dirty();
+                        }
+                        if material_combo(ui, "Material##cap_mat", materials, &mut capsule.material) {
+                            capsule.mark_dirty();
+                        }
+                    }
+                });
+                if let Some(i) = removed_capsule {
+                    scene.remove_capsule(i);
+                }
+
+                ui.separator();
+                ui.text("Tori");
+                if ui.button("Add Torus##add_torus") {
+                    scene.add_torus();
+                }
+                let mut removed_torus = None;
+                let materials = &scene.all_materials;
+                scene.all_tori.iter_mut().enumerate().for_each(|(i, torus)| {
+                    let _torus_id = ui.push_id(format!("torus{}", i));
+                    let opened = ui.collapsing_header(format!("Torus {}", i), imgui::TreeNodeFlags::BULLET | imgui::TreeNodeFlags::ALLOW_ITEM_OVERLAP);
+                    ui.same_line_with_pos(ui.window_content_region_max()[0] - 20.0);
+                    if ui.small_button("X##del") {
+                        removed_torus = Some(i);
+                    }
+                    if opened {
+                        if Drag::new("Center##tor_center").speed(0.05).build_array(ui, &mut torus.center) {
+                            torus.mark_dirty();
+                        }
+                        if ui.slider("Major radius##tor_major", 0.0, 10.0, &mut torus.major_radius) {
+                            torus.mark_dirty();
+                        }
+                        if ui.slider("Minor radius##tor_minor", 0.0, 5.0, &mut torus.minor_radius) {
+                            torus.mark_dirty();
+                        }
+                        let mut angles = torus.rotation_angles();
+                        let mut rotated = false;
+                        for (angle, label) in angles.iter_mut().zip(["Rotation X##tor_rx", "Rotation Y##tor_ry", "Rotation Z##tor_rz"]) {
+                            rotated |= AngleSlider::new(label).range_degrees(-180.0, 180.0).build(ui, angle);
+                        }
+                        if rotated {
+                            torus.rotation(angles).mark_dirty();
+                        }
+                        if material_combo(ui, "Material##tor_mat", materials, &mut torus.material) {
+                            torus.mark_dirty();
+                        }
                     }
                 });
+                if let Some(i) = removed_torus {
+                    scene.remove_torus(i);
+                }
             });
-    });
+    }).expect("Failed to create the app");
 
     device_name.as_ref().replace(app.vulkan.device_name.clone());
+    capabilities.as_ref().replace(app.vulkan.capabilities.table());
+
+    match &args.scene {
+        Some(path) => {
+            if let Err(e) = app.load_scene(path) {
+                println!("Failed to load scene {path}: {e}");
+                std::process::exit(1);
+            }
+        }
+        None => {
+            app.add_material() // 0
+                .color([1.0, 0.2, 0.2])
+                .smoothness(0.1)
+            // .emission([1.0, 0.0, 0.0])
+            ;
+
+            app.add_material() // 1
+                .color([0.2, 1.0, 0.2])
+                .smoothness(0.5)
+            // .emission([0.0, 1.0, 0.0])
+            ;
+
+            app.add_material() // 2
+                .color([0.2, 0.2, 1.0])
+                .smoothness(0.8)
+            // .emission([0.0, 0.0, 1.0])
+            ;
+
+            const INTENSITY: f32 = 0.7;
+            app.add_material() // 3
+                .color([0.7, 1.0, 0.03])
+                .emission([1.0 * INTENSITY, 0.917 * INTENSITY, 0.564 * INTENSITY]) // 4700K
+            ;
+
+            app.add_material() // 4
+                .color([0.4, 0.4, 0.4])
+                .smoothness(0.84)
+            ;
+
+            app.add_circle()
+                .position([1.0, 0.3, 0.3])
+                .radius(0.3)
+                .material(0);
 
-    app.add_material() // 0
-        .color([1.0, 0.2, 0.2])
-        .smoothness(0.1)
-    // .emission([1.0, 0.0, 0.0])
-    ;
-
-    app.add_material() // 1
-        .color([0.2, 1.0, 0.2])
-        .smoothness(0.5)
-    // .emission([0.0, 1.0, 0.0])
-    ;
-
-    app.add_material() // 2
-        .color([0.2, 0.2, 1.0])
-        .smoothness(0.8)
-    // .emission([0.0, 0.0, 1.0])
-    ;
-
-    const INTENSITY: f32 = 0.7;
-    app.add_material() // 3
-        .color([0.7, 1.0, 0.03])
-        .emission([1.0 * INTENSITY, 0.917 * INTENSITY, 0.564 * INTENSITY]) // 4700K
-    ;
-
-    app.add_material() // 4
-        .color([0.4, 0.4, 0.4])
-        .smoothness(0.84)
-    ;
-
-    app.add_circle()
-        .position([1.0, 0.3, 0.3])
-        .radius(0.3)
-        .material(0);
-
-    app.add_circle()
-        .position([0.0, 1.3, 0.3])
-        .radius(0.3)
-        .material(1);
-
-    app.add_circle()
-        .position([-1.0, 0.3, 0.3])
-        .radius(0.3)
-        .material(2);
-
-    app.add_circle()
-        .position([-50.0, 5.0, 50.0])
-        .radius(15.0)
-        .material(3);
-
-    app.add_circle()
-        .position([80.0, 30.0, 0.0])
-        .radius(25.0)
-        .material(3);
-
-    app.add_circle()
-        .position([0.0, -100.0, 0.0])
-        .radius(100.0)
-        .material(4);
+            app.add_circle()
+                .position([0.0, 1.3, 0.3])
+                .radius(0.3)
+                .material(1);
+
+            app.add_circle()
+                .position([-1.0, 0.3, 0.3])
+                .radius(0.3)
+                .material(2);
+
+            app.add_circle()
+                .position([-50.0, 5.0, 50.0])
+                .radius(15.0)
+                .material(3);
+
+            app.add_circle()
+                .position([80.0, 30.0, 0.0])
+                .radius(25.0)
+                .material(3);
+
+            app.add_plane()
+                .normal([0.0, 1.0, 0.0])
+                .distance(0.0)
+                .material(4);
+        }
+    }
+
+    if let Some(path) = &args.environment {
+        if let Err(e) = app.set_environment_map(path) {
+            println!("Failed to load environment map {path}: {e}");
+            std::process::exit(1);
+        }
+    }
+
+    if let Some(frames) = args.frames {
+        app.set_frame_limit(frames);
+    }
 
     app.main_loop();
 }
+
+/// Minimum, maximum and average of a non-empty history.
+fn history_stats(history: &[f32]) -> (f32, f32, f32) {
+    let min = history.iter().copied().fold(f32::INFINITY, f32::min);
+    let max = history.iter().copied().fold(f32::NEG_INFINITY, f32::max);
+    let average = history.iter().sum::<f32>() / history.len() as f32;
+    (min, max, average)
+}
+
+/// Combo listing the scene materials, shown in red when `material` points to a deleted one. A
+/// deleted material is replaced by the first one, which counts as a change.
+fn material_combo(ui: &Ui, label: &str, materials: &[Material], material: &mut i32) -> bool {
+    let current = materials.get(*material as usize).filter(|_| *material >= 0);
+    let preview = match current {
+        Some(current) => format!("{}: {}", material, current.name),
+        None => format!("{}: missing", material),
+    };
+    let mut changed = false;
+    let _color = current.is_none().then(|| ui.push_style_color(imgui::StyleColor::Text, [1.0, 0.3, 0.3, 1.0]));
+    if let Some(_combo) = ui.begin_combo(label, preview) {
+        for (m, entry) in materials.iter().enumerate() {
+            let selected = *material == m as i32;
+            if ui.selectable_config(format!("{}: {}##mat{}", m, entry.name, m)).selected(selected).build() {
+                *material = m as i32;
+                changed = true;
+            }
+        }
+    }
+    drop(_color);
+    if current.is_none() && !materials.is_empty() {
+        *material = 0;
+        changed = true;
+    }
+    changed
+}
+
+/// Drags an angle stored in radians, shown in degrees and optionally clamped to `range_degrees`.
+fn drag_degrees(ui: &Ui, label: &str, radians: &mut f32, range_degrees: Option<(f32, f32)>) -> bool {
+    let mut degrees = radians.to_degrees();
+    let mut drag = Drag::new(label).speed(0.5).display_format("%.1f deg");
+    if let Some((min, max)) = range_degrees {
+        drag = drag.range(min, max).flags(SliderFlags::ALWAYS_CLAMP);
+    }
+    let changed = drag.build(ui, &mut degrees);
+    if changed {
+        *radians = degrees.to_radians();
+    }
+    changed
+}
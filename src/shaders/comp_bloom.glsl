@@ -0,0 +1,73 @@
+#version 450
+
+// One step of the bloom chain. The raytraced color is thresholded and downsampled into each
+// bloom level in turn with the 13 tap filter from Call of Duty: Advanced Warfare, then every level
+// is upsampled with a 3x3 tent and added to the one above it.
+
+layout(local_size_x = 16, local_size_y = 16) in;
+
+layout(set = 0, binding = 0) uniform sampler2D u_source;
+layout(set = 0, binding = 1, rgba32f) uniform image2D u_target;
+
+layout(push_constant) uniform BloomInfo {
+    int mode; // 0 thresholds and downsamples, 1 downsamples, 2 upsamples and adds to the target
+    float threshold; // luminance the color has to exceed to bloom
+} bloomInfo;
+
+float luminance(vec3 color) {
+    return dot(color, vec3(0.2126, 0.7152, 0.0722));
+}
+
+vec3 tap(vec2 uv, vec2 texel, float x, float y) {
+    return textureLod(u_source, uv + texel * vec2(x, y), 0.0).rgb;
+}
+
+vec3 downsample(vec2 uv, vec2 texel) {
+    vec3 a = tap(uv, texel, -2.0, -2.0);
+    vec3 b = tap(uv, texel, 0.0, -2.0);
+    vec3 c = tap(uv, texel, 2.0, -2.0);
+    vec3 d = tap(uv, texel, -1.0, -1.0);
+    vec3 e = tap(uv, texel, 1.0, -1.0);
+    vec3 f = tap(uv, texel, -2.0, 0.0);
+    vec3 g = tap(uv, texel, 0.0, 0.0);
+    vec3 h = tap(uv, texel, 2.0, 0.0);
+    vec3 i = tap(uv, texel, -1.0, 1.0);
+    vec3 j = tap(uv, texel, 1.0, 1.0);
+    vec3 k = tap(uv, texel, -2.0, 2.0);
+    vec3 l = tap(uv, texel, 0.0, 2.0);
+    vec3 m = tap(uv, texel, 2.0, 2.0);
+    // the inner box has half of the weight, the four overlapping outer boxes share the rest
+    return (d + e + i + j) * 0.125
+        + (a + b + f + g) * 0.03125
+        + (b + c + g + h) * 0.03125
+        + (f + g + k + l) * 0.03125
+        + (g + h + l + m) * 0.03125;
+}
+
+vec3 upsample(vec2 uv, vec2 texel) {
+    return (tap(uv, texel, -1.0, -1.0) + tap(uv, texel, 1.0, -1.0) + tap(uv, texel, -1.0, 1.0) + tap(uv, texel, 1.0, 1.0)
+        + (tap(uv, texel, 0.0, -1.0) + tap(uv, texel, -1.0, 0.0) + tap(uv, texel, 1.0, 0.0) + tap(uv, texel, 0.0, 1.0)) * 2.0
+        + tap(uv, texel, 0.0, 0.0) * 4.0) / 16.0;
+}
+
+void main() {
+    ivec2 pixel = ivec2(gl_GlobalInvocationID.xy);
+    ivec2 size = imageSize(u_target);
+    if (any(greaterThanEqual(pixel, size))) {
+        return;
+    }
+
+    vec2 uv = (vec2(pixel) + 0.5) / vec2(size);
+    vec2 texel = 1.0 / vec2(textureSize(u_source, 0));
+    vec3 color;
+    if (bloomInfo.mode == 2) {
+        color = imageLoad(u_target, pixel).rgb + upsample(uv, texel);
+    } else {
+        color = downsample(uv, texel);
+        if (bloomInfo.mode == 0) {
+            float l = luminance(color);
+            color *= max(l - bloomInfo.threshold, 0.0) / max(l, 1e-4);
+        }
+    }
+    imageStore(u_target, pixel, vec4(color, 1.0));
+}
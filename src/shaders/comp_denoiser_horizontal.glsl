@@ -0,0 +1,78 @@
+#version 450
+
+// Horizontal half of the separable denoiser, frag_denoiser.glsl filters the result vertically
+// with the same edge stopping weights.
+
+layout(local_size_x = 16, local_size_y = 16) in;
+
+layout(set = 0, binding = 0) uniform sampler2D u_color;
+layout(set = 0, binding = 1) uniform sampler2D u_albedo;
+layout(set = 0, binding = 2) uniform sampler2D u_normal;
+layout(set = 0, binding = 3) uniform sampler2D u_depth;
+layout(set = 0, binding = 4) uniform sampler2D u_color_right;
+layout(set = 0, binding = 5) uniform sampler2D u_variance;
+
+// filtered color, with both eyes already composed in anaglyph mode
+layout(set = 0, binding = 6, rgba32f) uniform writeonly image2D u_horizontal;
+
+layout(push_constant) uniform DenoiseInfo {
+    int kernel_size;
+    int kernel_offset;
+    float albedo_weight;
+    float normal_weight;
+    float depth_weight;
+    float variance_weight;
+    int anaglyph;
+} denoiseInfo;
+
+float luminance(vec3 color) {
+    return dot(color, vec3(0.2126, 0.7152, 0.0722));
+}
+
+// the composition is linear, so composing before filtering is the same as after
+vec3 composeAnaglyph(vec3 left, vec3 right) {
+    float left_luma = dot(left, vec3(0.299, 0.587, 0.114));
+    return vec3(left_luma, right.g, right.b);
+}
+
+void main() {
+    ivec2 coord = ivec2(gl_GlobalInvocationID.xy);
+    if (any(greaterThanEqual(coord, textureSize(u_color, 0)))) {
+        return;
+    }
+
+    vec3 center_albedo = texelFetch(u_albedo, coord, 0).rgb;
+    vec3 center_normal = texelFetch(u_normal, coord, 0).rgb;
+    float center_depth = texelFetch(u_depth, coord, 0).r;
+    float center_luminance = luminance(texelFetch(u_color, coord, 0).rgb);
+    float center_variance = texelFetch(u_variance, coord, 0).w;
+
+    vec3 color = vec3(0);
+    float total_weight = 0.0;
+
+    for (int x = -denoiseInfo.kernel_size; x <= denoiseInfo.kernel_size; x += denoiseInfo.kernel_offset) {
+        ivec2 tap = coord + ivec2(x, 0);
+        vec3 c = texelFetch(u_color, tap, 0).rgb;
+        vec3 a = texelFetch(u_albedo, tap, 0).rgb;
+        vec3 n = texelFetch(u_normal, tap, 0).rgb;
+        float d = texelFetch(u_depth, tap, 0).r;
+
+        float weight = 1.0;
+
+        weight *= exp(-dot(a - center_albedo, a - center_albedo) / denoiseInfo.albedo_weight);
+        weight *= exp(-dot(n - center_normal, n - center_normal) / denoiseInfo.normal_weight);
+        weight *= exp(-abs(d - center_depth) / denoiseInfo.depth_weight);
+        if (denoiseInfo.variance_weight > 0.0) {
+            float luminance_diff = luminance(c) - center_luminance;
+            weight *= exp(-luminance_diff * luminance_diff / (denoiseInfo.variance_weight * center_variance + 1e-4));
+        }
+
+        if (denoiseInfo.anaglyph != 0) {
+            c = composeAnaglyph(c, texelFetch(u_color_right, tap, 0).rgb);
+        }
+        color += c * weight;
+        total_weight += weight;
+    }
+
+    imageStore(u_horizontal, coord, vec4(color / total_weight, 1.0));
+}
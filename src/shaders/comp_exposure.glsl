@@ -0,0 +1,58 @@
+#version 450
+
+// Measures the log-average luminance of the accumulated color and eases the stored value
+// towards it, so the auto exposure adapts over a few frames instead of flickering.
+
+layout(local_size_x = 16, local_size_y = 16) in;
+
+layout(set = 0, binding = 0, rgba32f) uniform readonly image2D accumulation;
+
+layout(set = 0, binding = 1) buffer AutoExposure {
+    float luminance;
+} autoExposure;
+
+// only every SAMPLE_STEP-th pixel on each axis is measured
+const int SAMPLE_STEP = 8;
+const float ADAPTATION = 0.05;
+
+shared float partialSum[256];
+shared int partialCount[256];
+
+void main() {
+    ivec2 size = imageSize(accumulation);
+    ivec2 local = ivec2(gl_LocalInvocationID.xy);
+    int stride = 16 * SAMPLE_STEP;
+
+    float sum = 0.0;
+    int count = 0;
+    for (int y = local.y * SAMPLE_STEP; y < size.y; y += stride) {
+        for (int x = local.x * SAMPLE_STEP; x < size.x; x += stride) {
+            vec3 color = imageLoad(accumulation, ivec2(x, y)).rgb;
+            sum += log(1e-4 + dot(color, vec3(0.2126, 0.7152, 0.0722)));
+            count++;
+        }
+    }
+
+    uint index = gl_LocalInvocationIndex;
+    partialSum[index] = sum;
+    partialCount[index] = count;
+    barrier();
+
+    for (uint offset = 128; offset > 0; offset >>= 1) {
+        if (index < offset) {
+            partialSum[index] += partialSum[index + offset];
+            partialCount[index] += partialCount[index + offset];
+        }
+        barrier();
+    }
+
+    if (index == 0 && partialCount[0] > 0) {
+        float average = exp(partialSum[0] / float(partialCount[0]));
+        // 0 until the first measurement
+        if (autoExposure.luminance <= 0.0) {
+            autoExposure.luminance = average;
+        } else {
+            autoExposure.luminance = mix(autoExposure.luminance, average, ADAPTATION);
+        }
+    }
+}
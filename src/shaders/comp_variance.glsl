@@ -0,0 +1,72 @@
+#version 450
+
+// Estimates the variance of the accumulated color for the denoiser, like SVGF: the luminance
+// moments of each frame's sample are accumulated over time, and a spatial estimate stands in
+// while the history is still short.
+
+layout(local_size_x = 16, local_size_y = 16) in;
+
+layout(set = 0, binding = 0, rgba32f) uniform readonly image2D accumulation;
+
+// rgb is the accumulated color of the last frame, used to recover this frame's sample
+layout(set = 0, binding = 1, rgba32f) uniform image2D historyColor;
+
+// x and y are the first and second luminance moments, z the frames they cover and w the
+// variance of the accumulated color
+layout(set = 0, binding = 2, rgba32f) uniform image2D variance;
+
+layout(push_constant) uniform VarianceInfo {
+    int frame_index; // frames accumulated before this one, 0 starts over
+} varianceInfo;
+
+// frames of history before the temporal moments replace the spatial estimate
+const float MIN_TEMPORAL_FRAMES = 4.0;
+
+float luminance(vec3 color) {
+    return dot(color, vec3(0.2126, 0.7152, 0.0722));
+}
+
+void main() {
+    ivec2 pixel = ivec2(gl_GlobalInvocationID.xy);
+    ivec2 size = imageSize(accumulation);
+    if (any(greaterThanEqual(pixel, size))) {
+        return;
+    }
+
+    vec3 average = imageLoad(accumulation, pixel).rgb;
+    vec4 moments;
+    if (varianceInfo.frame_index == 0) {
+        float l = luminance(average);
+        moments = vec4(l, l * l, 1.0, 0.0);
+    } else {
+        // the accumulation is (previous * n + sample) / (n + 1)
+        float n = float(varianceInfo.frame_index);
+        vec3 previous = imageLoad(historyColor, pixel).rgb;
+        float l = luminance(average * (n + 1.0) - previous * n);
+        moments = imageLoad(variance, pixel);
+        moments.z += 1.0;
+        moments.x += (l - moments.x) / moments.z;
+        moments.y += (l * l - moments.y) / moments.z;
+    }
+    imageStore(historyColor, pixel, vec4(average, 1.0));
+
+    float sampleVariance;
+    if (moments.z >= MIN_TEMPORAL_FRAMES) {
+        sampleVariance = max(moments.y - moments.x * moments.x, 0.0);
+    } else {
+        // the neighbors average as many samples as this pixel, scale back to one sample
+        float sum = 0.0;
+        float sumSquared = 0.0;
+        for (int y = -1; y <= 1; y++) {
+            for (int x = -1; x <= 1; x++) {
+                float l = luminance(imageLoad(accumulation, clamp(pixel + ivec2(x, y), ivec2(0), size - 1)).rgb);
+                sum += l;
+                sumSquared += l * l;
+            }
+        }
+        float mean = sum / 9.0;
+        sampleVariance = max(sumSquared / 9.0 - mean * mean, 0.0) * moments.z;
+    }
+    moments.w = sampleVariance / moments.z;
+    imageStore(variance, pixel, moments);
+}
@@ -1,5 +1,8 @@
 #version 450
 
+// screen uv from vert_denoiser.glsl
+layout(location = 0) in vec2 v_uv;
+
 layout(location = 0) out vec4 f_color;
 
 layout(set = 0, binding = 0) uniform sampler2D u_color;
@@ -14,27 +17,140 @@ layout(set = 0, binding = 4) uniform RenderInfo {
     float albedo_weight;
     float normal_weight;
     float depth_weight;
+    int anaglyph;
+    int tone_mapping_mode;
+    float exposure;
+    int auto_exposure;
+    float variance_weight; // scales the variance edge stopping term, 0 disables it
+    float temporal_alpha; // share of the history in the output, 0 when the accumulation restarts
+    int separable; // only filter vertically, over the output of comp_denoiser_horizontal.glsl
+    int taa_enabled; // also store the output in u_taa_input for frag_taa.glsl
+    float bloom_strength; // weight of u_bloom added after tone mapping, 0 disables it
 } renderInfo;
 
+layout(set = 0, binding = 5) uniform sampler2D u_color_right;
+layout(set = 0, binding = 6) uniform sampler2D u_shading_normal;
+
+// written by comp_exposure.glsl
+layout(set = 0, binding = 7) readonly buffer AutoExposure {
+    float luminance;
+} autoExposure;
+
+// written by comp_variance.glsl, w is the variance of the accumulated color
+layout(set = 0, binding = 8) uniform sampler2D u_variance;
+
+// denoised color of this frame, before tone mapping, read as u_previous_history next frame
+layout(set = 0, binding = 9, rgba32f) uniform writeonly image2D u_temporal_history;
+
+// horizontally filtered color, already composed in anaglyph mode
+layout(set = 0, binding = 10) uniform sampler2D u_horizontal;
+
+layout(set = 0, binding = 11) uniform sampler2D u_ao;
+
+// u_temporal_history of the last frame
+layout(set = 0, binding = 12) uniform sampler2D u_previous_history;
+// written by frag_raytracing.glsl, screen uv now minus in the last frame
+layout(set = 0, binding = 13) uniform sampler2D u_velocity;
+
+// tone mapped output, resolved by frag_taa.glsl in the next screen pass
+layout(set = 0, binding = 14, rgba32f) uniform writeonly image2D u_taa_input;
+
+// bright parts of the color blurred by comp_bloom.glsl, at half the screen size
+layout(set = 0, binding = 15) uniform sampler2D u_bloom;
+
+// middle gray, the auto exposure maps the average luminance to it
+const float EXPOSURE_KEY = 0.18;
+
+float luminance(vec3 color) {
+    return dot(color, vec3(0.2126, 0.7152, 0.0722));
+}
+
+// red channel from the left eye, green and blue from the right eye
+vec3 composeAnaglyph(vec3 left, vec3 right) {
+    float left_luma = dot(left, vec3(0.299, 0.587, 0.114));
+    return vec3(left_luma, right.g, right.b);
+}
+
+// previous denoised color at the reprojected uv, clamped to the range of the 3x3 neighborhood
+// of this frame so disoccluded history doesn't ghost. Returns false off screen.
+bool reprojectHistory(ivec2 coord, out vec3 history) {
+    vec2 history_uv = v_uv - texelFetch(u_velocity, coord, 0).rg;
+    if (any(lessThan(history_uv, vec2(0.0))) || any(greaterThan(history_uv, vec2(1.0)))) {
+        return false;
+    }
+    history = textureLod(u_previous_history, history_uv, 0.0).rgb;
+
+    vec3 low = vec3(1e30);
+    vec3 high = vec3(-1e30);
+    for (int y = -1; y <= 1; y++) {
+        for (int x = -1; x <= 1; x++) {
+            vec3 c = texelFetch(u_color, coord + ivec2(x, y), 0).rgb;
+            if (renderInfo.anaglyph != 0) {
+                c = composeAnaglyph(c, texelFetch(u_color_right, coord + ivec2(x, y), 0).rgb);
+            }
+            low = min(low, c);
+            high = max(high, c);
+        }
+    }
+    history = clamp(history, low, high);
+    return true;
+}
+
+// Narkowicz's fit of the ACES filmic curve
+vec3 acesFilmic(vec3 x) {
+    return clamp((x * (2.51 * x + 0.03)) / (x * (2.43 * x + 0.59) + 0.14), 0.0, 1.0);
+}
+
+// 0 leaves the color linear, 1 is Reinhard and 2 is ACES filmic, applied after the exposure
+vec3 toneMap(vec3 color) {
+    float exposure = renderInfo.exposure;
+    if (renderInfo.auto_exposure != 0 && autoExposure.luminance > 0.0) {
+        exposure *= EXPOSURE_KEY / autoExposure.luminance;
+    }
+    color *= exposure;
+    switch (renderInfo.tone_mapping_mode) {
+        case 1: return color / (1.0 + color);
+        case 2: return acesFilmic(color);
+    }
+    return color;
+}
+
 void main() {
 
     int view = renderInfo.selected_view;
 
     if (view != 0) {
         switch (view) {
-            case 1: f_color = texelFetch(u_color, ivec2(gl_FragCoord), 0); break;
+            case 1:
+                f_color = texelFetch(u_color, ivec2(gl_FragCoord), 0);
+                if (renderInfo.anaglyph != 0) {
+                    f_color.rgb = composeAnaglyph(f_color.rgb, texelFetch(u_color_right, ivec2(gl_FragCoord), 0).rgb);
+                }
+                f_color.rgb = toneMap(f_color.rgb);
+                break;
             case 2: f_color = vec4(texelFetch(u_albedo, ivec2(gl_FragCoord), 0).rgb, 1.0); break;
             case 3: f_color = vec4(texelFetch(u_normal, ivec2(gl_FragCoord), 0).rgb, 1.0); break;
             case 4: f_color = vec4(vec3(
             texelFetch(u_depth, ivec2(gl_FragCoord), 0).r
             ), 1.0); break;
+            case 5: f_color = vec4(vec3(texelFetch(u_ao, ivec2(gl_FragCoord), 0).r), 1.0); break;
+            case 14: f_color = vec4(vec3(sqrt(texelFetch(u_variance, ivec2(gl_FragCoord), 0).w)), 1.0); break;
+            case 12: f_color = vec4(texelFetch(u_shading_normal, ivec2(gl_FragCoord), 0).rgb, 1.0); break;
+            case 13: // geometric normals on the left half, shading normals on the right half
+                if (gl_FragCoord.x < textureSize(u_normal, 0).x * 0.5) {
+                    f_color = vec4(texelFetch(u_normal, ivec2(gl_FragCoord), 0).rgb, 1.0);
+                } else {
+                    f_color = vec4(texelFetch(u_shading_normal, ivec2(gl_FragCoord), 0).rgb, 1.0);
+                }
+                break;
         }
         return;
     }
 
     ivec2 coord = ivec2(gl_FragCoord);
 
-    int kernel_width = renderInfo.kernel_size;
+    bool separable = renderInfo.separable != 0;
+    int kernel_width = separable ? 0 : renderInfo.kernel_size;
     int kernel_height = renderInfo.kernel_size;
 
     int kernel_offset = renderInfo.kernel_offset;
@@ -44,11 +160,14 @@ void main() {
     float depth_weight = renderInfo.depth_weight;
 
     vec3 color = vec3(0);
+    vec3 color_right = vec3(0);
     float total_weight = 0.0;
 
     vec3 center_albedo = texelFetch(u_albedo, coord, 0).rgb;
     vec3 center_normal = texelFetch(u_normal, coord, 0).rgb;
     float center_depth = texelFetch(u_depth, coord, 0).r;
+    float center_luminance = luminance(texelFetch(u_color, coord, 0).rgb);
+    float center_variance = texelFetch(u_variance, coord, 0).w;
 
     for (int x = -kernel_width; x <= kernel_width; x += kernel_offset) {
         for (int y = -kernel_height; y <= kernel_height; y += kernel_offset) {
@@ -63,11 +182,37 @@ void main() {
             weight *= exp(-dot(a - center_albedo, a - center_albedo) / albedo_weight);
             weight *= exp(-dot(n - center_normal, n - center_normal) / normal_weight);
             weight *= exp(-abs(d - center_depth) / depth_weight);
+            // noisy pixels are blurred more, converged ones keep their detail
+            if (renderInfo.variance_weight > 0.0) {
+                float luminance_diff = luminance(c) - center_luminance;
+                weight *= exp(-luminance_diff * luminance_diff / (renderInfo.variance_weight * center_variance + 1e-4));
+            }
 
-            color += c * weight;
+            color += (separable ? texelFetch(u_horizontal, coord + ivec2(x, y), 0).rgb : c) * weight;
             total_weight += weight;
+
+            if (renderInfo.anaglyph != 0 && !separable) {
+                color_right += texelFetch(u_color_right, coord + ivec2(x, y), 0).rgb * weight;
+            }
         }
     }
 
-    f_color = vec4(color / total_weight, 1.0);
+    color /= total_weight;
+    if (renderInfo.anaglyph != 0 && !separable) {
+        color = composeAnaglyph(color, color_right / total_weight);
+    }
+
+    vec3 history;
+    if (renderInfo.temporal_alpha > 0.0 && reprojectHistory(coord, history)) {
+        color = mix(color, history, renderInfo.temporal_alpha);
+    }
+    imageStore(u_temporal_history, coord, vec4(color, 1.0));
+
+    f_color = vec4(toneMap(color), 1.0);
+    if (renderInfo.bloom_strength > 0.0) {
+        f_color.rgb += renderInfo.bloom_strength * toneMap(textureLod(u_bloom, v_uv, 0.0).rgb);
+    }
+    if (renderInfo.taa_enabled != 0) {
+        imageStore(u_taa_input, coord, f_color);
+    }
 }
\ No newline at end of file
@@ -4,24 +4,69 @@ layout(location = 0) out vec4 f_color;
 layout(location = 1) out vec3 f_albedo;
 layout(location = 2) out vec3 f_normal;
 layout(location = 3) out float f_depth;
+layout(location = 4) out vec4 f_color_right;
+layout(location = 5) out vec3 f_shading_normal;
+layout(location = 6) out float f_ao;
+layout(location = 7) out vec2 f_velocity;
 
 layout(location = 0) in vec2 coord;
 
-layout(set = 0, binding = 0) uniform ViewData {
+// the camera matrices, pushed for every draw, 128 bytes is the smallest limit devices report
+layout(push_constant) uniform ViewData {
     mat4 worldview;
     mat4 proj;
-    float blur;
 } viewData;
 
 layout(set = 0, binding = 1) uniform RenderInfo {
     float time;
     int sample_count;
+    int sort_by_material;
+    int anaglyph;
+    float eye_separation;
+    int jitter_mode;
+    int frame;
+    int frame_index; // frames accumulated since the last change, 0 starts over
+    int max_bounce_depth;
+    int use_russian_roulette;
+    int rr_min_bounces;
+    int stratum_x; // columns of the sub-pixel strata grid
+    int stratum_y; // rows, stratum_x * stratum_y >= sample_count
+    float blur;
+    float near;
+    float far;
+    int use_environment_map; // sample environmentMap instead of the sky gradient
+    int sky_enabled; // Preetham sky instead of the sky gradient
+    vec3 sun_direction; // towards the sun
+    float sun_turbidity;
+    int use_nee; // sample the emissive circles directly at every bounce
+    int light_count; // entries of lights.list in use
+    float firefly_threshold; // standard deviations a sample may exceed its neighborhood by, 0 disables
+    int ao_sample_count; // rays per pixel for the ambient occlusion buffer, 0 skips it
+    float ao_radius; // occluders farther than this don't count
+    mat4 prev_view; // world to view transform of the last frame, the inverse of its worldview
+    mat4 prev_proj; // projection of the last frame, without the Halton jitter
 } renderInfo;
 
+layout(set = 0, binding = 2) uniform sampler2D blueNoise;
+
+layout(set = 0, binding = 3, rgba32f) uniform image2D accumulation;
+
 struct Material {
     vec3 color;
     vec3 emission;
     float smoothness;
+    vec3 f0;
+    float metallic;
+    float ior;
+    float transmission;
+    int albedo_texture; // index in materialTextures, -1 for none
+    int normal_texture; // same as albedo_texture
+    vec3 color2; // alternates with color in the checker pattern
+    float checker_scale; // checker squares per unit of uv, 0 is a flat color
+    vec2 uv_scale; // tiling of the textures and the checker, applied before uv_offset
+    vec2 uv_offset;
+    float noise_scale; // frequency of the Perlin noise over world positions
+    float noise_strength; // how far the noise blends towards color2, 0 disables it
 };
 
 layout(set = 1, binding = 0) readonly buffer MaterialBuffer {
@@ -38,14 +83,159 @@ layout(set = 1, binding = 1) readonly buffer CircleBuffer {
     Circle list[];
 } circles;
 
+struct Box {
+    vec3 min;
+    int material;
+    vec3 max;
+};
+
+layout(set = 1, binding = 2) readonly buffer BoxBuffer {
+    Box list[];
+} boxes;
+
+struct Plane {
+    vec3 normal;
+    float distance;
+    int material;
+};
+
+layout(set = 1, binding = 3) readonly buffer PlaneBuffer {
+    Plane list[];
+} planes;
+
+struct Triangle {
+    vec3 v0;
+    int material;
+    vec3 v1;
+    int smooth_normals; // shade with n0, n1 and n2 interpolated instead of normal
+    vec3 v2;
+    vec3 normal;
+    vec3 n0;
+    vec3 n1;
+    vec3 n2;
+};
+
+layout(set = 1, binding = 4) readonly buffer TriangleBuffer {
+    Triangle list[];
+} triangles;
+
+// capped and standing on the y axis, position is the center of the bottom cap
+struct Cylinder {
+    vec3 position;
+    float radius;
+    float height;
+    int material;
+};
+
+layout(set = 1, binding = 12) readonly buffer CylinderBuffer {
+    Cylinder list[];
+} cylinders;
+
+// points within radius of the segment from start to end
+struct Capsule {
+    vec3 start;
+    float radius;
+    vec3 end;
+    int material;
+};
+
+layout(set = 1, binding = 13) readonly buffer CapsuleBuffer {
+    Capsule list[];
+} capsules;
+
+// the tube of minor_radius around the circle of major_radius in the xz plane, rotated by the
+// orientation quaternion (xyz is the vector part)
+struct Torus {
+    vec4 orientation;
+    vec3 center;
+    float major_radius;
+    float minor_radius;
+    int material;
+};
+
+layout(set = 1, binding = 14) readonly buffer TorusBuffer {
+    Torus list[];
+} tori;
+
+// sphere tracing steps before a torus is considered missed, only grazing rays take many
+#define TORUS_MAX_STEPS 128
+
+// inner nodes have count == 0 and their children at left_or_first and left_or_first + 1,
+// leaves have their circles at bvhIndices.list[left_or_first .. left_or_first + count]
+struct BvhNode {
+    vec3 min;
+    uint left_or_first;
+    vec3 max;
+    uint count;
+};
+
+layout(set = 1, binding = 5) readonly buffer BvhBuffer {
+    BvhNode list[];
+} bvh;
+
+layout(set = 1, binding = 6) readonly buffer BvhIndexBuffer {
+    uint list[];
+} bvhIndices;
+
+#define BVH_STACK_SIZE 64
+
+#define MAX_MATERIAL_TEXTURES 8
+
+layout(set = 1, binding = 7) uniform sampler2D materialTextures[MAX_MATERIAL_TEXTURES];
+
+// direction is the way the light travels, towards the scene
+struct DirectionalLight {
+    vec3 direction;
+    float intensity;
+    vec3 color;
+};
+
+layout(set = 1, binding = 8) readonly buffer DirectionalLightBuffer {
+    DirectionalLight list[];
+} directionalLights;
+
+// indices in circles.list of the circles with an emissive material
+layout(set = 1, binding = 10) readonly buffer LightBuffer {
+    uint list[];
+} lights;
+
+struct Volume {
+    vec3 bounds_min;
+    float density;
+    vec3 bounds_max;
+    float anisotropy; // Henyey-Greenstein g, positive scatters forward
+    vec3 absorption; // per unit of distance at density 1
+    vec3 scattering;
+};
+
+// homogeneous participating media, an empty scene has one with no density
+layout(set = 1, binding = 11) readonly buffer VolumeBuffer {
+    Volume list[];
+} volumes;
+
+// upper bound of the tentative collisions per volume crossing, see trackVolumes
+#define MAX_VOLUME_STEPS 256
+
+// latitude-longitude image, +y at the top row
+layout(set = 1, binding = 9) uniform sampler2D environmentMap;
+
+// cosine of the angular radius of a directional light, about 2.5 degrees so that bounced paths
+// find it often enough
+#define DIRECTIONAL_LIGHT_COS 0.999
+
+// upper bound for renderInfo.max_bounce_depth
 #define MAX_BOUNCE 32
 //#define SAMPLES 16
 
 struct HitResult {
     float distance;
-    vec3 normal;
+    vec3 normal; // geometric normal
+    vec3 shading_normal; // normal used for shading, after any perturbation
     vec3 location;
     int material;
+    vec2 uv;
+    vec3 tangent; // direction of increasing uv.x
+    int circle; // index in circles.list, -1 for the other objects
 };
 
 struct Ray {
@@ -79,6 +269,148 @@ Material getMaterial(int index) {
     return materials.list[index];
 }
 
+vec4 sampleMaterialTexture(int slot, vec2 uv) {
+    vec4 value = vec4(0.0);
+    for (int i = 0; i < MAX_MATERIAL_TEXTURES; i++) {
+        // indexing with the loop counter keeps the access dynamically uniform
+        if (i == slot) {
+            value = textureLod(materialTextures[i], uv, 0.0);
+        }
+    }
+    return value;
+}
+
+// same table as noise.rs
+const int PERMUTATION[256] = int[256](
+    151, 160, 137, 91, 90, 15, 131, 13, 201, 95, 96, 53, 194, 233, 7, 225,
+    140, 36, 103, 30, 69, 142, 8, 99, 37, 240, 21, 10, 23, 190, 6, 148,
+    247, 120, 234, 75, 0, 26, 197, 62, 94, 252, 219, 203, 117, 35, 11, 32,
+    57, 177, 33, 88, 237, 149, 56, 87, 174, 20, 125, 136, 171, 168, 68, 175,
+    74, 165, 71, 134, 139, 48, 27, 166, 77, 146, 158, 231, 83, 111, 229, 122,
+    60, 211, 133, 230, 220, 105, 92, 41, 55, 46, 245, 40, 244, 102, 143, 54,
+    65, 25, 63, 161, 1, 216, 80, 73, 209, 76, 132, 187, 208, 89, 18, 169,
+    200, 196, 135, 130, 116, 188, 159, 86, 164, 100, 109, 198, 173, 186, 3, 64,
+    52, 217, 226, 250, 124, 123, 5, 202, 38, 147, 118, 126, 255, 82, 85, 212,
+    207, 206, 59, 227, 47, 16, 58, 17, 182, 189, 28, 42, 223, 183, 170, 213,
+    119, 248, 152, 2, 44, 154, 163, 70, 221, 153, 101, 155, 167, 43, 172, 9,
+    129, 22, 39, 253, 19, 98, 108, 110, 79, 113, 224, 232, 178, 185, 112, 104,
+    218, 246, 97, 228, 251, 34, 242, 193, 238, 210, 144, 12, 191, 179, 162, 241,
+    81, 51, 145, 235, 249, 14, 239, 107, 49, 192, 214, 31, 181, 199, 106, 157,
+    184, 84, 204, 176, 115, 121, 50, 45, 127, 4, 150, 254, 138, 236, 205, 93,
+    222, 114, 67, 29, 24, 72, 243, 141, 128, 195, 78, 66, 215, 61, 156, 180
+);
+
+float perlinFade(float t) {
+    return t * t * t * (t * (t * 6.0 - 15.0) + 10.0);
+}
+
+// dot product with one of the 12 cube edge directions
+float perlinGrad(int hash, vec3 p) {
+    int h = hash & 15;
+    float u = h < 8 ? p.x : p.y;
+    float v = h < 4 ? p.y : (h == 12 || h == 14 ? p.x : p.z);
+    return ((h & 1) == 0 ? u : -u) + ((h & 2) == 0 ? v : -v);
+}
+
+// Improved Perlin noise (Ken Perlin, 2002), roughly in [-1, 1], same as perlin3d in noise.rs
+float perlin(vec3 position) {
+    ivec3 i = ivec3(floor(position)) & 255;
+    vec3 p = fract(position);
+    vec3 f = vec3(perlinFade(p.x), perlinFade(p.y), perlinFade(p.z));
+
+    int a = PERMUTATION[i.x] + i.y;
+    int aa = PERMUTATION[a & 255] + i.z;
+    int ab = PERMUTATION[(a + 1) & 255] + i.z;
+    int b = PERMUTATION[(i.x + 1) & 255] + i.y;
+    int ba = PERMUTATION[b & 255] + i.z;
+    int bb = PERMUTATION[(b + 1) & 255] + i.z;
+
+    return mix(
+        mix(
+            mix(perlinGrad(PERMUTATION[aa & 255], p), perlinGrad(PERMUTATION[ba & 255], p - vec3(1, 0, 0)), f.x),
+            mix(perlinGrad(PERMUTATION[ab & 255], p - vec3(0, 1, 0)), perlinGrad(PERMUTATION[bb & 255], p - vec3(1, 1, 0)), f.x),
+            f.y
+        ),
+        mix(
+            mix(perlinGrad(PERMUTATION[(aa + 1) & 255], p - vec3(0, 0, 1)), perlinGrad(PERMUTATION[(ba + 1) & 255], p - vec3(1, 0, 1)), f.x),
+            mix(perlinGrad(PERMUTATION[(ab + 1) & 255], p - vec3(0, 1, 1)), perlinGrad(PERMUTATION[(bb + 1) & 255], p - vec3(1, 1, 1)), f.x),
+            f.y
+        ),
+        f.z
+    );
+}
+
+vec2 materialUv(Material m, vec2 uv) {
+    return uv * m.uv_scale + m.uv_offset;
+}
+
+// The albedo texture replaces the flat color when the material has one, otherwise the checker
+// pattern alternates it with color2. The noise then blends towards color2, it follows the world
+// position so it has no seams where the uv wraps.
+vec3 getAlbedo(int index, vec2 uv, vec3 position) {
+    Material m = getMaterial(index);
+    if (index < 0) {
+        return m.color;
+    }
+    vec3 albedo = m.color;
+    uv = materialUv(m, uv);
+    if (m.albedo_texture >= 0) {
+        albedo = sampleMaterialTexture(m.albedo_texture, uv).rgb;
+    } else if (m.checker_scale > 0.0) {
+        vec2 square = floor(uv * m.checker_scale);
+        albedo = mod(square.x + square.y, 2.0) < 1.0 ? m.color : m.color2;
+    }
+    if (m.noise_strength > 0.0) {
+        float noise = perlin(position * m.noise_scale) * 0.5 + 0.5;
+        albedo = mix(albedo, m.color2, clamp(m.noise_strength * noise, 0.0, 1.0));
+    }
+    return albedo;
+}
+
+// perturbs the shading normal with the material's tangent space normal map, if any
+HitResult applyNormalMap(HitResult hit) {
+    if (hit.material < 0) {
+        return hit;
+    }
+    Material m = getMaterial(hit.material);
+    if (m.normal_texture < 0) {
+        return hit;
+    }
+    // around the interpolated normal of smooth triangles, the geometric one elsewhere
+    vec3 normal = hit.shading_normal;
+    vec3 tangent = hit.tangent - normal * dot(normal, hit.tangent);
+    if (dot(tangent, tangent) < 1e-8) {
+        return hit;
+    }
+    tangent = normalize(tangent);
+    mat3 tbn = mat3(tangent, cross(normal, tangent), normal);
+    vec3 mapped = sampleMaterialTexture(m.normal_texture, materialUv(m, hit.uv)).xyz * 2.0 - 1.0;
+    hit.shading_normal = normalize(tbn * mapped);
+    return hit;
+}
+
+#define MATERIAL_CLASS_DIFFUSE 0
+#define MATERIAL_CLASS_METALLIC 1
+#define MATERIAL_CLASS_DIELECTRIC 2
+#define MATERIAL_CLASS_COUNT 3
+
+// groups materials by the BRDF branch they take, used to keep shading coherent
+int getMaterialClass(int index) {
+    if (index < 0) {
+        return MATERIAL_CLASS_DIFFUSE;
+    }
+    Material m = materials.list[index];
+    if (m.transmission > 0.0) {
+        return MATERIAL_CLASS_DIELECTRIC;
+    }
+    if (m.metallic >= 0.5) {
+        return MATERIAL_CLASS_METALLIC;
+    }
+    return MATERIAL_CLASS_DIFFUSE;
+}
+
+#define PI 3.14159265359
+
 float rand(inout uint state) {
     state = state * 1103515245 + 104723;
     return 1.0 - float(state) / float(uint(-1));
@@ -91,6 +423,21 @@ vec3 randDirection(inout uint state) {
     return normalize(vec3(x, y, z));
 }
 
+// any two directions perpendicular to n and to each other
+void basis(vec3 n, out vec3 u, out vec3 v) {
+    u = normalize(cross(abs(n.x) > 0.5 ? vec3(0.0, 1.0, 0.0) : vec3(1.0, 0.0, 0.0), n));
+    v = cross(n, u);
+}
+
+// cosine weighted, the pdf is dot(direction, normal) / PI
+vec3 randCosineHemisphere(inout uint state, vec3 normal) {
+    float r = sqrt(rand(state));
+    float phi = 2.0 * PI * rand(state);
+    vec3 u, v;
+    basis(normal, u, v);
+    return normalize(u * r * cos(phi) + v * r * sin(phi) + normal * sqrt(max(1.0 - r * r, 0.0)));
+}
+
 vec3 randHemisphere(inout uint state, vec3 normal) {
     vec3 direction = randDirection(state);
     if (dot(direction, normal) < 0.0) {
@@ -103,7 +450,110 @@ vec3 lerp(vec3 a, vec3 b, float t) {
     return a + (b - a) * t;
 }
 
+vec3 fresnelSchlick(vec3 f0, float cosTheta) {
+    return f0 + (1.0 - f0) * pow(1.0 - cosTheta, 5.0);
+}
+
+// Picks between reflection and Snell refraction with the Schlick Fresnel term as probability,
+// so both are weighted by it. Handles leaving the medium and total internal reflection.
+Ray refractRay(Ray ray, HitResult hit, float ior, inout uint rngState) {
+    bool entering = dot(ray.direction, hit.normal) < 0.0;
+    vec3 normal = entering ? hit.normal : -hit.normal;
+    float eta = entering ? 1.0 / ior : ior;
+
+    float cosTheta = min(dot(-ray.direction, normal), 1.0);
+    float sinTheta2 = 1.0 - cosTheta * cosTheta;
+    bool totalInternalReflection = eta * eta * sinTheta2 > 1.0;
+
+    float r0 = (1.0 - ior) / (1.0 + ior);
+    float reflectance = fresnelSchlick(vec3(r0 * r0), cosTheta).x;
+
+    Ray next;
+    if (totalInternalReflection || rand(rngState) < reflectance) {
+        next.origin = hit.location + normal * 0.001;
+        next.direction = reflect(ray.direction, normal);
+    } else {
+        next.origin = hit.location - normal * 0.001;
+        next.direction = refract(ray.direction, normal, eta);
+    }
+    return next;
+}
+
+// MIS weight of the environment sample taken along the mirror direction of a near-mirror
+// material, against the path traced bounce reaching the sky through the same lobe
+// reflectance at normal incidence, dielectrics use f0 and metals are tinted by their color
+vec3 specularF0(Material m) {
+    return mix(m.f0, m.color, m.metallic);
+}
+
+float environmentWeight(Material m, vec3 normal, vec3 direction) {
+    // the specular lobe is treated as a Phong lobe, sharper as the surface gets smoother
+    float roughness = max(1.0 - m.smoothness, 0.01);
+    float exponent = 2.0 / (roughness * roughness) - 2.0;
+    float envPdf = (exponent + 1.0) / (2.0 * PI);
+    float brdfPdf = max(dot(normal, direction), 0.0) / PI;
+    return envPdf / (envPdf + brdfPdf);
+}
+
+// the Preetham sky gives the luminance in kcd/m2, a clear noon zenith is about 6
+#define SKY_LUMINANCE_SCALE 0.1
+
+// Perez distribution of the sky relative to its zenith, theta from the zenith and gamma from the sun
+float perez(float theta, float gamma, float A, float B, float C, float D, float E) {
+    return (1.0 + A * exp(B / max(cos(theta), 0.01))) * (1.0 + C * exp(D * gamma) + E * cos(gamma) * cos(gamma));
+}
+
+// "A Practical Analytic Model for Daylight", Preetham, Shirley and Smits 1999
+vec3 getPreethamSky(vec3 direction) {
+    float T = clamp(renderInfo.sun_turbidity, 1.0, 10.0);
+    vec3 sun = normalize(renderInfo.sun_direction);
+    float thetaS = acos(clamp(sun.y, 0.0, 1.0));
+    // below the horizon shows the horizon
+    vec3 d = normalize(vec3(direction.x, max(direction.y, 0.0), direction.z));
+    float theta = acos(clamp(d.y, 0.0, 1.0));
+    float gamma = acos(clamp(dot(d, sun), -1.0, 1.0));
+
+    float chi = (4.0 / 9.0 - T / 120.0) * (PI - 2.0 * thetaS);
+    float zenithY = (4.0453 * T - 4.9710) * tan(chi) - 0.2155 * T + 2.4192;
+    vec4 thetas = vec4(thetaS * thetaS * thetaS, thetaS * thetaS, thetaS, 1.0);
+    vec3 turbidity = vec3(T * T, T, 1.0);
+    float zenithX = dot(turbidity, vec3(
+        dot(thetas, vec4(0.00166, -0.00375, 0.00209, 0.0)),
+        dot(thetas, vec4(-0.02903, 0.06377, -0.03202, 0.00394)),
+        dot(thetas, vec4(0.11693, -0.21196, 0.06052, 0.25886))));
+    float zenithYChroma = dot(turbidity, vec3(
+        dot(thetas, vec4(0.00275, -0.00610, 0.00317, 0.0)),
+        dot(thetas, vec4(-0.04214, 0.08970, -0.04153, 0.00516)),
+        dot(thetas, vec4(0.15346, -0.26756, 0.06670, 0.26688))));
+
+    float Y = zenithY * perez(theta, gamma, 0.1787 * T - 1.4630, -0.3554 * T + 0.4275, -0.0227 * T + 5.3251, 0.1206 * T - 2.5771, -0.0670 * T + 0.3703)
+        / perez(0.0, thetaS, 0.1787 * T - 1.4630, -0.3554 * T + 0.4275, -0.0227 * T + 5.3251, 0.1206 * T - 2.5771, -0.0670 * T + 0.3703);
+    float x = zenithX * perez(theta, gamma, -0.0193 * T - 0.2592, -0.0665 * T + 0.0008, -0.0004 * T + 0.2125, -0.0641 * T - 0.8989, -0.0033 * T + 0.0452)
+        / perez(0.0, thetaS, -0.0193 * T - 0.2592, -0.0665 * T + 0.0008, -0.0004 * T + 0.2125, -0.0641 * T - 0.8989, -0.0033 * T + 0.0452);
+    float y = zenithYChroma * perez(theta, gamma, -0.0167 * T - 0.2608, -0.0950 * T + 0.0092, -0.0079 * T + 0.2102, -0.0441 * T - 1.6537, -0.0109 * T + 0.0529)
+        / perez(0.0, thetaS, -0.0167 * T - 0.2608, -0.0950 * T + 0.0092, -0.0079 * T + 0.2102, -0.0441 * T - 1.6537, -0.0109 * T + 0.0529);
+
+    // xyY to XYZ to linear sRGB
+    Y = max(Y, 0.0) * SKY_LUMINANCE_SCALE;
+    vec3 XYZ = vec3(x / y * Y, Y, (1.0 - x - y) / y * Y);
+    vec3 rgb = mat3(
+        3.2406, -0.9689, 0.0557,
+        -1.5372, 1.8758, -0.2040,
+        -0.4986, 0.0415, 1.0570) * XYZ;
+    return max(rgb, vec3(0.0));
+}
+
 vec3 getAmbientLight(Ray ray) { // make a background sky color with a sun
+    if (renderInfo.use_environment_map != 0) {
+        vec3 d = normalize(ray.direction);
+        vec2 uv = vec2(atan(d.z, d.x) / (2.0 * PI) + 0.5, acos(clamp(d.y, -1.0, 1.0)) / PI);
+        // explicit level, implicit derivatives aren't defined in the divergent path tracing loop
+        return textureLod(environmentMap, uv, 0.0).rgb;
+    }
+    if (renderInfo.sky_enabled != 0) {
+        return getPreethamSky(ray.direction);
+    }
+
     vec3 ray_origin = (viewData.worldview * vec4(vec3(0.0), 1.0)).xyz;
     vec3 ray_direction = normalize(ray.direction);
 
@@ -115,6 +565,19 @@ vec3 getAmbientLight(Ray ray) { // make a background sky color with a sun
     return lerp(sky_color, sun_color, sun_angle);
 }
 
+// light of the directional lights a ray leaving the scene looks into
+vec3 getDirectionalLight(Ray ray) {
+    vec3 direction = normalize(ray.direction);
+    vec3 light = vec3(0.0);
+    for (int i = 0; i < directionalLights.list.length(); i++) {
+        DirectionalLight l = directionalLights.list[i];
+        if (dot(direction, -l.direction) > DIRECTIONAL_LIGHT_COS) {
+            light += l.color * l.intensity;
+        }
+    }
+    return light;
+}
+
 bool raySphereIntersect(Ray ray, vec3 spherePosition, float sphereradius, out HitResult result) {
     vec3 oc = ray.origin - spherePosition;
     float a = dot(ray.direction, ray.direction);
@@ -123,16 +586,305 @@ bool raySphereIntersect(Ray ray, vec3 spherePosition, float sphereradius, out Hi
     float discriminant = b * b - 4 * a * c;
     if (discriminant > 0) {
         float t = (-b - sqrt(discriminant)) / (2.0 * a);
+        if (t <= 0) {
+            // the ray starts inside the sphere, e.g. after refracting into it
+            t = (-b + sqrt(discriminant)) / (2.0 * a);
+        }
         if (t > 0) {
             result.distance = t;
             result.location = ray.origin + ray.direction * t;
             result.normal = normalize(result.location - spherePosition);
+            result.shading_normal = result.normal;
+            // spherical mapping, the tangent follows the longitude
+            result.uv = vec2(
+                0.5 + atan(result.normal.z, result.normal.x) / (2.0 * PI),
+                0.5 - asin(result.normal.y) / PI
+            );
+            result.tangent = vec3(-result.normal.z, 0.0, result.normal.x);
             return true;
         }
     }
     return false;
 }
 
+// slab test, only tells whether the box is hit closer than maxDistance
+bool rayAabbHit(Ray ray, vec3 invDirection, vec3 boxMin, vec3 boxMax, float maxDistance) {
+    vec3 t0 = (boxMin - ray.origin) * invDirection;
+    vec3 t1 = (boxMax - ray.origin) * invDirection;
+    vec3 tMin = min(t0, t1);
+    vec3 tMax = max(t0, t1);
+    float tNear = max(max(tMin.x, tMin.y), tMin.z);
+    float tFar = min(min(tMax.x, tMax.y), tMax.z);
+    return tNear <= tFar && tFar > 0 && tNear < maxDistance;
+}
+
+bool rayBoxIntersect(Ray ray, vec3 boxMin, vec3 boxMax, out HitResult result) {
+    // slab test
+    vec3 invDirection = 1.0 / ray.direction;
+    vec3 t0 = (boxMin - ray.origin) * invDirection;
+    vec3 t1 = (boxMax - ray.origin) * invDirection;
+    vec3 tMin = min(t0, t1);
+    vec3 tMax = max(t0, t1);
+    float tNear = max(max(tMin.x, tMin.y), tMin.z);
+    float tFar = min(min(tMax.x, tMax.y), tMax.z);
+    if (tNear > tFar || tNear <= 0) {
+        return false;
+    }
+    result.distance = tNear;
+    result.location = ray.origin + ray.direction * tNear;
+
+    // the hit face is the one along the axis where the hit point is furthest from the center
+    vec3 local = (result.location - (boxMin + boxMax) * 0.5) / ((boxMax - boxMin) * 0.5);
+    vec3 d = abs(local);
+    if (d.x > d.y && d.x > d.z) {
+        result.normal = vec3(sign(local.x), 0.0, 0.0);
+    } else if (d.y > d.z) {
+        result.normal = vec3(0.0, sign(local.y), 0.0);
+    } else {
+        result.normal = vec3(0.0, 0.0, sign(local.z));
+    }
+    result.shading_normal = result.normal;
+    result.uv = vec2(0.0);
+    result.tangent = vec3(0.0);
+    return true;
+}
+
+// Möller-Trumbore
+bool rayTriangleIntersect(Ray ray, Triangle triangle, out HitResult result) {
+    vec3 edge1 = triangle.v1 - triangle.v0;
+    vec3 edge2 = triangle.v2 - triangle.v0;
+    vec3 p = cross(ray.direction, edge2);
+    float det = dot(edge1, p);
+    if (abs(det) < 1e-8) {
+        return false;
+    }
+    float invDet = 1.0 / det;
+    vec3 s = ray.origin - triangle.v0;
+    float u = dot(s, p) * invDet;
+    if (u < 0.0 || u > 1.0) {
+        return false;
+    }
+    vec3 q = cross(s, edge1);
+    float v = dot(ray.direction, q) * invDet;
+    if (v < 0.0 || u + v > 1.0) {
+        return false;
+    }
+    float t = dot(edge2, q) * invDet;
+    if (t > 0) {
+        result.distance = t;
+        result.location = ray.origin + ray.direction * t;
+        // triangles are two-sided, face the normal towards the ray
+        result.normal = dot(triangle.normal, ray.direction) > 0.0 ? -triangle.normal : triangle.normal;
+        result.shading_normal = result.normal;
+        if (triangle.smooth_normals != 0) {
+            vec3 n = triangle.n0 * (1.0 - u - v) + triangle.n1 * u + triangle.n2 * v;
+            if (dot(n, n) > 1e-12) {
+                // on the same side as the flipped geometric normal
+                n = normalize(n);
+                result.shading_normal = dot(n, result.normal) < 0.0 ? -n : n;
+            }
+        }
+        result.uv = vec2(u, v);
+        result.tangent = edge1;
+        return true;
+    }
+    return false;
+}
+
+// The barrel is the infinite cylinder around the axis clipped between the caps, and the caps are
+// planes clipped to the radius. The closest of the up to four hits is kept, the far ones are
+// needed from inside, e.g. after refracting into it.
+bool rayCylinderIntersect(Ray ray, Cylinder cylinder, out HitResult result) {
+    vec3 oc = ray.origin - cylinder.position;
+    float t = 1e30;
+    vec3 normal = vec3(0.0);
+
+    // barrel, the y components drop out of the quadratic
+    float a = dot(ray.direction.xz, ray.direction.xz);
+    float b = dot(oc.xz, ray.direction.xz);
+    float c = dot(oc.xz, oc.xz) - cylinder.radius * cylinder.radius;
+    float discriminant = b * b - a * c;
+    if (a > 1e-8 && discriminant > 0) {
+        float root = sqrt(discriminant);
+        for (int i = 0; i < 2; i++) {
+            float tb = (-b + (i == 0 ? -root : root)) / a;
+            float y = oc.y + ray.direction.y * tb;
+            if (tb > 0 && tb < t && y >= 0 && y <= cylinder.height) {
+                t = tb;
+                normal = vec3(oc.x + ray.direction.x * tb, 0.0, oc.z + ray.direction.z * tb) / cylinder.radius;
+            }
+        }
+    }
+
+    // caps
+    if (abs(ray.direction.y) > 0) {
+        for (int i = 0; i < 2; i++) {
+            float capY = i == 0 ? 0.0 : cylinder.height;
+            float tc = (capY - oc.y) / ray.direction.y;
+            vec2 p = oc.xz + ray.direction.xz * tc;
+            if (tc > 0 && tc < t && dot(p, p) <= cylinder.radius * cylinder.radius) {
+                t = tc;
+                normal = vec3(0.0, i == 0 ? -1.0 : 1.0, 0.0);
+            }
+        }
+    }
+
+    if (t == 1e30) {
+        return false;
+    }
+    result.distance = t;
+    result.location = ray.origin + ray.direction * t;
+    result.normal = normal;
+    result.shading_normal = normal;
+    result.uv = vec2(0.0);
+    result.tangent = vec3(0.0);
+    return true;
+}
+
+// The barrel is the infinite cylinder around the axis clipped to the segment, and each cap is the
+// half of the sphere at its end outside of the segment. Only the capsule's own surface is tested,
+// so the closest hit is also right from inside.
+bool rayCapsuleIntersect(Ray ray, Capsule capsule, out HitResult result) {
+    vec3 ba = capsule.end - capsule.start;
+    vec3 oa = ray.origin - capsule.start;
+    float baba = dot(ba, ba);
+    float bard = dot(ba, ray.direction);
+    float baoa = dot(ba, oa);
+    float radius2 = capsule.radius * capsule.radius;
+    float t = 1e30;
+    vec3 normal = vec3(0.0);
+
+    // barrel, the quadratic of the distance to the axis scaled by baba
+    float a = baba * dot(ray.direction, ray.direction) - bard * bard;
+    float b = baba * dot(oa, ray.direction) - baoa * bard;
+    float c = baba * dot(oa, oa) - baoa * baoa - radius2 * baba;
+    float discriminant = b * b - a * c;
+    if (a > 1e-8 && discriminant > 0) {
+        float root = sqrt(discriminant);
+        for (int i = 0; i < 2; i++) {
+            float tb = (-b + (i == 0 ? -root : root)) / a;
+            float y = baoa + tb * bard;
+            if (tb > 0 && tb < t && y >= 0 && y <= baba) {
+                t = tb;
+                // from the closest point on the axis
+                normal = (oa + ray.direction * tb - ba * (y / baba)) / capsule.radius;
+            }
+        }
+    }
+
+    // caps
+    for (int cap = 0; cap < 2; cap++) {
+        vec3 center = cap == 0 ? capsule.start : capsule.end;
+        vec3 oc = ray.origin - center;
+        float sphereA = dot(ray.direction, ray.direction);
+        float halfB = dot(oc, ray.direction);
+        float sphereDiscriminant = halfB * halfB - sphereA * (dot(oc, oc) - radius2);
+        if (sphereDiscriminant <= 0) {
+            continue;
+        }
+        float root = sqrt(sphereDiscriminant);
+        for (int i = 0; i < 2; i++) {
+            float ts = (-halfB + (i == 0 ? -root : root)) / sphereA;
+            vec3 p = oc + ray.direction * ts;
+            // along the axis away from the segment
+            bool outside = cap == 0 ? dot(p, ba) <= 0 : dot(p, ba) >= 0;
+            if (ts > 0 && ts < t && outside) {
+                t = ts;
+                normal = p / capsule.radius;
+            }
+        }
+    }
+
+    if (t == 1e30) {
+        return false;
+    }
+    result.distance = t;
+    result.location = ray.origin + ray.direction * t;
+    result.normal = normal;
+    result.shading_normal = normal;
+    result.uv = vec2(0.0);
+    result.tangent = vec3(0.0);
+    return true;
+}
+
+vec3 rotateByQuaternion(vec4 q, vec3 v) {
+    return v + 2.0 * cross(q.xyz, cross(q.xyz, v) + q.w * v);
+}
+
+float torusDistance(vec3 p, float majorRadius, float minorRadius) {
+    return length(vec2(length(p.xz) - majorRadius, p.y)) - minorRadius;
+}
+
+// The hit is a root of the quartic (|p|^2 + R^2 - r^2)^2 - 4 R^2 (p.x^2 + p.z^2) along the ray
+// in the torus frame. Sphere tracing the distance to the torus from where the ray enters its
+// bounding sphere finds the closest one without skipping thin tubes, and a few Newton steps on
+// the quartic polish it.
+bool rayTorusIntersect(Ray ray, Torus torus, out HitResult result) {
+    vec4 inverse = vec4(-torus.orientation.xyz, torus.orientation.w);
+    vec3 o = rotateByQuaternion(inverse, ray.origin - torus.center);
+    vec3 d = rotateByQuaternion(inverse, ray.direction);
+    float scale = length(d);
+    d /= scale;
+    float R = torus.major_radius;
+    float r = torus.minor_radius;
+
+    float bound = R + r;
+    float b = dot(o, d);
+    float h = b * b - (dot(o, o) - bound * bound);
+    if (h <= 0) {
+        return false;
+    }
+    float tFar = -b + sqrt(h);
+    float t = max(-b - sqrt(h), 0.0);
+    if (tFar <= 0) {
+        return false;
+    }
+
+    // the distance is negative inside the tube, its magnitude is still a safe step
+    float epsilon = max(1e-4 * r, 1e-6);
+    bool found = false;
+    for (int i = 0; i < TORUS_MAX_STEPS && t <= tFar; i++) {
+        float distance = abs(torusDistance(o + d * t, R, r));
+        if (distance < epsilon) {
+            found = true;
+            break;
+        }
+        t += distance;
+    }
+    if (!found) {
+        return false;
+    }
+
+    for (int i = 0; i < 3; i++) {
+        vec3 p = o + d * t;
+        float k = dot(p, p) + R * R - r * r;
+        float f = k * k - 4.0 * R * R * dot(p.xz, p.xz);
+        float df = dot(4.0 * k * p - 8.0 * R * R * vec3(p.x, 0.0, p.z), d);
+        if (abs(df) > 1e-8) {
+            t -= f / df;
+        }
+    }
+    if (t <= 0) {
+        return false;
+    }
+
+    vec3 p = o + d * t;
+    // away from the closest point of the major circle
+    vec3 ring = R * normalize(vec3(p.x, 0.0, p.z) + vec3(1e-8, 0.0, 0.0));
+    vec3 normal = normalize(p - ring);
+    result.distance = t / scale;
+    result.location = ray.origin + ray.direction * result.distance;
+    result.normal = rotateByQuaternion(torus.orientation, normal);
+    result.shading_normal = result.normal;
+    // around the major circle and around the tube
+    result.uv = vec2(
+        0.5 + atan(p.z, p.x) / (2.0 * PI),
+        0.5 + atan(p.y, length(p.xz) - R) / (2.0 * PI)
+    );
+    result.tangent = rotateByQuaternion(torus.orientation, vec3(-p.z, 0.0, p.x));
+    return true;
+}
+
 bool rayPlaneIntersect(Ray ray, vec3 planePosition, vec3 planeNormal, out HitResult result) {
     float d = -dot(planePosition, planeNormal);
     float v = dot(ray.direction, planeNormal);
@@ -144,6 +896,9 @@ bool rayPlaneIntersect(Ray ray, vec3 planePosition, vec3 planeNormal, out HitRes
         result.distance = t;
         result.location = ray.origin + ray.direction * t;
         result.normal = planeNormal;
+        result.shading_normal = planeNormal;
+        result.uv = vec2(0.0);
+        result.tangent = vec3(0.0);
         return true;
     }
     return false;
@@ -152,8 +907,11 @@ bool rayPlaneIntersect(Ray ray, vec3 planePosition, vec3 planeNormal, out HitRes
 
 bool rayAllObjects(Ray ray, out HitResult result) {
     bool didHit = false;
-    result.distance = 1.0 / 0.0;
+    // nothing past the far clip distance is hit
+    result.distance = renderInfo.far;
     result.normal = vec3(0.0, 0.0, 0.0);
+    result.shading_normal = vec3(0.0, 0.0, 0.0);
+    result.circle = -1;
 
     HitResult r;
 
@@ -171,106 +929,668 @@ bool rayAllObjects(Ray ray, out HitResult result) {
         didHit = true;
     }*/
 
-    for (int i = 0; i < circles.list.length(); i++) {
-        Circle circle = circles.list[i];
-        if (raySphereIntersect(ray, circle.position, circle.radius, r) && r.distance < result.distance) {
+    vec3 invDirection = 1.0 / ray.direction;
+    uint stack[BVH_STACK_SIZE];
+    int stackSize = 0;
+    stack[stackSize++] = 0;
+    while (stackSize > 0) {
+        BvhNode node = bvh.list[stack[--stackSize]];
+        if (!rayAabbHit(ray, invDirection, node.min, node.max, result.distance)) {
+            continue;
+        }
+        if (node.count > 0) {
+            for (uint i = node.left_or_first; i < node.left_or_first + node.count; i++) {
+                Circle circle = circles.list[bvhIndices.list[i]];
+                if (raySphereIntersect(ray, circle.position, circle.radius, r) && r.distance < result.distance) {
+                    result = r;
+                    result.material = circle.material;
+                    result.circle = int(bvhIndices.list[i]);
+                    didHit = true;
+                }
+            }
+        } else if (node.left_or_first != 0 && stackSize + 2 <= BVH_STACK_SIZE) {
+            // a root without circles has no children. Bvh::build limits the depth so the stack
+            // can't fill up, the size check only guards against a stale tree
+            stack[stackSize++] = node.left_or_first;
+            stack[stackSize++] = node.left_or_first + 1;
+        }
+    }
+
+    for (int i = 0; i < boxes.list.length(); i++) {
+        Box box = boxes.list[i];
+        if (rayBoxIntersect(ray, box.min, box.max, r) && r.distance < result.distance) {
             result = r;
-            result.material = circle.material;
+            result.material = box.material;
+            result.circle = -1;
+            didHit = true;
+        }
+    }
+
+    for (int i = 0; i < planes.list.length(); i++) {
+        Plane plane = planes.list[i];
+        if (rayPlaneIntersect(ray, plane.normal * plane.distance, plane.normal, r) && r.distance < result.distance) {
+            result = r;
+            result.material = plane.material;
+            result.circle = -1;
+            didHit = true;
+        }
+    }
+
+    for (int i = 0; i < cylinders.list.length(); i++) {
+        Cylinder cylinder = cylinders.list[i];
+        if (rayCylinderIntersect(ray, cylinder, r) && r.distance < result.distance) {
+            result = r;
+            result.material = cylinder.material;
+            result.circle = -1;
+            didHit = true;
+        }
+    }
+
+    for (int i = 0; i < capsules.list.length(); i++) {
+        Capsule capsule = capsules.list[i];
+        if (rayCapsuleIntersect(ray, capsule, r) && r.distance < result.distance) {
+            result = r;
+            result.material = capsule.material;
+            result.circle = -1;
+            didHit = true;
+        }
+    }
+
+    for (int i = 0; i < tori.list.length(); i++) {
+        Torus torus = tori.list[i];
+        if (rayTorusIntersect(ray, torus, r) && r.distance < result.distance) {
+            result = r;
+            result.material = torus.material;
+            result.circle = -1;
+            didHit = true;
+        }
+    }
+
+    for (int i = 0; i < triangles.list.length(); i++) {
+        Triangle triangle = triangles.list[i];
+        if (rayTriangleIntersect(ray, triangle, r) && r.distance < result.distance) {
+            result = r;
+            result.material = triangle.material;
+            result.circle = -1;
             didHit = true;
         }
     }
     return didHit;
 }
 
-vec3 rayTrace(Ray ray, inout uint rngState) {
+// solid angle pdf of sampling a direction towards the circle from origin with sampleLight,
+// 0 from inside it
+float lightPdf(Circle circle, vec3 origin) {
+    vec3 toCenter = circle.position - origin;
+    float distance2 = dot(toCenter, toCenter);
+    float radius2 = circle.radius * circle.radius;
+    if (distance2 <= radius2) {
+        return 0.0;
+    }
+    float cosThetaMax = sqrt(1.0 - radius2 / distance2);
+    return 1.0 / (2.0 * PI * max(1.0 - cosThetaMax, 1e-7) * float(renderInfo.light_count));
+}
+
+// entry and exit distances of the ray through the box, entry is greater when it misses
+vec2 rayAabbInterval(Ray ray, vec3 boxMin, vec3 boxMax) {
+    vec3 invDirection = 1.0 / ray.direction;
+    vec3 t0 = (boxMin - ray.origin) * invDirection;
+    vec3 t1 = (boxMax - ray.origin) * invDirection;
+    vec3 tMin = min(t0, t1);
+    vec3 tMax = max(t0, t1);
+    return vec2(max(max(tMin.x, tMin.y), max(tMin.z, 0.0)), min(min(tMax.x, tMax.y), tMax.z));
+}
+
+// share of each channel that crosses the volumes over the first distance units of the ray, the
+// media are homogeneous so it is exact
+vec3 volumeTransmittance(Ray ray, float distance) {
+    vec3 opticalDepth = vec3(0.0);
+    for (int i = 0; i < volumes.list.length(); i++) {
+        Volume v = volumes.list[i];
+        vec2 span = rayAabbInterval(ray, v.bounds_min, v.bounds_max);
+        opticalDepth += v.density * (v.absorption + v.scattering) * max(min(span.y, distance) - span.x, 0.0);
+    }
+    return exp(-opticalDepth);
+}
+
+// Next event estimation of the diffuse lobe: picks one emissive circle, a direction in the cone
+// it covers and casts a shadow ray to it. Weighted with the balance heuristic against the
+// cosine weighted diffuse bounce, which can reach the same circle.
+vec3 sampleLight(HitResult hit, vec3 diffuse, inout uint rngState) {
+    int count = renderInfo.light_count;
+    uint index = lights.list[min(int(rand(rngState) * float(count)), count - 1)];
+    Circle circle = circles.list[index];
+    vec3 origin = hit.location + hit.normal * 0.001;
+    float pdf = lightPdf(circle, origin);
+    if (pdf <= 0.0) {
+        return vec3(0.0);
+    }
+
+    vec3 toCenter = circle.position - origin;
+    float cosThetaMax = sqrt(1.0 - circle.radius * circle.radius / dot(toCenter, toCenter));
+    float cosTheta = 1.0 - rand(rngState) * (1.0 - cosThetaMax);
+    float sinTheta = sqrt(max(1.0 - cosTheta * cosTheta, 0.0));
+    float phi = 2.0 * PI * rand(rngState);
+    vec3 w = normalize(toCenter);
+    vec3 u, v;
+    basis(w, u, v);
+    Ray shadow;
+    shadow.origin = origin;
+    shadow.direction = normalize(u * cos(phi) * sinTheta + v * sin(phi) * sinTheta + w * cosTheta);
+
+    float cosSurface = dot(shadow.direction, hit.shading_normal);
+    if (cosSurface <= 0.0 || dot(shadow.direction, hit.normal) <= 0.0) {
+        return vec3(0.0);
+    }
+    HitResult occluder;
+    if (!rayAllObjects(shadow, occluder) || occluder.circle != int(index)) {
+        return vec3(0.0);
+    }
+
+    float bsdfPdf = cosSurface / PI;
+    float weight = pdf / (pdf + bsdfPdf);
+    return diffuse / PI * cosSurface * getMaterial(circle.material).emission * weight / pdf
+        * volumeTransmittance(shadow, occluder.distance);
+}
+
+// direction scattered from one travelling along direction
+vec3 sampleHenyeyGreenstein(vec3 direction, float g, inout uint rngState) {
+    float cosTheta;
+    if (abs(g) < 0.001) {
+        cosTheta = 1.0 - 2.0 * rand(rngState);
+    } else {
+        float s = (1.0 - g * g) / (1.0 - g + 2.0 * g * rand(rngState));
+        cosTheta = (1.0 + g * g - s * s) / (2.0 * g);
+    }
+    float sinTheta = sqrt(max(1.0 - cosTheta * cosTheta, 0.0));
+    float phi = 2.0 * PI * rand(rngState);
+    vec3 u, v;
+    basis(direction, u, v);
+    return normalize(u * cos(phi) * sinTheta + v * sin(phi) * sinTheta + direction * cosTheta);
+}
+
+#define VOLUME_PASSED 0
+#define VOLUME_SCATTERED 1
+#define VOLUME_ABSORBED 2
+
+// Delta (Woodcock) tracking through the volumes before maxDistance. The tentative collisions
+// use the summed largest extinction of the crossed volumes as majorant, and each one is a
+// scattering, null or absorption event picked by the average of its channels, with the color
+// weighted so every channel stays unbiased. On VOLUME_SCATTERED, t and g are the distance and
+// the phase function of the collision.
+int trackVolumes(Ray ray, float maxDistance, inout uint rngState, inout vec3 color, out float t, out float g) {
+    float majorant = 0.0;
+    float start = maxDistance;
+    float end = 0.0;
+    for (int i = 0; i < volumes.list.length(); i++) {
+        Volume v = volumes.list[i];
+        vec2 span = rayAabbInterval(ray, v.bounds_min, v.bounds_max);
+        span.y = min(span.y, maxDistance);
+        vec3 extinction = v.density * (v.absorption + v.scattering);
+        if (span.x < span.y && v.density > 0.0) {
+            majorant += max(extinction.r, max(extinction.g, extinction.b));
+            start = min(start, span.x);
+            end = max(end, span.y);
+        }
+    }
+    t = start;
+    g = 0.0;
+    if (majorant <= 0.0) {
+        return VOLUME_PASSED;
+    }
+
+    for (int step = 0; step < MAX_VOLUME_STEPS; step++) {
+        t -= log(1.0 - rand(rngState)) / majorant;
+        if (t >= end) {
+            return VOLUME_PASSED;
+        }
+        vec3 p = ray.origin + ray.direction * t;
+        vec3 absorption = vec3(0.0);
+        vec3 scattering = vec3(0.0);
+        bool inside = false;
+        for (int i = 0; i < volumes.list.length(); i++) {
+            Volume v = volumes.list[i];
+            if (all(greaterThanEqual(p, v.bounds_min)) && all(lessThanEqual(p, v.bounds_max))) {
+                absorption += v.density * v.absorption;
+                scattering += v.density * v.scattering;
+                // overlapping volumes scatter with the phase function of the first one
+                if (!inside) {
+                    g = v.anisotropy;
+                    inside = true;
+                }
+            }
+        }
+        vec3 nullCollision = max(vec3(majorant) - absorption - scattering, vec3(0.0));
+        float scatterChance = dot(scattering, vec3(1.0 / 3.0)) / majorant;
+        float nullChance = dot(nullCollision, vec3(1.0 / 3.0)) / majorant;
+        float xi = rand(rngState);
+        if (xi < scatterChance) {
+            color *= scattering / (majorant * scatterChance);
+            return VOLUME_SCATTERED;
+        }
+        if (xi < scatterChance + nullChance) {
+            color *= nullCollision / (majorant * nullChance);
+            continue;
+        }
+        return VOLUME_ABSORBED;
+    }
+    return VOLUME_PASSED;
+}
+
+// `firstHit` and `first` are the result of rayAllObjects for `ray`, so callers that already
+// traced it don't pay for it twice
+vec3 rayTraceFrom(Ray ray, bool firstHit, HitResult first, inout uint rngState) {
     vec3 color = vec3(1.0);
     vec3 light = vec3(0.0);
+    // share of the sky light still left to the path tracer, see environmentWeight
+    float skyWeight = 1.0;
+    bool nee = renderInfo.use_nee != 0 && renderInfo.light_count > 0;
+    // pdf of the last bounce if it was a diffuse one the lights were also sampled for, else 0
+    float diffusePdf = 0.0;
+    vec3 lastOrigin = ray.origin;
+
+    int maxBounce = clamp(renderInfo.max_bounce_depth, 1, MAX_BOUNCE);
+    for (int i = 0; i < maxBounce; i++) {
+        // end paths that carry little energy, the survivors are scaled up to stay unbiased
+        if (renderInfo.use_russian_roulette != 0 && i >= renderInfo.rr_min_bounces) {
+            float survival = min(max(color.r, max(color.g, color.b)), 1.0);
+            if (rand(rngState) >= survival) {
+                break;
+            }
+            color /= survival;
+        }
+
+        HitResult result = first;
+        bool hit = firstHit;
+        if (i > 0) {
+            hit = rayAllObjects(ray, result);
+        }
+
+        float volumeDistance;
+        float g;
+        int volumeEvent = trackVolumes(ray, result.distance, rngState, color, volumeDistance, g);
+        if (volumeEvent == VOLUME_ABSORBED) {
+            break;
+        }
+        if (volumeEvent == VOLUME_SCATTERED) {
+            ray.origin += ray.direction * volumeDistance;
+            ray.direction = sampleHenyeyGreenstein(ray.direction, g, rngState);
+            diffusePdf = 0.0;
+            skyWeight = 1.0;
+            continue;
+        }
 
-    for (int i = 0; i < MAX_BOUNCE; i++) {
-        HitResult result;
-        if (rayAllObjects(ray, result)) {
+        if (hit) {
+            result = applyNormalMap(result);
 
             Material m = getMaterial(result.material);
+            m.color = getAlbedo(result.material, result.uv, result.location);
+
+            vec3 diffuseDir = randCosineHemisphere(rngState, result.shading_normal);
+            vec3 specularDir = reflect(ray.direction, result.shading_normal);
+
+            // the lights sampled at the last vertex share this path, see sampleLight
+            float emissionWeight = 1.0;
+            if (diffusePdf > 0.0 && result.circle >= 0) {
+                float pdf = lightPdf(circles.list[result.circle], lastOrigin);
+                emissionWeight = diffusePdf / (diffusePdf + pdf);
+            }
+            light += m.emission * color * emissionWeight;
+            diffusePdf = 0.0;
+
+            skyWeight = 1.0;
+            if (m.transmission > 0.0) {
+                // hitting the back face means the ray travelled through the medium, Beer's law
+                // with the color as the share of each channel that is kept
+                bool inside = dot(ray.direction, result.normal) > 0.0;
+                if (inside) {
+                    color *= exp(-(1.0 - m.color) * result.distance);
+                }
+                // a ray that is already inside always leaves through refraction
+                if (inside || rand(rngState) < m.transmission) {
+                    ray = refractRay(ray, result, m.ior, rngState);
+                    continue;
+                }
+            }
 
-            vec3 diffuseDir = randHemisphere(rngState, result.normal);
-            vec3 specularDir = reflect(ray.direction, result.normal);
+            if (m.smoothness > 0.8 && m.metallic > 0.5) {
+                // sample the environment directly along the mirror direction, there is no
+                // environment map so this is the sky gradient
+                Ray envRay;
+                envRay.origin = result.location + result.normal * 0.001;
+                envRay.direction = specularDir;
+                float envWeight = environmentWeight(m, result.shading_normal, specularDir);
+                HitResult occluder;
+                if (!rayAllObjects(envRay, occluder)) {
+                    float cosTheta = max(dot(-ray.direction, result.shading_normal), 0.0);
+                    light += getAmbientLight(envRay) * fresnelSchlick(specularF0(m), cosTheta) * color * envWeight;
+                }
+                skyWeight = 1.0 - envWeight;
+            }
 
-            light += m.emission * color;
-            color *= m.color;
+            // pick the specular or the diffuse lobe with the Fresnel reflectance as probability,
+            // metals have no diffuse lobe
+            float cosTheta = max(dot(-ray.direction, result.shading_normal), 0.0);
+            vec3 fresnel = fresnelSchlick(specularF0(m), cosTheta);
+            float specularChance = clamp(dot(fresnel, vec3(1.0 / 3.0)), 0.05, 0.95);
+            if (m.metallic >= 1.0) {
+                specularChance = 1.0;
+            }
+
+            vec3 diffuse = m.color * (1.0 - m.metallic) * (1.0 - fresnel);
+            if (nee && m.metallic < 1.0) {
+                light += color * sampleLight(result, diffuse, rngState);
+            }
 
             ray.origin = result.location + result.normal * 0.001;
-            ray.direction = lerp(diffuseDir, specularDir, m.smoothness);
+            if (rand(rngState) < specularChance) {
+                color *= fresnel / specularChance;
+                ray.direction = lerp(diffuseDir, specularDir, m.smoothness);
+            } else {
+                color *= diffuse / (1.0 - specularChance);
+                ray.direction = diffuseDir;
+                if (nee && m.metallic < 1.0) {
+                    diffusePdf = max(dot(diffuseDir, result.shading_normal), 0.0) / PI;
+                    lastOrigin = ray.origin;
+                }
+            }
         } else {
-            light += getAmbientLight(ray) * color;
+            light += getAmbientLight(ray) * color * skyWeight;
+            light += getDirectionalLight(ray) * color;
             break;
         }
     }
     return light;
 }
 
+vec3 rayTrace(Ray ray, inout uint rngState) {
+    HitResult first;
+    bool firstHit = rayAllObjects(ray, first);
+    return rayTraceFrom(ray, firstHit, first, rngState);
+}
+
 HitResult rayTraceFirstHit(Ray ray, inout uint rngState) {
     HitResult result;
     rayAllObjects(ray, result);
     return result;
 }
 
-vec3 rayTraceSampled(Ray ray, inout uint rngState) {
+Ray jitterRay(Ray ray, inout uint rngState) {
+    vec3 jitter = randDirection(rngState) * renderInfo.blur * 0.01;
+    ray.direction = normalize(ray.direction + jitter);
+    return ray;
+}
+
+// random offset inside the sub-pixel cell of this sample, in the [-0.5, 0.5] pixel range. The
+// strata keep shifting with the accumulated frames, so all cells get covered when the grid has
+// more cells than samples
+vec2 stratumJitter(int sampleIndex, inout uint rngState) {
+    ivec2 strata = max(ivec2(renderInfo.stratum_x, renderInfo.stratum_y), ivec2(1));
+    int stratum = (sampleIndex + renderInfo.frame_index * renderInfo.sample_count) % (strata.x * strata.y);
+    vec2 cell = vec2(stratum % strata.x, stratum / strata.x);
+    return (cell + vec2(rand(rngState), rand(rngState))) / vec2(strata) - 0.5;
+}
+
+// moves the ray to a random point in its stratum, `pixel` holds the world space size of a
+// pixel along the camera right and up axes
+Ray stratifyRay(Ray ray, int sampleIndex, mat2x3 pixel, inout uint rngState) {
+    ray.direction = normalize(ray.direction + pixel * stratumJitter(sampleIndex, rngState));
+    return ray;
+}
+
+// samples whose first hits are traced before any of them is shaded
+#define SORT_BATCH 8
+
+// same as rayTraceSampled, but samples are shaded grouped by the material class of their
+// first hit, so neighbouring invocations evaluate the same BRDF branch at the same time. The
+// first hits are traced once per batch and handed to rayTraceFrom
+vec3 rayTraceSampledSorted(Ray ray, mat2x3 pixel, inout uint rngState) {
+    vec3 light = vec3(0.0);
+    int sample_count = renderInfo.sample_count;
+    uint baseState = rngState;
+    for (int start = 0; start < sample_count; start += SORT_BATCH) {
+        int count = min(SORT_BATCH, sample_count - start);
+        Ray rays[SORT_BATCH];
+        HitResult hits[SORT_BATCH];
+        bool hitAny[SORT_BATCH];
+        int classes[SORT_BATCH];
+        uint states[SORT_BATCH];
+        for (int j = 0; j < count; j++) {
+            int i = start + j;
+            states[j] = baseState + uint(i) * 7919u;
+            rays[j] = jitterRay(stratifyRay(ray, i, pixel, states[j]), states[j]);
+            hitAny[j] = rayAllObjects(rays[j], hits[j]);
+            classes[j] = hitAny[j] ? getMaterialClass(hits[j].material) : MATERIAL_CLASS_DIFFUSE;
+        }
+
+        for (int c = 0; c < MATERIAL_CLASS_COUNT; c++) {
+            for (int j = 0; j < count; j++) {
+                if (classes[j] == c) {
+                    light += rayTraceFrom(rays[j], hitAny[j], hits[j], states[j]);
+                }
+            }
+        }
+    }
+    return light / float(sample_count);
+}
+
+vec3 rayTraceSampled(Ray ray, mat2x3 pixel, inout uint rngState) {
+    if (renderInfo.sort_by_material != 0) {
+        return rayTraceSampledSorted(ray, pixel, rngState);
+    }
+
     vec3 light = vec3(0.0);
 //    int sample_count = SAMPLES;
     int sample_count = renderInfo.sample_count;
     for(int i = 0; i < sample_count; i++) {
+        Ray sampleRay = stratifyRay(ray, i, pixel, rngState);
 
-        vec3 jitter = randDirection(rngState) * viewData.blur * 0.01;
+        vec3 jitter = randDirection(rngState) * renderInfo.blur * 0.01;
         // rotate ray direction
-        ray.direction = normalize(ray.direction + jitter);
+        sampleRay.direction = normalize(sampleRay.direction + jitter);
 
-        light += rayTrace(ray, rngState);
+        light += rayTrace(sampleRay, rngState);
     }
     return light / float(sample_count);
 }
 
+#define JITTER_NONE 0
+#define JITTER_WHITE_NOISE 1
+#define JITTER_HALTON 2
+#define JITTER_R2 3
+#define JITTER_BLUE_NOISE 4
+
+uint hash(uint x) {
+    x ^= x >> 16;
+    x *= 0x7feb352du;
+    x ^= x >> 15;
+    x *= 0x846ca68bu;
+    x ^= x >> 16;
+    return x;
+}
+
+float halton(uint index, uint base) {
+    float f = 1.0;
+    float r = 0.0;
+    while (index > 0u) {
+        f /= float(base);
+        r += f * float(index % base);
+        index /= base;
+    }
+    return r;
+}
+
+// sub-pixel offset of the camera ray for this frame, in the [-0.5, 0.5] pixel range
+vec2 cameraJitter(int mode, uint frame) {
+    switch (mode) {
+        case JITTER_WHITE_NOISE: {
+            uint h = hash(uint(gl_FragCoord.x) + hash(uint(gl_FragCoord.y) + hash(frame)));
+            return vec2(float(h & 0xffffu), float(h >> 16)) / 65535.0 - 0.5;
+        }
+        case JITTER_HALTON:
+            return vec2(halton(frame + 1u, 2u), halton(frame + 1u, 3u)) - 0.5;
+        case JITTER_R2:
+            return fract(vec2(0.5) + float(frame) * vec2(0.7548776662, 0.5698402910)) - 0.5;
+        case JITTER_BLUE_NOISE: {
+            ivec2 size = textureSize(blueNoise, 0);
+            ivec2 p = ivec2(gl_FragCoord.xy) % size;
+            vec2 noise = vec2(
+                texelFetch(blueNoise, p, 0).r,
+                texelFetch(blueNoise, (p + size / 2) % size, 0).r
+            );
+            // shift the noise every frame so the pattern doesn't repeat over time
+            return fract(noise + float(frame) * vec2(0.7548776662, 0.5698402910)) - 0.5;
+        }
+    }
+    return vec2(0.0);
+}
+
 uint generateRngSeed() {
 //    return uint(gl_FragCoord.y) + uint(gl_FragCoord.x * 1080.0) * uint(renderInfo.time * 10.0);
-    return uint(gl_FragCoord.y) + uint(gl_FragCoord.x * 1080.0);
+    // accumulated frames need different samples
+    return uint(gl_FragCoord.y) + uint(gl_FragCoord.x * 1080.0) + uint(renderInfo.frame_index) * 719393u;
 }
 
-vec3 getPixelColor(Ray ray, vec2 coord) {
+vec3 getPixelColor(Ray ray, mat2x3 pixel) {
     uint rngState = generateRngSeed();
-    return rayTraceSampled(ray, rngState);
+    return rayTraceSampled(ray, pixel, rngState);
 }
 
-void getPixelNormal(Ray ray, vec2 coord, out vec3 albedo, out vec3 normal, out float depth) {
+// share of the cosine weighted rays from the hit that travel ao_radius without hitting anything,
+// 1 when the pixel sees the sky
+float ambientOcclusion(HitResult hit, inout uint rngState) {
+    if (renderInfo.ao_sample_count <= 0 || hit.distance >= renderInfo.far) {
+        return 1.0;
+    }
+    Ray ray;
+    ray.origin = hit.location + hit.normal * 0.001;
+    int unoccluded = 0;
+    for (int i = 0; i < renderInfo.ao_sample_count; i++) {
+        ray.direction = randCosineHemisphere(rngState, hit.normal);
+        HitResult occluder;
+        if (!rayAllObjects(ray, occluder) || occluder.distance > renderInfo.ao_radius) {
+            unoccluded++;
+        }
+    }
+    return float(unoccluded) / float(renderInfo.ao_sample_count);
+}
+
+void getPixelNormal(Ray ray, vec2 coord, out vec3 albedo, out vec3 normal, out vec3 shading_normal, out float depth, out float ao) {
     uint rngState = generateRngSeed();
-    HitResult r = rayTraceFirstHit(ray, rngState);
-    albedo = getMaterial(r.material).color;
+    HitResult r = applyNormalMap(rayTraceFirstHit(ray, rngState));
+    albedo = getAlbedo(r.material, r.uv, r.location);
     normal = r.normal;
+    shading_normal = r.shading_normal;
     depth =  r.distance;
+    ao = ambientOcclusion(r, rngState);
+}
+
+// Clamps each channel of a new sample to mean + k * stddev of the accumulated 3x3 neighborhood,
+// so a rare very bright path doesn't stay visible as a firefly. Neighbors may already hold this
+// frame's value, the reference is only statistical.
+vec3 clampFirefly(vec3 light, ivec2 pixel) {
+    ivec2 size = imageSize(accumulation);
+    vec3 sum = vec3(0.0);
+    vec3 sumSquared = vec3(0.0);
+    for (int y = -1; y <= 1; y++) {
+        for (int x = -1; x <= 1; x++) {
+            vec3 value = imageLoad(accumulation, clamp(pixel + ivec2(x, y), ivec2(0), size - 1)).rgb;
+            sum += value;
+            sumSquared += value * value;
+        }
+    }
+    vec3 mean = sum / 9.0;
+    vec3 stddev = sqrt(max(sumSquared / 9.0 - mean * mean, vec3(0.0)));
+    return min(light, mean + renderInfo.firefly_threshold * stddev);
+}
+
+// screen uv of a view space position or direction, without the sub-pixel jitter. The inverse of
+// the ray setup in main, positions behind the camera are put off screen.
+vec2 viewToUv(vec3 view, mat4 proj) {
+    if (view.z <= 0.0) {
+        return vec2(-1.0);
+    }
+    vec2 real_coord = view.xy / view.z * proj[1][1];
+    real_coord.x /= proj[0][0] / proj[1][1];
+    // the vertex shader flips the coordinates
+    return 0.5 - real_coord * 0.5;
+}
+
+// misses are reprojected as directions, only the camera rotation moves the sky
+vec2 velocity(Ray ray, float depth) {
+    vec4 world = depth >= renderInfo.far ? vec4(ray.direction, 0.0) : vec4(ray.origin + ray.direction * depth, 1.0);
+    vec2 current = viewToUv((inverse(viewData.worldview) * world).xyz, viewData.proj);
+    vec2 previous = viewToUv((renderInfo.prev_view * world).xyz, renderInfo.prev_proj);
+    return current - previous;
 }
 
 void main() {
     mat4 proj = viewData.proj;
     float aspect = proj[0][0] / proj[1][1];
 
-    vec2 real_coord = coord;
+    vec2 pixel_size = vec2(dFdx(coord.x), dFdy(coord.y));
+    vec2 real_coord = coord + cameraJitter(renderInfo.jitter_mode, uint(renderInfo.frame)) * pixel_size;
+    // the Halton jitter is stored in the projection like a rasterizer would take it
+    real_coord -= vec2(proj[2][0], proj[2][1]);
     real_coord.x *= aspect;
 
     vec3 ray_origin = (viewData.worldview * vec4(vec3(0.0), 1.0)).xyz;
-    vec3 ray_target = (viewData.worldview * vec4(real_coord, 1.0, 1.0)).xyz;
+    // proj[1][1] is 1 / tan(fov / 2)
+    vec3 ray_target = (viewData.worldview * vec4(real_coord / proj[1][1], 1.0, 1.0)).xyz;
     vec3 ray_direction = normalize(ray_target - ray_origin);
+    mat2x3 pixel_axes = mat2x3(
+        (viewData.worldview * vec4(pixel_size.x * aspect / proj[1][1], 0.0, 0.0, 0.0)).xyz,
+        (viewData.worldview * vec4(0.0, pixel_size.y / proj[1][1], 0.0, 0.0)).xyz
+    );
 
     Ray ray;
-    ray.origin = ray_origin;
+    // start at the near clip distance, anything closer is clipped
+    ray.origin = ray_origin + ray_direction * renderInfo.near;
     ray.direction = ray_direction.xyz;
 
-    vec3 light = getPixelColor(ray, real_coord);
+    vec3 light;
+    if (renderInfo.anaglyph != 0) {
+        // shift each eye along the camera right axis, the center ray is kept for the g-buffer
+        vec3 eye_offset = normalize((viewData.worldview * vec4(1.0, 0.0, 0.0, 0.0)).xyz)
+            * renderInfo.eye_separation * 0.5;
+
+        Ray left = ray;
+        left.origin -= eye_offset;
+        left.direction = normalize(ray_target - left.origin);
+        light = getPixelColor(left, pixel_axes);
+
+        Ray right = ray;
+        right.origin += eye_offset;
+        right.direction = normalize(ray_target - right.origin);
+        f_color_right = vec4(getPixelColor(right, pixel_axes), 1.0);
+    } else {
+        light = getPixelColor(ray, pixel_axes);
+        f_color_right = vec4(0.0);
+    }
 
     vec3 albedo;
     vec3 normal;
+    vec3 shading_normal;
     float depth;
-    getPixelNormal(ray, real_coord, albedo, normal, depth);
+    float ao;
+    getPixelNormal(ray, real_coord, albedo, normal, shading_normal, depth, ao);
+
+    // progressive rendering, only the main color is accumulated
+    ivec2 pixel = ivec2(gl_FragCoord.xy);
+    if (renderInfo.frame_index > 0) {
+        if (renderInfo.firefly_threshold > 0.0) {
+            light = clampFirefly(light, pixel);
+        }
+        vec3 previous = imageLoad(accumulation, pixel).rgb;
+        light = (previous * renderInfo.frame_index + light) / (renderInfo.frame_index + 1);
+    }
+    imageStore(accumulation, pixel, vec4(light, 1.0));
 
     f_color = vec4(light, 1.0);
     f_albedo = albedo;
     f_normal = normal;
+    f_shading_normal = shading_normal;
     f_depth = 1.0 / depth;
+    f_ao = ao;
+    f_velocity = velocity(ray, depth);
 }
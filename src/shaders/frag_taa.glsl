@@ -0,0 +1,72 @@
+#version 450
+
+// Temporal anti-aliasing resolve. The Halton jitter moves the primary rays inside each pixel every
+// frame, blending with the reprojected history averages them over time.
+
+// screen uv from vert_denoiser.glsl
+layout(location = 0) in vec2 v_uv;
+
+layout(location = 0) out vec4 f_color;
+
+// tone mapped output of frag_denoiser.glsl
+layout(set = 0, binding = 0) uniform sampler2D u_current;
+// u_resolved of the last frame
+layout(set = 0, binding = 1) uniform sampler2D u_history;
+// written by frag_raytracing.glsl, screen uv now minus in the last frame
+layout(set = 0, binding = 2) uniform sampler2D u_velocity;
+layout(set = 0, binding = 3, rgba32f) uniform writeonly image2D u_resolved;
+
+layout(push_constant) uniform TaaInfo {
+    float blend_factor; // weight of the current frame
+    int reset; // the history is from before a change, only the current frame is shown
+} taaInfo;
+
+vec3 rgbToYCoCg(vec3 c) {
+    return vec3(
+        0.25 * c.r + 0.5 * c.g + 0.25 * c.b,
+        0.5 * c.r - 0.5 * c.b,
+        -0.25 * c.r + 0.5 * c.g - 0.25 * c.b
+    );
+}
+
+vec3 yCoCgToRgb(vec3 c) {
+    return vec3(c.x + c.y - c.z, c.x + c.z, c.x - c.y - c.z);
+}
+
+// moves the history towards the center of the box until it is inside, which keeps its hue
+// better than clamping each channel
+vec3 clipToAabb(vec3 history, vec3 low, vec3 high) {
+    vec3 center = 0.5 * (high + low);
+    vec3 extent = 0.5 * (high - low) + 1e-5;
+    vec3 offset = history - center;
+    vec3 units = abs(offset / extent);
+    float outside = max(units.x, max(units.y, units.z));
+    return outside > 1.0 ? center + offset / outside : history;
+}
+
+void main() {
+    ivec2 coord = ivec2(gl_FragCoord.xy);
+    ivec2 size = textureSize(u_current, 0);
+    vec3 current = texelFetch(u_current, coord, 0).rgb;
+
+    vec3 resolved = current;
+    vec2 history_uv = v_uv - texelFetch(u_velocity, coord, 0).rg;
+    bool on_screen = all(greaterThanEqual(history_uv, vec2(0.0))) && all(lessThanEqual(history_uv, vec2(1.0)));
+    if (taaInfo.reset == 0 && on_screen) {
+        vec3 low = vec3(1e30);
+        vec3 high = vec3(-1e30);
+        for (int y = -1; y <= 1; y++) {
+            for (int x = -1; x <= 1; x++) {
+                vec3 c = rgbToYCoCg(texelFetch(u_current, clamp(coord + ivec2(x, y), ivec2(0), size - 1), 0).rgb);
+                low = min(low, c);
+                high = max(high, c);
+            }
+        }
+        vec3 history = rgbToYCoCg(textureLod(u_history, history_uv, 0.0).rgb);
+        history = yCoCgToRgb(clipToAabb(history, low, high));
+        resolved = mix(history, current, taaInfo.blend_factor);
+    }
+
+    imageStore(u_resolved, coord, vec4(resolved, 1.0));
+    f_color = vec4(resolved, 1.0);
+}
@@ -1,2 +1,8 @@
 pub mod vk;
-pub mod imgui;
\ No newline at end of file
+pub mod imgui;
+pub mod descriptor_pool;
+pub mod shader_cache;
+pub mod capabilities;
+pub mod profiler;
+pub mod pipeline_cache;
+pub mod timeline;
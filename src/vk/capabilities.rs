@@ -0,0 +1,60 @@
+use vulkano::device::physical::PhysicalDevice;
+
+/// Optional device features and extensions the renderer may take advantage of.
+#[derive(Debug, Clone, Copy, Default)]
+pub struct SupportedFeatures {
+    pub shader_float64: bool,
+    pub shader_int64: bool,
+    pub geometry_shader: bool,
+    pub tessellation_shader: bool,
+    pub multi_draw_indirect: bool,
+    pub sampler_anisotropy: bool,
+    pub timeline_semaphore: bool,
+    pub dynamic_rendering: bool,
+    pub synchronization2: bool,
+    pub ray_tracing_pipeline: bool,
+    pub acceleration_structure: bool,
+    pub memory_budget: bool,
+    pub pipeline_statistics_query: bool,
+}
+
+impl SupportedFeatures {
+    pub fn from_physical_device(physical_device: &PhysicalDevice) -> Self {
+        let features = physical_device.supported_features();
+        let extensions = physical_device.supported_extensions();
+        Self {
+            shader_float64: features.shader_float64,
+            shader_int64: features.shader_int64,
+            geometry_shader: features.geometry_shader,
+            tessellation_shader: features.tessellation_shader,
+            multi_draw_indirect: features.multi_draw_indirect,
+            sampler_anisotropy: features.sampler_anisotropy,
+            timeline_semaphore: features.timeline_semaphore,
+            dynamic_rendering: features.dynamic_rendering,
+            synchronization2: features.synchronization2,
+            ray_tracing_pipeline: extensions.khr_ray_tracing_pipeline,
+            acceleration_structure: extensions.khr_acceleration_structure,
+            memory_budget: extensions.ext_memory_budget,
+            pipeline_statistics_query: features.pipeline_statistics_query,
+        }
+    }
+
+    /// Name and availability of every entry, for display.
+    pub fn table(&self) -> Vec<(&'static str, bool)> {
+        vec![
+            ("shaderFloat64", self.shader_float64),
+            ("shaderInt64", self.shader_int64),
+            ("geometryShader", self.geometry_shader),
+            ("tessellationShader", self.tessellation_shader),
+            ("multiDrawIndirect", self.multi_draw_indirect),
+            ("samplerAnisotropy", self.sampler_anisotropy),
+            ("timelineSemaphore", self.timeline_semaphore),
+            ("dynamicRendering", self.dynamic_rendering),
+            ("synchronization2", self.synchronization2),
+            ("VK_KHR_ray_tracing_pipeline", self.ray_tracing_pipeline),
+            ("VK_KHR_acceleration_structure", self.acceleration_structure),
+            ("VK_EXT_memory_budget", self.memory_budget),
+            ("pipelineStatisticsQuery", self.pipeline_statistics_query),
+        ]
+    }
+}
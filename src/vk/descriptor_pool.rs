@@ -0,0 +1,167 @@
+use std::cell::{Cell, RefCell};
+use std::collections::HashMap;
+use std::sync::{Arc, Mutex};
+
+use vulkano::descriptor_set::allocator::{DescriptorSetAlloc, DescriptorSetAllocator};
+use vulkano::descriptor_set::layout::{DescriptorSetLayout, DescriptorType};
+use vulkano::descriptor_set::pool::{DescriptorPool, DescriptorPoolAllocError, DescriptorPoolCreateInfo, DescriptorSetAllocateInfo};
+use vulkano::descriptor_set::sys::UnsafeDescriptorSet;
+use vulkano::device::{Device, DeviceOwned};
+use vulkano::OomError;
+
+/// Descriptor set allocator for sets that are rebuilt every frame.
+///
+/// Keeps one `DescriptorPool` per frame in flight and cycles through them. Sets are never freed
+/// individually, instead the whole pool is reset when its turn comes again in `next_frame`.
+/// If sets from that pool are still alive at that point (e.g. still referenced by a command
+/// buffer), the pool is replaced by a new one and the old one is released with its last set.
+pub struct DescriptorPoolAllocator {
+    device: Arc<Device>,
+    max_sets: u32,
+    pool_sizes: HashMap<DescriptorType, u32>,
+
+    pools: RefCell<Vec<Arc<Mutex<DescriptorPool>>>>,
+    current: Cell<usize>,
+    allocated: Cell<u32>,
+}
+
+pub struct DescriptorPoolAlloc {
+    inner: UnsafeDescriptorSet,
+    // keeps the pool alive, the mutex makes it `Sync` as `DescriptorSetAlloc` requires
+    _pool: Arc<Mutex<DescriptorPool>>,
+}
+
+impl DescriptorPoolAllocator {
+    /// Creates `pool_count` pools, each able to hold `max_sets` sets of any of the given layouts.
+    pub fn new(
+        device: Arc<Device>,
+        layouts: &[&Arc<DescriptorSetLayout>],
+        pool_count: usize,
+        max_sets: u32,
+    ) -> Result<Self, OomError> {
+        // every set needs at most the largest count a single layout has for each type
+        let mut pool_sizes: HashMap<DescriptorType, u32> = HashMap::new();
+        for layout in layouts {
+            let mut counts: HashMap<DescriptorType, u32> = HashMap::new();
+            for binding in layout.bindings().values() {
+                *counts.entry(binding.descriptor_type).or_default() += binding.descriptor_count;
+            }
+            for (ty, count) in counts {
+                let size = pool_sizes.entry(ty).or_default();
+                *size = (*size).max(count * max_sets);
+            }
+        }
+
+        let mut pools = Vec::with_capacity(pool_count);
+        for _ in 0..pool_count {
+            pools.push(create_pool(&device, max_sets, &pool_sizes)?);
+        }
+
+        Ok(Self {
+            device,
+            max_sets,
+            pool_sizes,
+            pools: RefCell::new(pools),
+            current: Cell::new(0),
+            allocated: Cell::new(0),
+        })
+    }
+
+    /// Sets allocated from the current pool since it was last reset.
+    pub fn allocated_sets(&self) -> u32 {
+        self.allocated.get()
+    }
+
+    /// Moves to the next pool in the cycle and resets it. Must be called once per frame.
+    pub fn next_frame(&self) -> Result<(), OomError> {
+        let mut pools = self.pools.borrow_mut();
+        let current = (self.current.get() + 1) % pools.len();
+        self.current.set(current);
+        self.allocated.set(0);
+
+        let pool = &mut pools[current];
+        if Arc::strong_count(pool) == 1 {
+            // no set allocated from this pool is alive anymore
+            unsafe { pool.lock().unwrap().reset()?; }
+        } else {
+            *pool = create_pool(&self.device, self.max_sets, &self.pool_sizes)?;
+        }
+        Ok(())
+    }
+
+    fn replace_current_pool(&self) -> Result<(), OomError> {
+        let pool = create_pool(&self.device, self.max_sets, &self.pool_sizes)?;
+        self.pools.borrow_mut()[self.current.get()] = pool;
+        self.allocated.set(0);
+        Ok(())
+    }
+}
+
+unsafe impl DescriptorSetAllocator for DescriptorPoolAllocator {
+    type Alloc = DescriptorPoolAlloc;
+
+    fn allocate(
+        &self,
+        layout: &Arc<DescriptorSetLayout>,
+        variable_descriptor_count: u32,
+    ) -> Result<Self::Alloc, OomError> {
+        if self.allocated.get() >= self.max_sets {
+            self.replace_current_pool()?;
+        }
+
+        loop {
+            let pool = self.pools.borrow()[self.current.get()].clone();
+            let result = unsafe {
+                pool.lock().unwrap().allocate_descriptor_sets([DescriptorSetAllocateInfo {
+                    layout,
+                    variable_descriptor_count,
+                }])
+            };
+            match result {
+                Ok(mut sets) => {
+                    self.allocated.set(self.allocated.get() + 1);
+                    return Ok(DescriptorPoolAlloc {
+                        inner: sets.next().unwrap(),
+                        _pool: pool,
+                    });
+                }
+                Err(DescriptorPoolAllocError::OutOfHostMemory) => return Err(OomError::OutOfHostMemory),
+                Err(DescriptorPoolAllocError::OutOfDeviceMemory) => return Err(OomError::OutOfDeviceMemory),
+                Err(DescriptorPoolAllocError::FragmentedPool)
+                | Err(DescriptorPoolAllocError::OutOfPoolMemory) => self.replace_current_pool()?,
+            }
+        }
+    }
+}
+
+unsafe impl DeviceOwned for DescriptorPoolAllocator {
+    fn device(&self) -> &Arc<Device> {
+        &self.device
+    }
+}
+
+impl DescriptorSetAlloc for DescriptorPoolAlloc {
+    fn inner(&self) -> &UnsafeDescriptorSet {
+        &self.inner
+    }
+
+    fn inner_mut(&mut self) -> &mut UnsafeDescriptorSet {
+        &mut self.inner
+    }
+}
+
+fn create_pool(
+    device: &Arc<Device>,
+    max_sets: u32,
+    pool_sizes: &HashMap<DescriptorType, u32>,
+) -> Result<Arc<Mutex<DescriptorPool>>, OomError> {
+    let pool = DescriptorPool::new(
+        device.clone(),
+        DescriptorPoolCreateInfo {
+            max_sets,
+            pool_sizes: pool_sizes.iter().map(|(&ty, &count)| (ty, count)).collect(),
+            ..Default::default()
+        },
+    )?;
+    Ok(Arc::new(Mutex::new(pool)))
+}
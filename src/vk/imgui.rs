@@ -12,9 +12,9 @@ use vulkano::image::{ImageDimensions, ImmutableImage, MipmapsCount};
 use vulkano::image::view::ImageView;
 use vulkano::pipeline::{GraphicsPipeline, Pipeline, PipelineBindPoint, StateMode};
 use vulkano::pipeline::graphics::color_blend::{AttachmentBlend, ColorBlendAttachmentState, ColorBlendState, ColorComponents};
+use vulkano::pipeline::graphics::render_pass::PipelineRenderPassType;
 use vulkano::pipeline::graphics::vertex_input::Vertex;
 use vulkano::pipeline::graphics::viewport::{Scissor, ViewportState};
-use vulkano::render_pass::Subpass;
 use vulkano::sampler::{Filter, Sampler, SamplerAddressMode, SamplerCreateInfo};
 
 use crate::vk::vk::Vk;
@@ -94,7 +94,7 @@ impl ImGuiRenderer {
         ctx: &mut imgui::Context,
         vk: &Vk,
         // format: Format,
-        render_pass: Subpass,
+        render_pass: PipelineRenderPassType,
     ) -> Result<ImGuiRenderer, Box<dyn std::error::Error>> {
         let vs = vs::load(vk.device.clone()).unwrap();
         let fs = fs::load(vk.device.clone()).unwrap();
@@ -129,6 +129,7 @@ impl ImGuiRenderer {
                 ..Default::default()
             })
             .render_pass(render_pass)
+            .build_with_cache(vk.pipeline_cache.clone())
             .build(vk.device.clone())?;
 
 
@@ -357,6 +358,15 @@ impl ImGuiRenderer {
         Ok((texture, sampler))
     }
 
+    /// Registers a texture, so it can be drawn by the UI or used by a material.
+    pub fn add_texture(&mut self, texture: Texture) -> TextureId {
+        self.textures.insert(texture)
+    }
+
+    pub fn texture(&self, texture_id: TextureId) -> Option<&Texture> {
+        self.textures.get(texture_id)
+    }
+
     fn lookup_texture(&self, texture_id: TextureId) -> Result<&Texture, RendererError> {
         if texture_id.id() == usize::MAX {
             Ok(&self.font_texture)
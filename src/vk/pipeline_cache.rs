@@ -0,0 +1,57 @@
+use std::path::PathBuf;
+use std::sync::Arc;
+
+use vulkano::device::Device;
+use vulkano::pipeline::cache::PipelineCache;
+
+// VkPipelineCacheHeaderVersionOne: length, version, vendor id, device id, then the cache UUID
+const HEADER_SIZE: usize = 32;
+
+/// `$XDG_CACHE_HOME/raytracing-demo/pipeline_cache.bin`, under `~/.cache` when the variable is unset.
+pub fn pipeline_cache_path() -> Option<PathBuf> {
+    let cache_dir = std::env::var_os("XDG_CACHE_HOME")
+        .map(PathBuf::from)
+        .or_else(|| std::env::var_os("HOME").map(|home| PathBuf::from(home).join(".cache")))?;
+    Some(cache_dir.join("raytracing-demo").join("pipeline_cache.bin"))
+}
+
+/// Creates the pipeline cache with the data saved by the last run, or an empty one if there is
+/// none or it was written by another device or driver.
+pub fn load_pipeline_cache(device: &Arc<Device>) -> Arc<PipelineCache> {
+    let data = pipeline_cache_path()
+        .and_then(|path| std::fs::read(path).ok())
+        .filter(|data| is_compatible(device, data));
+    match data {
+        Some(data) => {
+            println!("Loaded pipeline cache ({} bytes)", data.len());
+            // the header matches this device, so the driver accepts the data
+            unsafe { PipelineCache::with_data(device.clone(), &data).unwrap() }
+        }
+        None => PipelineCache::empty(device.clone()).unwrap(),
+    }
+}
+
+pub fn save_pipeline_cache(cache: &PipelineCache) {
+    let Some(path) = pipeline_cache_path() else {
+        return;
+    };
+    let data = cache.get_data().unwrap();
+    let result = std::fs::create_dir_all(path.parent().unwrap())
+        .and_then(|_| std::fs::write(&path, data));
+    if let Err(e) = result {
+        println!("Failed to save the pipeline cache to {}: {}", path.display(), e);
+    }
+}
+
+fn is_compatible(device: &Arc<Device>, data: &[u8]) -> bool {
+    if data.len() < HEADER_SIZE {
+        return false;
+    }
+    let field = |i: usize| u32::from_ne_bytes(data[i * 4..i * 4 + 4].try_into().unwrap());
+    let properties = device.physical_device().properties();
+    field(0) as usize >= HEADER_SIZE
+        && field(1) == 1
+        && field(2) == properties.vendor_id
+        && field(3) == properties.device_id
+        && data[16..32] == properties.pipeline_cache_uuid
+}
@@ -0,0 +1,174 @@
+use std::sync::Arc;
+
+use vulkano::command_buffer::{AutoCommandBufferBuilder, PrimaryAutoCommandBuffer};
+use vulkano::device::Device;
+use vulkano::query::{QueryControlFlags, QueryPipelineStatisticFlags, QueryPool, QueryPoolCreateInfo, QueryResultFlags, QueryType};
+use vulkano::sync::PipelineStage;
+
+/// Points of the frame a timestamp is written at, in recording order.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Timestamp {
+    FrameStart,
+    RaytraceEnd,
+    /// Start of the second submit, after the semaphore wait.
+    DenoiserStart,
+    DenoiserEnd,
+    ImguiEnd,
+}
+
+const TIMESTAMPS_PER_FRAME: u32 = 5;
+// two frames, one is read back while the other is recorded
+const QUERY_COUNT: u32 = TIMESTAMPS_PER_FRAME * 2;
+
+/// GPU timestamps of the frame passes.
+///
+/// Does nothing on queues without timestamp support.
+pub struct Profiler {
+    query_pool: Option<Arc<QueryPool>>,
+    /// Fragment shader invocations of the raytrace pass, one query per half, `None` without the
+    /// `pipeline_statistics_query` feature.
+    stats_pool: Option<Arc<QueryPool>>,
+    /// Nanoseconds per timestamp tick.
+    timestamp_period: f32,
+    frame: u32,
+    /// Whether each half of the pool was reset, unreset queries can't be read.
+    recorded: [bool; 2],
+}
+
+impl Profiler {
+    pub fn new(device: &Arc<Device>, queue_family_index: u32) -> Self {
+        let physical_device = device.physical_device();
+        let supported = physical_device.queue_family_properties()[queue_family_index as usize]
+            .timestamp_valid_bits
+            .is_some();
+        let query_pool = supported.then(|| {
+            QueryPool::new(
+                device.clone(),
+                QueryPoolCreateInfo {
+                    query_count: QUERY_COUNT,
+                    ..QueryPoolCreateInfo::query_type(QueryType::Timestamp)
+                },
+            ).unwrap()
+        });
+        println!("Timestamp queries supported: {}", supported);
+
+        let stats_pool = device.enabled_features().pipeline_statistics_query.then(|| {
+            QueryPool::new(
+                device.clone(),
+                QueryPoolCreateInfo {
+                    query_count: 2,
+                    ..QueryPoolCreateInfo::query_type(QueryType::PipelineStatistics(
+                        QueryPipelineStatisticFlags::FRAGMENT_SHADER_INVOCATIONS,
+                    ))
+                },
+            ).unwrap()
+        });
+
+        Self {
+            query_pool,
+            stats_pool,
+            timestamp_period: physical_device.properties().timestamp_period,
+            frame: 0,
+            recorded: [false; 2],
+        }
+    }
+
+    /// Switches to the other half of the pool and resets it, must be recorded outside of a render pass.
+    pub fn begin_frame(&mut self, command_builder: &mut AutoCommandBufferBuilder<PrimaryAutoCommandBuffer>) {
+        let Some(query_pool) = &self.query_pool else {
+            return;
+        };
+        self.frame = self.frame.wrapping_add(1);
+        let half = self.frame % 2;
+        let first = half * TIMESTAMPS_PER_FRAME;
+        unsafe {
+            command_builder
+                .reset_query_pool(query_pool.clone(), first..first + TIMESTAMPS_PER_FRAME)
+                .unwrap();
+            if let Some(stats_pool) = &self.stats_pool {
+                command_builder
+                    .reset_query_pool(stats_pool.clone(), half..half + 1)
+                    .unwrap();
+            }
+        }
+        self.recorded[half as usize] = true;
+    }
+
+    /// Starts counting the raytrace pass invocations, `end_raytrace_stats` must follow in the same subpass.
+    pub fn begin_raytrace_stats(&self, command_builder: &mut AutoCommandBufferBuilder<PrimaryAutoCommandBuffer>) {
+        let (Some(stats_pool), Some(_)) = (&self.stats_pool, &self.query_pool) else {
+            return;
+        };
+        unsafe {
+            command_builder
+                .begin_query(stats_pool.clone(), self.frame % 2, QueryControlFlags::empty())
+                .unwrap();
+        }
+    }
+
+    pub fn end_raytrace_stats(&self, command_builder: &mut AutoCommandBufferBuilder<PrimaryAutoCommandBuffer>) {
+        let (Some(stats_pool), Some(_)) = (&self.stats_pool, &self.query_pool) else {
+            return;
+        };
+        command_builder
+            .end_query(stats_pool.clone(), self.frame % 2)
+            .unwrap();
+    }
+
+    /// Fragment shader invocations of the raytrace pass in the same frame as `pass_times_ms`.
+    pub fn raytrace_invocations(&self) -> Option<u64> {
+        let (Some(stats_pool), Some(_)) = (&self.stats_pool, &self.query_pool) else {
+            return None;
+        };
+        let half = (self.frame + 1) % 2;
+        if !self.recorded[half as usize] {
+            return None;
+        }
+        let mut invocations = [0u64];
+        let ready = stats_pool
+            .queries_range(half..half + 1)
+            .unwrap()
+            .get_results(&mut invocations, QueryResultFlags::empty())
+            .unwrap();
+        ready.then_some(invocations[0])
+    }
+
+    pub fn write_timestamp(&self, command_builder: &mut AutoCommandBufferBuilder<PrimaryAutoCommandBuffer>, timestamp: Timestamp) {
+        let Some(query_pool) = &self.query_pool else {
+            return;
+        };
+        let stage = match timestamp {
+            Timestamp::FrameStart | Timestamp::DenoiserStart => PipelineStage::TopOfPipe,
+            _ => PipelineStage::BottomOfPipe,
+        };
+        let query = (self.frame % 2) * TIMESTAMPS_PER_FRAME + timestamp as u32;
+        unsafe {
+            command_builder
+                .write_timestamp(query_pool.clone(), query, stage)
+                .unwrap();
+        }
+    }
+
+    /// Raytrace, raytrace to denoiser latency, denoiser, imgui and whole frame times of the frame
+    /// before the last recorded one, `None` while the GPU hasn't finished it.
+    pub fn pass_times_ms(&self) -> Option<[f32; 5]> {
+        let query_pool = self.query_pool.as_ref()?;
+        let half = (self.frame + 1) % 2;
+        if !self.recorded[half as usize] {
+            return None;
+        }
+        let first = half * TIMESTAMPS_PER_FRAME;
+        let mut ticks = [0u64; TIMESTAMPS_PER_FRAME as usize];
+        let ready = query_pool
+            .queries_range(first..first + TIMESTAMPS_PER_FRAME)
+            .unwrap()
+            .get_results(&mut ticks, QueryResultFlags::empty())
+            .unwrap();
+        if !ready {
+            return None;
+        }
+
+        let ms = |from: usize, to: usize| ticks[to].wrapping_sub(ticks[from]) as f32 * self.timestamp_period / 1_000_000.0;
+        Some([ms(0, 1), ms(1, 2), ms(2, 3), ms(3, 4), ms(0, 4)])
+    }
+}
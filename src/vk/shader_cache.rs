@@ -0,0 +1,58 @@
+use std::collections::HashMap;
+use std::sync::{Arc, Mutex};
+
+use vulkano::pipeline::GraphicsPipeline;
+
+/// Identifies a pipeline variant: which shaders it was built from and with which
+/// specialization constant values.
+#[derive(Debug, Clone, PartialEq, Eq, Hash)]
+pub struct ShaderKey {
+    pub shader_type: &'static str,
+    pub specialization: Vec<u32>,
+}
+
+impl ShaderKey {
+    pub fn new(shader_type: &'static str) -> Self {
+        Self {
+            shader_type,
+            specialization: Vec::new(),
+        }
+    }
+
+    pub fn specialization(mut self, specialization: Vec<u32>) -> Self {
+        self.specialization = specialization;
+        self
+    }
+}
+
+/// Pipelines already built, by variant.
+///
+/// Cloning it shares the same cache, so it can be handed to other `Vk` instances as long as
+/// they use the same device.
+#[derive(Clone, Default)]
+pub struct ShaderVariantCache {
+    pipelines: Arc<Mutex<HashMap<ShaderKey, Arc<GraphicsPipeline>>>>,
+}
+
+impl ShaderVariantCache {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Returns the cached pipeline for `key`, building it with `create` on a miss.
+    pub fn get_or_create<F>(&self, key: ShaderKey, create: F) -> Arc<GraphicsPipeline>
+        where F: FnOnce() -> Arc<GraphicsPipeline>
+    {
+        let mut pipelines = self.pipelines.lock().unwrap();
+        pipelines.entry(key).or_insert_with(create).clone()
+    }
+
+    /// Drops the pipeline for `key`, so the next request rebuilds it. Used when a shader changes.
+    pub fn invalidate(&self, key: &ShaderKey) {
+        self.pipelines.lock().unwrap().remove(key);
+    }
+
+    pub fn clear(&self) {
+        self.pipelines.lock().unwrap().clear();
+    }
+}
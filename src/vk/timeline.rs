@@ -0,0 +1,92 @@
+use std::sync::Arc;
+
+use vulkano::device::{Device, Queue};
+use vulkano::VulkanObject;
+
+/// Semaphore counting up with each signal, vulkano 0.33 only creates binary semaphores so this
+/// one goes through the raw device functions.
+///
+/// Needs the `timeline_semaphore` feature, core in Vulkan 1.2 and `VK_KHR_timeline_semaphore` before.
+pub struct TimelineSemaphore {
+    device: Arc<Device>,
+    handle: ash::vk::Semaphore,
+    /// Last value signaled, the next signal uses the one after it.
+    value: u64,
+}
+
+impl TimelineSemaphore {
+    /// `None` when the device was created without the `timeline_semaphore` feature.
+    pub fn new(device: Arc<Device>) -> Option<Self> {
+        if !device.enabled_features().timeline_semaphore {
+            return None;
+        }
+        let type_info = ash::vk::SemaphoreTypeCreateInfo {
+            semaphore_type: ash::vk::SemaphoreType::TIMELINE,
+            initial_value: 0,
+            ..Default::default()
+        };
+        let info = ash::vk::SemaphoreCreateInfo {
+            p_next: &type_info as *const _ as *const _,
+            ..Default::default()
+        };
+        let mut handle = ash::vk::Semaphore::null();
+        unsafe {
+            (device.fns().v1_0.create_semaphore)(device.handle(), &info, std::ptr::null(), &mut handle)
+                .result()
+                .unwrap();
+        }
+        Some(Self { device, handle, value: 0 })
+    }
+
+    /// Submits a batch signaling the next value and a batch waiting for it, both without command
+    /// buffers. A signal covers everything submitted before it and a wait everything submitted
+    /// after it, so the next submit starts once the GPU finished the previous ones, without the
+    /// CPU in between. Returns the signaled value.
+    pub fn signal_and_wait(&mut self, queue: &Arc<Queue>) -> u64 {
+        self.value += 1;
+        let values = [self.value];
+        let semaphores = [self.handle];
+        let stages = [ash::vk::PipelineStageFlags::ALL_COMMANDS];
+        let signal_values = ash::vk::TimelineSemaphoreSubmitInfo {
+            signal_semaphore_value_count: 1,
+            p_signal_semaphore_values: values.as_ptr(),
+            ..Default::default()
+        };
+        let wait_values = ash::vk::TimelineSemaphoreSubmitInfo {
+            wait_semaphore_value_count: 1,
+            p_wait_semaphore_values: values.as_ptr(),
+            ..Default::default()
+        };
+        let submits = [
+            ash::vk::SubmitInfo {
+                p_next: &signal_values as *const _ as *const _,
+                signal_semaphore_count: 1,
+                p_signal_semaphores: semaphores.as_ptr(),
+                ..Default::default()
+            },
+            ash::vk::SubmitInfo {
+                p_next: &wait_values as *const _ as *const _,
+                wait_semaphore_count: 1,
+                p_wait_semaphores: semaphores.as_ptr(),
+                p_wait_dst_stage_mask: stages.as_ptr(),
+                ..Default::default()
+            },
+        ];
+        // the guard keeps vulkano from submitting to the queue at the same time
+        queue.with(|_guard| unsafe {
+            (self.device.fns().v1_0.queue_submit)(queue.handle(), submits.len() as u32, submits.as_ptr(), ash::vk::Fence::null())
+                .result()
+                .unwrap();
+        });
+        self.value
+    }
+}
+
+impl Drop for TimelineSemaphore {
+    fn drop(&mut self) {
+        // `Vk` waits for the device to be idle before its fields are dropped
+        unsafe {
+            (self.device.fns().v1_0.destroy_semaphore)(self.device.handle(), self.handle, std::ptr::null());
+        }
+    }
+}
@@ -1,57 +1,121 @@
 use std::cell::RefCell;
 use std::cmp::max;
+use std::ffi::{c_void, CString};
 use std::sync::Arc;
 use std::time::Duration;
 
-use vulkano::{sync, Version, VulkanLibrary};
+use ash::vk::Handle;
+use vulkano::{sync, Version, VulkanLibrary, VulkanObject};
 use vulkano::buffer::allocator::{SubbufferAllocator, SubbufferAllocatorCreateInfo};
-use vulkano::buffer::BufferUsage;
-use vulkano::command_buffer::{AutoCommandBufferBuilder, CommandBufferUsage, PrimaryAutoCommandBuffer, PrimaryCommandBufferAbstract, RenderPassBeginInfo, SubpassContents};
+use vulkano::buffer::{Buffer, BufferContents, BufferCreateInfo, BufferUsage, Subbuffer};
+use vulkano::command_buffer::{AutoCommandBufferBuilder, CommandBufferUsage, CopyBufferInfo, CopyImageToBufferInfo, PrimaryAutoCommandBuffer, PrimaryCommandBufferAbstract, RenderingAttachmentInfo, RenderingInfo, RenderPassBeginInfo, SubpassContents};
 use vulkano::command_buffer::allocator::StandardCommandBufferAllocator;
+use vulkano::descriptor_set::DescriptorSetsCollection;
 use vulkano::descriptor_set::allocator::StandardDescriptorSetAllocator;
-use vulkano::device::{Device, DeviceCreateInfo, DeviceExtensions, Queue, QueueCreateInfo};
-use vulkano::device::physical::PhysicalDeviceType;
+use vulkano::device::{Device, DeviceCreateInfo, DeviceExtensions, DeviceOwned, Features, Queue, QueueCreateInfo};
+use vulkano::device::physical::{PhysicalDevice, PhysicalDeviceType};
 use vulkano::format::Format;
-use vulkano::image::{AttachmentImage, ImageAccess, ImageUsage, SwapchainImage};
-use vulkano::image::view::ImageView;
+use vulkano::image::{AttachmentImage, ImageAccess, ImageCreateFlags, ImageDimensions, ImageUsage, ImmutableImage, MipmapsCount, StorageImage};
+use vulkano::image::view::{ImageView, ImageViewAbstract};
 use vulkano::instance::{Instance, InstanceCreateInfo};
-use vulkano::instance::debug::{DebugUtilsMessageSeverity, DebugUtilsMessageType, DebugUtilsMessenger, DebugUtilsMessengerCreateInfo};
-use vulkano::memory::allocator::StandardMemoryAllocator;
+use vulkano::instance::debug::{DebugUtilsLabel, DebugUtilsMessageSeverity, DebugUtilsMessageType, DebugUtilsMessenger, DebugUtilsMessengerCreateInfo};
+use vulkano::memory::MemoryHeapFlags;
+use vulkano::memory::allocator::{AllocationCreateInfo, MemoryUsage, StandardMemoryAllocator};
 use vulkano::pipeline::graphics::color_blend::ColorBlendState;
 use vulkano::pipeline::graphics::input_assembly::InputAssemblyState;
 use vulkano::pipeline::graphics::vertex_input::VertexDefinition;
 use vulkano::pipeline::graphics::viewport::{Viewport, ViewportState};
-use vulkano::pipeline::GraphicsPipeline;
-use vulkano::render_pass::{Framebuffer, FramebufferCreateInfo, RenderPass, Subpass};
-use vulkano::shader::EntryPoint;
-use vulkano::swapchain::{acquire_next_image, AcquireError, Surface, Swapchain, SwapchainAcquireFuture, SwapchainCreateInfo, SwapchainCreationError, SwapchainPresentInfo};
+use vulkano::pipeline::graphics::render_pass::{PipelineRenderingCreateInfo, PipelineRenderPassType};
+use vulkano::pipeline::{ComputePipeline, GraphicsPipeline, Pipeline, PipelineBindPoint};
+use vulkano::pipeline::cache::PipelineCache;
+use vulkano::render_pass::{Framebuffer, FramebufferCreateInfo, LoadOp, RenderPass, RenderPassCreationError, StoreOp, Subpass};
+use vulkano::shader::ShaderModule;
+use vulkano::swapchain::{acquire_next_image, AcquireError, PresentMode, Surface, Swapchain, SwapchainAcquireFuture, SwapchainCreateInfo, SwapchainCreationError, SwapchainPresentInfo};
 use vulkano::sync::{FlushError, GpuFuture};
+use vulkano::sync::future::FenceSignalFuture;
 use winit::window::Window;
 
-#[cfg(all(debug_assertions))]
-const ENABLE_VALIDATION_LAYERS: bool = true;
-#[cfg(not(debug_assertions))]
-const ENABLE_VALIDATION_LAYERS: bool = false;
+use crate::error::RaytracingError;
+use crate::vk::capabilities::SupportedFeatures;
+use crate::vk::pipeline_cache::{load_pipeline_cache, save_pipeline_cache};
+use crate::vk::profiler::{Profiler, Timestamp};
+use crate::vk::shader_cache::{ShaderKey, ShaderVariantCache};
+use crate::vk::timeline::TimelineSemaphore;
+
+
+/// Bloom images in `Buffers`, each half the size of the one before.
+pub const BLOOM_LEVELS: usize = 4;
+
+/// Format of the offscreen image used instead of the swapchain by `VkBuilder::build_headless`.
+const OFFSCREEN_FORMAT: Format = Format::B8G8R8A8_SRGB;
 
 pub struct Buffers {
-    pub raytrace_fb: Arc<Framebuffer>,
-    pub screen_fb: Arc<Framebuffer>,
+    pub raytrace_fb: Option<Arc<Framebuffer>>,
+    pub screen_fb: Option<Arc<Framebuffer>>,
+    pub screen_output: Arc<ImageView<dyn ImageAccess>>,
 
     pub ray_color_image: Arc<ImageView<AttachmentImage>>,
     pub ray_albedo_image: Arc<ImageView<AttachmentImage>>,
     pub ray_normal_image: Arc<ImageView<AttachmentImage>>,
     pub ray_depth_image: Arc<ImageView<AttachmentImage>>,
+    pub ray_color_right_image: Arc<ImageView<AttachmentImage>>,
+    pub ray_shading_normal_image: Arc<ImageView<AttachmentImage>>,
+    /// Share of the short rays around the first hit that escape, 1 is unoccluded.
+    pub ray_ao_image: Arc<ImageView<AttachmentImage>>,
+    /// Screen uv of each pixel's surface now minus where it was in the last frame.
+    pub ray_velocity_image: Arc<ImageView<AttachmentImage>>,
+    /// Running average of the raytraced color, the same image for every swapchain image.
+    pub accumulation_image: Arc<ImageView<StorageImage>>,
+    /// Luminance moments and variance of the accumulated color, written by the variance pass
+    /// and read by the denoiser. Shared like `accumulation_image`.
+    pub variance_image: Arc<ImageView<StorageImage>>,
+    /// Accumulated color of the previous frame, so the variance pass can recover each new sample.
+    pub history_color_image: Arc<ImageView<StorageImage>>,
+    /// Denoised color of the last frames, blended into the next one by the denoiser. Each frame
+    /// reads one and writes the other, so the history can be sampled at the reprojected uv.
+    pub temporal_history_images: [Arc<ImageView<StorageImage>>; 2],
+    /// Output of the horizontal pass of the separable denoiser, read by the vertical pass.
+    pub denoiser_ping_image: Arc<ImageView<StorageImage>>,
+    /// Tone mapped denoiser output, the current frame of the TAA resolve.
+    pub taa_input_image: Arc<ImageView<StorageImage>>,
+    /// Resolved TAA output, read and written alternately like `temporal_history_images`.
+    pub taa_history_images: [Arc<ImageView<StorageImage>>; 2],
+    /// Downsampled bright parts of the color, from half the screen size down.
+    pub bloom_images: Vec<Arc<ImageView<StorageImage>>>,
+}
+
+impl Buffers {
+    /// Color attachments written by the raytracing pass, in shader output location order.
+    pub fn raytrace_attachments(&self) -> Vec<Arc<dyn ImageViewAbstract>> {
+        vec![
+            self.ray_color_image.clone(),
+            self.ray_albedo_image.clone(),
+            self.ray_normal_image.clone(),
+            self.ray_depth_image.clone(),
+            self.ray_color_right_image.clone(),
+            self.ray_shading_normal_image.clone(),
+            self.ray_ao_image.clone(),
+            self.ray_velocity_image.clone(),
+        ]
+    }
 }
 
 pub struct Vk {
     pub device_name: String,
+    pub capabilities: SupportedFeatures,
+    /// Whether `VK_EXT_memory_budget` is enabled, so `memory_usage` knows the used memory.
+    pub memory_budget: bool,
+    /// Whether `begin_rendering` is used instead of render pass and framebuffer objects.
+    pub vk13_rendering: bool,
 
     pub instance: Arc<Instance>,
     pub device: Arc<Device>,
     pub queue: Arc<Queue>,
-    pub surface: Arc<Surface>,
-    pub swapchain: Arc<Swapchain>,
-    pub images: Vec<Arc<SwapchainImage>>,
+    /// `None` when built with `build_headless`, the frames are then rendered to `images` only.
+    pub surface: Option<Arc<Surface>>,
+    pub swapchain: Option<Arc<Swapchain>>,
+    /// Swapchain images, or the offscreen images standing in for them without a surface.
+    pub images: Vec<Arc<dyn ImageAccess>>,
 
     pub uploads: Option<RefCell<AutoCommandBufferBuilder<PrimaryAutoCommandBuffer>>>,
     pub raytrace_render_pass: Arc<RenderPass>,
@@ -65,28 +129,80 @@ pub struct Vk {
     pub storage_buffer: SubbufferAllocator,
     pub buffers: Option<Vec<Buffers>>,
 
+    pub shader_cache: ShaderVariantCache,
+    /// Shared by every pipeline build, saved to disk when `Vk` is dropped.
+    pub pipeline_cache: Arc<PipelineCache>,
+    pub profiler: Profiler,
+    /// Orders the denoiser submit after the raytrace submit, `None` without timeline support.
+    pub timeline_semaphore: Option<TimelineSemaphore>,
+    /// Chains the submits through `timeline_semaphore` instead of a binary semaphore when it exists.
+    pub use_timeline_semaphore: bool,
+    compute_pipelines: RefCell<Vec<(Arc<ShaderModule>, Arc<ComputePipeline>)>>,
+
     pub previous_frame_end: Option<Box<dyn GpuFuture>>,
     should_recreate_swapchain: bool,
     acquire_future: Option<SwapchainAcquireFuture>,
     current_image_index: u32,
 }
 
+/// Copy of an image on its way to host memory, `buffer` holds the texels once it is ready.
+pub struct Readback {
+    pub buffer: Subbuffer<[u8]>,
+    pub dimensions: [u32; 2],
+    pub format: Format,
+    fence: Arc<FenceSignalFuture<Box<dyn GpuFuture>>>,
+}
+
+impl Readback {
+    pub fn is_ready(&self) -> bool {
+        self.fence.is_signaled().unwrap()
+    }
+
+    pub fn wait(&self) {
+        self.fence.wait(None).unwrap();
+    }
+
+    /// Texels as linear RGBA floats, single channel formats fill red. `None` for the formats
+    /// the render targets don't use.
+    pub fn texels(&self) -> Option<Vec<[f32; 4]>> {
+        let data = self.buffer.read().unwrap();
+        let float = |bytes: &[u8]| f32::from_ne_bytes(bytes.try_into().unwrap());
+        let srgb = |byte: u8| {
+            let c = byte as f32 / 255.0;
+            if c <= 0.04045 { c / 12.92 } else { ((c + 0.055) / 1.055).powf(2.4) }
+        };
+        let texels = match self.format {
+            Format::R32G32B32A32_SFLOAT => data.chunks_exact(16)
+                .map(|t| [float(&t[0..4]), float(&t[4..8]), float(&t[8..12]), float(&t[12..16])])
+                .collect(),
+            Format::R32_SFLOAT => data.chunks_exact(4)
+                .map(|t| [float(t), 0.0, 0.0, 1.0])
+                .collect(),
+            Format::B8G8R8A8_SRGB => data.chunks_exact(4)
+                .map(|t| [srgb(t[2]), srgb(t[1]), srgb(t[0]), t[3] as f32 / 255.0])
+                .collect(),
+            _ => return None,
+        };
+        Some(texels)
+    }
+}
+
 pub enum DrawStatus {
     Ok,
     ShouldRecreateSwapchain,
 }
 
 impl<'a> Vk {
-    pub fn create_instance() -> Arc<Instance> {
-        let library = VulkanLibrary::new()
-            .expect("Failed to load Vulkan library");
+    /// `enable_validation` turns on the Khronos validation layer and the debug utils extension.
+    pub fn create_instance(enable_validation: bool) -> Result<Arc<Instance>, RaytracingError> {
+        let library = VulkanLibrary::new()?;
 
         let mut required_extensions = vulkano_win::required_extensions(&library);
-        required_extensions.ext_debug_utils = ENABLE_VALIDATION_LAYERS;
+        required_extensions.ext_debug_utils = enable_validation;
 
-        if ENABLE_VALIDATION_LAYERS {
+        if enable_validation {
             println!("List of Vulkan debugging layers available to use:");
-            let layers = library.layer_properties().unwrap();
+            let layers = library.layer_properties()?;
             for l in layers {
                 println!("\t{}", l.name());
             }
@@ -97,265 +213,157 @@ impl<'a> Vk {
             enumerate_portability: true,
             application_name: Some("Raytracing Demo".to_string()),
             application_version: Version::major_minor(1, 0),
+            max_api_version: Some(Version::V1_3),
             ..Default::default()
         };
 
-        if ENABLE_VALIDATION_LAYERS {
+        if enable_validation {
             let layers = vec!["VK_LAYER_KHRONOS_validation".to_owned()];
             info.enabled_layers = layers;
         }
 
-        Instance::new(library, info)
-            .expect("Failed to create Vulkan instance")
+        Ok(Instance::new(library, info)?)
     }
 
-    pub fn create_device(instance: Arc<Instance>, surface: Arc<Surface>) -> Self {
-        if ENABLE_VALIDATION_LAYERS {
-            let messenger = setup_debug_callback(&instance);
-            Box::leak(Box::new(messenger));
-        }
-
-        let device_extensions = DeviceExtensions {
-            khr_swapchain: true,
-            khr_storage_buffer_storage_class: true,
-            ..DeviceExtensions::empty()
-        };
-
-        let (physical_device, queue_family_index) = instance
+    /// Name and type of every device the instance sees, in enumeration order.
+    pub fn list_devices(instance: &Arc<Instance>) -> Vec<(String, PhysicalDeviceType)> {
+        instance
             .enumerate_physical_devices()
             .unwrap()
-            .filter(|p| p.supported_extensions().contains(&device_extensions))
-            .map(|p| {
-                (!p.queue_family_properties().is_empty())
-                    .then_some((p, 0))
-                    .expect("couldn't find a queue family")
-            })
-            .min_by_key(|(p, _)| match p.properties().device_type {
-                PhysicalDeviceType::DiscreteGpu => 0,
-                PhysicalDeviceType::IntegratedGpu => 1,
-                PhysicalDeviceType::VirtualGpu => 2,
-                PhysicalDeviceType::Cpu => 3,
-                PhysicalDeviceType::Other => 4,
-                _ => 5,
-            })
-            .expect("no device available");
-
-        let physical_properties = physical_device.properties();
-        println!(
-            "Using device: {} (type: {:?})",
-            physical_properties.device_name,
-            physical_properties.device_type,
-        );
-
-        let (device, mut queues) = Device::new(
-            physical_device.clone(),
-            DeviceCreateInfo {
-                enabled_extensions: device_extensions,
-                queue_create_infos: vec![QueueCreateInfo {
-                    queue_family_index,
-                    ..Default::default()
-                }],
-                ..Default::default()
-            },
-        ).expect("failed to create device");
-        let queue = queues.next().unwrap();
-
-        let (swapchain, images) = {
-            let surface_capabilities = device
-                .physical_device()
-                .surface_capabilities(&surface, Default::default())
-                .unwrap();
-
-            let image_format = Some(
-                device
-                    .physical_device()
-                    .surface_formats(&surface, Default::default())
-                    .unwrap()[0]
-                    .0,
-            );
-            let window = surface.object().unwrap().downcast_ref::<Window>().unwrap();
-            Swapchain::new(
-                device.clone(),
-                surface.clone(),
-                SwapchainCreateInfo {
-                    min_image_count: max(4, surface_capabilities.min_image_count),
-                    image_format,
-                    image_extent: window.inner_size().into(),
-
-                    image_usage: ImageUsage::COLOR_ATTACHMENT,
-                    composite_alpha: surface_capabilities
-                        .supported_composite_alpha
-                        .into_iter()
-                        .next()
-                        .unwrap(),
-
-                    ..Default::default()
-                },
-            ).unwrap()
-        };
+            .map(|p| (p.properties().device_name.clone(), p.properties().device_type))
+            .collect()
+    }
 
-        let memory_allocator = Arc::new(StandardMemoryAllocator::new_default(device.clone()));
+    /// First device whose name contains `name`, ignoring case.
+    pub fn select_device_by_name(instance: &Arc<Instance>, name: &str) -> Option<Arc<PhysicalDevice>> {
+        let name = name.to_lowercase();
+        instance
+            .enumerate_physical_devices()
+            .unwrap()
+            .find(|p| p.properties().device_name.to_lowercase().contains(&name))
+    }
 
-        let descriptor_set_allocator = Arc::new(StandardDescriptorSetAllocator::new(device.clone()));
+    /// Creates the device with the default `VkBuilder` options.
+    pub fn create_device(instance: Arc<Instance>, surface: Arc<Surface>) -> Result<Self, RaytracingError> {
+        VkBuilder::default().build(instance, surface)
+    }
 
-        let command_buffer_allocator = StandardCommandBufferAllocator::new(
-            device.clone(),
-            Default::default(),
-        );
+    pub fn query_device_features(&self) -> SupportedFeatures {
+        SupportedFeatures::from_physical_device(self.device.physical_device())
+    }
 
-        let uniform_buffer = SubbufferAllocator::new(
-            memory_allocator.clone(),
-            SubbufferAllocatorCreateInfo {
-                buffer_usage: BufferUsage::UNIFORM_BUFFER,
+    /// Render target description for pipelines drawing in the raytracing pass.
+    pub fn raytrace_pipeline_target(&self) -> PipelineRenderPassType {
+        if self.vk13_rendering {
+            PipelineRenderingCreateInfo {
+                color_attachment_formats: self.raytrace_render_pass.attachments().iter()
+                    .map(|a| a.format)
+                    .collect(),
                 ..Default::default()
-            },
-        );
+            }.into()
+        } else {
+            Subpass::from(self.raytrace_render_pass.clone(), 0).unwrap().into()
+        }
+    }
 
-        let storage_buffer = SubbufferAllocator::new(
-            memory_allocator.clone(),
-            SubbufferAllocatorCreateInfo {
-                buffer_usage: BufferUsage::STORAGE_BUFFER,
+    /// Render target description for pipelines drawing to the swapchain image.
+    pub fn screen_pipeline_target(&self) -> PipelineRenderPassType {
+        if self.vk13_rendering {
+            PipelineRenderingCreateInfo {
+                color_attachment_formats: vec![Some(self.images[0].format())],
                 ..Default::default()
-            },
-        );
-
-        let previous_frame_end = Some(sync::now(device.clone()).boxed());
+            }.into()
+        } else {
+            Subpass::from(self.screen_render_pass.clone(), 0).unwrap().into()
+        }
+    }
 
-        let uploads = RefCell::new(AutoCommandBufferBuilder::primary(
-            &command_buffer_allocator,
-            queue.queue_family_index(),
-            CommandBufferUsage::OneTimeSubmit,
-        ).unwrap());
+    /// Builds a pipeline from the `main` entry points of the modules returned by `load_shaders`,
+    /// or returns the one built before with the same `key`, without loading the shaders again.
+    pub fn create_pipeline<T, F>(
+        &self,
+        key: ShaderKey,
+        target: PipelineRenderPassType,
+        vertex_input_state: T,
+        load_shaders: F,
+    ) -> Arc<GraphicsPipeline>
+        where T: VertexDefinition, F: FnOnce(&Arc<Device>) -> (Arc<ShaderModule>, Arc<ShaderModule>)
+    {
+        let name = format!("{}_pipeline", key.shader_type);
+        self.shader_cache.get_or_create(key, || {
+            let (vertex_shader, fragment_shader) = load_shaders(&self.device);
+            let blend_count = match &target {
+                PipelineRenderPassType::BeginRenderPass(subpass) => subpass.num_color_attachments(),
+                PipelineRenderPassType::BeginRendering(info) => info.color_attachment_formats.len() as u32,
+            };
+            let pipeline = GraphicsPipeline::start()
+                .render_pass(target)
+                .vertex_input_state(vertex_input_state)
+                .input_assembly_state(InputAssemblyState::new())
+                .vertex_shader(vertex_shader.entry_point("main").unwrap(), ())
+                .fragment_shader(fragment_shader.entry_point("main").unwrap(), ())
+                .viewport_state(ViewportState::viewport_dynamic_scissor_irrelevant())
+                .color_blend_state(ColorBlendState::new(blend_count))
+                .build_with_cache(self.pipeline_cache.clone())
+                .build(self.device.clone())
+                .unwrap();
+            self.set_debug_name(&*pipeline, &name);
+            pipeline
+        })
+    }
 
-        let raytrace_render_pass = vulkano::ordered_passes_renderpass!(
-            device.clone(),
-            attachments: {
-                raytracing_output: {
-                    load: DontCare,
-                    store: Store,
-                    format: Format::R32G32B32A32_SFLOAT,
-                    samples: 1,
-                },
-                raytracing_albedo: {
-                    load: DontCare,
-                    store: Store,
-                    format: Format::B8G8R8A8_SRGB,
-                    samples: 1,
-                },
-                raytracing_normal: {
-                    load: DontCare,
-                    store: Store,
-                    format: Format::B8G8R8A8_SRGB,
-                    samples: 1,
-                },
-                raytracing_depth: {
-                    load: DontCare,
-                    store: Store,
-                    format: Format::R32_SFLOAT,
-                    samples: 1,
-                },
-            },
-            passes: [
-                {
-                    color: [raytracing_output, raytracing_albedo, raytracing_normal, raytracing_depth],
-                    depth_stencil: {},
-                    input: [],
-                },
-            ],
-        ).unwrap();
+    /// Builds a compute pipeline from the `main` entry point of `shader`, reusing the one built
+    /// before for the same module.
+    pub fn create_compute_pipeline(&self, shader: Arc<ShaderModule>, name: &str) -> Arc<ComputePipeline> {
+        let mut pipelines = self.compute_pipelines.borrow_mut();
+        if let Some((_, pipeline)) = pipelines.iter().find(|(module, _)| Arc::ptr_eq(module, &shader)) {
+            return pipeline.clone();
+        }
 
-        let screen_render_pass = vulkano::ordered_passes_renderpass!(
-            device.clone(),
-            attachments: {
-                screen_output: {
-                    load: Clear,
-                    store: Store,
-                    format: swapchain.image_format(),
-                    samples: 1,
-                },
-                raytracing_output: {
-                    load: Load,
-                    store: DontCare,
-                    format: Format::R32G32B32A32_SFLOAT,
-                    samples: 1,
-                },
-                raytracing_albedo: {
-                    load: Load,
-                    store: DontCare,
-                    format: Format::B8G8R8A8_SRGB,
-                    samples: 1,
-                },
-                raytracing_normal: {
-                    load: Load,
-                    store: DontCare,
-                    format: Format::B8G8R8A8_SRGB,
-                    samples: 1,
-                },
-                raytracing_depth: {
-                    load: Load,
-                    store: DontCare,
-                    format: Format::R32_SFLOAT,
-                    samples: 1,
-                },
-            },
-            passes: [
-                {
-                    color: [screen_output],
-                    depth_stencil: {},
-                    input: [raytracing_output, raytracing_albedo, raytracing_normal, raytracing_depth],
-                },
-            ],
+        let pipeline = ComputePipeline::new(
+            self.device.clone(),
+            shader.entry_point("main").unwrap(),
+            &(),
+            Some(self.pipeline_cache.clone()),
+            |_| {},
         ).unwrap();
+        self.set_debug_name(&*pipeline, name);
+        pipelines.push((shader, pipeline.clone()));
+        pipeline
+    }
 
-        return Vk {
-            device_name: physical_properties.device_name.clone(),
-
-            instance,
-            device,
-            queue,
-            surface,
-            swapchain,
-            images,
-
-            uploads: Some(uploads),
-            raytrace_render_pass,
-            screen_render_pass,
-
-            memory_allocator,
-            descriptor_set_allocator,
-            command_buffer_allocator,
-
-            uniform_buffer,
-            storage_buffer,
-            buffers: None,
-
-            previous_frame_end,
-            should_recreate_swapchain: false,
-            acquire_future: None,
-            current_image_index: 0,
-        };
+    /// Records binding `pipeline` with `descriptor_sets` at set 0 and dispatching `groups`
+    /// workgroups. Push constants, if any, are pushed by the caller before.
+    pub fn dispatch_compute<S>(
+        command_builder: &mut AutoCommandBufferBuilder<PrimaryAutoCommandBuffer>,
+        pipeline: Arc<ComputePipeline>,
+        descriptor_sets: S,
+        groups: [u32; 3],
+    ) where S: DescriptorSetsCollection {
+        command_builder
+            .bind_pipeline_compute(pipeline.clone())
+            .bind_descriptor_sets(PipelineBindPoint::Compute, pipeline.layout().clone(), 0, descriptor_sets)
+            .dispatch(groups)
+            .unwrap();
     }
 
-    pub fn create_pipeline<T>(
-        &self,
-        subpass: Subpass,
-        vertex_input_state: T,
-        vertex_shader: EntryPoint,
-        fragment_shader: EntryPoint,
-        blend_count: u32,
-    ) -> Arc<GraphicsPipeline>
-        where T: VertexDefinition
-    {
-        GraphicsPipeline::start()
-            .render_pass(subpass)
-            .vertex_input_state(vertex_input_state)
-            .input_assembly_state(InputAssemblyState::new())
-            .vertex_shader(vertex_shader, ())
-            .fragment_shader(fragment_shader, ())
-            .viewport_state(ViewportState::viewport_dynamic_scissor_irrelevant())
-            .color_blend_state(ColorBlendState::new(blend_count))
-            .build(self.device.clone())
-            .unwrap()
+    /// Device local image for compute and fragment shaders to write, sampled by later passes or
+    /// copied back for a readback.
+    pub fn create_storage_image(&self, format: Format, dimensions: [u32; 2]) -> Arc<ImageView<StorageImage>> {
+        ImageView::new_default(
+            StorageImage::with_usage(
+                &self.memory_allocator,
+                ImageDimensions::Dim2d {
+                    width: dimensions[0],
+                    height: dimensions[1],
+                    array_layers: 1,
+                },
+                format,
+                ImageUsage::STORAGE | ImageUsage::SAMPLED | ImageUsage::TRANSFER_SRC,
+                ImageCreateFlags::empty(),
+                [self.queue.queue_family_index()],
+            ).unwrap(),
+        ).unwrap()
     }
 
     pub fn setup_framebuffer(&mut self, viewport: &mut Viewport) {
@@ -364,13 +372,38 @@ impl<'a> Vk {
 
         let count = self.images.len();
 
+        let accumulation_image = self.create_storage_image(Format::R32G32B32A32_SFLOAT, dimensions);
+        let variance_image = self.create_storage_image(Format::R32G32B32A32_SFLOAT, dimensions);
+        let history_color_image = self.create_storage_image(Format::R32G32B32A32_SFLOAT, dimensions);
+        let temporal_history_images = [(); 2].map(|_| self.create_storage_image(Format::R32G32B32A32_SFLOAT, dimensions));
+        let denoiser_ping_image = self.create_storage_image(Format::R32G32B32A32_SFLOAT, dimensions);
+        let taa_input_image = self.create_storage_image(Format::R32G32B32A32_SFLOAT, dimensions);
+        let taa_history_images = [(); 2].map(|_| self.create_storage_image(Format::R32G32B32A32_SFLOAT, dimensions));
+        let bloom_images: Vec<_> = (1..=BLOOM_LEVELS)
+            .map(|level| self.create_storage_image(Format::R32G32B32A32_SFLOAT, dimensions.map(|d| max(d >> level, 1))))
+            .collect();
+        self.set_image_debug_name(&accumulation_image, "accumulation");
+        self.set_image_debug_name(&variance_image, "variance");
+        self.set_image_debug_name(&history_color_image, "history_color");
+        for (i, image) in temporal_history_images.iter().enumerate() {
+            self.set_image_debug_name(image, &format!("temporal_history_{}", i));
+        }
+        self.set_image_debug_name(&denoiser_ping_image, "denoiser_ping");
+        self.set_image_debug_name(&taa_input_image, "taa_input");
+        for (i, image) in taa_history_images.iter().enumerate() {
+            self.set_image_debug_name(image, &format!("taa_history_{}", i));
+        }
+        for (i, image) in bloom_images.iter().enumerate() {
+            self.set_image_debug_name(image, &format!("bloom_{}", i + 1));
+        }
+
         let buffers = (0..count).map(|idx| {
             let ray_color_image = ImageView::new_default(
                 AttachmentImage::with_usage(
                     &self.memory_allocator,
                     dimensions,
                     Format::R32G32B32A32_SFLOAT,
-                    ImageUsage::INPUT_ATTACHMENT | ImageUsage::SAMPLED,
+                    ImageUsage::INPUT_ATTACHMENT | ImageUsage::SAMPLED | ImageUsage::TRANSFER_SRC,
                 ).unwrap(),
             ).unwrap();
 
@@ -379,7 +412,7 @@ impl<'a> Vk {
                     &self.memory_allocator,
                     dimensions,
                     Format::B8G8R8A8_SRGB,
-                    ImageUsage::INPUT_ATTACHMENT | ImageUsage::SAMPLED,
+                    ImageUsage::INPUT_ATTACHMENT | ImageUsage::SAMPLED | ImageUsage::TRANSFER_SRC,
                 ).unwrap(),
             ).unwrap();
 
@@ -388,7 +421,7 @@ impl<'a> Vk {
                     &self.memory_allocator,
                     dimensions,
                     Format::B8G8R8A8_SRGB,
-                    ImageUsage::INPUT_ATTACHMENT | ImageUsage::SAMPLED,
+                    ImageUsage::INPUT_ATTACHMENT | ImageUsage::SAMPLED | ImageUsage::TRANSFER_SRC,
                 ).unwrap(),
             ).unwrap();
 
@@ -397,77 +430,364 @@ impl<'a> Vk {
                     &self.memory_allocator,
                     dimensions,
                     Format::R32_SFLOAT,
-                    ImageUsage::INPUT_ATTACHMENT | ImageUsage::SAMPLED,
+                    ImageUsage::INPUT_ATTACHMENT | ImageUsage::SAMPLED | ImageUsage::TRANSFER_SRC,
                 ).unwrap(),
             ).unwrap();
 
-            let screen_output = ImageView::new_default(self.images[idx].clone()).unwrap();
+            let ray_color_right_image = ImageView::new_default(
+                AttachmentImage::with_usage(
+                    &self.memory_allocator,
+                    dimensions,
+                    Format::R32G32B32A32_SFLOAT,
+                    ImageUsage::INPUT_ATTACHMENT | ImageUsage::SAMPLED | ImageUsage::TRANSFER_SRC,
+                ).unwrap(),
+            ).unwrap();
 
-            let raytrace_fb = Framebuffer::new(
-                self.raytrace_render_pass.clone(),
-                FramebufferCreateInfo {
-                    attachments: vec![
-                        ray_color_image.clone(),
-                        ray_albedo_image.clone(),
-                        ray_normal_image.clone(),
-                        ray_depth_image.clone(),
-                    ],
-                    ..Default::default()
-                },
+            let ray_shading_normal_image = ImageView::new_default(
+                AttachmentImage::with_usage(
+                    &self.memory_allocator,
+                    dimensions,
+                    Format::B8G8R8A8_SRGB,
+                    ImageUsage::INPUT_ATTACHMENT | ImageUsage::SAMPLED | ImageUsage::TRANSFER_SRC,
+                ).unwrap(),
             ).unwrap();
 
-            let screen_fb = Framebuffer::new(
-                self.screen_render_pass.clone(),
-                FramebufferCreateInfo {
-                    attachments: vec![
-                        screen_output,
-                        ray_color_image.clone(),
-                        ray_albedo_image.clone(),
-                        ray_normal_image.clone(),
-                        ray_depth_image.clone(),
-                    ],
-                    ..Default::default()
-                },
+            let ray_ao_image = ImageView::new_default(
+                AttachmentImage::with_usage(
+                    &self.memory_allocator,
+                    dimensions,
+                    Format::R8_UNORM,
+                    ImageUsage::INPUT_ATTACHMENT | ImageUsage::SAMPLED | ImageUsage::TRANSFER_SRC,
+                ).unwrap(),
+            ).unwrap();
+
+            let ray_velocity_image = ImageView::new_default(
+                AttachmentImage::with_usage(
+                    &self.memory_allocator,
+                    dimensions,
+                    Format::R16G16_SFLOAT,
+                    ImageUsage::INPUT_ATTACHMENT | ImageUsage::SAMPLED | ImageUsage::TRANSFER_SRC,
+                ).unwrap(),
             ).unwrap();
 
-            Buffers {
-                raytrace_fb,
-                screen_fb,
+            self.set_image_debug_name(&ray_color_image, "ray_color");
+            self.set_image_debug_name(&ray_albedo_image, "ray_albedo");
+            self.set_image_debug_name(&ray_normal_image, "ray_normal");
+            self.set_image_debug_name(&ray_depth_image, "ray_depth");
+            self.set_image_debug_name(&ray_color_right_image, "ray_color_right");
+            self.set_image_debug_name(&ray_shading_normal_image, "ray_shading_normal");
+            self.set_image_debug_name(&ray_ao_image, "ray_ao");
+            self.set_image_debug_name(&ray_velocity_image, "ray_velocity");
+
+            let screen_output = ImageView::new_default(self.images[idx].clone()).unwrap();
+
+            let mut buffers = Buffers {
+                raytrace_fb: None,
+                screen_fb: None,
+                screen_output,
                 ray_color_image,
                 ray_albedo_image,
                 ray_normal_image,
                 ray_depth_image,
+                ray_color_right_image,
+                ray_shading_normal_image,
+                ray_ao_image,
+                ray_velocity_image,
+                accumulation_image: accumulation_image.clone(),
+                variance_image: variance_image.clone(),
+                history_color_image: history_color_image.clone(),
+                temporal_history_images: temporal_history_images.clone(),
+                denoiser_ping_image: denoiser_ping_image.clone(),
+                taa_input_image: taa_input_image.clone(),
+                taa_history_images: taa_history_images.clone(),
+                bloom_images: bloom_images.clone(),
+            };
+
+            // with dynamic rendering the image views are bound directly in begin_frame
+            if !self.vk13_rendering {
+                let raytrace_attachments = buffers.raytrace_attachments();
+
+                buffers.raytrace_fb = Some(Framebuffer::new(
+                    self.raytrace_render_pass.clone(),
+                    FramebufferCreateInfo {
+                        attachments: raytrace_attachments.clone(),
+                        ..Default::default()
+                    },
+                ).unwrap());
+
+                let mut screen_attachments: Vec<Arc<dyn ImageViewAbstract>> = vec![buffers.screen_output.clone()];
+                screen_attachments.extend(raytrace_attachments);
+                buffers.screen_fb = Some(Framebuffer::new(
+                    self.screen_render_pass.clone(),
+                    FramebufferCreateInfo {
+                        attachments: screen_attachments,
+                        ..Default::default()
+                    },
+                ).unwrap());
             }
+
+            buffers
         }).collect();
 
         self.buffers = Some(buffers);
     }
 
-    pub fn recreate_swapchain(&mut self, size: [u32; 2], viewport: &mut Viewport) {
-        let (new_swapchain, new_images) =
-            match self.swapchain.recreate(SwapchainCreateInfo {
-                image_extent: size,
-                ..self.swapchain.create_info()
-            }) {
-                Ok(r) => r,
-                Err(SwapchainCreationError::ImageExtentNotSupported { .. }) => return,
-                Err(err) => panic!("failed to recreate swapchain: {}", err),
-            };
+    /// Bytes used by this process and total size of the device local heaps. The used memory is
+    /// only known with `memory_budget`.
+    pub fn memory_usage(&self) -> (Option<u64>, u64) {
+        let physical_device = self.device.physical_device();
+        let heaps = &physical_device.memory_properties().memory_heaps;
+        let device_local: Vec<usize> = heaps.iter()
+            .enumerate()
+            .filter(|(_, heap)| heap.flags.intersects(MemoryHeapFlags::DEVICE_LOCAL))
+            .map(|(i, _)| i)
+            .collect();
+        let total = device_local.iter().map(|&i| heaps[i].size).sum();
+        if !self.memory_budget {
+            return (None, total);
+        }
 
-        self.swapchain = new_swapchain;
-        self.images = new_images;
+        let mut budget = ash::vk::PhysicalDeviceMemoryBudgetPropertiesEXT::default();
+        let mut properties = ash::vk::PhysicalDeviceMemoryProperties2 {
+            p_next: &mut budget as *mut _ as *mut c_void,
+            ..Default::default()
+        };
+        unsafe {
+            (self.instance.fns().v1_1.get_physical_device_memory_properties2)(physical_device.handle(), &mut properties);
+        }
+        let used = device_local.iter().map(|&i| budget.heap_usage[i]).sum();
+        (Some(used), total)
+    }
+
+    /// Present modes the surface supports, FIFO always is. Only FIFO without a surface.
+    pub fn supported_present_modes(&self) -> Vec<PresentMode> {
+        match &self.surface {
+            Some(surface) => self.device
+                .physical_device()
+                .surface_present_modes(surface)
+                .unwrap()
+                .collect(),
+            None => vec![PresentMode::Fifo],
+        }
+    }
+
+    /// Without a swapchain the offscreen images are created again at `size`.
+    pub fn recreate_swapchain(&mut self, size: [u32; 2], present_mode: PresentMode, viewport: &mut Viewport) {
+        match &self.swapchain {
+            Some(swapchain) => {
+                let (new_swapchain, new_images) =
+                    match swapchain.recreate(SwapchainCreateInfo {
+                        image_extent: size,
+                        present_mode,
+                        ..swapchain.create_info()
+                    }) {
+                        Ok(r) => r,
+                        Err(SwapchainCreationError::ImageExtentNotSupported { .. }) => return,
+                        Err(err) => panic!("failed to recreate swapchain: {}", err),
+                    };
+
+                self.set_debug_name(&*new_swapchain, "main_swapchain");
+                self.swapchain = Some(new_swapchain);
+                self.images = new_images.into_iter().map(|i| i as Arc<dyn ImageAccess>).collect();
+            }
+            None => {
+                let format = self.images[0].format();
+                self.images = vec![create_offscreen_image(&self.memory_allocator, size, format)];
+            }
+        }
 
         self.setup_framebuffer(viewport);
     }
 
+    /// Render targets of the frame being recorded, or of the last one outside of a frame.
+    pub fn current_buffers(&self) -> &Buffers {
+        &self.buffers.as_ref().unwrap()[self.current_image_index as usize]
+    }
+
     pub fn wait_frame(&mut self) {
         self.previous_frame_end.as_mut().unwrap().cleanup_finished();
     }
 
+    /// Blocks until the device has finished all submitted work, so it is safe to destroy
+    /// resources that may still be referenced by in-flight command buffers.
+    pub fn wait_idle(&self) {
+        unsafe {
+            self.device.wait_idle().unwrap();
+        }
+    }
+
+    /// Creates a buffer holding `data`.
+    ///
+    /// On integrated GPUs the buffer is host visible and written directly. Otherwise it is
+    /// device local and filled from a staging buffer, recorded in `uploads` when it is pending
+    /// or in a one-shot command buffer that is waited on.
+    pub fn upload_buffer_data<T>(&self, data: &[T], usage: BufferUsage) -> Subbuffer<[T]>
+        where T: BufferContents + Clone
+    {
+        let unified_memory = self.device.physical_device().properties().device_type == PhysicalDeviceType::IntegratedGpu;
+        if unified_memory {
+            return Buffer::from_iter(
+                &self.memory_allocator,
+                BufferCreateInfo {
+                    usage,
+                    ..Default::default()
+                },
+                AllocationCreateInfo {
+                    usage: MemoryUsage::Upload,
+                    ..Default::default()
+                },
+                data.iter().cloned(),
+            ).unwrap();
+        }
+
+        let staging = Buffer::from_iter(
+            &self.memory_allocator,
+            BufferCreateInfo {
+                usage: BufferUsage::TRANSFER_SRC,
+                ..Default::default()
+            },
+            AllocationCreateInfo {
+                usage: MemoryUsage::Upload,
+                ..Default::default()
+            },
+            data.iter().cloned(),
+        ).unwrap();
+        let buffer = Buffer::new_slice::<T>(
+            &self.memory_allocator,
+            BufferCreateInfo {
+                usage: usage | BufferUsage::TRANSFER_DST,
+                ..Default::default()
+            },
+            AllocationCreateInfo {
+                usage: MemoryUsage::DeviceOnly,
+                ..Default::default()
+            },
+            data.len() as u64,
+        ).unwrap();
+
+        if let Some(uploads) = self.uploads.as_ref() {
+            uploads.borrow_mut()
+                .copy_buffer(CopyBufferInfo::buffers(staging, buffer.clone()))
+                .unwrap();
+        } else {
+            let mut command_builder = AutoCommandBufferBuilder::primary(
+                &self.command_buffer_allocator,
+                self.queue.queue_family_index(),
+                CommandBufferUsage::OneTimeSubmit,
+            ).unwrap();
+            command_builder
+                .copy_buffer(CopyBufferInfo::buffers(staging, buffer.clone()))
+                .unwrap();
+            command_builder
+                .build()
+                .unwrap()
+                .execute(self.queue.clone())
+                .unwrap()
+                .then_signal_fence_and_flush()
+                .unwrap()
+                .wait(None)
+                .unwrap();
+        }
+
+        buffer
+    }
+
+    /// Creates a single mip image filled with `data`, recorded in `uploads` when it is pending
+    /// or in a one-shot command buffer that is waited on.
+    pub fn upload_image_data(&self, data: &[u8], dimensions: ImageDimensions, format: Format) -> Arc<ImmutableImage> {
+        if let Some(uploads) = self.uploads.as_ref() {
+            return ImmutableImage::from_iter(
+                &self.memory_allocator,
+                data.iter().copied(),
+                dimensions,
+                MipmapsCount::One,
+                format,
+                &mut *uploads.borrow_mut(),
+            ).unwrap();
+        }
+
+        let mut command_builder = AutoCommandBufferBuilder::primary(
+            &self.command_buffer_allocator,
+            self.queue.queue_family_index(),
+            CommandBufferUsage::OneTimeSubmit,
+        ).unwrap();
+        let image = ImmutableImage::from_iter(
+            &self.memory_allocator,
+            data.iter().copied(),
+            dimensions,
+            MipmapsCount::One,
+            format,
+            &mut command_builder,
+        ).unwrap();
+        command_builder
+            .build()
+            .unwrap()
+            .execute(self.queue.clone())
+            .unwrap()
+            .then_signal_fence_and_flush()
+            .unwrap()
+            .wait(None)
+            .unwrap();
+        image
+    }
+
+    /// Copies `image` to a host visible buffer once the work submitted so far is done, without
+    /// waiting for it. Poll `Readback::is_ready` before reading the buffer.
+    pub fn read_image<I>(&mut self, image: Arc<I>) -> Readback
+        where I: ImageAccess + 'static
+    {
+        let dimensions = image.dimensions().width_height();
+        let format = image.format();
+        let size = dimensions[0] as u64 * dimensions[1] as u64 * format.block_size().unwrap();
+        let buffer = Buffer::new_slice::<u8>(
+            &self.memory_allocator,
+            BufferCreateInfo {
+                usage: BufferUsage::TRANSFER_DST,
+                ..Default::default()
+            },
+            AllocationCreateInfo {
+                usage: MemoryUsage::Download,
+                ..Default::default()
+            },
+            size,
+        ).unwrap();
+
+        let mut command_builder = AutoCommandBufferBuilder::primary(
+            &self.command_buffer_allocator,
+            self.queue.queue_family_index(),
+            CommandBufferUsage::OneTimeSubmit,
+        ).unwrap();
+        command_builder
+            .copy_image_to_buffer(CopyImageToBufferInfo::image_buffer(image, buffer.clone()))
+            .unwrap();
+
+        let fence = self.previous_frame_end
+            .take()
+            .unwrap()
+            .then_execute(self.queue.clone(), command_builder.build().unwrap())
+            .unwrap()
+            .boxed()
+            .then_signal_fence_and_flush()
+            .unwrap();
+        // shared between the readback and the frame future chain, both live on this thread
+        #[allow(clippy::arc_with_non_send_sync)]
+        let fence = Arc::new(fence);
+        // the next frame waits on the copy too, it is what the frame future chain ends with
+        self.previous_frame_end = Some(fence.clone().boxed());
+
+        Readback {
+            buffer,
+            dimensions,
+            format,
+            fence,
+        }
+    }
+
     pub fn do_upload(&mut self) {
         self.wait_frame();
         let uploads = self.uploads.take().unwrap();
-        let uploads = uploads.into_inner();
+        let mut uploads = uploads.into_inner();
+        Self::insert_debug_label(&mut uploads, "Upload Geometry");
         self.previous_frame_end = Some(
             uploads
                 .build()
@@ -478,21 +798,86 @@ impl<'a> Vk {
         );
     }
 
+    /// Names `object` in validation messages and in tools like RenderDoc and Nsight. Does nothing
+    /// without the debug utils extension, which is only enabled with validation. `object` must
+    /// belong to `device`.
+    pub fn set_debug_name<T: VulkanObject>(&self, object: &T, name: &str) {
+        if !self.instance.enabled_extensions().ext_debug_utils {
+            return;
+        }
+        let name = CString::new(name).unwrap();
+        let info = ash::vk::DebugUtilsObjectNameInfoEXT {
+            object_type: T::Handle::TYPE,
+            object_handle: object.handle().as_raw(),
+            p_object_name: name.as_ptr(),
+            ..Default::default()
+        };
+        unsafe {
+            (self.instance.fns().ext_debug_utils.set_debug_utils_object_name_ext)(self.device.handle(), &info)
+                .result()
+                .unwrap();
+        }
+    }
+
+    /// `set_debug_name` for the image behind `view`.
+    pub fn set_image_debug_name<I: ImageAccess + ?Sized>(&self, view: &ImageView<I>, name: &str) {
+        self.set_debug_name(&**view.image().inner().image, name);
+    }
+
+    /// Opens a labeled, colored region of commands, shown as a section by tools like RenderDoc.
+    pub fn begin_debug_label(cmd: &mut AutoCommandBufferBuilder<PrimaryAutoCommandBuffer>, label: &str, color: [f32; 4]) {
+        if !cmd.device().instance().enabled_extensions().ext_debug_utils {
+            return;
+        }
+        cmd.begin_debug_utils_label(DebugUtilsLabel {
+            label_name: label.to_string(),
+            color,
+            ..Default::default()
+        }).unwrap();
+    }
+
+    /// Closes the region opened by the last `begin_debug_label` on the same command buffer.
+    pub fn end_debug_label(cmd: &mut AutoCommandBufferBuilder<PrimaryAutoCommandBuffer>) {
+        if !cmd.device().instance().enabled_extensions().ext_debug_utils {
+            return;
+        }
+        unsafe {
+            cmd.end_debug_utils_label().unwrap();
+        }
+    }
+
+    pub fn insert_debug_label(cmd: &mut AutoCommandBufferBuilder<PrimaryAutoCommandBuffer>, label: &str) {
+        if !cmd.device().instance().enabled_extensions().ext_debug_utils {
+            return;
+        }
+        cmd.insert_debug_utils_label(DebugUtilsLabel {
+            label_name: label.to_string(),
+            ..Default::default()
+        }).unwrap();
+    }
+
     pub fn begin_frame(&mut self) -> Option<AutoCommandBufferBuilder<PrimaryAutoCommandBuffer>> {
-        let (image_index, suboptimal, acquire_future) =
-            match acquire_next_image(self.swapchain.clone(), Some(Duration::from_secs(1))) {
-                Ok(r) => r,
-                Err(AcquireError::OutOfDate) => {
-                    self.should_recreate_swapchain = true;
-                    return None;
-                }
-                Err(e) => {
-                    println!("failed to acquire next image: {e}");
-                    return None;
-                }
-            };
-        self.should_recreate_swapchain = suboptimal;
-        self.acquire_future = Some(acquire_future);
+        // offscreen there is a single image, the frames are ordered by `previous_frame_end`
+        let image_index = match &self.swapchain {
+            Some(swapchain) => {
+                let (image_index, suboptimal, acquire_future) =
+                    match acquire_next_image(swapchain.clone(), Some(Duration::from_secs(1))) {
+                        Ok(r) => r,
+                        Err(AcquireError::OutOfDate) => {
+                            self.should_recreate_swapchain = true;
+                            return None;
+                        }
+                        Err(e) => {
+                            println!("failed to acquire next image: {e}");
+                            return None;
+                        }
+                    };
+                self.should_recreate_swapchain = suboptimal;
+                self.acquire_future = Some(acquire_future);
+                image_index
+            }
+            None => 0,
+        };
         self.current_image_index = image_index;
 
         let queue_index = self.queue.queue_family_index();
@@ -501,25 +886,44 @@ impl<'a> Vk {
             queue_index,
             CommandBufferUsage::OneTimeSubmit,
         ).unwrap();
-
-        command_builder
-            .begin_render_pass(
-                RenderPassBeginInfo {
-                    clear_values: (0..self.raytrace_render_pass.attachments().len())
-                        .map(|_| Some([0.0, 0.0, 1.0, 1.0].into()))
+        self.profiler.begin_frame(&mut command_builder);
+
+        let buf = &self.buffers.as_ref().unwrap()[image_index as usize];
+        if self.vk13_rendering {
+            command_builder
+                .begin_rendering(RenderingInfo {
+                    color_attachments: buf.raytrace_attachments().into_iter()
+                        .map(|view| Some(RenderingAttachmentInfo {
+                            load_op: LoadOp::DontCare,
+                            store_op: StoreOp::Store,
+                            ..RenderingAttachmentInfo::image_view(view)
+                        }))
                         .collect(),
-                    ..RenderPassBeginInfo::framebuffer(
-                        self.buffers.as_ref().unwrap()[image_index as usize].raytrace_fb.clone(),
-                    )
-                },
-                SubpassContents::Inline,
-            )
-            .unwrap();
+                    ..Default::default()
+                })
+                .unwrap();
+        } else {
+            command_builder
+                .begin_render_pass(
+                    RenderPassBeginInfo {
+                        clear_values: (0..self.raytrace_render_pass.attachments().len())
+                            .map(|_| Some([0.0, 0.0, 1.0, 1.0].into()))
+                            .collect(),
+                        ..RenderPassBeginInfo::framebuffer(buf.raytrace_fb.clone().unwrap())
+                    },
+                    SubpassContents::Inline,
+                )
+                .unwrap();
+        }
 
         return Some(command_builder);
     }
 
-    pub fn next_render_pass(&mut self, command_builder: &mut AutoCommandBufferBuilder<PrimaryAutoCommandBuffer>) -> &Buffers {
+    /// Ends the raytrace pass and begins the screen pass, `between_passes` records commands
+    /// that can't run inside a render pass, like compute dispatches.
+    pub fn next_render_pass<F>(&mut self, command_builder: &mut AutoCommandBufferBuilder<PrimaryAutoCommandBuffer>, between_passes: F) -> &Buffers
+        where F: FnOnce(&mut AutoCommandBufferBuilder<PrimaryAutoCommandBuffer>)
+    {
         let mut first_cmd_builder = AutoCommandBufferBuilder::primary(
             &self.command_buffer_allocator,
             self.queue.queue_family_index(),
@@ -528,39 +932,79 @@ impl<'a> Vk {
 
         std::mem::swap(command_builder, &mut first_cmd_builder);
 
-        first_cmd_builder
-            .end_render_pass()
-            .unwrap();
+        self.end_pass(&mut first_cmd_builder);
         let raytrace_cmd = first_cmd_builder
             .build()
             .unwrap();
 
+        // The denoiser submit waits on a semaphore signaled by the raytrace submit, so the
+        // dependency is resolved on the GPU instead of through a fence
         let future = self.previous_frame_end
             .take()
             .unwrap()
             .then_execute(self.queue.clone(), raytrace_cmd)
-            .unwrap()
-            .then_signal_fence_and_flush()
-            .unwrap()
-            .boxed();
+            .unwrap();
+        let use_timeline = self.use_timeline_semaphore;
+        let future = match self.timeline_semaphore.as_mut().filter(|_| use_timeline) {
+            Some(timeline) => {
+                future.flush().unwrap();
+                timeline.signal_and_wait(&self.queue);
+                future.boxed()
+            }
+            None => future.then_signal_semaphore_and_flush().unwrap().boxed(),
+        };
         self.previous_frame_end = Some(future);
 
-        let buf = &self.buffers.as_ref().unwrap()[self.current_image_index as usize];
-        command_builder
-            .begin_render_pass(
-                RenderPassBeginInfo {
-                    clear_values: (0..self.screen_render_pass.attachments().len())
-                        .map(|_| Some([0.0, 1.0, 0.0, 1.0].into()))
-                        .collect(),
-                    ..RenderPassBeginInfo::framebuffer(
-                        buf.screen_fb.clone(),
-                    )
-                },
-                SubpassContents::Inline,
-            )
-            .unwrap();
+        // the time between RaytraceEnd and this one is the cost of the chaining
+        self.profiler.write_timestamp(command_builder, Timestamp::DenoiserStart);
+        between_passes(command_builder);
+
+        self.begin_screen_pass(command_builder);
+        self.current_buffers()
+    }
+
+    /// Ends the screen pass and begins it again, so the next draws can sample what was written
+    /// before. The swapchain image is cleared, the next draw has to cover it.
+    pub fn next_screen_pass(&self, command_builder: &mut AutoCommandBufferBuilder<PrimaryAutoCommandBuffer>) {
+        self.end_pass(command_builder);
+        self.begin_screen_pass(command_builder);
+    }
 
-        return buf;
+    fn begin_screen_pass(&self, command_builder: &mut AutoCommandBufferBuilder<PrimaryAutoCommandBuffer>) {
+        let buf = self.current_buffers();
+        if self.vk13_rendering {
+            command_builder
+                .begin_rendering(RenderingInfo {
+                    color_attachments: vec![Some(RenderingAttachmentInfo {
+                        load_op: LoadOp::Clear,
+                        store_op: StoreOp::Store,
+                        clear_value: Some([0.0, 1.0, 0.0, 1.0].into()),
+                        ..RenderingAttachmentInfo::image_view(buf.screen_output.clone())
+                    })],
+                    ..Default::default()
+                })
+                .unwrap();
+        } else {
+            command_builder
+                .begin_render_pass(
+                    RenderPassBeginInfo {
+                        clear_values: (0..self.screen_render_pass.attachments().len())
+                            .map(|_| Some([0.0, 1.0, 0.0, 1.0].into()))
+                            .collect(),
+                        ..RenderPassBeginInfo::framebuffer(buf.screen_fb.clone().unwrap())
+                    },
+                    SubpassContents::Inline,
+                )
+                .unwrap();
+        }
+    }
+
+    fn end_pass(&self, command_builder: &mut AutoCommandBufferBuilder<PrimaryAutoCommandBuffer>) {
+        if self.vk13_rendering {
+            command_builder.end_rendering().unwrap();
+        } else {
+            command_builder.end_render_pass().unwrap();
+        }
     }
 
     pub fn end_frame(&mut self, command_builder: Option<AutoCommandBufferBuilder<PrimaryAutoCommandBuffer>>) -> DrawStatus {
@@ -576,23 +1020,26 @@ impl<'a> Vk {
 
         let mut command_builder = command_builder.unwrap();
 
-        command_builder
-            .end_render_pass()
-            .unwrap();
+        self.end_pass(&mut command_builder);
 
         let command_buffer = command_builder.build().unwrap();
 
-        let future = self.previous_frame_end
-            .take()
-            .unwrap()
-            .join(self.acquire_future.take().expect("start_frame() must be called before end_frame()"))
-            .then_execute(self.queue.clone(), command_buffer)
-            .unwrap()
-            .then_swapchain_present(
-                self.queue.clone(),
-                SwapchainPresentInfo::swapchain_image_index(self.swapchain.clone(), self.current_image_index),
-            )
-            .then_signal_fence_and_flush();
+        let previous_frame_end = self.previous_frame_end.take().unwrap();
+        let future = match &self.swapchain {
+            Some(swapchain) => previous_frame_end
+                .join(self.acquire_future.take().expect("start_frame() must be called before end_frame()"))
+                .then_execute(self.queue.clone(), command_buffer)
+                .unwrap()
+                .then_swapchain_present(
+                    self.queue.clone(),
+                    SwapchainPresentInfo::swapchain_image_index(swapchain.clone(), self.current_image_index),
+                )
+                .boxed(),
+            None => previous_frame_end
+                .then_execute(self.queue.clone(), command_buffer)
+                .unwrap()
+                .boxed(),
+        }.then_signal_fence_and_flush();
 
         match future {
             Ok(future) => {
@@ -616,6 +1063,453 @@ impl<'a> Vk {
     }
 }
 
+/// Stands in for the swapchain image without a surface, it can be read back like the others.
+fn create_offscreen_image(allocator: &StandardMemoryAllocator, dimensions: [u32; 2], format: Format) -> Arc<dyn ImageAccess> {
+    AttachmentImage::with_usage(
+        allocator,
+        dimensions,
+        format,
+        ImageUsage::COLOR_ATTACHMENT | ImageUsage::TRANSFER_SRC,
+    ).unwrap()
+}
+
+/// Creates the raytrace and screen render passes for the swapchain format.
+pub type RenderPassFn = fn(&Arc<Device>, Format) -> Result<(Arc<RenderPass>, Arc<RenderPass>), RenderPassCreationError>;
+
+/// Options for `Vk` creation, `Vk::create_device` builds with the defaults.
+pub struct VkBuilder {
+    physical_device: Option<Arc<PhysicalDevice>>,
+    preferred_device_type: PhysicalDeviceType,
+    device_extensions: DeviceExtensions,
+    swapchain_min_images: u32,
+    render_pass_fn: RenderPassFn,
+}
+
+impl Default for VkBuilder {
+    fn default() -> Self {
+        Self {
+            physical_device: None,
+            preferred_device_type: PhysicalDeviceType::DiscreteGpu,
+            device_extensions: DeviceExtensions {
+                khr_swapchain: true,
+                khr_storage_buffer_storage_class: true,
+                ..DeviceExtensions::empty()
+            },
+            swapchain_min_images: 4,
+            render_pass_fn: default_render_passes,
+        }
+    }
+}
+
+impl VkBuilder {
+    /// Skips the device ranking, the build fails if `physical_device` lacks the required extensions.
+    pub fn use_device(&mut self, physical_device: Arc<PhysicalDevice>) -> &mut Self {
+        self.physical_device = Some(physical_device);
+        self
+    }
+
+    /// Device type picked first, the others keep the discrete, integrated, virtual, CPU order.
+    pub fn prefer_device_type(&mut self, device_type: PhysicalDeviceType) -> &mut Self {
+        self.preferred_device_type = device_type;
+        self
+    }
+
+    /// Extensions a device needs to be picked, `khr_swapchain` is always added.
+    pub fn required_extensions(&mut self, extensions: DeviceExtensions) -> &mut Self {
+        self.device_extensions = extensions.union(&DeviceExtensions {
+            khr_swapchain: true,
+            ..DeviceExtensions::empty()
+        });
+        self
+    }
+
+    /// Raised to the surface minimum when lower.
+    pub fn swapchain_min_images(&mut self, count: u32) -> &mut Self {
+        self.swapchain_min_images = count;
+        self
+    }
+
+    /// The passes must keep the attachments of `default_render_passes`, the framebuffers and
+    /// pipelines are built for them.
+    pub fn with_render_pass_fn(&mut self, render_pass_fn: RenderPassFn) -> &mut Self {
+        self.render_pass_fn = render_pass_fn;
+        self
+    }
+
+    pub fn build(&self, instance: Arc<Instance>, surface: Arc<Surface>) -> Result<Vk, RaytracingError> {
+        let window = surface.object().unwrap().downcast_ref::<Window>().unwrap();
+        let dimensions = window.inner_size().into();
+        self.build_with(instance, Some(surface), dimensions)
+    }
+
+    /// Builds without a surface, the frames are rendered to an offscreen image of `dimensions`
+    /// and never presented. `khr_swapchain` is not required then.
+    pub fn build_headless(&self, instance: Arc<Instance>, dimensions: [u32; 2]) -> Result<Vk, RaytracingError> {
+        self.build_with(instance, None, dimensions)
+    }
+
+    fn build_with(&self, instance: Arc<Instance>, surface: Option<Arc<Surface>>, dimensions: [u32; 2]) -> Result<Vk, RaytracingError> {
+        let device_extensions = match surface {
+            Some(_) => self.device_extensions,
+            None => self.device_extensions.difference(&DeviceExtensions {
+                khr_swapchain: true,
+                ..DeviceExtensions::empty()
+            }),
+        };
+
+        if instance.enabled_extensions().ext_debug_utils {
+            let messenger = setup_debug_callback(&instance);
+            Box::leak(Box::new(messenger));
+        }
+
+        let candidates: Vec<_> = match &self.physical_device {
+            Some(p) => vec![p.clone()],
+            None => instance.enumerate_physical_devices()?.collect(),
+        };
+        let (physical_device, queue_family_index) = candidates
+            .into_iter()
+            .filter(|p| p.supported_extensions().contains(&device_extensions))
+            // the raytracing shader writes the accumulation image from the fragment stage
+            .filter(|p| {
+                let supported = p.supported_features().fragment_stores_and_atomics;
+                if !supported {
+                    println!("Skipping device {}: fragmentStoresAndAtomics is not supported", p.properties().device_name);
+                }
+                supported
+            })
+            .filter_map(|p| {
+                (!p.queue_family_properties().is_empty())
+                    .then_some((p, 0))
+            })
+            .min_by_key(|(p, _)| match p.properties().device_type {
+                t if t == self.preferred_device_type => 0,
+                PhysicalDeviceType::DiscreteGpu => 1,
+                PhysicalDeviceType::IntegratedGpu => 2,
+                PhysicalDeviceType::VirtualGpu => 3,
+                PhysicalDeviceType::Cpu => 4,
+                PhysicalDeviceType::Other => 5,
+                _ => 6,
+            })
+            .ok_or(RaytracingError::NoSuitableDevice)?;
+
+        let physical_properties = physical_device.properties();
+        println!(
+            "Using device: {} (type: {:?})",
+            physical_properties.device_name,
+            physical_properties.device_type,
+        );
+
+        let capabilities = SupportedFeatures::from_physical_device(&physical_device);
+
+        // the features are core 1.3, so both the device and the instance have to be on 1.3
+        // since the KHR extensions aren't enabled
+        let vk13_rendering = physical_device.api_version() >= Version::V1_3
+            && instance.api_version() >= Version::V1_3
+            && capabilities.dynamic_rendering
+            && capabilities.synchronization2;
+        println!("Dynamic rendering: {}", vk13_rendering);
+
+        // reports how much of each heap the process uses, queried through Vulkan 1.1
+        let memory_budget = physical_device.supported_extensions().ext_memory_budget
+            && physical_device.api_version() >= Version::V1_1
+            && instance.api_version() >= Version::V1_1;
+        println!("Memory budget: {}", memory_budget);
+
+        // core in 1.2, the extension has the same functions before
+        let timeline_core = physical_device.api_version() >= Version::V1_2
+            && instance.api_version() >= Version::V1_2;
+        let timeline_extension = !timeline_core && physical_device.supported_extensions().khr_timeline_semaphore;
+        let timeline_semaphore = capabilities.timeline_semaphore && (timeline_core || timeline_extension);
+        println!("Timeline semaphores: {}", timeline_semaphore);
+        let enabled_extensions = device_extensions.union(&DeviceExtensions {
+            ext_memory_budget: memory_budget,
+            khr_timeline_semaphore: timeline_semaphore && timeline_extension,
+            ..DeviceExtensions::empty()
+        });
+        let enabled_features = Features {
+            dynamic_rendering: vk13_rendering,
+            synchronization2: vk13_rendering,
+            // the raytracing shader writes the accumulation image
+            fragment_stores_and_atomics: true,
+            // counts the raytrace pass invocations for the profiler
+            pipeline_statistics_query: capabilities.pipeline_statistics_query,
+            timeline_semaphore,
+            ..Features::empty()
+        };
+
+        let (device, mut queues) = Device::new(
+            physical_device.clone(),
+            DeviceCreateInfo {
+                enabled_extensions,
+                enabled_features,
+                queue_create_infos: vec![QueueCreateInfo {
+                    queue_family_index,
+                    ..Default::default()
+                }],
+                ..Default::default()
+            },
+        )?;
+        let queue = queues.next().unwrap();
+        let profiler = Profiler::new(&device, queue_family_index);
+        let timeline_semaphore = TimelineSemaphore::new(device.clone());
+        let pipeline_cache = load_pipeline_cache(&device);
+
+        let memory_allocator = Arc::new(StandardMemoryAllocator::new_default(device.clone()));
+
+        let (swapchain, images) = match &surface {
+            Some(surface) => {
+                let surface_capabilities = device
+                    .physical_device()
+                    .surface_capabilities(surface, Default::default())?;
+
+                let image_format = Some(
+                    device
+                        .physical_device()
+                        .surface_formats(surface, Default::default())?[0]
+                        .0,
+                );
+                let (swapchain, images) = Swapchain::new(
+                    device.clone(),
+                    surface.clone(),
+                    SwapchainCreateInfo {
+                        min_image_count: max(self.swapchain_min_images, surface_capabilities.min_image_count),
+                        image_format,
+                        image_extent: dimensions,
+
+                        image_usage: ImageUsage::COLOR_ATTACHMENT,
+                        composite_alpha: surface_capabilities
+                            .supported_composite_alpha
+                            .into_iter()
+                            .next()
+                            .unwrap(),
+
+                        ..Default::default()
+                    },
+                )?;
+                (Some(swapchain), images.into_iter().map(|i| i as Arc<dyn ImageAccess>).collect())
+            }
+            None => (None, vec![create_offscreen_image(&memory_allocator, dimensions, OFFSCREEN_FORMAT)]),
+        };
+
+        let descriptor_set_allocator = Arc::new(StandardDescriptorSetAllocator::new(device.clone()));
+
+        let command_buffer_allocator = StandardCommandBufferAllocator::new(
+            device.clone(),
+            Default::default(),
+        );
+
+        let uniform_buffer = SubbufferAllocator::new(
+            memory_allocator.clone(),
+            SubbufferAllocatorCreateInfo {
+                buffer_usage: BufferUsage::UNIFORM_BUFFER,
+                ..Default::default()
+            },
+        );
+
+        let storage_buffer = SubbufferAllocator::new(
+            memory_allocator.clone(),
+            SubbufferAllocatorCreateInfo {
+                buffer_usage: BufferUsage::STORAGE_BUFFER,
+                ..Default::default()
+            },
+        );
+
+        let previous_frame_end = Some(sync::now(device.clone()).boxed());
+
+        let uploads = RefCell::new(AutoCommandBufferBuilder::primary(
+            &command_buffer_allocator,
+            queue.queue_family_index(),
+            CommandBufferUsage::OneTimeSubmit,
+        )?);
+
+        let (raytrace_render_pass, screen_render_pass) = (self.render_pass_fn)(&device, images[0].format())?;
+
+        let vk = Vk {
+            device_name: physical_properties.device_name.clone(),
+            capabilities,
+            memory_budget,
+            vk13_rendering,
+
+            instance,
+            device,
+            queue,
+            surface,
+            swapchain,
+            images,
+
+            uploads: Some(uploads),
+            raytrace_render_pass,
+            screen_render_pass,
+
+            memory_allocator,
+            descriptor_set_allocator,
+            command_buffer_allocator,
+
+            uniform_buffer,
+            storage_buffer,
+            buffers: None,
+
+            shader_cache: ShaderVariantCache::new(),
+            pipeline_cache,
+            profiler,
+            timeline_semaphore,
+            use_timeline_semaphore: true,
+            compute_pipelines: RefCell::new(Vec::new()),
+
+            previous_frame_end,
+            should_recreate_swapchain: false,
+            acquire_future: None,
+            current_image_index: 0,
+        };
+        if let Some(swapchain) = &vk.swapchain {
+            vk.set_debug_name(&**swapchain, "main_swapchain");
+        }
+        vk.set_debug_name(&*vk.raytrace_render_pass, "raytrace_render_pass");
+        vk.set_debug_name(&*vk.screen_render_pass, "screen_render_pass");
+        Ok(vk)
+    }
+}
+
+pub fn default_render_passes(device: &Arc<Device>, swapchain_format: Format) -> Result<(Arc<RenderPass>, Arc<RenderPass>), RenderPassCreationError> {
+    let raytrace_render_pass = vulkano::ordered_passes_renderpass!(
+        device.clone(),
+        attachments: {
+            raytracing_output: {
+                load: DontCare,
+                store: Store,
+                format: Format::R32G32B32A32_SFLOAT,
+                samples: 1,
+            },
+            raytracing_albedo: {
+                load: DontCare,
+                store: Store,
+                format: Format::B8G8R8A8_SRGB,
+                samples: 1,
+            },
+            raytracing_normal: {
+                load: DontCare,
+                store: Store,
+                format: Format::B8G8R8A8_SRGB,
+                samples: 1,
+            },
+            raytracing_depth: {
+                load: DontCare,
+                store: Store,
+                format: Format::R32_SFLOAT,
+                samples: 1,
+            },
+            raytracing_output_right: {
+                load: DontCare,
+                store: Store,
+                format: Format::R32G32B32A32_SFLOAT,
+                samples: 1,
+            },
+            raytracing_shading_normal: {
+                load: DontCare,
+                store: Store,
+                format: Format::B8G8R8A8_SRGB,
+                samples: 1,
+            },
+            raytracing_ao: {
+                load: DontCare,
+                store: Store,
+                format: Format::R8_UNORM,
+                samples: 1,
+            },
+            raytracing_velocity: {
+                load: DontCare,
+                store: Store,
+                format: Format::R16G16_SFLOAT,
+                samples: 1,
+            },
+        },
+        passes: [
+            {
+                color: [raytracing_output, raytracing_albedo, raytracing_normal, raytracing_depth, raytracing_output_right, raytracing_shading_normal, raytracing_ao, raytracing_velocity],
+                depth_stencil: {},
+                input: [],
+            },
+        ],
+    )?;
+
+    let screen_render_pass = vulkano::ordered_passes_renderpass!(
+        device.clone(),
+        attachments: {
+            screen_output: {
+                load: Clear,
+                store: Store,
+                format: swapchain_format,
+                samples: 1,
+            },
+            raytracing_output: {
+                load: Load,
+                store: DontCare,
+                format: Format::R32G32B32A32_SFLOAT,
+                samples: 1,
+            },
+            raytracing_albedo: {
+                load: Load,
+                store: DontCare,
+                format: Format::B8G8R8A8_SRGB,
+                samples: 1,
+            },
+            raytracing_normal: {
+                load: Load,
+                store: DontCare,
+                format: Format::B8G8R8A8_SRGB,
+                samples: 1,
+            },
+            raytracing_depth: {
+                load: Load,
+                store: DontCare,
+                format: Format::R32_SFLOAT,
+                samples: 1,
+            },
+            raytracing_output_right: {
+                load: Load,
+                store: DontCare,
+                format: Format::R32G32B32A32_SFLOAT,
+                samples: 1,
+            },
+            raytracing_shading_normal: {
+                load: Load,
+                store: DontCare,
+                format: Format::B8G8R8A8_SRGB,
+                samples: 1,
+            },
+            raytracing_ao: {
+                load: Load,
+                store: DontCare,
+                format: Format::R8_UNORM,
+                samples: 1,
+            },
+            raytracing_velocity: {
+                load: Load,
+                store: DontCare,
+                format: Format::R16G16_SFLOAT,
+                samples: 1,
+            },
+        },
+        passes: [
+            {
+                color: [screen_output],
+                depth_stencil: {},
+                input: [raytracing_output, raytracing_albedo, raytracing_normal, raytracing_depth, raytracing_output_right, raytracing_shading_normal, raytracing_ao, raytracing_velocity],
+            },
+        ],
+    )?;
+
+    Ok((raytrace_render_pass, screen_render_pass))
+}
+
+impl Drop for Vk {
+    fn drop(&mut self) {
+        self.wait_idle();
+        save_pipeline_cache(&self.pipeline_cache);
+        // fields are dropped right after this, in declaration order, once the GPU is idle
+    }
+}
+
 fn setup_debug_callback(instance: &Arc<Instance>) -> DebugUtilsMessenger {
     return unsafe {
         DebugUtilsMessenger::new(